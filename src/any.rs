@@ -0,0 +1,72 @@
+//! Unchecked downcasting of `Any` trait objects.
+//!
+//! The maps in this crate know the type of every value they hold, so checking it again on every
+//! access is wasted effort; this is how they avoid it. It is exported for other type-erased
+//! containers which keep the same invariant, but it is only sound if the type really is known.
+//!
+//! Not available with the `forbid-unsafe` feature.
+
+use std::any::Any;
+
+use audit_downcast;
+
+/// An extension of `Any` trait objects allowing downcasting without checking the type.
+///
+/// With debug assertions enabled, the type is checked after all, and a mismatch panics rather
+/// than invoking undefined behaviour.
+///
+/// ```rust
+/// # use anymap::any::UncheckedAnyExt;
+/// # use std::any::Any;
+/// let value = Box::new(42i32) as Box<dyn Any>;
+/// // Safe, as the value was boxed as an i32 just above.
+/// assert_eq!(unsafe { *value.downcast_ref_unchecked::<i32>() }, 42);
+/// ```
+pub trait UncheckedAnyExt {
+    /// Returns a reference to the value, assuming that it is of type `T`.
+    ///
+    /// # Safety
+    ///
+    /// This is undefined behaviour if the value is not of type `T`.
+    unsafe fn downcast_ref_unchecked<T: 'static>(&self) -> &T;
+
+    /// Returns a mutable reference to the value, assuming that it is of type `T`.
+    ///
+    /// # Safety
+    ///
+    /// This is undefined behaviour if the value is not of type `T`.
+    unsafe fn downcast_mut_unchecked<T: 'static>(&mut self) -> &mut T;
+
+    /// Returns the boxed value, assuming that it is of type `T`.
+    ///
+    /// # Safety
+    ///
+    /// This is undefined behaviour if the value is not of type `T`.
+    unsafe fn downcast_unchecked<T: 'static>(self: Box<Self>) -> Box<T>;
+}
+
+impl UncheckedAnyExt for dyn Any {
+    #[inline]
+    unsafe fn downcast_ref_unchecked<T: 'static>(&self) -> &T {
+        audit_downcast::<T>((*self).type_id());
+
+        // Casting the fat pointer to a thin one keeps just the data pointer
+        &*(self as *const dyn Any as *const T)
+    }
+
+    #[inline]
+    unsafe fn downcast_mut_unchecked<T: 'static>(&mut self) -> &mut T {
+        audit_downcast::<T>((*self).type_id());
+
+        // Casting the fat pointer to a thin one keeps just the data pointer
+        &mut *(self as *mut dyn Any as *mut T)
+    }
+
+    #[inline]
+    unsafe fn downcast_unchecked<T: 'static>(self: Box<dyn Any>) -> Box<T> {
+        audit_downcast::<T>((*self).type_id());
+
+        // Take ownership of the allocation back from the box, keeping just the data pointer
+        Box::from_raw(Box::into_raw(self) as *mut T)
+    }
+}
@@ -0,0 +1,142 @@
+//! Random `AnyMap`s for property tests, with `quickcheck`.
+//!
+//! Only available with the `quickcheck` feature.
+
+use std::any::Any;
+use std::fmt;
+use std::ops::Deref;
+
+use quickcheck::{Arbitrary, Gen};
+
+use AnyMap;
+
+/// A set of types from which random maps are built.
+///
+/// This is implemented for tuples of up to eight types, each implementing `Arbitrary`.
+pub trait Palette: 'static {
+    /// Which of the types a map has values of, and what those values are.
+    type Entries: Arbitrary + fmt::Debug;
+
+    /// Inserts the values in `entries` into a map.
+    fn fill(entries: &Self::Entries, map: &mut AnyMap);
+}
+
+macro_rules! palette {
+    ($($t:ident $v:ident),+) => {
+        impl<$($t: Arbitrary + Any + fmt::Debug),+> Palette for ($($t,)+) {
+            type Entries = ($(Option<$t>,)+);
+
+            fn fill(entries: &($(Option<$t>,)+), map: &mut AnyMap) {
+                let ($(ref $v,)+) = *entries;
+                $(if let Some(ref value) = *$v { let _ = map.insert(value.clone()); })+
+            }
+        }
+    }
+}
+
+palette!(A a);
+palette!(A a, B b);
+palette!(A a, B b, C c);
+palette!(A a, B b, C c, D d);
+palette!(A a, B b, C c, D d, E e);
+palette!(A a, B b, C c, D d, E e, F f);
+palette!(A a, B b, C c, D d, E e, F f, G g);
+palette!(A a, B b, C c, D d, E e, F f, G g, H h);
+
+/// An `AnyMap` holding random values of a random selection of the types of the palette `P`.
+///
+/// Shrinking it removes entries and shrinks the remaining values.
+///
+/// ```rust
+/// # extern crate anymap;
+/// # extern crate quickcheck;
+/// # use anymap::arbitrary::ArbitraryAnyMap;
+/// # fn main() {
+/// fn prop(map: ArbitraryAnyMap<(i32, String)>) -> bool {
+///     map.len() <= 2
+/// }
+/// quickcheck::quickcheck(prop as fn(ArbitraryAnyMap<(i32, String)>) -> bool);
+/// # }
+/// ```
+///
+/// All of the non-mutating `AnyMap` methods are available through `Deref`.
+pub struct ArbitraryAnyMap<P: Palette> {
+    entries: P::Entries,
+    map: AnyMap,
+}
+
+impl<P: Palette> ArbitraryAnyMap<P> {
+    /// Construct an `ArbitraryAnyMap` holding the given values.
+    pub fn from_entries(entries: P::Entries) -> ArbitraryAnyMap<P> {
+        let mut map = AnyMap::new();
+        P::fill(&entries, &mut map);
+        ArbitraryAnyMap {
+            entries,
+            map,
+        }
+    }
+
+    /// Returns which of the types the map has values of, and what those values are.
+    #[inline]
+    pub fn entries(&self) -> &P::Entries {
+        &self.entries
+    }
+
+    /// Unwraps the generated `AnyMap`.
+    #[inline]
+    pub fn into_inner(self) -> AnyMap {
+        self.map
+    }
+}
+
+impl<P: Palette> Deref for ArbitraryAnyMap<P> {
+    type Target = AnyMap;
+
+    #[inline]
+    fn deref(&self) -> &AnyMap {
+        &self.map
+    }
+}
+
+impl<P: Palette> Clone for ArbitraryAnyMap<P> {
+    fn clone(&self) -> ArbitraryAnyMap<P> {
+        ArbitraryAnyMap::from_entries(self.entries.clone())
+    }
+}
+
+impl<P: Palette> fmt::Debug for ArbitraryAnyMap<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ArbitraryAnyMap {:?}", self.entries)
+    }
+}
+
+impl<P: Palette> Arbitrary for ArbitraryAnyMap<P> {
+    fn arbitrary(g: &mut Gen) -> ArbitraryAnyMap<P> {
+        ArbitraryAnyMap::from_entries(Arbitrary::arbitrary(g))
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = ArbitraryAnyMap<P>>> {
+        Box::new(self.entries.shrink().map(ArbitraryAnyMap::from_entries))
+    }
+}
+
+#[test]
+fn test_arbitrary() {
+    let map = ArbitraryAnyMap::<(i32, String)>::from_entries((Some(3), Some("x".to_string())));
+    assert_eq!(map.get::<i32>(), Some(&3));
+    assert_eq!(map.clone().get::<String>().map(|s| &s[..]), Some("x"));
+
+    let shrunk: Vec<_> = map.shrink().collect();
+    assert!(!shrunk.is_empty());
+    assert!(shrunk.iter().all(|map| map.len() <= 2));
+    assert!(shrunk.iter().any(|map| map.len() == 1 && !map.contains::<i32>()));
+
+    let mut g = Gen::new(10);
+    for _ in 0..10 {
+        let map = ArbitraryAnyMap::<(i32, u8, String)>::arbitrary(&mut g);
+        let (ref a, ref b, ref c) = *map.entries();
+        assert_eq!(map.get::<u8>(), b.as_ref());
+        let present = [a.is_some(), b.is_some(), c.is_some()];
+        assert_eq!(map.len(), present.iter().filter(|&&present| present).count());
+    }
+}
@@ -0,0 +1,193 @@
+//! Atomic values updated in place through shared references to a map, such as counters.
+//!
+//! An `AtomicBool`, `AtomicU64` or `AtomicUsize`, or a type of the application's own wrapping
+//! one and implementing `AtomicEntry`, can be stored in an `AnyMap` or a `SyncAnyMap` like any
+//! other value, and then read and updated with the `_in` methods through `&self`. A map shared
+//! between threads behind a lock thus needs only the read lock to bump a counter, and one shared
+//! without a lock needs none at all. All operations use `SeqCst` ordering.
+
+use std::any::Any;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use AnyMap;
+use sync::SyncAnyMap;
+
+/// One of the atomic types which can back an `AtomicEntry`.
+pub trait Primitive: Send + Sync {
+    /// The plain type of the values held.
+    type Value: Copy;
+
+    /// Loads the value.
+    fn load(&self) -> Self::Value;
+
+    /// Stores a value, returning the previous one.
+    fn swap(&self, value: Self::Value) -> Self::Value;
+}
+
+/// An atomic integer type, which can be added to and subtracted from, wrapping around on
+/// overflow.
+pub trait Counter: Primitive {
+    /// Adds to the value, returning the previous one.
+    fn fetch_add(&self, delta: Self::Value) -> Self::Value;
+
+    /// Subtracts from the value, returning the previous one.
+    fn fetch_sub(&self, delta: Self::Value) -> Self::Value;
+}
+
+macro_rules! impl_primitive {
+    ($atomic:ident, $value:ty) => {
+        impl Primitive for $atomic {
+            type Value = $value;
+
+            #[inline]
+            fn load(&self) -> $value {
+                $atomic::load(self, Ordering::SeqCst)
+            }
+
+            #[inline]
+            fn swap(&self, value: $value) -> $value {
+                $atomic::swap(self, value, Ordering::SeqCst)
+            }
+        }
+
+        impl AtomicEntry for $atomic {
+            type Atomic = $atomic;
+
+            #[inline]
+            fn atomic(&self) -> &$atomic {
+                self
+            }
+        }
+    };
+}
+
+macro_rules! impl_counter {
+    ($atomic:ident, $value:ty) => {
+        impl_primitive!($atomic, $value);
+
+        impl Counter for $atomic {
+            #[inline]
+            fn fetch_add(&self, delta: $value) -> $value {
+                $atomic::fetch_add(self, delta, Ordering::SeqCst)
+            }
+
+            #[inline]
+            fn fetch_sub(&self, delta: $value) -> $value {
+                $atomic::fetch_sub(self, delta, Ordering::SeqCst)
+            }
+        }
+    };
+}
+
+impl_primitive!(AtomicBool, bool);
+impl_counter!(AtomicU64, u64);
+impl_counter!(AtomicUsize, usize);
+
+/// A type stored in a map whose value is held in an atomic, so that it can be updated through
+/// a shared reference to the map.
+///
+/// The atomic types implement it themselves; a newtype around one, giving it a type of its own
+/// in the map, implements it by returning a reference to its atomic.
+///
+/// ```rust
+/// # use anymap::AnyMap;
+/// # use anymap::atomic::AtomicEntry;
+/// # use std::sync::atomic::AtomicU64;
+/// struct RequestCount(AtomicU64);
+///
+/// impl AtomicEntry for RequestCount {
+///     type Atomic = AtomicU64;
+///     fn atomic(&self) -> &AtomicU64 { &self.0 }
+/// }
+///
+/// let mut data = AnyMap::new();
+/// data.insert(RequestCount(AtomicU64::new(0)));
+///
+/// let data = &data;
+/// assert_eq!(data.fetch_add_in::<RequestCount>(1), Some(0));
+/// assert_eq!(data.fetch_add_in::<RequestCount>(1), Some(1));
+/// assert_eq!(data.load_in::<RequestCount>(), Some(2));
+/// ```
+pub trait AtomicEntry: Any + Send + Sync + 'static {
+    /// The atomic type holding the value.
+    type Atomic: Primitive;
+
+    /// Returns a reference to the atomic holding the value.
+    fn atomic(&self) -> &Self::Atomic;
+}
+
+macro_rules! impl_atomic_access {
+    ($map:ident) => {
+        impl $map {
+            /// Loads the value of the atomic entry of type `T`, if there is one.
+            #[inline]
+            pub fn load_in<T: AtomicEntry>(&self) -> Option<<T::Atomic as Primitive>::Value> {
+                self.get::<T>().map(|entry| entry.atomic().load())
+            }
+
+            /// Stores a value in the atomic entry of type `T`, returning the previous value, or
+            /// `None`, storing nothing, if there is no such entry.
+            #[inline]
+            pub fn swap_in<T: AtomicEntry>(&self, value: <T::Atomic as Primitive>::Value)
+                                           -> Option<<T::Atomic as Primitive>::Value> {
+                self.get::<T>().map(|entry| entry.atomic().swap(value))
+            }
+
+            /// Adds to the atomic counter of type `T`, returning the previous value, or `None`
+            /// if there is no such counter.
+            #[inline]
+            pub fn fetch_add_in<T: AtomicEntry>(&self, delta: <T::Atomic as Primitive>::Value)
+                                                -> Option<<T::Atomic as Primitive>::Value>
+            where T::Atomic: Counter {
+                self.get::<T>().map(|entry| entry.atomic().fetch_add(delta))
+            }
+
+            /// Subtracts from the atomic counter of type `T`, returning the previous value, or
+            /// `None` if there is no such counter.
+            #[inline]
+            pub fn fetch_sub_in<T: AtomicEntry>(&self, delta: <T::Atomic as Primitive>::Value)
+                                                -> Option<<T::Atomic as Primitive>::Value>
+            where T::Atomic: Counter {
+                self.get::<T>().map(|entry| entry.atomic().fetch_sub(delta))
+            }
+        }
+    };
+}
+
+impl_atomic_access!(AnyMap);
+impl_atomic_access!(SyncAnyMap);
+
+#[test]
+fn test_atomic() {
+    use std::sync::RwLock;
+    use std::thread;
+
+    struct Hits(AtomicUsize);
+    impl AtomicEntry for Hits {
+        type Atomic = AtomicUsize;
+        fn atomic(&self) -> &AtomicUsize { &self.0 }
+    }
+
+    let mut map = AnyMap::new();
+    let _ = map.insert(AtomicBool::new(false));
+    assert_eq!(map.swap_in::<AtomicBool>(true), Some(false));
+    assert_eq!(map.load_in::<AtomicBool>(), Some(true));
+    assert_eq!(map.fetch_add_in::<AtomicU64>(1), None);
+
+    let mut sync = SyncAnyMap::new();
+    let _ = sync.insert(Hits(AtomicUsize::new(10)));
+    let sync = RwLock::new(sync);
+    thread::scope(|scope| {
+        for _ in 0..4 {
+            let _ = scope.spawn(|| {
+                let sync = sync.read().unwrap();
+                for _ in 0..100 {
+                    let _ = sync.fetch_add_in::<Hits>(1);
+                }
+            });
+        }
+    });
+    let sync = sync.read().unwrap();
+    assert_eq!(sync.fetch_sub_in::<Hits>(10), Some(410));
+    assert_eq!(sync.load_in::<Hits>(), Some(400));
+}
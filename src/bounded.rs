@@ -0,0 +1,153 @@
+//! An `AnyMap` flavour admitting only types which have been opted in for it.
+
+use std::any::{Any, TypeId};
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+use AnyMap;
+use like::AnyMapLike;
+
+/// Opts the type into being stored in a `BoundedAnyMap<M>`.
+///
+/// `M` is a marker type of the application's own; if it is not public, no other crate can opt
+/// types in, so only the application decides what its maps may hold. A blanket impl over a
+/// trait of the application's opts in every type implementing that trait.
+pub trait Storable<M: ?Sized>: Any + 'static { }
+
+/// An `AnyMap` into which only values of types implementing `Storable<M>` can be inserted.
+///
+/// All of the non-mutating `AnyMap` methods are available through `Deref`; the mutating ones
+/// are reimplemented, with the bound on those which add values.
+///
+/// ```rust
+/// # use anymap::bounded::{BoundedAnyMap, Storable};
+/// struct Context;
+///
+/// struct User { name: &'static str }
+/// impl Storable<Context> for User { }
+///
+/// let mut context = BoundedAnyMap::<Context>::new();
+/// context.insert(User { name: "admin" });
+/// // context.insert(42u8) would not compile.
+/// assert_eq!(context.get::<User>().unwrap().name, "admin");
+/// ```
+pub struct BoundedAnyMap<M: ?Sized> {
+    map: AnyMap,
+    marker: PhantomData<fn() -> M>,
+}
+
+impl<M: ?Sized> Default for BoundedAnyMap<M> {
+    #[inline]
+    fn default() -> BoundedAnyMap<M> {
+        BoundedAnyMap::new()
+    }
+}
+
+impl<M: ?Sized> BoundedAnyMap<M> {
+    /// Construct a new, empty `BoundedAnyMap`.
+    #[inline]
+    pub fn new() -> BoundedAnyMap<M> {
+        BoundedAnyMap {
+            map: AnyMap::new(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns a mutable reference to the value stored in the collection for the type `T`,
+    /// if it exists.
+    #[inline]
+    pub fn get_mut<T: Storable<M>>(&mut self) -> Option<&mut T> {
+        self.map.get_mut::<T>()
+    }
+
+    /// Sets the value stored in the collection for the type `T`.
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    #[inline]
+    pub fn insert<T: Storable<M>>(&mut self, value: T) -> Option<T> {
+        self.map.insert(value)
+    }
+
+    /// Removes the `T` value from the collection,
+    /// returning it if there was one or `None` if there was not.
+    #[inline]
+    pub fn remove<T: Any + 'static>(&mut self) -> Option<T> {
+        self.map.remove::<T>()
+    }
+
+    /// Removes all items from the collection. Keeps the allocated memory for reuse.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    /// Unwraps the underlying `AnyMap`, which is no longer bounded.
+    #[inline]
+    pub fn into_inner(self) -> AnyMap {
+        self.map
+    }
+}
+
+impl<M: ?Sized> Deref for BoundedAnyMap<M> {
+    type Target = AnyMap;
+
+    #[inline]
+    fn deref(&self) -> &AnyMap {
+        &self.map
+    }
+}
+
+/// Values inserted through `AnyMapLike` are refused, as whether their types are `Storable<M>`
+/// cannot be checked at runtime.
+impl<M: ?Sized> AnyMapLike for BoundedAnyMap<M> {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.map.contains_id(id)
+    }
+
+    #[inline]
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.map.get_raw(id)
+    }
+
+    #[inline]
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        self.map.get_raw_mut(id)
+    }
+
+    #[inline]
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        Err(value)
+    }
+
+    #[inline]
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        self.map.remove_raw(id)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+#[test]
+fn test_bounded() {
+    trait Safe { }
+    impl Safe for u8 { }
+    impl Safe for u16 { }
+
+    enum Marker { }
+    impl<T: Safe + Any + 'static> Storable<Marker> for T { }
+
+    let mut map = BoundedAnyMap::<Marker>::new();
+    assert_eq!(map.insert(1u8), None);
+    assert_eq!(map.insert(2u16), None);
+    *map.get_mut::<u8>().unwrap() += 1;
+    assert_eq!(map.get::<u8>(), Some(&2));
+    assert_eq!(map.remove::<u16>(), Some(2));
+    assert!(!map.contains::<u32>());
+    map.clear();
+    assert!(map.into_inner().is_empty());
+}
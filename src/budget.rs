@@ -0,0 +1,423 @@
+//! An `AnyMap` flavour bounded by the estimated number of bytes its values take up, evicting the
+//! least recently used values to stay within its budget.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::mem::{size_of, size_of_val};
+
+use instrument::Instruments;
+use like::AnyMapLike;
+use {CallbackPanic, EvictionCallback, TypeIdBuildHasher, UNKNOWN_TYPE, call_on_evict, cast_box,
+     cast_mut, cast_ref, type_name};
+
+/// A value together with its weight and the time it was last used.
+struct Slot {
+    value: Box<dyn Any + 'static>,
+    /// The name of the type of the value, for diagnostics.
+    name: &'static str,
+    bytes: usize,
+    last_used: u64,
+}
+
+/// Estimates the number of bytes a value of one type takes up.
+type Weigher = Box<dyn Fn(&dyn Any) -> usize + 'static>;
+
+/// A collection containing zero or one values for any given type, holding values weighing at
+/// most a fixed number of bytes in total: inserting a value which would take the map over its
+/// budget first evicts the types which were least recently inserted or accessed until it fits,
+/// among the types of the lowest eviction priority set by `set_priority`.
+///
+/// A value weighs `size_of::<T>()` bytes, unless a function estimating the weight of values of
+/// its type, including whatever they own on the heap, has been recorded with `weigh_with`.
+/// Values are weighed when they are inserted and after they are changed by `modify`; to keep the
+/// weights true there is no `get_mut`.
+///
+/// Evicted values are dropped, unless a callback has been registered with `on_evict` to take
+/// them.
+///
+/// ```rust
+/// # use anymap::budget::BudgetedAnyMap;
+/// struct Thumbnail(Vec<u8>);
+/// struct Page(Vec<u8>);
+///
+/// let mut cache = BudgetedAnyMap::new(1 << 20);
+/// cache.weigh_with(|thumbnail: &Thumbnail| thumbnail.0.len());
+/// cache.weigh_with(|page: &Page| page.0.len());
+/// assert!(cache.insert(Thumbnail(vec![0; 16 << 10])).is_ok());
+/// assert!(cache.insert(Page(vec![0; 1 << 20])).is_ok());
+/// assert!(!cache.contains::<Thumbnail>());
+/// assert_eq!(cache.bytes(), 1 << 20);
+/// ```
+pub struct BudgetedAnyMap {
+    data: HashMap<TypeId, Slot, TypeIdBuildHasher>,
+    weighers: HashMap<TypeId, Weigher, TypeIdBuildHasher>,
+    max_bytes: usize,
+    bytes: usize,
+    clock: u64,
+    on_evict: Option<EvictionCallback>,
+    evict_panics: Vec<CallbackPanic>,
+    priorities: HashMap<TypeId, u32, TypeIdBuildHasher>,
+    instruments: Instruments,
+}
+
+impl BudgetedAnyMap {
+    /// Construct a new `BudgetedAnyMap` holding values weighing at most `max_bytes` in total.
+    #[inline]
+    pub fn new(max_bytes: usize) -> BudgetedAnyMap {
+        BudgetedAnyMap {
+            data: HashMap::with_hasher(TypeIdBuildHasher),
+            weighers: HashMap::with_hasher(TypeIdBuildHasher),
+            max_bytes,
+            bytes: 0,
+            clock: 0,
+            on_evict: None,
+            evict_panics: Vec::new(),
+            priorities: HashMap::with_hasher(TypeIdBuildHasher),
+            instruments: Instruments::new(),
+        }
+    }
+
+    /// Registers a callback to be given each value evicted to make room, replacing any callback
+    /// registered before. Values removed explicitly are not passed to it.
+    ///
+    /// A callback which panics does not unwind through the map: the panic is caught, the
+    /// callback unregistered, and the panic recorded, to be collected with `take_evict_panics`.
+    pub fn on_evict<F: FnMut(TypeId, Box<dyn Any + 'static>) + 'static>(&mut self, callback: F) {
+        self.on_evict = Some(Box::new(callback) as EvictionCallback);
+    }
+
+    /// Returns the panics of eviction callbacks caught since the last call, in the order they
+    /// happened, leaving none recorded.
+    #[inline]
+    pub fn take_evict_panics(&mut self) -> Vec<CallbackPanic> {
+        std::mem::take(&mut self.evict_panics)
+    }
+
+    /// Sets the eviction priority of the type `T`, zero by default. Values of the types of the
+    /// lowest priority are evicted first, the least recently used first among them, so giving
+    /// a type which is expensive to recompute a higher priority protects it from eviction for
+    /// as long as there are values of lower priority to evict instead.
+    pub fn set_priority<T: Any + 'static>(&mut self, priority: u32) {
+        let id = TypeId::of::<T>();
+        if priority == 0 {
+            let _ = self.priorities.remove(&id);
+        } else {
+            let _ = self.priorities.insert(id, priority);
+        }
+    }
+
+    /// Returns the eviction priority of the type `T`.
+    #[inline]
+    pub fn priority<T: Any + 'static>(&self) -> u32 {
+        self.priorities.get(&TypeId::of::<T>()).cloned().unwrap_or(0)
+    }
+
+    /// Sets the prefix of the names of the metrics emitted for this map, `anymap` by default.
+    ///
+    /// With the `metrics` feature, each eviction increments the counter `<prefix>.evictions`,
+    /// labelled with the name of the type evicted.
+    #[cfg(feature = "metrics")]
+    #[inline]
+    pub fn set_metrics_prefix(&mut self, prefix: &str) {
+        self.instruments.set_metrics_prefix(prefix)
+    }
+
+    /// Records how to estimate the number of bytes a value of type `T` takes up, replacing what
+    /// was recorded before. A value already in the map is not weighed again until it is next
+    /// inserted or modified.
+    pub fn weigh_with<T: Any + 'static, F: Fn(&T) -> usize + 'static>(&mut self, weigh: F) {
+        let weigher = Box::new(move |value: &dyn Any| weigh(cast_ref::<T>(value))) as Weigher;
+        let _ = self.weighers.insert(TypeId::of::<T>(), weigher);
+    }
+
+    /// Returns the maximum number of bytes the values in the map may weigh in total.
+    #[inline]
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// Changes the maximum number of bytes the values in the map may weigh in total, evicting
+    /// the least recently used values if they now weigh too much.
+    pub fn set_max_bytes(&mut self, max_bytes: usize) {
+        self.max_bytes = max_bytes;
+        self.evict_to_fit(0);
+    }
+
+    /// Returns the number of bytes the values in the map weigh in total.
+    #[inline]
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Returns the number of bytes the value of type `T` weighed when it was last weighed, if
+    /// the collection contains one.
+    pub fn weight_of<T: Any + 'static>(&self) -> Option<usize> {
+        self.data.get(&TypeId::of::<T>()).map(|slot| slot.bytes)
+    }
+
+    /// Returns a reference to the value stored in the collection for the type `T`, if it exists,
+    /// marking it as the most recently used.
+    pub fn get<T: Any + 'static>(&mut self) -> Option<&T> {
+        let now = self.tick();
+        self.data.get_mut(&TypeId::of::<T>()).map(|slot| {
+            slot.last_used = now;
+            cast_ref::<T>(&*slot.value)
+        })
+    }
+
+    /// Returns a reference to the value stored in the collection for the type `T`, if it exists,
+    /// without marking it as used.
+    pub fn peek<T: Any + 'static>(&self) -> Option<&T> {
+        self.data.get(&TypeId::of::<T>())
+            .map(|slot| cast_ref::<T>(&*slot.value))
+    }
+
+    /// Returns true if the collection contains a value of type `T`. This does not count as a use.
+    #[inline]
+    pub fn contains<T: Any + 'static>(&self) -> bool {
+        self.data.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Applies a change to the value stored in the collection for the type `T`, if it exists,
+    /// marking it as the most recently used and weighing it again, evicting the least recently
+    /// used values if the map now weighs too much. Returns the result of the change.
+    ///
+    /// Should the value alone now weigh more than the budget, it is evicted itself, and nothing
+    /// else is.
+    pub fn modify<T: Any + 'static, R, F: FnOnce(&mut T) -> R>(&mut self, f: F) -> Option<R> {
+        let now = self.tick();
+        let id = TypeId::of::<T>();
+        let slot = self.data.get_mut(&id)?;
+        let result = f(cast_mut::<T>(&mut *slot.value));
+        let bytes = weigh(&self.weighers, &*slot.value, size_of::<T>());
+        slot.last_used = now;
+        if bytes > self.max_bytes {
+            self.evict_id(id);
+        } else {
+            self.bytes = self.bytes - slot.bytes + bytes;
+            slot.bytes = bytes;
+            self.evict_to_fit(0);
+        }
+        Some(result)
+    }
+
+    /// Sets the value stored in the collection for the type `T`, marking it as the most recently
+    /// used. If the collection would weigh too much with it, the least recently used values of
+    /// other types are evicted until it fits.
+    ///
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    ///
+    /// Fails, handing the value back and leaving the collection untouched, if the value alone
+    /// weighs more than the budget.
+    pub fn insert<T: Any + 'static>(&mut self, value: T) -> Result<Option<T>, T> {
+        let value = Box::new(value) as Box<dyn Any>;
+        match self.insert_any(TypeId::of::<T>(), type_name::<T>(), value) {
+            Ok(old) => Ok(old.map(|any| *cast_box::<T>(any))),
+            Err(value) => Err(*cast_box::<T>(value)),
+        }
+    }
+
+    /// Removes the `T` value from the collection,
+    /// returning it if there was one or `None` if there was not.
+    #[inline]
+    pub fn remove<T: Any + 'static>(&mut self) -> Option<T> {
+        self.remove_any(TypeId::of::<T>()).map(|any| *cast_box::<T>(any))
+    }
+
+    /// Returns the number of items in the collection.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns true if there are no items in the collection.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Removes all items from the collection. Keeps the allocated memory for reuse.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.bytes = 0;
+    }
+
+    fn insert_any(&mut self, id: TypeId, name: &'static str, value: Box<dyn Any + 'static>)
+                  -> Result<Option<Box<dyn Any + 'static>>, Box<dyn Any + 'static>> {
+        let bytes = weigh(&self.weighers, &*value, size_of_val(&*value));
+        if bytes > self.max_bytes {
+            return Err(value);
+        }
+        let old = self.remove_any(id);
+        self.evict_to_fit(bytes);
+        let slot = Slot {
+            value,
+            name,
+            bytes,
+            last_used: self.tick(),
+        };
+        let _ = self.data.insert(id, slot);
+        self.bytes += bytes;
+        Ok(old)
+    }
+
+    fn remove_any(&mut self, id: TypeId) -> Option<Box<dyn Any + 'static>> {
+        self.data.remove(&id).map(|slot| {
+            self.bytes -= slot.bytes;
+            slot.value
+        })
+    }
+
+    /// Advances the clock, returning the new time.
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Evicts the least recently used values until `extra` more bytes fit within the budget.
+    fn evict_to_fit(&mut self, extra: usize) {
+        // A total too large to count is over any budget.
+        let max_bytes = self.max_bytes;
+        let fits = |bytes: usize| bytes.checked_add(extra).is_some_and(|total| total <= max_bytes);
+        while !fits(self.bytes) && !self.data.is_empty() {
+            self.evict();
+        }
+    }
+
+    /// Evicts the least recently used value of the lowest priority, if there are any values.
+    fn evict(&mut self) {
+        let mut oldest: Option<(TypeId, (u32, u64))> = None;
+        for (&id, slot) in self.data.iter() {
+            let rank = (self.priorities.get(&id).cloned().unwrap_or(0), slot.last_used);
+            match oldest {
+                Some((_, oldest_rank)) if oldest_rank <= rank => (),
+                _ => oldest = Some((id, rank)),
+            }
+        }
+        if let Some((id, _)) = oldest {
+            self.evict_id(id);
+        }
+    }
+
+    /// Evicts the value of the type with the given ID, which must be present.
+    fn evict_id(&mut self, id: TypeId) {
+        let slot = self.data.remove(&id).unwrap();
+        self.bytes -= slot.bytes;
+        self.instruments.evicted(id, slot.name);
+        call_on_evict(&mut self.on_evict, &mut self.evict_panics, id, slot.value);
+    }
+}
+
+/// Mutable lookups through `AnyMapLike` are refused, as the value could not be weighed again
+/// after it was changed; there is no `get_mut` for the same reason. Lookups by shared reference
+/// are peeks, which do not count as uses, and insertions are refused if the value alone weighs
+/// more than the budget, as by `insert`.
+impl AnyMapLike for BudgetedAnyMap {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.data.contains_key(&id)
+    }
+
+    #[inline]
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.data.get(&id).map(|slot| &*slot.value)
+    }
+
+    #[inline]
+    fn get_raw_mut(&mut self, _: TypeId) -> Option<&mut dyn Any> {
+        None
+    }
+
+    #[inline]
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        self.insert_any((*value).type_id(), UNKNOWN_TYPE, value)
+    }
+
+    #[inline]
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        self.remove_any(id)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+/// Returns the weight of a value, by the weigher recorded for its type or else `default`.
+fn weigh(weighers: &HashMap<TypeId, Weigher, TypeIdBuildHasher>, value: &dyn Any,
+         default: usize) -> usize {
+    match weighers.get(&(*value).type_id()) {
+        Some(weigh) => (**weigh)(value),
+        None => default,
+    }
+}
+
+#[test]
+fn test_budgeted() {
+    let mut map = BudgetedAnyMap::new(10);
+    map.weigh_with(|v: &Vec<u8>| v.len());
+    assert_eq!(map.insert(1u32), Ok(None));
+    assert_eq!(map.insert(vec![0u8; 4]), Ok(None));
+    assert_eq!((map.bytes(), map.weight_of::<Vec<u8>>()), (8, Some(4)));
+    assert_eq!(map.insert(vec![0u8; 11]), Err(vec![0u8; 11]));
+    assert_eq!(map.bytes(), 8);
+
+    // u32 is the least recently used, so it goes first when the Vec grows.
+    assert_eq!(map.modify(|v: &mut Vec<u8>| v.extend_from_slice(&[1; 4])), Some(()));
+    assert!(!map.contains::<u32>());
+    assert_eq!(map.bytes(), 8);
+    assert_eq!(map.insert(2u16), Ok(None));
+    assert_eq!(map.get::<Vec<u8>>().map(|v| v.len()), Some(8));
+    assert_eq!(map.insert(3i16), Ok(None));
+    assert!(!map.contains::<u16>() && map.contains::<Vec<u8>>());
+    assert_eq!(map.insert(4i16), Ok(Some(3)));
+
+    // The Vec alone now weighs more than the budget, so it is evicted without the i16.
+    assert_eq!(map.modify(|v: &mut Vec<u8>| v.resize(20, 0)), Some(()));
+    assert!(!map.contains::<Vec<u8>>());
+    assert_eq!((map.peek::<i16>(), map.len(), map.bytes()), (Some(&4), 1, 2));
+    assert_eq!(map.remove::<i16>(), Some(4));
+    assert_eq!(map.insert(5u32), Ok(None));
+    map.set_max_bytes(2);
+    assert!(map.is_empty() && map.bytes() == 0);
+
+    let mut huge = BudgetedAnyMap::new(usize::MAX);
+    huge.weigh_with(|_: &u16| usize::MAX);
+    assert_eq!(huge.insert(1u8), Ok(None));
+    assert_eq!(huge.insert(1u16), Ok(None));
+    assert!(!huge.contains::<u8>() && huge.bytes() == usize::MAX);
+}
+
+#[test]
+fn test_budgeted_on_evict() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let evicted = Rc::new(RefCell::new(Vec::new()));
+    let log = evicted.clone();
+    let mut map = BudgetedAnyMap::new(4);
+    map.on_evict(move |id, _| log.borrow_mut().push(id));
+    assert!(map.insert(1u16).is_ok() && map.insert(2u16).is_ok() && map.insert(3i16).is_ok());
+    assert!(map.insert(4u32).is_ok());
+    assert_eq!(map.remove::<u32>(), Some(4));
+    map.set_priority::<u16>(2);
+    assert!(map.insert(1u16).is_ok() && map.insert(1i16).is_ok() && map.insert(1i8).is_ok());
+    assert!(map.contains::<u16>() && !map.contains::<i16>());
+    let mut expected = vec![TypeId::of::<u16>(), TypeId::of::<i16>(), TypeId::of::<i16>()];
+    expected.sort();
+    evicted.borrow_mut().sort();
+    assert_eq!(*evicted.borrow(), expected);
+
+    map.on_evict(|_, _| panic!("bad callback"));
+    map.set_priority::<u16>(0);
+    assert!(map.insert(1u32).is_ok() && map.insert(2u32).is_ok());
+    assert!(map.contains::<u32>() && map.bytes() <= 4);
+    let panics = map.take_evict_panics();
+    assert_eq!(panics.len(), 1);
+    assert_eq!(panics[0].message, Some("bad callback".to_string()));
+    assert!(map.take_evict_panics().is_empty());
+}
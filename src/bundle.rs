@@ -0,0 +1,132 @@
+//! Inserting several values into an `AnyMap` at once, each under its own type.
+
+use std::any::Any;
+
+use AnyMap;
+
+/// A group of values which can be inserted into an `AnyMap` at once with
+/// `AnyMap::insert_bundle`, each as an entry of its own.
+///
+/// It is implemented for tuples of up to twelve values, and can be implemented for a struct of
+/// values by declaring the struct with the `bundle!` macro.
+///
+/// ```rust
+/// # use anymap::AnyMap;
+/// let mut data = AnyMap::new();
+/// data.insert_bundle((1u8, "two", 3.0f64));
+/// assert_eq!(data.get::<&str>(), Some(&"two"));
+/// assert_eq!(data.len(), 3);
+/// ```
+pub trait Bundle {
+    /// Inserts each of the values into the map under its own type, replacing any value of that
+    /// type already there. Where two of the values are of the same type, the later one is kept.
+    fn insert_into(self, map: &mut AnyMap);
+}
+
+macro_rules! impl_bundle_for_tuple {
+    ($($t:ident $value:ident),+) => {
+        impl<$($t: Any + 'static),+> Bundle for ($($t,)+) {
+            fn insert_into(self, map: &mut AnyMap) {
+                let ($($value,)+) = self;
+                $(let _ = map.insert($value);)+
+            }
+        }
+    }
+}
+
+impl_bundle_for_tuple!(A a);
+impl_bundle_for_tuple!(A a, B b);
+impl_bundle_for_tuple!(A a, B b, C c);
+impl_bundle_for_tuple!(A a, B b, C c, D d);
+impl_bundle_for_tuple!(A a, B b, C c, D d, E e);
+impl_bundle_for_tuple!(A a, B b, C c, D d, E e, F f);
+impl_bundle_for_tuple!(A a, B b, C c, D d, E e, F f, G g);
+impl_bundle_for_tuple!(A a, B b, C c, D d, E e, F f, G g, H h);
+impl_bundle_for_tuple!(A a, B b, C c, D d, E e, F f, G g, H h, I i);
+impl_bundle_for_tuple!(A a, B b, C c, D d, E e, F f, G g, H h, I i, J j);
+impl_bundle_for_tuple!(A a, B b, C c, D d, E e, F f, G g, H h, I i, J j, K k);
+impl_bundle_for_tuple!(A a, B b, C c, D d, E e, F f, G g, H h, I i, J j, K k, L l);
+
+/// Declares a struct and implements `Bundle` for it, so that each of its fields is inserted
+/// into a map as an entry of its own.
+///
+/// The struct may have attributes, and be public with public fields or private with private
+/// fields; it may not have type or lifetime parameters.
+///
+/// ```rust
+/// # #[macro_use] extern crate anymap;
+/// # fn main() {
+/// struct Config { verbose: bool }
+/// struct Clock { now: u64 }
+///
+/// bundle! {
+///     struct Context {
+///         config: Config,
+///         clock: Clock,
+///     }
+/// }
+///
+/// let mut data = anymap::AnyMap::new();
+/// data.insert_bundle(Context {
+///     config: Config { verbose: true },
+///     clock: Clock { now: 0 },
+/// });
+/// assert!(data.get::<Config>().unwrap().verbose);
+/// assert_eq!(data.get::<Clock>().unwrap().now, 0);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! bundle {
+    ($(#[$attr:meta])* pub struct $name:ident { $(pub $field:ident: $t:ty),+ $(,)* }) => {
+        $(#[$attr])*
+        pub struct $name {
+            $(pub $field: $t),+
+        }
+
+        bundle!(@impl $name { $($field),+ });
+    };
+    ($(#[$attr:meta])* struct $name:ident { $($field:ident: $t:ty),+ $(,)* }) => {
+        $(#[$attr])*
+        struct $name {
+            $($field: $t),+
+        }
+
+        bundle!(@impl $name { $($field),+ });
+    };
+    (@impl $name:ident { $($field:ident),+ }) => {
+        impl $crate::bundle::Bundle for $name {
+            fn insert_into(self, map: &mut $crate::AnyMap) {
+                $(let _ = map.insert(self.$field);)+
+            }
+        }
+    };
+}
+
+#[test]
+fn test_bundle() {
+    bundle! {
+        #[derive(Clone)]
+        struct Numbers {
+            byte: u8,
+            short: u16,
+            other_byte: u8,
+        }
+    }
+
+    let mut map = AnyMap::new();
+    let _ = map.insert(1u32);
+    map.insert_bundle(Numbers { byte: 1, short: 2, other_byte: 3 });
+    assert_eq!(map.get::<u8>(), Some(&3));
+    assert_eq!(map.get::<u16>(), Some(&2));
+    assert_eq!(map.len(), 3);
+}
+
+#[test]
+fn test_tuple_bundle() {
+    let mut map = AnyMap::new();
+    map.insert_bundle((1u8,));
+    map.insert_bundle((2u8, 3u16, "four", 5u8));
+    assert_eq!(map.get::<u8>(), Some(&5));
+    assert_eq!(map.get::<u16>(), Some(&3));
+    assert_eq!(map.len(), 3);
+}
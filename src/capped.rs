@@ -0,0 +1,194 @@
+//! An `AnyMap` flavour which refuses to hold more than a fixed number of values.
+
+use std::any::{Any, TypeId};
+use std::error::Error;
+use std::fmt;
+use std::ops::Deref;
+
+use AnyMap;
+use like::AnyMapLike;
+
+/// An `AnyMap` holding at most a fixed number of values.
+///
+/// Unlike `LruAnyMap`, nothing is ever evicted: once the map is full, inserting a value of a new
+/// type fails, handing the value back. Replacing the value of a type already present always
+/// succeeds. This makes it suitable for maps exposed to untrusted code, which could otherwise
+/// insert an unbounded number of types.
+///
+/// All of the non-mutating `AnyMap` methods are available through `Deref`.
+///
+/// ```rust
+/// # use anymap::capped::CappedAnyMap;
+/// let mut data = CappedAnyMap::new(1);
+/// assert_eq!(data.try_insert(1i32), Ok(None));
+/// assert_eq!(data.try_insert(2i32), Ok(Some(1)));
+/// assert_eq!(data.try_insert(3u8).unwrap_err().into_value(), 3);
+/// ```
+pub struct CappedAnyMap {
+    map: AnyMap,
+    max_entries: usize,
+}
+
+impl CappedAnyMap {
+    /// Construct a new `CappedAnyMap` holding at most `max_entries` values.
+    #[inline]
+    pub fn new(max_entries: usize) -> CappedAnyMap {
+        CappedAnyMap {
+            map: AnyMap::new(),
+            max_entries,
+        }
+    }
+
+    /// Returns the maximum number of values the map will hold.
+    #[inline]
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    /// Unwraps the underlying `AnyMap`.
+    #[inline]
+    pub fn into_inner(self) -> AnyMap {
+        self.map
+    }
+
+    /// Returns a mutable reference to the value stored in the collection for the type `T`,
+    /// if it exists.
+    #[inline]
+    pub fn get_mut<T: Any + 'static>(&mut self) -> Option<&mut T> {
+        self.map.get_mut::<T>()
+    }
+
+    /// Sets the value stored in the collection for the type `T`, unless the collection is full
+    /// and has no `T` yet, in which case the value is returned in the error.
+    ///
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    pub fn try_insert<T: Any + 'static>(&mut self, value: T)
+                                        -> Result<Option<T>, CapacityError<T>> {
+        if self.map.len() >= self.max_entries && !self.map.contains::<T>() {
+            Err(CapacityError {
+                value,
+                max_entries: self.max_entries,
+            })
+        } else {
+            Ok(self.map.insert(value))
+        }
+    }
+
+    /// Removes the `T` value from the collection,
+    /// returning it if there was one or `None` if there was not.
+    #[inline]
+    pub fn remove<T: Any + 'static>(&mut self) -> Option<T> {
+        self.map.remove::<T>()
+    }
+
+    /// Removes all items from the collection. Keeps the allocated memory for reuse.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+}
+
+/// Insertions through `AnyMapLike` are refused when the collection is full, as by `try_insert`.
+impl AnyMapLike for CappedAnyMap {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.map.contains_id(id)
+    }
+
+    #[inline]
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.map.get_raw(id)
+    }
+
+    #[inline]
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        self.map.get_raw_mut(id)
+    }
+
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        if self.map.len() >= self.max_entries && !self.map.contains_id((*value).type_id()) {
+            return Err(value);
+        }
+        self.map.insert_raw(value)
+    }
+
+    #[inline]
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        self.map.remove_raw(id)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+impl Deref for CappedAnyMap {
+    type Target = AnyMap;
+
+    #[inline]
+    fn deref(&self) -> &AnyMap {
+        &self.map
+    }
+}
+
+/// The error returned when inserting into a full `CappedAnyMap`, holding the rejected value.
+#[derive(PartialEq)]
+pub struct CapacityError<T> {
+    value: T,
+    max_entries: usize,
+}
+
+impl<T> CapacityError<T> {
+    /// Returns the value which could not be inserted.
+    #[inline]
+    pub fn into_value(self) -> T {
+        self.value
+    }
+
+    /// Returns the limit which would have been exceeded.
+    #[inline]
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+}
+
+impl<T> fmt::Debug for CapacityError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CapacityError {{ max_entries: {} }}", self.max_entries)
+    }
+}
+
+impl<T> fmt::Display for CapacityError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the map is full (it may hold at most {} entries)", self.max_entries)
+    }
+}
+
+impl<T> Error for CapacityError<T> {
+    fn description(&self) -> &str {
+        "the map is full"
+    }
+}
+
+#[test]
+fn test_capped() {
+    let mut map = CappedAnyMap::new(2);
+    assert_eq!(map.try_insert(1i32), Ok(None));
+    assert_eq!(map.try_insert(1u8), Ok(None));
+    let error = map.try_insert(1u16).unwrap_err();
+    assert_eq!(error.max_entries(), 2);
+    assert_eq!(error.into_value(), 1);
+    assert_eq!(map.try_insert(2u8), Ok(Some(1)));
+    assert_eq!(map.len(), 2);
+
+    assert_eq!(map.remove::<i32>(), Some(1));
+    assert_eq!(map.try_insert(1u16), Ok(None));
+    assert!(map.try_insert(()).is_err());
+
+    // A refused value need not be Send for the error to be boxed.
+    let error: Box<dyn Error> = Box::new(map.try_insert(::std::rc::Rc::new(1u32)).unwrap_err());
+    assert_eq!(error.to_string(), "the map is full (it may hold at most 2 entries)");
+}
@@ -0,0 +1,236 @@
+//! An `AnyMap` flavour with a `RefCell` per entry, whose values can be borrowed mutably through a
+//! shared reference to the map.
+
+use std::any::{Any, TypeId};
+use std::cell::{BorrowError, BorrowMutError, Ref, RefCell, RefMut};
+use std::collections::HashMap;
+
+use {TypeIdBuildHasher, cast_box, cast_mut, cast_ref};
+use like::AnyMapLike;
+
+/// A `RefCell<T>`, with ways of getting at its value without borrowing it at runtime.
+struct Slot {
+    cell: Box<dyn Any + 'static>,
+    get_mut: fn(&mut dyn Any) -> &mut dyn Any,
+    into_inner: fn(Box<dyn Any + 'static>) -> Box<dyn Any + 'static>,
+}
+
+impl Slot {
+    fn new<T: Any + 'static>(value: T) -> Slot {
+        Slot {
+            cell: Box::new(RefCell::new(value)) as Box<dyn Any>,
+            get_mut: cell_get_mut::<T>,
+            into_inner: cell_into_inner::<T>,
+        }
+    }
+}
+
+/// Returns the value of a `RefCell<T>` mutably, which needs no runtime check.
+fn cell_get_mut<T: Any + 'static>(cell: &mut dyn Any) -> &mut dyn Any {
+    cast_mut::<RefCell<T>>(cell).get_mut()
+}
+
+/// Returns the value of a `RefCell<T>`.
+fn cell_into_inner<T: Any + 'static>(cell: Box<dyn Any + 'static>) -> Box<dyn Any + 'static> {
+    Box::new(cast_box::<RefCell<T>>(cell).into_inner()) as Box<dyn Any>
+}
+
+/// A collection containing zero or one values for any given type, each in a `RefCell` of its
+/// own, so that values can be borrowed from a shared reference to the map, mutably or not.
+///
+/// As with `RefCell`, the borrows are checked at runtime: `get` and `get_mut` panic if the value
+/// is already borrowed mutably, or at all, while `try_get` and `try_get_mut` return an error.
+/// Values of different types can be borrowed mutably at once. Adding and removing values still
+/// takes a mutable reference to the map.
+///
+/// ```rust
+/// # use anymap::cell::CellAnyMap;
+/// struct Clicks(u32);
+/// struct Theme(&'static str);
+///
+/// let mut state = CellAnyMap::new();
+/// state.insert(Clicks(0));
+/// state.insert(Theme("dark"));
+///
+/// let state = &state;
+/// let on_click = || state.get_mut::<Clicks>().unwrap().0 += 1;
+/// on_click();
+/// on_click();
+/// assert_eq!(state.get::<Clicks>().unwrap().0, 2);
+///
+/// let theme = state.get::<Theme>().unwrap();
+/// assert!(state.try_get_mut::<Theme>().is_err());
+/// assert_eq!(theme.0, "dark");
+/// ```
+pub struct CellAnyMap {
+    data: HashMap<TypeId, Slot, TypeIdBuildHasher>,
+}
+
+impl Default for CellAnyMap {
+    #[inline]
+    fn default() -> CellAnyMap {
+        CellAnyMap::new()
+    }
+}
+
+impl CellAnyMap {
+    /// Construct a new, empty `CellAnyMap`.
+    #[inline]
+    pub fn new() -> CellAnyMap {
+        CellAnyMap {
+            data: HashMap::with_hasher(TypeIdBuildHasher),
+        }
+    }
+
+    /// Borrows the value stored in the collection for the type `T`, if it exists.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently borrowed mutably.
+    pub fn get<T: Any + 'static>(&self) -> Option<Ref<'_, T>> {
+        self.cell::<T>().map(|cell| cell.borrow())
+    }
+
+    /// Borrows the value stored in the collection for the type `T` mutably, if it exists.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn get_mut<T: Any + 'static>(&self) -> Option<RefMut<'_, T>> {
+        self.cell::<T>().map(|cell| cell.borrow_mut())
+    }
+
+    /// Borrows the value stored in the collection for the type `T`, if it exists, returning an
+    /// error if it is currently borrowed mutably.
+    pub fn try_get<T: Any + 'static>(&self) -> Result<Option<Ref<'_, T>>, BorrowError> {
+        match self.cell::<T>() {
+            Some(cell) => cell.try_borrow().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Borrows the value stored in the collection for the type `T` mutably, if it exists,
+    /// returning an error if it is currently borrowed.
+    pub fn try_get_mut<T: Any + 'static>(&self) -> Result<Option<RefMut<'_, T>>, BorrowMutError> {
+        match self.cell::<T>() {
+            Some(cell) => cell.try_borrow_mut().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns true if the collection contains a value of type `T`.
+    #[inline]
+    pub fn contains<T: Any + 'static>(&self) -> bool {
+        self.data.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Sets the value stored in the collection for the type `T`.
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    pub fn insert<T: Any + 'static>(&mut self, value: T) -> Option<T> {
+        self.data.insert(TypeId::of::<T>(), Slot::new(value))
+            .map(|slot| cast_box::<RefCell<T>>(slot.cell).into_inner())
+    }
+
+    /// Removes the `T` value from the collection,
+    /// returning it if there was one or `None` if there was not.
+    pub fn remove<T: Any + 'static>(&mut self) -> Option<T> {
+        self.data.remove(&TypeId::of::<T>())
+            .map(|slot| cast_box::<RefCell<T>>(slot.cell).into_inner())
+    }
+
+    /// Returns the number of items in the collection.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns true if there are no items in the collection.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Removes all items from the collection. Keeps the allocated memory for reuse.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    fn cell<T: Any + 'static>(&self) -> Option<&RefCell<T>> {
+        self.data.get(&TypeId::of::<T>())
+            .map(|slot| cast_ref::<RefCell<T>>(&*slot.cell))
+    }
+}
+
+/// Lookups through `AnyMapLike` by shared reference are refused, as a plain reference to the
+/// value could not hold a runtime borrow of it; those through a mutable reference need none.
+/// Values inserted through it are refused, as they cannot be put in a `RefCell` without knowing
+/// their type.
+impl AnyMapLike for CellAnyMap {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.data.contains_key(&id)
+    }
+
+    #[inline]
+    fn get_raw(&self, _: TypeId) -> Option<&dyn Any> {
+        None
+    }
+
+    #[inline]
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        self.data.get_mut(&id).map(|slot| (slot.get_mut)(&mut *slot.cell))
+    }
+
+    #[inline]
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        Err(value)
+    }
+
+    #[inline]
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        self.data.remove(&id).map(|slot| (slot.into_inner)(slot.cell))
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+#[test]
+fn test_cell() {
+    let mut map = CellAnyMap::new();
+    assert_eq!(map.insert(1u8), None);
+    assert_eq!(map.insert(1u16), None);
+    {
+        let mut byte = map.get_mut::<u8>().unwrap();
+        let mut short = map.get_mut::<u16>().unwrap();
+        *byte += 1;
+        *short += 2;
+        assert!(map.try_get::<u8>().is_err());
+        assert!(map.try_get_mut::<u16>().is_err());
+    }
+    assert_eq!(*map.get::<u8>().unwrap(), 2);
+    {
+        let _short = map.get::<u16>().unwrap();
+        assert_eq!(map.try_get::<u16>().ok().unwrap().map(|short| *short), Some(3));
+        assert!(map.try_get_mut::<u16>().is_err());
+    }
+    assert!(map.try_get::<u32>().ok().unwrap().is_none());
+    assert!(map.contains::<u8>() && !map.contains::<u32>());
+    assert_eq!(map.remove::<u16>(), Some(3));
+    assert_eq!(map.insert(4u8), Some(2));
+    assert_eq!(map.len(), 1);
+}
+
+#[test]
+#[should_panic(expected = "already mutably borrowed")]
+fn test_cell_conflict() {
+    let mut map = CellAnyMap::new();
+    let _ = map.insert(1u8);
+    let _byte = map.get_mut::<u8>();
+    let _ = map.get::<u8>();
+}
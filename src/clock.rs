@@ -0,0 +1,77 @@
+//! Clock sources for the time-based map flavours.
+//!
+//! Everything which deals in time takes its idea of “now” from a `Clock`, defaulting to
+//! `SystemClock`; tests can substitute a `ManualClock` to drive time deterministically, and other
+//! tick sources can be plugged in by implementing `Clock`.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// A source of the current time.
+pub trait Clock {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+}
+
+/// The operating system’s monotonic clock, as given by `Instant::now`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock which only moves when told to.
+///
+/// Clones share the same time, so one can be handed to a map while the test keeps another to
+/// advance it.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: Rc<Cell<Instant>>,
+}
+
+impl Default for ManualClock {
+    #[inline]
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl ManualClock {
+    /// Construct a new `ManualClock`, stopped at the current system time.
+    #[inline]
+    pub fn new() -> ManualClock {
+        ManualClock::starting_at(Instant::now())
+    }
+
+    /// Construct a new `ManualClock`, stopped at the given time.
+    #[inline]
+    pub fn starting_at(now: Instant) -> ManualClock {
+        ManualClock {
+            now: Rc::new(Cell::new(now)),
+        }
+    }
+
+    /// Moves the clock forwards.
+    #[inline]
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+
+    /// Sets the clock to the given time.
+    #[inline]
+    pub fn set(&self, now: Instant) {
+        self.now.set(now);
+    }
+}
+
+impl Clock for ManualClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+}
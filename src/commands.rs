@@ -0,0 +1,117 @@
+//! A queue of mutations to be made to an `AnyMap` later, which can be recorded without mutable
+//! access to it.
+
+use std::any::Any;
+use std::sync::Mutex;
+
+use AnyMap;
+
+/// One mutation waiting in an `AnyMapCommands` queue.
+type Command = Box<dyn FnOnce(&mut AnyMap) + Send + 'static>;
+
+/// A queue of insertions and removals to be applied to an `AnyMap` later with `apply`.
+///
+/// Commands are recorded through a shared reference, so code holding only a shared reference to
+/// a map, perhaps while iterating over it, can still arrange for it to be changed; and as the
+/// queue is both `Send` and `Sync` it can be shared between threads, such as the systems of a
+/// `Schedule`, to be applied once they are done. Only values which are `Send` can be queued.
+///
+/// ```rust
+/// # use anymap::AnyMap;
+/// # use anymap::commands::AnyMapCommands;
+/// let mut data = AnyMap::new();
+/// data.insert(3u32);
+///
+/// let mut commands = AnyMapCommands::new();
+/// for value in data.iter() {
+///     if let Some(&n) = value.downcast_ref::<u32>() {
+///         commands.insert(n as u64 * 2);
+///         commands.remove::<u32>();
+///     }
+/// }
+/// commands.apply(&mut data);
+/// assert_eq!(data.get::<u64>(), Some(&6));
+/// assert!(!data.contains::<u32>());
+/// ```
+pub struct AnyMapCommands {
+    queue: Mutex<Vec<Command>>,
+}
+
+impl Default for AnyMapCommands {
+    #[inline]
+    fn default() -> AnyMapCommands {
+        AnyMapCommands::new()
+    }
+}
+
+impl AnyMapCommands {
+    /// Construct a new, empty `AnyMapCommands` queue.
+    #[inline]
+    pub fn new() -> AnyMapCommands {
+        AnyMapCommands {
+            queue: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Queues the insertion of a value of type `T`, replacing any the map has by then.
+    pub fn insert<T: Any + Send + 'static>(&self, value: T) {
+        self.push(Box::new(move |map: &mut AnyMap| { let _ = map.insert(value); }) as Command);
+    }
+
+    /// Queues the removal of the map's value of type `T`, if it has one by then.
+    pub fn remove<T: Any + 'static>(&self) {
+        self.push(Box::new(|map: &mut AnyMap| { let _ = map.remove::<T>(); }) as Command);
+    }
+
+    /// Queues the removal of every value from the map.
+    pub fn clear(&self) {
+        self.push(Box::new(|map: &mut AnyMap| map.clear()) as Command);
+    }
+
+    /// Returns the number of commands queued.
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    /// Returns true if no commands are queued.
+    pub fn is_empty(&self) -> bool {
+        self.queue.lock().unwrap().is_empty()
+    }
+
+    /// Applies the queued commands to a map in the order they were queued, leaving the queue
+    /// empty.
+    pub fn apply(&mut self, map: &mut AnyMap) {
+        let queue = std::mem::take(&mut *self.queue.lock().unwrap());
+        for command in queue.into_iter() {
+            command(map);
+        }
+    }
+
+    fn push(&self, command: Command) {
+        self.queue.lock().unwrap().push(command);
+    }
+}
+
+#[test]
+fn test_commands() {
+    use std::thread;
+
+    let mut map = AnyMap::new();
+    let _ = map.insert(1u8);
+    let _ = map.insert(1u16);
+    let mut commands = AnyMapCommands::new();
+    commands.clear();
+    commands.insert(2u8);
+    thread::scope(|scope| {
+        let commands = &commands;
+        let _ = scope.spawn(move || commands.remove::<u8>());
+    });
+    commands.insert(3u8);
+    assert_eq!(commands.len(), 4);
+    assert_eq!(map.len(), 2);
+
+    commands.apply(&mut map);
+    assert!(commands.is_empty());
+    assert_eq!(map.get::<u8>(), Some(&3));
+    assert_eq!(map.len(), 1);
+}
@@ -0,0 +1,404 @@
+//! An `AnyMap` flavour which locks itself, so that it can be shared between threads and used
+//! through shared references, and which versions its entries for optimistic concurrency.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use TypeIdBuildHasher;
+use like::AnyMapLike;
+use sync::SyncAnyMap;
+
+/// A collection containing zero or one values for any given type, each of which must be `Send`
+/// and `Sync`, guarded by a lock of its own, so that it can be read and changed from many
+/// threads at once through `&self`.
+///
+/// Each type slot carries a version, as in an `ObservedAnyMap`: it starts at zero and is bumped
+/// on every insertion, removal and modification of its value. A read-modify-write can then be
+/// done without holding the write lock throughout: read the value and its version with
+/// `get_versioned`, compute the new value, and store it with `replace_if_version`, retrying if
+/// another thread changed the value in the meantime.
+///
+/// ```rust
+/// # use anymap::concurrent::ConcurrentAnyMap;
+/// # use std::sync::Arc;
+/// # use std::thread;
+/// let data = Arc::new(ConcurrentAnyMap::new());
+/// data.insert(0u64);
+/// let threads: Vec<_> = (0..4).map(|_| {
+///     let data = data.clone();
+///     thread::spawn(move || loop {
+///         let (n, version) = data.get_versioned::<u64>().unwrap();
+///         if data.replace_if_version(version, n + 1).is_ok() {
+///             break;
+///         }
+///     })
+/// }).collect();
+/// for thread in threads {
+///     thread.join().unwrap();
+/// }
+/// assert_eq!(data.get_cloned::<u64>(), Some(4));
+/// ```
+pub struct ConcurrentAnyMap {
+    inner: RwLock<Inner>,
+}
+
+/// The state of a `ConcurrentAnyMap`, behind its lock.
+struct Inner {
+    map: SyncAnyMap,
+    versions: HashMap<TypeId, u64, TypeIdBuildHasher>,
+}
+
+impl Inner {
+    /// Returns the current version of a type slot.
+    fn version(&self, id: &TypeId) -> u64 {
+        self.versions.get(id).map_or(0, |&version| version)
+    }
+
+    /// Bumps the version of a type slot, returning the new version.
+    fn touch(&mut self, id: TypeId) -> u64 {
+        let version = self.versions.entry(id).or_insert(0);
+        *version += 1;
+        *version
+    }
+}
+
+impl Default for ConcurrentAnyMap {
+    #[inline]
+    fn default() -> ConcurrentAnyMap {
+        ConcurrentAnyMap::new()
+    }
+}
+
+impl ConcurrentAnyMap {
+    /// Construct a new, empty `ConcurrentAnyMap`.
+    #[inline]
+    pub fn new() -> ConcurrentAnyMap {
+        ConcurrentAnyMap::from_map(SyncAnyMap::new())
+    }
+
+    /// Wrap an existing `SyncAnyMap`. Values already in it start at version zero.
+    #[inline]
+    pub fn from_map(map: SyncAnyMap) -> ConcurrentAnyMap {
+        ConcurrentAnyMap {
+            inner: RwLock::new(Inner {
+                map,
+                versions: HashMap::with_hasher(TypeIdBuildHasher),
+            }),
+        }
+    }
+
+    /// Unwraps the underlying `SyncAnyMap`, dropping the versions.
+    pub fn into_inner(self) -> SyncAnyMap {
+        self.inner.into_inner().unwrap().map
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Inner> {
+        self.inner.read().unwrap()
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Inner> {
+        self.inner.write().unwrap()
+    }
+
+    /// Calls a function with the map, holding the read lock, returning its result.
+    ///
+    /// The function must not use this map again, which could deadlock.
+    pub fn read_with<R, F: FnOnce(&SyncAnyMap) -> R>(&self, f: F) -> R {
+        f(&self.read().map)
+    }
+
+    /// Calls a function with a reference to the value stored for the type `T`, holding the read
+    /// lock, returning its result, or `None` if there is no `T`.
+    ///
+    /// The function must not use this map again, which could deadlock.
+    pub fn with<T, R, F>(&self, f: F) -> Option<R>
+    where T: Any + Send + Sync + 'static, F: FnOnce(&T) -> R {
+        self.read().map.get::<T>().map(f)
+    }
+
+    /// Returns a clone of the value stored for the type `T`, if it exists.
+    pub fn get_cloned<T: Any + Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.with(T::clone)
+    }
+
+    /// Returns a clone of the value stored for the type `T` and the version it is at, if it
+    /// exists, for a later `replace_if_version`.
+    pub fn get_versioned<T: Any + Clone + Send + Sync + 'static>(&self) -> Option<(T, u64)> {
+        let inner = self.read();
+        let value = inner.map.get::<T>()?.clone();
+        Some((value, inner.version(&TypeId::of::<T>())))
+    }
+
+    /// Returns the current version of the `T` slot.
+    ///
+    /// This starts at zero and increases every time a `T` is inserted, removed or modified; it
+    /// is never reset, even by removing the value.
+    pub fn version<T: Any + Send + Sync + 'static>(&self) -> u64 {
+        self.read().version(&TypeId::of::<T>())
+    }
+
+    /// Returns true if the collection contains a value of type `T`.
+    pub fn contains<T: Any + Send + Sync + 'static>(&self) -> bool {
+        self.read().map.contains::<T>()
+    }
+
+    /// Sets the value stored in the collection for the type `T`.
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    pub fn insert<T: Any + Send + Sync + 'static>(&self, value: T) -> Option<T> {
+        let mut inner = self.write();
+        let _ = inner.touch(TypeId::of::<T>());
+        inner.map.insert(value)
+    }
+
+    /// Sets the value stored in the collection for the type `T`, provided that the `T` slot is
+    /// still at the expected version, returning the new version.
+    ///
+    /// Otherwise, the value is handed back with the version the slot is at. An expected version
+    /// of zero matches a slot which has never been used, so this can also insert a first value.
+    pub fn replace_if_version<T: Any + Send + Sync + 'static>(&self, expected: u64, value: T)
+                                                             -> Result<u64, VersionConflict<T>> {
+        let mut inner = self.write();
+        let id = TypeId::of::<T>();
+        let current = inner.version(&id);
+        if current != expected {
+            return Err(VersionConflict { current, value });
+        }
+        let _ = inner.map.insert(value);
+        Ok(inner.touch(id))
+    }
+
+    /// Calls a function with a mutable reference to the value stored for the type `T`, holding
+    /// the write lock, returning its result, or `None` if there is no `T`.
+    ///
+    /// This bumps the version of the `T` slot whether or not the function changes the value.
+    /// The function must not use this map again, which would deadlock.
+    pub fn modify<T, R, F>(&self, f: F) -> Option<R>
+    where T: Any + Send + Sync + 'static, F: FnOnce(&mut T) -> R {
+        let mut inner = self.write();
+        let result = inner.map.get_mut::<T>().map(f);
+        if result.is_some() {
+            let _ = inner.touch(TypeId::of::<T>());
+        }
+        result
+    }
+
+    /// Removes the `T` value from the collection,
+    /// returning it if there was one or `None` if there was not.
+    pub fn remove<T: Any + Send + Sync + 'static>(&self) -> Option<T> {
+        let mut inner = self.write();
+        let value = inner.map.remove::<T>();
+        if value.is_some() {
+            let _ = inner.touch(TypeId::of::<T>());
+        }
+        value
+    }
+
+    /// Calls a function with a `Batch` through which it can mutate the map, holding the write
+    /// lock throughout, returning its result.
+    ///
+    /// No other thread can observe the map with some of the batch's mutations applied and not
+    /// others, so values which must change together can be kept consistent. Versions are bumped
+    /// as by the methods of the same names. The function must not use this map again, which
+    /// would deadlock; if it panics, the lock is poisoned and later uses of the map panic too.
+    ///
+    /// ```rust
+    /// # use anymap::concurrent::ConcurrentAnyMap;
+    /// struct Balance(i64);
+    /// struct Ledger(Vec<i64>);
+    ///
+    /// let data = ConcurrentAnyMap::new();
+    /// data.insert(Balance(0));
+    /// data.insert(Ledger(vec![]));
+    /// data.apply_batch(|batch| {
+    ///     batch.get_mut::<Balance>().unwrap().0 += 10;
+    ///     batch.get_mut::<Ledger>().unwrap().0.push(10);
+    /// });
+    /// assert_eq!(data.with(|ledger: &Ledger| ledger.0.len()), Some(1));
+    /// ```
+    pub fn apply_batch<R, F: FnOnce(&mut Batch) -> R>(&self, f: F) -> R {
+        let mut inner = self.write();
+        f(&mut Batch { inner: &mut inner })
+    }
+
+    /// Returns the number of items in the collection.
+    pub fn len(&self) -> usize {
+        self.read().map.len()
+    }
+
+    /// Returns true if there are no items in the collection.
+    pub fn is_empty(&self) -> bool {
+        self.read().map.is_empty()
+    }
+
+    /// Removes all items from the collection, bumping the version of each.
+    pub fn clear(&self) {
+        let mut inner = self.write();
+        let ids: Vec<TypeId> = inner.map.data.keys().copied().collect();
+        for id in ids {
+            let _ = inner.touch(id);
+        }
+        inner.map.clear();
+    }
+}
+
+/// The mutations of a `ConcurrentAnyMap` made by `apply_batch`, applied while it holds the
+/// write lock.
+pub struct Batch<'a> {
+    inner: &'a mut Inner,
+}
+
+impl<'a> Batch<'a> {
+    /// Returns a reference to the value stored in the collection for the type `T`, if it exists.
+    #[inline]
+    pub fn get<T: Any + Send + Sync + 'static>(&self) -> Option<&T> {
+        self.inner.map.get::<T>()
+    }
+
+    /// Returns a mutable reference to the value stored in the collection for the type `T`,
+    /// if it exists, bumping the version of the `T` slot.
+    pub fn get_mut<T: Any + Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        if self.inner.map.contains::<T>() {
+            let _ = self.inner.touch(TypeId::of::<T>());
+        }
+        self.inner.map.get_mut::<T>()
+    }
+
+    /// Returns true if the collection contains a value of type `T`.
+    #[inline]
+    pub fn contains<T: Any + Send + Sync + 'static>(&self) -> bool {
+        self.inner.map.contains::<T>()
+    }
+
+    /// Returns the current version of the `T` slot.
+    #[inline]
+    pub fn version<T: Any + Send + Sync + 'static>(&self) -> u64 {
+        self.inner.version(&TypeId::of::<T>())
+    }
+
+    /// Sets the value stored in the collection for the type `T`.
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    pub fn insert<T: Any + Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        let _ = self.inner.touch(TypeId::of::<T>());
+        self.inner.map.insert(value)
+    }
+
+    /// Removes the `T` value from the collection,
+    /// returning it if there was one or `None` if there was not.
+    pub fn remove<T: Any + Send + Sync + 'static>(&mut self) -> Option<T> {
+        let value = self.inner.map.remove::<T>();
+        if value.is_some() {
+            let _ = self.inner.touch(TypeId::of::<T>());
+        }
+        value
+    }
+}
+
+/// Lookups through `AnyMapLike` by shared reference are refused, as the reference could not
+/// hold the read lock; those through a mutable reference need no lock and bump the version, as
+/// does every change. Values inserted through it are refused, as they cannot be checked to be
+/// `Send` and `Sync`.
+impl AnyMapLike for ConcurrentAnyMap {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.read().map.contains_id(id)
+    }
+
+    #[inline]
+    fn get_raw(&self, _: TypeId) -> Option<&dyn Any> {
+        None
+    }
+
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        let inner = self.inner.get_mut().unwrap();
+        if inner.map.contains_id(id) {
+            let _ = inner.touch(id);
+        }
+        inner.map.get_raw_mut(id)
+    }
+
+    #[inline]
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        Err(value)
+    }
+
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        let inner = self.inner.get_mut().unwrap();
+        let value = inner.map.remove_raw(id);
+        if value.is_some() {
+            let _ = inner.touch(id);
+        }
+        value
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.read().map.len()
+    }
+}
+
+/// The error of `replace_if_version` when the slot has moved on from the expected version.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct VersionConflict<T> {
+    /// The version the slot is at.
+    pub current: u64,
+    /// The value which was not stored.
+    pub value: T,
+}
+
+impl<T> fmt::Display for VersionConflict<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the value has changed, and is now at version {}", self.current)
+    }
+}
+
+impl<T: fmt::Debug> Error for VersionConflict<T> {
+    fn description(&self) -> &str {
+        "the value has changed"
+    }
+}
+
+#[test]
+fn test_concurrent() {
+    fn assert_send_sync<T: Send + Sync + ::std::panic::RefUnwindSafe>(_: &T) {}
+
+    let map = ConcurrentAnyMap::new();
+    assert_send_sync(&map);
+    assert_eq!(map.version::<u8>(), 0);
+    assert_eq!(map.replace_if_version(0, 1u8), Ok(1));
+    assert_eq!(map.replace_if_version(0, 2u8), Err(VersionConflict { current: 1, value: 2 }));
+    assert_eq!(map.get_versioned::<u8>(), Some((1, 1)));
+    assert_eq!(map.modify(|n: &mut u8| { *n += 1; *n }), Some(2));
+    assert_eq!(map.modify(|n: &mut u16| *n), None);
+    assert_eq!(map.version::<u8>(), 2);
+    assert_eq!(map.insert(5u8), Some(2));
+    assert_eq!(map.with(|n: &u8| *n * 2), Some(10));
+    assert_eq!(map.replace_if_version(3, 6u8), Ok(4));
+    assert_eq!(map.remove::<u8>(), Some(6));
+    assert_eq!(map.remove::<u8>(), None);
+    assert_eq!((map.version::<u8>(), map.contains::<u8>()), (5, false));
+    assert_eq!(map.replace_if_version(5, 7u8), Ok(6));
+
+    let _ = map.insert(1u16);
+    let moved = map.apply_batch(|batch| {
+        let n = batch.remove::<u8>().unwrap();
+        *batch.get_mut::<u16>().unwrap() += n as u16;
+        assert!(batch.get_mut::<u32>().is_none());
+        assert_eq!(batch.insert(n as u32), None);
+        (batch.contains::<u8>(), *batch.get::<u16>().unwrap(), batch.version::<u16>())
+    });
+    assert_eq!(moved, (false, 8, 2));
+    assert_eq!((map.version::<u8>(), map.version::<u32>()), (7, 1));
+    let _ = map.insert(7u8);
+    let _ = map.remove::<u32>();
+    assert_eq!(map.read_with(|map| map.len()), 2);
+    map.clear();
+    assert!(map.is_empty());
+    assert_eq!((map.version::<u8>(), map.version::<u16>()), (9, 3));
+    assert!(map.into_inner().is_empty());
+}
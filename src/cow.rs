@@ -0,0 +1,145 @@
+//! A whole map shared through an `Arc`, and copied only when it is first mutated.
+
+use std::any::{Any, TypeId};
+use std::ops::Deref;
+use std::sync::Arc;
+
+use like::AnyMapLike;
+use shared::SharedAnyMap;
+
+/// A map shared through an `Arc` with the other copies of it, until it is first mutated, when it
+/// is cloned to become its own.
+///
+/// Cloning a `CowAnyMap` only shares the map, so many contexts can be made from a template at
+/// the cost of one reference count each. The map must be of a cloneable flavour, such as
+/// `SharedAnyMap`, which is the default; its non-mutating methods are available through
+/// `Deref`, and its mutating ones through `to_mut`.
+///
+/// ```rust
+/// # use anymap::cow::CowAnyMap;
+/// # use anymap::shared::SharedAnyMap;
+/// let mut template = SharedAnyMap::new();
+/// template.insert(format!("guest"));
+/// let template = CowAnyMap::new(template);
+///
+/// let children: Vec<CowAnyMap> = (0..100).map(|_| template.clone()).collect();
+/// assert!(children.iter().all(|child| child.is_shared()));
+///
+/// let mut child = template.clone();
+/// *child.to_mut().get_mut::<String>().unwrap() = format!("admin");
+/// assert!(!child.is_shared());
+/// assert_eq!(template.get::<String>().unwrap(), "guest");
+/// ```
+pub struct CowAnyMap<M: Clone = SharedAnyMap> {
+    map: Arc<M>,
+}
+
+impl<M: Clone> CowAnyMap<M> {
+    /// Wraps a map, to be shared by the copies of the `CowAnyMap`.
+    #[inline]
+    pub fn new(map: M) -> CowAnyMap<M> {
+        CowAnyMap {
+            map: Arc::new(map),
+        }
+    }
+
+    /// Returns true if the map is shared with another `CowAnyMap`, and so would be cloned by
+    /// `to_mut`.
+    #[inline]
+    pub fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.map) > 1
+    }
+
+    /// Returns a mutable reference to the map, cloning it first if it is shared.
+    #[inline]
+    pub fn to_mut(&mut self) -> &mut M {
+        Arc::make_mut(&mut self.map)
+    }
+
+    /// Unwraps the map, cloning it if it is shared.
+    pub fn into_inner(self) -> M {
+        match Arc::try_unwrap(self.map) {
+            Ok(map) => map,
+            Err(map) => (*map).clone(),
+        }
+    }
+}
+
+impl<M: Clone> Clone for CowAnyMap<M> {
+    /// Shares the map with the new copy, without cloning it.
+    #[inline]
+    fn clone(&self) -> CowAnyMap<M> {
+        CowAnyMap {
+            map: self.map.clone(),
+        }
+    }
+}
+
+impl<M: Clone> Deref for CowAnyMap<M> {
+    type Target = M;
+
+    #[inline]
+    fn deref(&self) -> &M {
+        &self.map
+    }
+}
+
+/// Changes go through `to_mut`, so the map is cloned first if it is shared, but only once it is
+/// known that there is something to change: looking for a missing entry mutably leaves it
+/// shared. The map refuses what it would refuse unwrapped.
+impl<M: AnyMapLike + Clone> AnyMapLike for CowAnyMap<M> {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.map.contains_id(id)
+    }
+
+    #[inline]
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.map.get_raw(id)
+    }
+
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        if !self.map.contains_id(id) {
+            return None;
+        }
+        self.to_mut().get_raw_mut(id)
+    }
+
+    #[inline]
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        self.to_mut().insert_raw(value)
+    }
+
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        if !self.map.contains_id(id) {
+            return None;
+        }
+        self.to_mut().remove_raw(id)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+#[test]
+fn test_cow() {
+    let mut template = SharedAnyMap::new();
+    let _ = template.insert(1u8);
+    let template = CowAnyMap::new(template);
+    assert!(!template.is_shared());
+
+    let mut child = template.clone();
+    assert!(child.is_shared() && template.is_shared());
+    assert_eq!(child.get::<u8>(), Some(&1));
+    let _ = child.to_mut().insert(2u16);
+    *child.to_mut().get_mut::<u8>().unwrap() += 1;
+    assert!(!child.is_shared() && !template.is_shared());
+    assert_eq!((template.get::<u8>(), template.get::<u16>()), (Some(&1), None));
+
+    let shared = template.clone();
+    assert_eq!(shared.into_inner().len(), 1);
+    assert_eq!(child.into_inner().get::<u8>(), Some(&2));
+}
@@ -0,0 +1,282 @@
+//! Merging the maps of two nodes which have each mutated their own copy, resolving every
+//! conflict with the merge registered for the type in a `TypeRegistry`, after the manner of
+//! conflict-free replicated data types.
+
+use std::any::TypeId;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+
+use AnyMap;
+use registry::TypeRegistry;
+
+/// A type whose values can be merged, for `Registration::mergeable` to record.
+///
+/// For the copies of two nodes to converge whatever order they merge in, `merge` should be
+/// commutative, associative and idempotent: merging `a` with `b` leaves the same value as
+/// merging `b` with `a`, and merging a value with itself leaves it unchanged.
+pub trait Merge {
+    /// Merges `other` into this value.
+    fn merge(&mut self, other: Self);
+}
+
+/// Sets merge by union.
+impl<T: Eq + Hash, S: BuildHasher> Merge for HashSet<T, S> {
+    #[inline]
+    fn merge(&mut self, other: HashSet<T, S>) {
+        self.extend(other);
+    }
+}
+
+/// Sets merge by union.
+impl<T: Ord> Merge for BTreeSet<T> {
+    #[inline]
+    fn merge(&mut self, other: BTreeSet<T>) {
+        self.extend(other);
+    }
+}
+
+/// A value which merges by keeping whichever was written last, by the timestamp it was written
+/// with, ties being broken by the ID of the node which wrote it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LastWriterWins<T> {
+    value: T,
+    timestamp: u64,
+    node: u64,
+}
+
+impl<T> LastWriterWins<T> {
+    /// Construct a new `LastWriterWins`, written by the node with the given ID at the given
+    /// time.
+    #[inline]
+    pub fn new(value: T, timestamp: u64, node: u64) -> LastWriterWins<T> {
+        LastWriterWins {
+            value,
+            timestamp,
+            node,
+        }
+    }
+
+    /// Returns a reference to the value.
+    #[inline]
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    /// Returns the time at which the value was written.
+    #[inline]
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Writes a new value, as the node with the given ID at the given time, unless the current
+    /// value was written later. Returns true if the value was written.
+    pub fn set(&mut self, value: T, timestamp: u64, node: u64) -> bool {
+        if (timestamp, node) < (self.timestamp, self.node) {
+            return false;
+        }
+        *self = LastWriterWins::new(value, timestamp, node);
+        true
+    }
+
+    /// Unwraps the value.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Merge for LastWriterWins<T> {
+    #[inline]
+    fn merge(&mut self, other: LastWriterWins<T>) {
+        let _ = self.set(other.value, other.timestamp, other.node);
+    }
+}
+
+/// A counter which any node can increment and decrement, and which merges by keeping the
+/// changes made by each node, so that none are lost or counted twice.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Counter {
+    /// The total of the increments and of the decrements made by each node, by its ID.
+    changes: HashMap<u64, (u64, u64)>,
+}
+
+impl Default for Counter {
+    #[inline]
+    fn default() -> Counter {
+        Counter::new()
+    }
+}
+
+impl Counter {
+    /// Construct a new `Counter` with the value zero.
+    #[inline]
+    pub fn new() -> Counter {
+        Counter {
+            changes: HashMap::new(),
+        }
+    }
+
+    /// Adds to the counter, as the node with the given ID.
+    pub fn increment(&mut self, node: u64, by: u64) {
+        self.changes.entry(node).or_insert((0, 0)).0 += by;
+    }
+
+    /// Subtracts from the counter, as the node with the given ID.
+    pub fn decrement(&mut self, node: u64, by: u64) {
+        self.changes.entry(node).or_insert((0, 0)).1 += by;
+    }
+
+    /// Returns the value of the counter.
+    pub fn value(&self) -> i64 {
+        self.changes.values().map(|&(up, down)| up as i64 - down as i64).sum()
+    }
+}
+
+impl Merge for Counter {
+    fn merge(&mut self, other: Counter) {
+        for (node, (up, down)) in other.changes {
+            let changes = self.changes.entry(node).or_insert((0, 0));
+            *changes = (changes.0.max(up), changes.1.max(down));
+        }
+    }
+}
+
+impl AnyMap {
+    /// Merges the map of another node into this one. Values of types only in `other` are moved
+    /// in, and values of types in both are merged with the merge registered for their type in
+    /// `registry`, with this map's value given as the one to keep.
+    ///
+    /// Fails, leaving this map unchanged, if a type in both is not registered or has no merge.
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// # use anymap::crdt::{Counter, LastWriterWins};
+    /// # use anymap::registry::TypeRegistry;
+    /// let mut registry = TypeRegistry::new();
+    /// registry.register::<Counter>("visits").mergeable();
+    /// registry.register::<LastWriterWins<&'static str>>("theme").mergeable();
+    ///
+    /// let mut a = AnyMap::new();
+    /// a.insert(Counter::new());
+    /// a.get_mut::<Counter>().unwrap().increment(1, 2);
+    /// a.insert(LastWriterWins::new("dark", 10, 1));
+    ///
+    /// let mut b = AnyMap::new();
+    /// b.insert(Counter::new());
+    /// b.get_mut::<Counter>().unwrap().increment(2, 3);
+    /// b.insert(LastWriterWins::new("light", 20, 2));
+    ///
+    /// a.merge_crdt(b, &registry).unwrap();
+    /// assert_eq!(a.get::<Counter>().unwrap().value(), 5);
+    /// assert_eq!(*a.get::<LastWriterWins<&str>>().unwrap().get(), "light");
+    /// ```
+    pub fn merge_crdt(&mut self, other: AnyMap, registry: &TypeRegistry)
+                      -> Result<(), MergeError> {
+        for id in other.data.keys() {
+            if !self.data.contains_key(id) {
+                continue;
+            }
+            match registry.name_of(id) {
+                None => return Err(MergeError::Unregistered(*id)),
+                Some(name) if !registry.is_mergeable(id) => {
+                    return Err(MergeError::NotMergeable(name));
+                },
+                Some(_) => (),
+            }
+        }
+        for (id, theirs) in other.data {
+            match self.data.get_mut(&id) {
+                Some(ours) => {
+                    let _ = registry.merge_values(&mut **ours, theirs);
+                },
+                None => {
+                    let _ = self.insert_by_id(id, theirs);
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The reasons two maps may fail to merge.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MergeError {
+    /// A type in both maps is not registered.
+    Unregistered(TypeId),
+    /// The named type is in both maps, but has no registered merge.
+    NotMergeable(&'static str),
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MergeError::Unregistered(id) => write!(f, "the type {:?} is not registered", id),
+            MergeError::NotMergeable(name) => write!(f, "the type {} is not mergeable", name),
+        }
+    }
+}
+
+impl Error for MergeError {
+    fn description(&self) -> &str {
+        match *self {
+            MergeError::Unregistered(_) => "a type is not registered",
+            MergeError::NotMergeable(_) => "a type is not mergeable",
+        }
+    }
+}
+
+#[test]
+fn test_merge_crdt() {
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<Counter>("counter").mergeable();
+    let _ = registry.register::<BTreeSet<u8>>("tags").mergeable();
+    let _ = registry.register::<LastWriterWins<u8>>("lww").mergeable();
+    let _ = registry.register::<u16>("u16").merges_with(|ours: &mut u16, theirs| {
+        *ours = (*ours).max(theirs)
+    });
+    let _ = registry.register::<u32>("u32");
+
+    let mut lww = LastWriterWins::new(1u8, 5, 1);
+    assert!(!lww.set(2, 4, 9) && lww.set(3, 5, 2) && lww.set(4, 6, 0));
+    assert_eq!((*lww.get(), lww.timestamp()), (4, 6));
+
+    let node = |id: u64, tag: u8, lww: u64| {
+        let mut map = AnyMap::new();
+        let mut counter = Counter::new();
+        counter.increment(id, 3);
+        counter.decrement(id, 1);
+        let _ = map.insert(counter);
+        let _ = map.insert(vec![tag].into_iter().collect::<BTreeSet<u8>>());
+        let _ = map.insert(LastWriterWins::new(id as u8, lww, id));
+        let _ = map.insert(id as u16);
+        map
+    };
+    let mut a = node(1, 10, 7);
+    let mut b = node(2, 20, 7);
+    let _ = b.insert(1i8);
+    let mut c = node(2, 20, 7);
+    let _ = c.insert(1i8);
+    let mut d = node(1, 10, 7);
+    assert_eq!(a.merge_crdt(b, &registry), Ok(()));
+    assert_eq!(c.merge_crdt(d, &registry), Ok(()));
+    for map in &[&a, &c] {
+        assert_eq!(map.get::<Counter>().unwrap().value(), 4);
+        assert_eq!(map.get::<BTreeSet<u8>>().unwrap().len(), 2);
+        assert_eq!(*map.get::<LastWriterWins<u8>>().unwrap().get(), 2);
+        assert_eq!((map.get::<u16>(), map.get::<i8>()), (Some(&2), Some(&1)));
+    }
+    let copy = node(2, 20, 7);
+    assert_eq!(a.merge_crdt(copy, &registry), Ok(()));
+    assert_eq!(a.get::<Counter>().unwrap().value(), 4);
+
+    d = node(1, 10, 7);
+    let _ = a.insert(1u32);
+    let _ = d.insert(2u32);
+    assert_eq!(a.merge_crdt(d, &registry), Err(MergeError::NotMergeable("u32")));
+    let mut e = AnyMap::new();
+    let _ = e.insert(2i8);
+    assert_eq!(a.merge_crdt(e, &registry), Err(MergeError::Unregistered(TypeId::of::<i8>())));
+    assert_eq!(a.get::<u32>(), Some(&1));
+}
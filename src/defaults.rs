@@ -0,0 +1,121 @@
+//! A registry of default values, from which an `AnyMap` can be filled in.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+use {AnyMap, TypeIdBuildHasher};
+
+/// A registry of constructors of default values, one per type, with which
+/// `AnyMap::populate_defaults` fills in the types a map lacks.
+///
+/// An application can so describe its standard context once, then start each map from it and
+/// override only the entries which differ.
+///
+/// ```rust
+/// # use anymap::AnyMap;
+/// # use anymap::defaults::DefaultsRegistry;
+/// #[derive(Default)]
+/// struct Retries(u32);
+/// struct Timeout(u32);
+///
+/// let mut defaults = DefaultsRegistry::new();
+/// defaults.register::<Retries>();
+/// defaults.register_with(|| Timeout(30));
+///
+/// let mut context = AnyMap::new();
+/// context.insert(Timeout(5));
+/// context.populate_defaults(&defaults);
+/// assert_eq!(context.get::<Retries>().unwrap().0, 0);
+/// assert_eq!(context.get::<Timeout>().unwrap().0, 5);
+/// ```
+pub struct DefaultsRegistry {
+    fillers: HashMap<TypeId, Filler, TypeIdBuildHasher>,
+}
+
+/// Inserts the default value of one type into a map lacking one.
+type Filler = Box<dyn Fn(&mut AnyMap) + 'static>;
+
+impl Default for DefaultsRegistry {
+    #[inline]
+    fn default() -> DefaultsRegistry {
+        DefaultsRegistry::new()
+    }
+}
+
+impl DefaultsRegistry {
+    /// Construct a new, empty `DefaultsRegistry`.
+    #[inline]
+    pub fn new() -> DefaultsRegistry {
+        DefaultsRegistry {
+            fillers: HashMap::with_hasher(TypeIdBuildHasher),
+        }
+    }
+
+    /// Registers `Default::default` as the constructor of the default value of type `T`,
+    /// returning true if the type had no constructor before, which this replaces.
+    #[inline]
+    pub fn register<T: Default + Any + 'static>(&mut self) -> bool {
+        self.register_with(<T as Default>::default)
+    }
+
+    /// Registers a function constructing the default value of type `T`, returning true if the
+    /// type had no constructor before, which this replaces.
+    pub fn register_with<T, F>(&mut self, constructor: F) -> bool
+    where T: Any + 'static, F: Fn() -> T + 'static {
+        let fill = move |map: &mut AnyMap| if !map.contains::<T>() {
+            let _ = map.insert(constructor());
+        };
+        self.fillers.insert(TypeId::of::<T>(), Box::new(fill)).is_none()
+    }
+
+    /// Removes the constructor of the type `T`, returning true if there was one.
+    #[inline]
+    pub fn unregister<T: Any + 'static>(&mut self) -> bool {
+        self.fillers.remove(&TypeId::of::<T>()).is_some()
+    }
+
+    /// Returns true if the type `T` has a constructor registered.
+    #[inline]
+    pub fn is_registered<T: Any + 'static>(&self) -> bool {
+        self.fillers.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Returns the number of types with constructors registered.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.fillers.len()
+    }
+
+    /// Returns true if no types have constructors registered.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.fillers.is_empty()
+    }
+}
+
+impl AnyMap {
+    /// Inserts the default value of every type registered in `registry` of which the collection
+    /// has no value, leaving the values it has untouched.
+    pub fn populate_defaults(&mut self, registry: &DefaultsRegistry) {
+        for fill in registry.fillers.values() {
+            (**fill)(self);
+        }
+    }
+}
+
+#[test]
+fn test_defaults() {
+    let mut registry = DefaultsRegistry::new();
+    assert!(registry.register::<u8>());
+    assert!(registry.register_with(|| 2u16));
+    assert!(!registry.register_with(|| 3u16));
+    assert!(registry.register_with(|| 4u32));
+    assert!(registry.unregister::<u32>() && !registry.is_registered::<u32>());
+    assert_eq!(registry.len(), 2);
+
+    let mut map = AnyMap::new();
+    let _ = map.insert(1u8);
+    map.populate_defaults(&registry);
+    assert_eq!((map.get::<u8>(), map.get::<u16>()), (Some(&1), Some(&3)));
+    assert_eq!(map.len(), 2);
+}
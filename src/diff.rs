@@ -0,0 +1,225 @@
+//! Structured differences between two `AnyMap`s, which can be applied as patches.
+
+use std::any::{Any, TypeId};
+use std::error::Error;
+use std::fmt;
+
+use {AnyMap, TypeSet, UNKNOWN_TYPE};
+use registry::TypeRegistry;
+
+/// A value carried by a `Diff`, to be inserted when it is applied.
+struct Entry {
+    id: TypeId,
+    name: &'static str,
+    value: Box<dyn Any + 'static>,
+}
+
+/// The difference between two maps: which types were added, which were removed and which had
+/// their values changed, along with the new values.
+///
+/// A diff is computed with `Diff::between`, which compares values with the equality and takes
+/// copies of them with the clone registered for their types in a `TypeRegistry`. Applying the
+/// diff from one map to another onto the first makes it equal to the second.
+///
+/// ```rust
+/// # use anymap::AnyMap;
+/// # use anymap::diff::Diff;
+/// # use anymap::registry::TypeRegistry;
+/// let mut registry = TypeRegistry::new();
+/// registry.register::<i32>("i32").comparable().cloneable();
+/// registry.register::<u8>("u8").comparable().cloneable();
+///
+/// let mut old = AnyMap::new();
+/// old.insert(1i32);
+/// let mut new = AnyMap::new();
+/// new.insert(2i32);
+/// new.insert(3u8);
+///
+/// let diff = Diff::between(&registry, &old, &new).unwrap();
+/// assert_eq!(diff.changed().len(), 1);
+/// assert_eq!(diff.added().len(), 1);
+/// diff.apply(&mut old);
+/// assert_eq!(old.get::<i32>(), Some(&2));
+/// assert_eq!(old.get::<u8>(), Some(&3));
+/// ```
+pub struct Diff {
+    added: Vec<Entry>,
+    removed: TypeSet,
+    changed: Vec<Entry>,
+}
+
+impl Diff {
+    /// Computes the difference from the map `from` to the map `to`.
+    ///
+    /// Every type in `to` must have a registered clone, and every type in both maps a
+    /// registered equality; types only in `from` need not be registered at all.
+    pub fn between(registry: &TypeRegistry, from: &AnyMap, to: &AnyMap)
+                   -> Result<Diff, DiffError> {
+        let mut diff = Diff {
+            added: Vec::new(),
+            removed: TypeSet::new(),
+            changed: Vec::new(),
+        };
+        for (&id, old) in from.data.iter() {
+            if !to.data.contains_key(&id) {
+                let name = registry.name_of(&id).unwrap_or(UNKNOWN_TYPE);
+                let _ = diff.removed.insert_id(id, name);
+                continue;
+            }
+            match registry.eq_values(&**old, &*to.data[&id]) {
+                Some(true) => (),
+                Some(false) => diff.changed.push(entry(registry, id, &*to.data[&id])?),
+                None => return Err(missing(registry, id, DiffError::NotComparable)),
+            }
+        }
+        for (&id, new) in to.data.iter() {
+            if !from.data.contains_key(&id) {
+                diff.added.push(entry(registry, id, &**new)?);
+            }
+        }
+        Ok(diff)
+    }
+
+    /// Returns true if the two maps were equal.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Returns the types present only in the new map.
+    pub fn added(&self) -> TypeSet {
+        type_set(&self.added[..])
+    }
+
+    /// Returns the types present only in the old map.
+    #[inline]
+    pub fn removed(&self) -> &TypeSet {
+        &self.removed
+    }
+
+    /// Returns the types present in both maps with different values.
+    pub fn changed(&self) -> TypeSet {
+        type_set(&self.changed[..])
+    }
+
+    /// Applies the diff to a map as a patch, removing the removed types and inserting the new
+    /// values of the added and changed types.
+    ///
+    /// Applying it to a map other than the one it was computed from does the same, so types the
+    /// map has which the diff does not mention are left alone.
+    pub fn apply(self, map: &mut AnyMap) {
+        for (id, _) in self.removed.iter() {
+            let _ = map.remove_by_id(&id);
+        }
+        for entry in self.added.into_iter().chain(self.changed) {
+            let _ = map.insert_by_id(entry.id, entry.value);
+        }
+    }
+}
+
+impl fmt::Debug for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Diff {{ added: {:?}, removed: {:?}, changed: {:?} }}",
+               self.added(), self.removed, self.changed())
+    }
+}
+
+fn entry(registry: &TypeRegistry, id: TypeId, value: &dyn Any) -> Result<Entry, DiffError> {
+    match registry.clone_value(value) {
+        Some(value) => Ok(Entry {
+            id,
+            name: registry.name_of(&id).unwrap(),
+            value,
+        }),
+        None => Err(missing(registry, id, DiffError::NotCloneable)),
+    }
+}
+
+/// Builds the error for a type lacking a registration, or not registered at all.
+fn missing<F: FnOnce(&'static str) -> DiffError>(registry: &TypeRegistry, id: TypeId, error: F)
+                                                -> DiffError {
+    match registry.name_of(&id) {
+        Some(name) => error(name),
+        None => DiffError::Unregistered(id),
+    }
+}
+
+fn type_set(entries: &[Entry]) -> TypeSet {
+    let mut types = TypeSet::new();
+    for entry in entries.iter() {
+        let _ = types.insert_id(entry.id, entry.name);
+    }
+    types
+}
+
+/// The reasons a diff may fail to be computed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DiffError {
+    /// A type in the maps is not registered.
+    Unregistered(TypeId),
+    /// The named type is in both maps, but has no registered equality.
+    NotComparable(&'static str),
+    /// The named type is in the new map, but has no registered clone.
+    NotCloneable(&'static str),
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DiffError::Unregistered(id) => write!(f, "the type {:?} is not registered", id),
+            DiffError::NotComparable(name) => write!(f, "the type {} is not comparable", name),
+            DiffError::NotCloneable(name) => write!(f, "the type {} is not cloneable", name),
+        }
+    }
+}
+
+impl Error for DiffError {
+    fn description(&self) -> &str {
+        match *self {
+            DiffError::Unregistered(_) => "a type is not registered",
+            DiffError::NotComparable(_) => "a type is not comparable",
+            DiffError::NotCloneable(_) => "a type is not cloneable",
+        }
+    }
+}
+
+#[test]
+fn test_diff() {
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<i32>("i32").comparable().cloneable();
+    let _ = registry.register::<u8>("u8").comparable().cloneable();
+    let _ = registry.register::<u16>("u16").comparable().cloneable();
+    let _ = registry.register::<u32>("u32").cloneable();
+
+    let mut old = AnyMap::new();
+    let _ = old.insert(1i32);
+    let _ = old.insert(1u8);
+    let _ = old.insert(());
+    let mut new = AnyMap::new();
+    let _ = new.insert(1i32);
+    let _ = new.insert(2u8);
+    let _ = new.insert(2u16);
+
+    let diff = Diff::between(&registry, &old, &new).unwrap();
+    assert!(!diff.is_empty());
+    assert!(diff.added().contains::<u16>() && diff.added().len() == 1);
+    assert!(diff.removed().contains_type_id(&TypeId::of::<()>()) && diff.removed().len() == 1);
+    assert!(diff.changed().contains::<u8>() && diff.changed().len() == 1);
+    diff.apply(&mut old);
+    assert!(Diff::between(&registry, &old, &new).unwrap().is_empty());
+    assert_eq!(old.get::<u8>(), Some(&2));
+    let mut emptied = AnyMap::new();
+    let _ = emptied.insert(1u8);
+    let _ = emptied.insert(1u16);
+    emptied.set_shrink_threshold(Some(0.5));
+    Diff::between(&registry, &emptied, &AnyMap::new()).unwrap().apply(&mut emptied);
+    assert_eq!((emptied.len(), emptied.capacity()), (0, 0));
+
+    let _ = old.insert(3u32);
+    let _ = new.insert(3u32);
+    assert_eq!(Diff::between(&registry, &old, &new).unwrap_err(),
+               DiffError::NotComparable("u32"));
+    let _ = new.insert(());
+    assert_eq!(Diff::between(&registry, &AnyMap::new(), &new).unwrap_err(),
+               DiffError::Unregistered(TypeId::of::<()>()));
+}
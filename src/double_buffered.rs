@@ -0,0 +1,101 @@
+//! A pair of maps, one read while the other is written, swapped at phase boundaries.
+
+use std::mem;
+
+/// Two maps, of `AnyMap` or any other flavour: the current one, read-only, holding the state of
+/// the last phase, and the next one, being written with the state of this phase. `swap` makes
+/// the next map current at the end of each phase, without copying either.
+///
+/// After a swap the next map holds the state of the phase before last, which can be updated in
+/// place or cleared first.
+///
+/// ```rust
+/// # use anymap::AnyMap;
+/// # use anymap::double_buffered::DoubleBuffered;
+/// struct Position(i32);
+/// struct Velocity(i32);
+///
+/// let mut initial = AnyMap::new();
+/// initial.insert(Position(0));
+/// initial.insert(Velocity(2));
+/// let mut state = DoubleBuffered::new(initial, AnyMap::new());
+///
+/// for _ in 0..3 {
+///     {
+///         let (current, next) = state.split_mut();
+///         let velocity = current.get::<Velocity>().unwrap().0;
+///         next.insert(Position(current.get::<Position>().unwrap().0 + velocity));
+///         next.insert(Velocity(velocity));
+///     }
+///     state.swap();
+/// }
+/// assert_eq!(state.current().get::<Position>().unwrap().0, 6);
+/// ```
+pub struct DoubleBuffered<M> {
+    current: M,
+    next: M,
+}
+
+impl<M> DoubleBuffered<M> {
+    /// Construct a pair of buffers from the current and next maps.
+    #[inline]
+    pub fn new(current: M, next: M) -> DoubleBuffered<M> {
+        DoubleBuffered {
+            current,
+            next,
+        }
+    }
+
+    /// Returns a reference to the current map.
+    #[inline]
+    pub fn current(&self) -> &M {
+        &self.current
+    }
+
+    /// Returns a reference to the next map.
+    #[inline]
+    pub fn next(&self) -> &M {
+        &self.next
+    }
+
+    /// Returns a mutable reference to the next map.
+    #[inline]
+    pub fn next_mut(&mut self) -> &mut M {
+        &mut self.next
+    }
+
+    /// Returns a reference to the current map and a mutable reference to the next map at once.
+    #[inline]
+    pub fn split_mut(&mut self) -> (&M, &mut M) {
+        (&self.current, &mut self.next)
+    }
+
+    /// Makes the next map current, and the current map next.
+    #[inline]
+    pub fn swap(&mut self) {
+        mem::swap(&mut self.current, &mut self.next)
+    }
+
+    /// Unwraps the current and next maps.
+    #[inline]
+    pub fn into_inner(self) -> (M, M) {
+        (self.current, self.next)
+    }
+}
+
+#[test]
+fn test_double_buffered() {
+    use AnyMap;
+
+    let mut buffers = DoubleBuffered::new(AnyMap::new(), AnyMap::new());
+    let _ = buffers.next_mut().insert(1u8);
+    assert!(buffers.current().is_empty() && buffers.next().contains::<u8>());
+    buffers.swap();
+    {
+        let (current, next) = buffers.split_mut();
+        let _ = next.insert(*current.get::<u8>().unwrap() + 1);
+    }
+    buffers.swap();
+    let (current, next) = buffers.into_inner();
+    assert_eq!((current.get::<u8>(), next.get::<u8>()), (Some(&2), Some(&1)));
+}
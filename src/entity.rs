@@ -0,0 +1,256 @@
+//! A collection of many small maps keyed by entity, one value per type per entity, stored by
+//! type rather than by entity.
+
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, hash_map};
+use std::hash::Hash;
+
+use {TypeIdBuildHasher, cast_mut, cast_ref};
+
+/// The values of one type, keyed by entity; always a `HashMap<K, T>`.
+trait Column<K> {
+    /// Removes the value for the given entity, returning true if there was one.
+    fn remove_entity(&mut self, key: &K) -> bool;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<K: Eq + Hash + 'static, T: Any + 'static> Column<K> for HashMap<K, T> {
+    fn remove_entity(&mut self, key: &K) -> bool {
+        self.remove(key).is_some()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// A collection mapping keys, such as entity IDs, to zero or one values for any given type: in
+/// effect an `AnyMap` per key, but stored as one table of values per type.
+///
+/// However many entities there are, each type is recorded once, and its values are stored
+/// unboxed alongside each other, so that going over every value of one type is cheap.
+///
+/// ```rust
+/// # use anymap::entity::EntityMap;
+/// #[derive(PartialEq, Debug)]
+/// struct Position(i32, i32);
+/// struct Name(&'static str);
+///
+/// let mut world = EntityMap::new();
+/// world.insert(1u64, Position(0, 0));
+/// world.insert(1u64, Name("player"));
+/// world.insert(2u64, Position(3, 4));
+/// world.insert(3u64, Name("sign"));
+///
+/// let mut positions: Vec<u64> = world.iter::<Position>().map(|(&entity, _)| entity).collect();
+/// positions.sort();
+/// assert_eq!(positions, vec![1, 2]);
+/// assert_eq!(world.get::<Position>(&2), Some(&Position(3, 4)));
+/// assert_eq!(world.len(), 3);
+/// ```
+pub struct EntityMap<K> {
+    columns: HashMap<TypeId, Box<dyn Column<K> + 'static>, TypeIdBuildHasher>,
+    /// The number of values each entity has.
+    entities: HashMap<K, usize>,
+}
+
+impl<K: Eq + Hash + Clone + 'static> Default for EntityMap<K> {
+    #[inline]
+    fn default() -> EntityMap<K> {
+        EntityMap::new()
+    }
+}
+
+impl<K: Eq + Hash + Clone + 'static> EntityMap<K> {
+    /// Construct a new, empty `EntityMap`.
+    #[inline]
+    pub fn new() -> EntityMap<K> {
+        EntityMap {
+            columns: HashMap::with_hasher(TypeIdBuildHasher),
+            entities: HashMap::new(),
+        }
+    }
+
+    /// Returns a reference to the value of type `T` for the entity, if it has one.
+    pub fn get<T: Any + 'static>(&self, key: &K) -> Option<&T> {
+        self.column::<T>().and_then(|column| column.get(key))
+    }
+
+    /// Returns a mutable reference to the value of type `T` for the entity, if it has one.
+    pub fn get_mut<T: Any + 'static>(&mut self, key: &K) -> Option<&mut T> {
+        self.column_mut::<T>().and_then(|column| column.get_mut(key))
+    }
+
+    /// Returns true if the entity has a value of type `T`.
+    pub fn contains<T: Any + 'static>(&self, key: &K) -> bool {
+        self.column::<T>().is_some_and(|column| column.contains_key(key))
+    }
+
+    /// Returns true if the entity has any values.
+    #[inline]
+    pub fn contains_entity(&self, key: &K) -> bool {
+        self.entities.contains_key(key)
+    }
+
+    /// Sets the value of type `T` for the entity.
+    /// If the entity already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    pub fn insert<T: Any + 'static>(&mut self, key: K, value: T) -> Option<T> {
+        let column = match self.columns.entry(TypeId::of::<T>()) {
+            hash_map::Entry::Occupied(e) => e.into_mut(),
+            hash_map::Entry::Vacant(e) => {
+                e.insert(Box::new(HashMap::<K, T>::new()) as Box<dyn Column<K>>)
+            },
+        };
+        let old = cast_mut::<HashMap<K, T>>(column.as_any_mut()).insert(key.clone(), value);
+        if old.is_none() {
+            match self.entities.entry(key) {
+                hash_map::Entry::Occupied(e) => *e.into_mut() += 1,
+                hash_map::Entry::Vacant(e) => { let _ = e.insert(1); },
+            }
+        }
+        old
+    }
+
+    /// Removes the value of type `T` from the entity,
+    /// returning it if there was one or `None` if there was not.
+    pub fn remove<T: Any + 'static>(&mut self, key: &K) -> Option<T> {
+        let value = self.column_mut::<T>().and_then(|column| column.remove(key));
+        if value.is_some() {
+            let remaining = {
+                let count = self.entities.get_mut(key).unwrap();
+                *count -= 1;
+                *count
+            };
+            if remaining == 0 {
+                let _ = self.entities.remove(key);
+            }
+        }
+        value
+    }
+
+    /// Removes every value of the entity, returning true if it had any.
+    pub fn remove_entity(&mut self, key: &K) -> bool {
+        if self.entities.remove(key).is_none() {
+            return false;
+        }
+        for column in self.columns.values_mut() {
+            let _ = column.remove_entity(key);
+        }
+        true
+    }
+
+    /// Returns an iterator over every entity with a value of type `T`, along with that value,
+    /// in no particular order.
+    pub fn iter<T: Any + 'static>(&self) -> Iter<'_, K, T> {
+        Iter {
+            inner: self.column::<T>().map(|column| column.iter()),
+        }
+    }
+
+    /// Returns an iterator over every entity with a value of type `T`, along with a mutable
+    /// reference to that value, in no particular order.
+    pub fn iter_mut<T: Any + 'static>(&mut self) -> IterMut<'_, K, T> {
+        IterMut {
+            inner: self.column_mut::<T>().map(|column| column.iter_mut()),
+        }
+    }
+
+    /// Returns the number of entities with any values.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Returns true if no entity has any values.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Removes every value of every entity.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.columns.clear();
+        self.entities.clear();
+    }
+
+    fn column<T: Any + 'static>(&self) -> Option<&HashMap<K, T>> {
+        self.columns.get(&TypeId::of::<T>())
+            .map(|column| cast_ref::<HashMap<K, T>>(column.as_any()))
+    }
+
+    fn column_mut<T: Any + 'static>(&mut self) -> Option<&mut HashMap<K, T>> {
+        self.columns.get_mut(&TypeId::of::<T>())
+            .map(|column| cast_mut::<HashMap<K, T>>(column.as_any_mut()))
+    }
+}
+
+/// Iterator over the values of one type in an `EntityMap`, as returned by `EntityMap::iter`.
+pub struct Iter<'a, K: 'a, T: 'a> {
+    inner: Option<hash_map::Iter<'a, K, T>>,
+}
+
+/// Iterator over mutable references to the values of one type in an `EntityMap`, as returned
+/// by `EntityMap::iter_mut`.
+pub struct IterMut<'a, K: 'a, T: 'a> {
+    inner: Option<hash_map::IterMut<'a, K, T>>,
+}
+
+impl<'a, K, T> Iterator for Iter<'a, K, T> {
+    type Item = (&'a K, &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<(&'a K, &'a T)> {
+        self.inner.as_mut().and_then(|inner| inner.next())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.as_ref().map_or((0, Some(0)), |inner| inner.size_hint())
+    }
+}
+
+impl<'a, K, T> Iterator for IterMut<'a, K, T> {
+    type Item = (&'a K, &'a mut T);
+
+    #[inline]
+    fn next(&mut self) -> Option<(&'a K, &'a mut T)> {
+        self.inner.as_mut().and_then(|inner| inner.next())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.as_ref().map_or((0, Some(0)), |inner| inner.size_hint())
+    }
+}
+
+#[test]
+fn test_entity_map() {
+    let mut map = EntityMap::new();
+    assert_eq!(map.insert(1u32, 1u8), None);
+    assert_eq!(map.insert(1u32, 2u8), Some(1));
+    assert_eq!(map.insert(1u32, 10i32), None);
+    assert_eq!(map.insert(2u32, 20i32), None);
+    assert_eq!(map.len(), 2);
+    assert!(map.contains::<u8>(&1) && !map.contains::<u8>(&2));
+
+    for (_, value) in map.iter_mut::<i32>() {
+        *value += 1;
+    }
+    let mut values: Vec<(u32, i32)> = map.iter::<i32>().map(|(&k, &v)| (k, v)).collect();
+    values.sort();
+    assert_eq!(values, vec![(1, 11), (2, 21)]);
+    assert_eq!(map.iter::<u16>().count(), 0);
+
+    assert_eq!(map.remove::<i32>(&2), Some(21));
+    assert!(!map.contains_entity(&2));
+    assert!(map.remove_entity(&1));
+    assert_eq!(map.get::<u8>(&1), None);
+    assert!(map.is_empty());
+}
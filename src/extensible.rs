@@ -0,0 +1,118 @@
+//! Types carrying an `AnyMap` of extensions, such as the requests and responses of a web
+//! framework, given the map's methods under names of their own.
+
+use std::any::Any;
+
+use AnyMap;
+
+/// A type with an `AnyMap` of extensions, which gets methods for its values from the two
+/// methods giving access to the map.
+///
+/// The `extensible!` macro implements it for a struct with an `AnyMap` field.
+pub trait Extensible {
+    /// Returns a reference to the map of extensions.
+    fn extensions(&self) -> &AnyMap;
+
+    /// Returns a mutable reference to the map of extensions.
+    fn extensions_mut(&mut self) -> &mut AnyMap;
+
+    /// Returns a reference to the extension of type `T`, if there is one.
+    #[inline]
+    fn ext_get<T: Any + 'static>(&self) -> Option<&T> {
+        self.extensions().get::<T>()
+    }
+
+    /// Returns a mutable reference to the extension of type `T`, if there is one.
+    #[inline]
+    fn ext_get_mut<T: Any + 'static>(&mut self) -> Option<&mut T> {
+        self.extensions_mut().get_mut::<T>()
+    }
+
+    /// Returns true if there is an extension of type `T`.
+    #[inline]
+    fn ext_contains<T: Any + 'static>(&self) -> bool {
+        self.extensions().contains::<T>()
+    }
+
+    /// Sets the extension of type `T`, returning the old one if there was one.
+    #[inline]
+    fn ext_insert<T: Any + 'static>(&mut self, value: T) -> Option<T> {
+        self.extensions_mut().insert(value)
+    }
+
+    /// Removes the extension of type `T`, returning it if there was one.
+    #[inline]
+    fn ext_remove<T: Any + 'static>(&mut self) -> Option<T> {
+        self.extensions_mut().remove::<T>()
+    }
+}
+
+impl Extensible for AnyMap {
+    #[inline]
+    fn extensions(&self) -> &AnyMap {
+        self
+    }
+
+    #[inline]
+    fn extensions_mut(&mut self) -> &mut AnyMap {
+        self
+    }
+}
+
+/// Implements `Extensible` for a type, given the name of its `AnyMap` field.
+///
+/// ```rust
+/// # #[macro_use] extern crate anymap;
+/// # fn main() {
+/// use anymap::AnyMap;
+/// use anymap::extensible::Extensible;
+///
+/// struct Request {
+///     path: String,
+///     extensions: AnyMap,
+/// }
+///
+/// extensible!(Request, extensions);
+///
+/// struct UserId(u32);
+///
+/// let mut request = Request { path: format!("/"), extensions: AnyMap::new() };
+/// request.ext_insert(UserId(7));
+/// assert_eq!(request.ext_get::<UserId>().unwrap().0, 7);
+/// assert_eq!(request.path, "/");
+/// # }
+/// ```
+#[macro_export]
+macro_rules! extensible {
+    ($t:ty, $field:ident) => {
+        impl $crate::extensible::Extensible for $t {
+            #[inline]
+            fn extensions(&self) -> &$crate::AnyMap {
+                &self.$field
+            }
+
+            #[inline]
+            fn extensions_mut(&mut self) -> &mut $crate::AnyMap {
+                &mut self.$field
+            }
+        }
+    };
+}
+
+#[test]
+fn test_extensible() {
+    struct Response {
+        status: u16,
+        extra: AnyMap,
+    }
+
+    extensible!(Response, extra);
+
+    let mut response = Response { status: 200, extra: AnyMap::new() };
+    assert_eq!(response.ext_insert(1u8), None);
+    *response.ext_get_mut::<u8>().unwrap() += 1;
+    assert_eq!(response.ext_get::<u8>(), Some(&2));
+    assert!(response.ext_contains::<u8>() && !response.ext_contains::<u16>());
+    assert_eq!(response.ext_remove::<u8>(), Some(2));
+    assert!(response.extensions().is_empty() && response.status == 200);
+}
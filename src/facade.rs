@@ -0,0 +1,187 @@
+//! Wrappers of an `AnyMap` closed to a declared set of types, generated by `facade!`.
+
+/// Implemented by each type declared in the façade `F`, by the `facade!` macro which declares
+/// it; the methods of `F` accept only these types.
+pub trait Member<F> { }
+
+/// Declares a struct wrapping an `AnyMap`, whose methods accept only the types listed.
+///
+/// The map is as flexible as ever inside, but the struct's surface is closed: asking it for any
+/// type not in its declaration is a compile-time error, so what may be stored in it can be
+/// reviewed at a glance where it crosses a crate boundary. The struct has `new`, `get`,
+/// `get_mut`, `contains`, `insert`, `remove`, `len`, `is_empty` and `clear`, restricted to the
+/// declared types, as well as `declared`, returning the set of them, and `into_inner`. It may
+/// have attributes and be public; it may not have type or lifetime parameters. A type may be
+/// listed only once.
+///
+/// ```rust
+/// # #[macro_use] extern crate anymap;
+/// # fn main() {
+/// struct Config { verbose: bool }
+/// struct Stats { requests: u32 }
+///
+/// facade! {
+///     pub struct Context { Config, Stats }
+/// }
+///
+/// let mut context = Context::new();
+/// context.insert(Config { verbose: true });
+/// context.insert(Stats { requests: 0 });
+/// context.get_mut::<Stats>().unwrap().requests += 1;
+/// assert!(context.get::<Config>().unwrap().verbose);
+/// assert_eq!(Context::declared().len(), 2);
+/// # }
+/// ```
+///
+/// An undeclared type is refused:
+///
+/// ```rust,compile_fail
+/// # #[macro_use] extern crate anymap;
+/// # fn main() {
+/// struct Config;
+/// struct Secret;
+///
+/// facade! {
+///     struct Context { Config }
+/// }
+///
+/// let context = Context::new();
+/// let _ = context.get::<Secret>();
+/// # }
+/// ```
+#[macro_export]
+macro_rules! facade {
+    ($(#[$attr:meta])* pub struct $name:ident { $($t:ty),+ $(,)* }) => {
+        $(#[$attr])*
+        pub struct $name {
+            map: $crate::AnyMap,
+        }
+
+        facade!(@impl $name { $($t),+ });
+    };
+    ($(#[$attr:meta])* struct $name:ident { $($t:ty),+ $(,)* }) => {
+        $(#[$attr])*
+        struct $name {
+            map: $crate::AnyMap,
+        }
+
+        facade!(@impl $name { $($t),+ });
+    };
+    (@impl $name:ident { $($t:ty),+ }) => {
+        $(impl $crate::facade::Member<$name> for $t { })+
+
+        impl Default for $name {
+            #[inline]
+            fn default() -> $name {
+                $name::new()
+            }
+        }
+
+        #[allow(dead_code)]
+        impl $name {
+            /// Construct a new, empty collection.
+            #[inline]
+            pub fn new() -> $name {
+                $name {
+                    map: $crate::AnyMap::new(),
+                }
+            }
+
+            /// Returns the set of the types the collection accepts.
+            pub fn declared() -> $crate::TypeSet {
+                $crate::TypeSet::new()$(.with::<$t>())+
+            }
+
+            /// Returns a reference to the value stored in the collection for the type `T`,
+            /// if it exists.
+            #[inline]
+            pub fn get<T>(&self) -> Option<&T>
+            where T: $crate::facade::Member<$name> + ::std::any::Any + 'static {
+                self.map.get::<T>()
+            }
+
+            /// Returns a mutable reference to the value stored in the collection for the type
+            /// `T`, if it exists.
+            #[inline]
+            pub fn get_mut<T>(&mut self) -> Option<&mut T>
+            where T: $crate::facade::Member<$name> + ::std::any::Any + 'static {
+                self.map.get_mut::<T>()
+            }
+
+            /// Returns true if the collection contains a value of type `T`.
+            #[inline]
+            pub fn contains<T>(&self) -> bool
+            where T: $crate::facade::Member<$name> + ::std::any::Any + 'static {
+                self.map.contains::<T>()
+            }
+
+            /// Sets the value stored in the collection for the type `T`.
+            /// If the collection already had a value of type `T`, that value is returned.
+            /// Otherwise, `None` is returned.
+            #[inline]
+            pub fn insert<T>(&mut self, value: T) -> Option<T>
+            where T: $crate::facade::Member<$name> + ::std::any::Any + 'static {
+                self.map.insert(value)
+            }
+
+            /// Removes the `T` value from the collection,
+            /// returning it if there was one or `None` if there was not.
+            #[inline]
+            pub fn remove<T>(&mut self) -> Option<T>
+            where T: $crate::facade::Member<$name> + ::std::any::Any + 'static {
+                self.map.remove::<T>()
+            }
+
+            /// Returns the number of items in the collection.
+            #[inline]
+            pub fn len(&self) -> usize {
+                self.map.len()
+            }
+
+            /// Returns true if there are no items in the collection.
+            #[inline]
+            pub fn is_empty(&self) -> bool {
+                self.map.is_empty()
+            }
+
+            /// Removes all items from the collection. Keeps the allocated memory for reuse.
+            #[inline]
+            pub fn clear(&mut self) {
+                self.map.clear()
+            }
+
+            /// Unwraps the underlying `AnyMap`, opening it to every type.
+            #[inline]
+            pub fn into_inner(self) -> $crate::AnyMap {
+                self.map
+            }
+        }
+    };
+}
+
+#[test]
+fn test_facade() {
+    use AnyMap;
+
+    facade! {
+        struct Numbers { u8, Vec<u16>, }
+    }
+
+    let mut numbers = Numbers::default();
+    assert!(numbers.is_empty());
+    assert_eq!(numbers.insert(1u8), None);
+    assert_eq!(numbers.insert(vec![2u16]), None);
+    *numbers.get_mut::<u8>().unwrap() += 1;
+    assert_eq!(numbers.insert(3u8), Some(2));
+    assert!(numbers.contains::<Vec<u16>>());
+    assert_eq!(numbers.remove::<Vec<u16>>(), Some(vec![2]));
+    assert_eq!((numbers.get::<u8>(), numbers.len()), (Some(&3), 1));
+    assert!(Numbers::declared().contains::<Vec<u16>>());
+    assert_eq!(Numbers::declared().len(), 2);
+
+    let map: AnyMap = numbers.into_inner();
+    assert_eq!(map.get::<u8>(), Some(&3));
+    numbers = Numbers::new();
+    numbers.clear();
+    assert!(numbers.is_empty());
+}
@@ -0,0 +1,215 @@
+//! A read-only `AnyMap`, rebuilt when it is frozen into a layout made for fast lookups.
+
+use std::any::{Any, TypeId};
+use std::hash::BuildHasher;
+
+use {AnyMap, TypeIdBuildHasher, cast_ref};
+use like::AnyMapLike;
+
+/// The marker of an empty slot in the perfect hash table.
+const EMPTY: u32 = u32::MAX;
+
+/// The most bits of table size tried beyond the least which holds every value at half load.
+const EXTRA_BITS: u32 = 8;
+
+/// The multipliers tried at each table size.
+const ATTEMPTS: u64 = 32;
+
+/// A collection containing zero or one values for any given type, which can no longer be
+/// changed, made by `AnyMap::optimize_for_reads`.
+///
+/// The values are kept in an array sorted by type, indexed by a perfect hash table: a table
+/// with a slot for each type, found with a multiplication and a shift of its ID, with no
+/// probing, so a lookup reads one slot and one entry and compares one ID. This is for maps such
+/// as configuration, written once and then read many times. (In the practically impossible
+/// event that no perfect hash can be found, lookups fall back to a binary search.)
+///
+/// ```rust
+/// # use anymap::AnyMap;
+/// struct Port(u16);
+/// struct Host(&'static str);
+///
+/// let mut config = AnyMap::new();
+/// config.insert(Port(8080));
+/// config.insert(Host("localhost"));
+/// let config = config.optimize_for_reads();
+/// assert_eq!(config.get::<Port>().unwrap().0, 8080);
+/// assert!(config.get::<u8>().is_none());
+///
+/// let mut config = config.into_inner();
+/// config.insert(Port(80));
+/// ```
+pub struct FrozenAnyMap {
+    entries: Vec<(TypeId, Box<dyn Any + 'static>)>,
+    slots: Vec<u32>,
+    multiplier: u64,
+    shift: u32,
+}
+
+impl AnyMap {
+    /// Freezes the collection, rebuilding it into a `FrozenAnyMap`, which can only be read, but
+    /// is faster to read from.
+    ///
+    /// Any statistics collected for the collection are lost.
+    pub fn optimize_for_reads(self) -> FrozenAnyMap {
+        let mut entries: Vec<_> = self.data.into_iter().collect();
+        entries.sort_by_key(|&(id, _)| id);
+        let hashes: Vec<u64> = entries.iter().map(|&(id, _)| hash(&id)).collect();
+        let mut frozen = FrozenAnyMap {
+            entries,
+            slots: Vec::new(),
+            multiplier: 0,
+            shift: 0,
+        };
+        let least = (hashes.len() * 2).next_power_of_two().trailing_zeros().max(1);
+        for bits in least..least + EXTRA_BITS {
+            if let Some((multiplier, slots)) = perfect_hash(&hashes, bits) {
+                frozen.multiplier = multiplier;
+                frozen.shift = 64 - bits;
+                frozen.slots = slots;
+                break;
+            }
+        }
+        frozen
+    }
+}
+
+impl FrozenAnyMap {
+    /// Returns a reference to the value stored in the collection for the type `T`, if it exists.
+    #[inline]
+    pub fn get<T: Any + 'static>(&self) -> Option<&T> {
+        self.find(TypeId::of::<T>()).map(|i| cast_ref::<T>(&*self.entries[i].1))
+    }
+
+    /// Returns true if the collection contains a value of type `T`.
+    #[inline]
+    pub fn contains<T: Any + 'static>(&self) -> bool {
+        self.find(TypeId::of::<T>()).is_some()
+    }
+
+    /// Returns the number of items in the collection.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if there are no items in the collection.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Thaws the collection, returning its values in an `AnyMap` which can be changed again.
+    pub fn into_inner(self) -> AnyMap {
+        let mut map = AnyMap::with_capcity(self.entries.len());
+        map.data.extend(self.entries);
+        map.instruments.resized(map.data.len());
+        map
+    }
+
+    /// Returns the index of the entry of the given type, if there is one.
+    #[inline]
+    fn find(&self, id: TypeId) -> Option<usize> {
+        if self.slots.is_empty() {
+            return self.entries.binary_search_by_key(&id, |&(id, _)| id).ok();
+        }
+        let slot = self.slots[(hash(&id).wrapping_mul(self.multiplier) >> self.shift) as usize];
+        if slot != EMPTY && self.entries[slot as usize].0 == id {
+            Some(slot as usize)
+        } else {
+            None
+        }
+    }
+}
+
+/// Every change through `AnyMapLike` is refused.
+impl AnyMapLike for FrozenAnyMap {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.find(id).is_some()
+    }
+
+    #[inline]
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.find(id).map(|i| &*self.entries[i].1)
+    }
+
+    #[inline]
+    fn get_raw_mut(&mut self, _: TypeId) -> Option<&mut dyn Any> {
+        None
+    }
+
+    #[inline]
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        Err(value)
+    }
+
+    #[inline]
+    fn remove_raw(&mut self, _: TypeId) -> Option<Box<dyn Any>> {
+        None
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// Hashes a type ID as an `AnyMap` does.
+#[inline]
+fn hash(id: &TypeId) -> u64 {
+    TypeIdBuildHasher.hash_one(id)
+}
+
+/// Looks for a multiplier under which the hashes map to distinct slots of a table of `2^bits`
+/// slots, returning it with the table, each slot holding the index of its hash.
+fn perfect_hash(hashes: &[u64], bits: u32) -> Option<(u64, Vec<u32>)> {
+    let mut slots = vec![EMPTY; 1 << bits];
+    for attempt in 0..ATTEMPTS {
+        // Odd multipliers spread by the golden ratio, as in Fibonacci hashing.
+        let multiplier = 0x9e37_79b9_7f4a_7c15u64.wrapping_mul(attempt * 2 + 1) | 1;
+        let mut placed = true;
+        for (i, &hash) in hashes.iter().enumerate() {
+            let slot = &mut slots[(hash.wrapping_mul(multiplier) >> (64 - bits)) as usize];
+            if *slot != EMPTY {
+                placed = false;
+                break;
+            }
+            *slot = i as u32;
+        }
+        if placed {
+            return Some((multiplier, slots));
+        }
+        for slot in slots.iter_mut() {
+            *slot = EMPTY;
+        }
+    }
+    None
+}
+
+#[test]
+fn test_frozen() {
+    let mut map = AnyMap::new();
+    assert!(map.optimize_for_reads().get::<u8>().is_none());
+    map = AnyMap::new();
+    let _ = map.insert(1u8);
+    let _ = map.insert(2u16);
+    let _ = map.insert(3u32);
+    let _ = map.insert(4u64);
+    let _ = map.insert("five");
+    let _ = map.insert(vec![6i8]);
+    let frozen = map.optimize_for_reads();
+    assert!(!frozen.slots.is_empty());
+    assert_eq!(frozen.len(), 6);
+    assert_eq!((frozen.get::<u8>(), frozen.get::<u64>()), (Some(&1), Some(&4)));
+    assert_eq!(frozen.get::<&str>(), Some(&"five"));
+    assert_eq!(frozen.get::<Vec<i8>>(), Some(&vec![6]));
+    assert!(frozen.contains::<u32>() && !frozen.contains::<i32>() && !frozen.contains::<()>());
+
+    let mut searched = frozen;
+    searched.slots.clear();
+    assert_eq!((searched.get::<u16>(), searched.get::<i16>()), (Some(&2), None));
+    let thawed = searched.into_inner();
+    assert_eq!((thawed.len(), thawed.get::<u32>()), (6, Some(&3)));
+}
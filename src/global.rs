@@ -0,0 +1,88 @@
+//! A process-wide store of one value of each type, initialized when first used.
+//!
+//! There are two kinds of global values: those set once with `set`, which can then be borrowed
+//! for `'static` without locking but never changed or removed, and those in the lock-guarded
+//! `SyncAnyMap` returned by `map`, which can be changed freely.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+
+use {TypeIdBuildHasher, cast_ref};
+use sync::SyncAnyMap;
+
+/// The values set with `set`, leaked so that they may be borrowed for `'static`.
+type Constants = HashMap<TypeId, &'static (dyn Any + Send + Sync + 'static), TypeIdBuildHasher>;
+
+static CONSTANTS: OnceLock<RwLock<Constants>> = OnceLock::new();
+
+static MAP: OnceLock<RwLock<SyncAnyMap>> = OnceLock::new();
+
+fn constants() -> &'static RwLock<Constants> {
+    CONSTANTS.get_or_init(|| RwLock::new(HashMap::with_hasher(TypeIdBuildHasher)))
+}
+
+/// Sets the global value of type `T`, returning a reference to it. This can be done only once
+/// for each type: if there is a global value of type `T` already, the new value is returned
+/// instead.
+///
+/// The value lives for the rest of the process; it is never dropped.
+///
+/// ```rust
+/// # use anymap::global;
+/// struct Config { name: &'static str }
+///
+/// assert!(global::get::<Config>().is_none());
+/// global::set(Config { name: "demo" }).ok().unwrap();
+/// assert!(global::set(Config { name: "again" }).is_err());
+/// let config: &'static Config = global::get().unwrap();
+/// assert_eq!(config.name, "demo");
+/// ```
+pub fn set<T: Any + Send + Sync + 'static>(value: T) -> Result<&'static T, T> {
+    let mut constants = constants().write().unwrap();
+    if constants.contains_key(&TypeId::of::<T>()) {
+        return Err(value);
+    }
+    let value: &'static T = Box::leak(Box::new(value));
+    let _ = constants.insert(TypeId::of::<T>(), value as &'static (dyn Any + Send + Sync));
+    Ok(value)
+}
+
+/// Returns the global value of type `T` set with `set`, if it has been set.
+pub fn get<T: Any + Send + Sync + 'static>() -> Option<&'static T> {
+    constants().read().unwrap().get(&TypeId::of::<T>()).map(|&value| cast_ref::<T>(value))
+}
+
+/// Returns the global `SyncAnyMap`, which is empty until values are inserted into it.
+///
+/// ```rust
+/// # use anymap::global;
+/// struct Requests(u64);
+///
+/// global::map().write().unwrap().insert(Requests(0));
+/// global::map().write().unwrap().get_mut::<Requests>().unwrap().0 += 1;
+/// assert_eq!(global::map().read().unwrap().get::<Requests>().unwrap().0, 1);
+/// ```
+pub fn map() -> &'static RwLock<SyncAnyMap> {
+    MAP.get_or_init(|| RwLock::new(SyncAnyMap::new()))
+}
+
+#[test]
+fn test_global() {
+    use std::thread;
+
+    struct Constant(u32);
+    struct Counter(u32);
+
+    assert!(get::<Constant>().is_none());
+    let threads: Vec<_> = (0..4u32)
+        .map(|n| thread::spawn(move || set(Constant(n)).is_ok()))
+        .collect();
+    let successes = threads.into_iter().map(|t| t.join().unwrap()).filter(|&set| set).count();
+    assert_eq!(successes, 1);
+    assert!(get::<Constant>().unwrap().0 < 4);
+
+    let _ = map().write().unwrap().insert(Counter(1));
+    map().write().unwrap().get_mut::<Counter>().unwrap().0 += 1;
+    assert_eq!(map().read().unwrap().get::<Counter>().unwrap().0, 2);
+}
@@ -0,0 +1,173 @@
+//! A registry of message handlers, one per message type, to which messages are dispatched.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+
+use {CallbackPanic, TypeIdBuildHasher, cast_ref, panic_message};
+
+/// A handler for messages of one type, taking them type-erased.
+type Handler = Box<dyn FnMut(&dyn Any) + 'static>;
+
+/// A collection containing zero or one handlers for any given message type, which routes each
+/// message dispatched to it to the handler for its type.
+///
+/// Messages may be dispatched with their type known statically, by `dispatch`, or type-erased,
+/// by `dispatch_any`:
+///
+/// ```rust
+/// # use anymap::handlers::HandlerMap;
+/// # use std::any::Any;
+/// # use std::cell::Cell;
+/// # use std::rc::Rc;
+/// struct Resize { width: u32 }
+/// struct Quit;
+///
+/// let width = Rc::new(Cell::new(0));
+/// let mut handlers = HandlerMap::new();
+/// {
+///     let width = width.clone();
+///     handlers.insert(move |message: &Resize| width.set(message.width));
+/// }
+///
+/// assert!(handlers.dispatch(&Resize { width: 80 }));
+/// assert_eq!(width.get(), 80);
+///
+/// let queue: Vec<Box<dyn Any>> = vec![Box::new(Resize { width: 120 }), Box::new(Quit)];
+/// let handled: Vec<bool> = queue.iter()
+///     .map(|message| handlers.dispatch_any(&**message))
+///     .collect();
+/// assert_eq!(handled, vec![true, false]);
+/// assert_eq!(width.get(), 120);
+/// ```
+///
+/// A handler which panics does not unwind through the map: the panic is caught, the handler
+/// removed, and the panic recorded, to be collected with `take_handler_panics`.
+pub struct HandlerMap {
+    handlers: HashMap<TypeId, Handler, TypeIdBuildHasher>,
+    panics: Vec<CallbackPanic>,
+}
+
+impl Default for HandlerMap {
+    #[inline]
+    fn default() -> HandlerMap {
+        HandlerMap::new()
+    }
+}
+
+impl HandlerMap {
+    /// Construct a new `HandlerMap` with no handlers.
+    #[inline]
+    pub fn new() -> HandlerMap {
+        HandlerMap {
+            handlers: HashMap::with_hasher(TypeIdBuildHasher),
+            panics: Vec::new(),
+        }
+    }
+
+    /// Sets the handler for messages of type `T`, returning true if it replaces another.
+    pub fn insert<T, F>(&mut self, mut handler: F) -> bool
+    where T: Any + 'static, F: FnMut(&T) + 'static {
+        let handler = Box::new(move |message: &dyn Any| handler(cast_ref::<T>(message))) as Handler;
+        self.handlers.insert(TypeId::of::<T>(), handler).is_some()
+    }
+
+    /// Removes the handler for messages of type `T`, returning true if there was one.
+    #[inline]
+    pub fn remove<T: Any + 'static>(&mut self) -> bool {
+        self.handlers.remove(&TypeId::of::<T>()).is_some()
+    }
+
+    /// Returns true if there is a handler for messages of type `T`.
+    #[inline]
+    pub fn handles<T: Any + 'static>(&self) -> bool {
+        self.handlers.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Passes a message to the handler for its type, returning false if there is none.
+    #[inline]
+    pub fn dispatch<T: Any + 'static>(&mut self, message: &T) -> bool {
+        self.dispatch_any(message)
+    }
+
+    /// Passes a message of a type known only at runtime to the handler for its type, returning
+    /// false if there is none. A message whose handler panics counts as handled.
+    pub fn dispatch_any(&mut self, message: &dyn Any) -> bool {
+        let id = message.type_id();
+        let payload = match self.handlers.get_mut(&id) {
+            Some(handler) => {
+                match panic::catch_unwind(AssertUnwindSafe(|| (*handler)(message))) {
+                    Ok(()) => return true,
+                    Err(payload) => payload,
+                }
+            },
+            None => return false,
+        };
+        // A handler which panics is removed, so that whatever state it was left in is never seen.
+        let _ = self.handlers.remove(&id);
+        self.panics.push(CallbackPanic { type_id: id, message: panic_message(&*payload) });
+        true
+    }
+
+    /// Returns the panics of handlers caught since the last call, in the order they happened,
+    /// leaving none recorded.
+    #[inline]
+    pub fn take_handler_panics(&mut self) -> Vec<CallbackPanic> {
+        std::mem::take(&mut self.panics)
+    }
+
+    /// Returns the number of handlers.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.handlers.len()
+    }
+
+    /// Returns true if there are no handlers.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    /// Removes all handlers. Keeps the allocated memory for reuse.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.handlers.clear();
+    }
+}
+
+#[test]
+fn test_handlers() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut handlers = HandlerMap::new();
+    let bytes = log.clone();
+    assert!(!handlers.insert(move |n: &u8| bytes.borrow_mut().push(*n as i32)));
+    let shorts = log.clone();
+    assert!(!handlers.insert(move |n: &i16| shorts.borrow_mut().push(*n as i32)));
+    let replacement = log.clone();
+    assert!(handlers.insert(move |n: &i16| replacement.borrow_mut().push(-*n as i32)));
+    assert!(handlers.handles::<u8>() && !handlers.handles::<u16>());
+    assert_eq!(handlers.len(), 2);
+
+    assert!(handlers.dispatch(&1u8));
+    assert!(handlers.dispatch(&2i16));
+    assert!(!handlers.dispatch(&3u16));
+    let boxed = Box::new(4u8) as Box<dyn Any>;
+    assert!(handlers.dispatch_any(&*boxed));
+    assert_eq!(*log.borrow(), vec![1, -2, 4]);
+
+    assert!(handlers.remove::<u8>());
+    assert!(!handlers.dispatch(&5u8));
+
+    assert!(!handlers.insert(|_: &u32| panic!("bad handler")));
+    assert!(handlers.dispatch(&6u32));
+    assert!(!handlers.handles::<u32>() && handlers.handles::<i16>());
+    let panics = handlers.take_handler_panics();
+    assert_eq!(panics, vec![CallbackPanic { type_id: TypeId::of::<u32>(),
+                                            message: Some("bad handler".to_string()) }]);
+    assert!(handlers.take_handler_panics().is_empty());
+    handlers.clear();
+    assert!(handlers.is_empty());
+}
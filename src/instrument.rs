@@ -0,0 +1,272 @@
+//! The points at which the optional instrumentation features observe `AnyMap` operations.
+//!
+//! With none of those features enabled, everything here compiles away to nothing.
+
+use std::any::{Any, TypeId};
+#[cfg(feature = "stats")]
+use std::collections::HashMap;
+#[cfg(feature = "stats")]
+use std::collections::hash_map;
+#[cfg(feature = "metrics")]
+use std::sync::Arc;
+#[cfg(feature = "stats")]
+use std::sync::Mutex;
+
+#[cfg(feature = "metrics")]
+use metrics::SharedString;
+
+#[cfg(feature = "stats")]
+use TypeIdBuildHasher;
+#[cfg(any(feature = "stats", feature = "metrics", feature = "tracing"))]
+use type_name;
+
+/// An operation on one type in a map.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A lookup found a value.
+    Hit,
+    /// A lookup found no value.
+    Miss,
+    /// A value was inserted, possibly replacing an old one.
+    Insert,
+    /// A value was removed.
+    Remove,
+}
+
+/// The instrumentation state of one map.
+pub struct Instruments {
+    #[cfg(feature = "stats")]
+    stats: Mutex<HashMap<TypeId, TypeStats, TypeIdBuildHasher>>,
+    #[cfg(feature = "metrics")]
+    metric_names: MetricNames,
+    /// The number of entries this map has contributed to the entries gauge.
+    #[cfg(feature = "metrics")]
+    reported_len: usize,
+}
+
+impl Instruments {
+    #[inline]
+    pub fn new() -> Instruments {
+        Instruments {
+            #[cfg(feature = "stats")]
+            stats: Mutex::new(HashMap::with_hasher(TypeIdBuildHasher)),
+            #[cfg(feature = "metrics")]
+            metric_names: MetricNames::new(DEFAULT_METRICS_PREFIX),
+            #[cfg(feature = "metrics")]
+            reported_len: 0,
+        }
+    }
+
+    /// Records a lookup of the type `T`, successful or not.
+    #[inline]
+    pub fn lookup<T: Any + 'static>(&self, found: bool) {
+        self.record::<T>(if found { Event::Hit } else { Event::Miss })
+    }
+
+    /// Records an operation on the type `T`.
+    #[inline]
+    #[allow(unused_variables)]
+    pub fn record<T: Any + 'static>(&self, event: Event) {
+        #[cfg(feature = "stats")]
+        self.count::<T>(event);
+        #[cfg(feature = "metrics")]
+        self.emit::<T>(event);
+        #[cfg(feature = "tracing")]
+        trace_event::<T>(event);
+    }
+
+    /// Records that a value of the given type has been evicted.
+    #[inline]
+    #[allow(unused_variables)]
+    pub fn evicted(&self, id: TypeId, name: &'static str) {
+        #[cfg(feature = "metrics")]
+        counter!(self.metric_names.evictions.clone(), "type" => name).increment(1);
+        #[cfg(feature = "tracing")]
+        debug!(ty = name, type_id = ?id, "anymap value evicted");
+    }
+
+    /// Records the new number of entries in the map, after a change.
+    #[inline]
+    #[allow(unused_variables)]
+    pub fn resized(&mut self, len: usize) {
+        #[cfg(feature = "metrics")]
+        self.report_len(len);
+    }
+
+    #[cfg(feature = "stats")]
+    fn count<T: Any + 'static>(&self, event: Event) {
+        let mut stats = self.stats.lock().unwrap();
+        let stats = match stats.entry(TypeId::of::<T>()) {
+            hash_map::Entry::Occupied(e) => e.into_mut(),
+            hash_map::Entry::Vacant(e) => e.insert(TypeStats::new::<T>()),
+        };
+        match event {
+            Event::Hit => stats.hits += 1,
+            Event::Miss => stats.misses += 1,
+            Event::Insert => stats.inserts += 1,
+            Event::Remove => stats.removes += 1,
+        }
+    }
+
+    /// Returns a snapshot of the statistics collected so far.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Vec<TypeStats> {
+        self.stats.lock().unwrap().values().copied().collect()
+    }
+
+    /// Forgets the statistics collected so far.
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&self) {
+        self.stats.lock().unwrap().clear()
+    }
+}
+
+/// Emits a `tracing` event for an operation. Successful lookups are too common to be of interest.
+#[cfg(feature = "tracing")]
+fn trace_event<T: Any + 'static>(event: Event) {
+    let ty = type_name::<T>();
+    match event {
+        Event::Hit => (),
+        Event::Miss => trace!(ty, "anymap lookup found no value"),
+        Event::Insert => debug!(ty, "anymap value inserted"),
+        Event::Remove => debug!(ty, "anymap value removed"),
+    }
+}
+
+/// The prefix of metric names unless another is configured.
+#[cfg(feature = "metrics")]
+const DEFAULT_METRICS_PREFIX: &str = "anymap";
+
+/// The names of the metrics emitted for one map.
+#[cfg(feature = "metrics")]
+struct MetricNames {
+    prefix: SharedString,
+    hits: SharedString,
+    misses: SharedString,
+    inserts: SharedString,
+    removes: SharedString,
+    evictions: SharedString,
+    entries: SharedString,
+}
+
+#[cfg(feature = "metrics")]
+impl MetricNames {
+    fn new(prefix: &str) -> MetricNames {
+        // These are cloned for every operation, so make that cheap.
+        let name = |suffix: &str| {
+            SharedString::from(Arc::<str>::from(format!("{}{}", prefix, suffix)))
+        };
+        MetricNames {
+            prefix: name(""),
+            hits: name(".hits"),
+            misses: name(".misses"),
+            inserts: name(".inserts"),
+            removes: name(".removes"),
+            evictions: name(".evictions"),
+            entries: name(".entries"),
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Instruments {
+    /// Returns the prefix of the metric names.
+    pub fn metrics_prefix(&self) -> &str {
+        &self.metric_names.prefix
+    }
+
+    /// Changes the prefix of the metric names, moving this map’s contribution to the entries
+    /// gauge over to the new name.
+    pub fn set_metrics_prefix(&mut self, prefix: &str) {
+        let len = self.reported_len;
+        self.report_len(0);
+        self.metric_names = MetricNames::new(prefix);
+        self.report_len(len);
+    }
+
+    fn emit<T: Any + 'static>(&self, event: Event) {
+        let name = match event {
+            Event::Hit => &self.metric_names.hits,
+            Event::Miss => &self.metric_names.misses,
+            Event::Insert => &self.metric_names.inserts,
+            Event::Remove => &self.metric_names.removes,
+        };
+        counter!(name.clone(), "type" => type_name::<T>()).increment(1);
+    }
+
+    fn report_len(&mut self, len: usize) {
+        // The gauge is shared by every map with the same prefix, so it is kept as the total of
+        // their lengths by reporting only the change in this one’s.
+        let entries = gauge!(self.metric_names.entries.clone());
+        if len > self.reported_len {
+            entries.increment((len - self.reported_len) as f64);
+        } else if len < self.reported_len {
+            entries.decrement((self.reported_len - len) as f64);
+        }
+        self.reported_len = len;
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Drop for Instruments {
+    fn drop(&mut self) {
+        self.report_len(0);
+    }
+}
+
+/// Access counts for one type in an `AnyMap`, collected when the `stats` feature is enabled.
+#[cfg(feature = "stats")]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TypeStats {
+    /// The ID of the type.
+    pub type_id: TypeId,
+    /// The name of the type.
+    pub name: &'static str,
+    /// The number of lookups which found a value of the type.
+    pub hits: u64,
+    /// The number of lookups which found no value of the type.
+    pub misses: u64,
+    /// The number of values of the type inserted, including replacements.
+    pub inserts: u64,
+    /// The number of values of the type removed.
+    pub removes: u64,
+}
+
+#[cfg(feature = "stats")]
+impl TypeStats {
+    fn new<T: Any + 'static>() -> TypeStats {
+        TypeStats {
+            type_id: TypeId::of::<T>(),
+            name: type_name::<T>(),
+            hits: 0,
+            misses: 0,
+            inserts: 0,
+            removes: 0,
+        }
+    }
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_stats() {
+    use AnyMap;
+
+    let mut map = AnyMap::new();
+    let _ = map.get::<i32>();
+    let _ = map.insert(1i32);
+    let _ = map.insert(2i32);
+    let _ = map.get::<i32>();
+    let _ = map.get_mut::<i32>();
+    let _ = map.remove::<i32>();
+    let _ = map.remove::<i32>();
+    let _ = map.insert(());
+    let mut stats = map.stats();
+    stats.sort_by_key(|stats| ::std::cmp::Reverse(stats.inserts));
+    assert_eq!(stats.len(), 2);
+    assert_eq!((stats[0].hits, stats[0].misses, stats[0].inserts, stats[0].removes), (2, 1, 2, 1));
+    assert_eq!(stats[0].type_id, TypeId::of::<i32>());
+    assert_eq!(stats[1].inserts, 1);
+
+    map.reset_stats();
+    assert!(map.stats().is_empty());
+}
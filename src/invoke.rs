@@ -0,0 +1,214 @@
+//! Calling functions with their parameters taken from an `AnyMap`, as by `AnyMap::invoke`.
+
+use std::any::{Any, TypeId};
+use std::error::Error;
+use std::fmt;
+use std::ops::Deref;
+
+use {AnyMap, cast_mut, cast_ref, type_name};
+
+/// How a parameter refers to the value in the map.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Access {
+    /// By shared reference.
+    Shared,
+    /// By mutable reference.
+    Mut,
+    /// By shared reference, the function being one to run only when the value has changed.
+    Changed,
+}
+
+/// A parameter of a function called with `AnyMap::invoke`: a shared or mutable reference to a
+/// value in the map.
+pub trait Param<'a>: Sized {
+    /// Returns the ID of the type of the value referred to.
+    fn type_id() -> TypeId;
+
+    /// Returns the name of the type of the value referred to, for use in errors.
+    fn type_name() -> &'static str;
+
+    /// Returns how the value is referred to.
+    #[inline]
+    fn access() -> Access {
+        Access::Shared
+    }
+
+    /// Borrows the value from the map, which is known to be of the right type.
+    fn from_any(value: &'a mut dyn Any) -> Self;
+}
+
+impl<'a, T: Any + 'static> Param<'a> for &'a T {
+    #[inline]
+    fn type_id() -> TypeId { TypeId::of::<T>() }
+
+    #[inline]
+    fn type_name() -> &'static str { type_name::<T>() }
+
+    #[inline]
+    fn from_any(value: &'a mut dyn Any) -> &'a T {
+        cast_ref::<T>(&*value)
+    }
+}
+
+impl<'a, T: Any + 'static> Param<'a> for &'a mut T {
+    #[inline]
+    fn type_id() -> TypeId { TypeId::of::<T>() }
+
+    #[inline]
+    fn type_name() -> &'static str { type_name::<T>() }
+
+    #[inline]
+    fn access() -> Access { Access::Mut }
+
+    #[inline]
+    fn from_any(value: &'a mut dyn Any) -> &'a mut T {
+        cast_mut::<T>(value)
+    }
+}
+
+/// A parameter which is a shared reference to a value in the map, and which marks the function
+/// as one to run only when the value has changed since it last ran, with
+/// `ObservedAnyMap::invoke_if_changed`. Other ways of invoking the function ignore the mark.
+pub struct Changed<'a, T: 'a>(&'a T);
+
+impl<'a, T> Deref for Changed<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.0
+    }
+}
+
+impl<'a, T: Any + 'static> Param<'a> for Changed<'a, T> {
+    #[inline]
+    fn type_id() -> TypeId { TypeId::of::<T>() }
+
+    #[inline]
+    fn type_name() -> &'static str { type_name::<T>() }
+
+    #[inline]
+    fn access() -> Access { Access::Changed }
+
+    #[inline]
+    fn from_any(value: &'a mut dyn Any) -> Changed<'a, T> {
+        Changed(cast_ref::<T>(&*value))
+    }
+}
+
+/// A function which can be called with `AnyMap::invoke`, each of its parameters, of which there
+/// may be up to eight, being a `Param`. `Args` is the tuple of the parameter types.
+pub trait Invoke<'a, Args> {
+    /// The return type of the function.
+    type Output;
+
+    /// Calls the function with its parameters borrowed from the map.
+    fn invoke(self, map: &'a mut AnyMap) -> Result<Self::Output, InvokeError>;
+
+    /// Returns the ID and name of the type of each parameter, and how it refers to the value.
+    #[inline]
+    fn params() -> Vec<(TypeId, &'static str, Access)> {
+        Vec::new()
+    }
+}
+
+macro_rules! impl_invoke {
+    ($($t:ident $value:ident),+) => {
+        impl<'a, F, R, $($t: Param<'a>),+> Invoke<'a, ($($t,)+)> for F
+        where F: FnOnce($($t),+) -> R {
+            type Output = R;
+
+            fn invoke(self, map: &'a mut AnyMap) -> Result<R, InvokeError> {
+                let ids = [$(<$t as Param<'a>>::type_id()),+];
+                let names = [$(<$t as Param<'a>>::type_name()),+];
+                for (i, id) in ids.iter().enumerate() {
+                    if ids[..i].contains(id) {
+                        return Err(InvokeError::Conflict(names[i]));
+                    }
+                }
+
+                $(let mut $value = None;)+
+                for (id, value) in map.data.iter_mut() {
+                    let value: &'a mut dyn Any = &mut **value;
+                    $(if *id == <$t as Param<'a>>::type_id() {
+                        $value = Some(value);
+                        continue;
+                    })+
+                }
+
+                let mut missing = Vec::new();
+                $(if $value.is_none() {
+                    missing.push(<$t as Param<'a>>::type_name());
+                })+
+                if !missing.is_empty() {
+                    return Err(InvokeError::Missing(missing));
+                }
+                Ok(self($(<$t as Param<'a>>::from_any($value.unwrap())),+))
+            }
+
+            fn params() -> Vec<(TypeId, &'static str, Access)> {
+                vec![$((<$t as Param<'a>>::type_id(), <$t as Param<'a>>::type_name(),
+                        <$t as Param<'a>>::access())),+]
+            }
+        }
+    }
+}
+
+impl_invoke!(A a);
+impl_invoke!(A a, B b);
+impl_invoke!(A a, B b, C c);
+impl_invoke!(A a, B b, C c, D d);
+impl_invoke!(A a, B b, C c, D d, E e);
+impl_invoke!(A a, B b, C c, D d, E e, G g);
+impl_invoke!(A a, B b, C c, D d, E e, G g, H h);
+impl_invoke!(A a, B b, C c, D d, E e, G g, H h, I i);
+
+/// The reasons a function may fail to be invoked.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum InvokeError {
+    /// The map has no values of the named types, which are parameter types of the function.
+    Missing(Vec<&'static str>),
+    /// The named type is the type of more than one parameter, which cannot be borrowed at once.
+    Conflict(&'static str),
+}
+
+impl fmt::Display for InvokeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvokeError::Missing(ref names) =>
+                write!(f, "the map has no values of the types {}", names.join(", ")),
+            InvokeError::Conflict(name) =>
+                write!(f, "more than one parameter refers to the type {}", name),
+        }
+    }
+}
+
+impl Error for InvokeError {
+    fn description(&self) -> &str {
+        match *self {
+            InvokeError::Missing(_) => "the map lacks values for parameters",
+            InvokeError::Conflict(_) => "parameters refer to the same type",
+        }
+    }
+}
+
+#[test]
+fn test_invoke() {
+    let mut map = AnyMap::new();
+    let _ = map.insert(2u8);
+    let _ = map.insert(10u16);
+    assert_eq!(map.invoke(|n: &u8| *n * 2), Ok(4));
+    assert_eq!(map.invoke(|n: &u8, total: &mut u16| *total += *n as u16), Ok(()));
+    assert_eq!(map.get::<u16>(), Some(&12));
+
+    assert_eq!(map.invoke(|_: &u8, _: &mut i32, _: &i64| ()),
+               Err(InvokeError::Missing(vec![type_name::<i32>(), type_name::<i64>()])));
+    assert_eq!(map.invoke(|_: &u8, _: &mut u8| ()),
+               Err(InvokeError::Conflict(type_name::<u8>())));
+    assert_eq!(map.invoke(|n: Changed<u8>| *n + 1), Ok(3));
+    assert_eq!(map.invoke(|_: &u8, _: Changed<u8>| ()),
+               Err(InvokeError::Conflict(type_name::<u8>())));
+    let params = <fn(&u8, &mut u16, Changed<i8>) as Invoke<_>>::params();
+    assert_eq!(params.iter().map(|param| param.2).collect::<Vec<_>>(),
+               vec![Access::Shared, Access::Mut, Access::Changed]);
+}
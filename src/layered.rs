@@ -0,0 +1,357 @@
+//! A layered `AnyMap`, where lookups fall through to a chain of parent maps.
+
+use std::any::{Any, TypeId};
+use std::collections::HashSet;
+use std::fmt;
+
+use {AnyMap, TypeIdBuildHasher, type_name};
+use like::AnyMapLike;
+
+/// An `AnyMap` layered over a chain of parent maps.
+///
+/// Reads look in the local layer first and then fall through to the parent (and its parent, and
+/// so on) when the local layer lacks the type; writes always go to the local layer, so parents
+/// are never modified. This models things like application defaults, overridden per route,
+/// overridden again per request:
+///
+/// ```rust
+/// # use anymap::layered::LayeredAnyMap;
+/// let mut app = LayeredAnyMap::new();
+/// app.insert(1i32);
+/// app.insert("app");
+/// let mut route = app.child();
+/// route.insert("route");
+/// let mut request = route.child();
+/// request.insert(3i32);
+/// assert_eq!(request.get::<i32>(), Some(&3));
+/// assert_eq!(request.get::<&str>(), Some(&"route"));
+/// assert_eq!(route.get::<i32>(), Some(&1));
+/// ```
+pub struct LayeredAnyMap<'a> {
+    local: AnyMap,
+    parent: Option<&'a LayeredAnyMap<'a>>,
+    label: Option<String>,
+}
+
+impl<'a> Default for LayeredAnyMap<'a> {
+    #[inline]
+    fn default() -> LayeredAnyMap<'a> {
+        LayeredAnyMap::new()
+    }
+}
+
+impl<'a> LayeredAnyMap<'a> {
+    /// Construct a new root layer, with no parent.
+    #[inline]
+    pub fn new() -> LayeredAnyMap<'a> {
+        LayeredAnyMap::from_map(AnyMap::new())
+    }
+
+    /// Construct a new root layer from an existing `AnyMap`.
+    #[inline]
+    pub fn from_map(map: AnyMap) -> LayeredAnyMap<'a> {
+        LayeredAnyMap {
+            local: map,
+            parent: None,
+            label: None,
+        }
+    }
+
+    /// Construct a new, empty layer on top of `parent`.
+    #[inline]
+    pub fn with_parent(parent: &'a LayeredAnyMap<'a>) -> LayeredAnyMap<'a> {
+        LayeredAnyMap {
+            local: AnyMap::new(),
+            parent: Some(parent),
+            label: None,
+        }
+    }
+
+    /// Construct a new, empty layer on top of this one.
+    #[inline]
+    pub fn child(&self) -> LayeredAnyMap<'_> {
+        LayeredAnyMap::with_parent(self)
+    }
+
+    /// Returns the layer this one falls through to, if any.
+    #[inline]
+    pub fn parent(&self) -> Option<&'a LayeredAnyMap<'a>> {
+        self.parent
+    }
+
+    /// Returns the label of this layer, if it has one.
+    #[inline]
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Labels this layer, for `explain` to name it by.
+    #[inline]
+    pub fn set_label<S: Into<String>>(&mut self, label: S) {
+        self.label = Some(label.into());
+    }
+
+    /// Returns the number of parent layers below this one.
+    pub fn depth(&self) -> usize {
+        self.layers().count() - 1
+    }
+
+    /// Returns the local layer.
+    #[inline]
+    pub fn local(&self) -> &AnyMap {
+        &self.local
+    }
+
+    /// Returns the local layer mutably.
+    #[inline]
+    pub fn local_mut(&mut self) -> &mut AnyMap {
+        &mut self.local
+    }
+
+    /// Unwraps the local layer, detaching it from its parents.
+    #[inline]
+    pub fn into_local(self) -> AnyMap {
+        self.local
+    }
+
+    /// Returns a reference to the value for the type `T` from the nearest layer which has one.
+    pub fn get<T: Any + 'static>(&self) -> Option<&T> {
+        self.layers().filter_map(|layer| layer.local.get::<T>()).next()
+    }
+
+    /// Returns true if any layer contains a value of type `T`.
+    pub fn contains<T: Any + 'static>(&self) -> bool {
+        self.layers().any(|layer| layer.local.contains::<T>())
+    }
+
+    /// Explains where the value for the type `T` comes from: which layer supplies it, and which
+    /// have values of their own which it shadows, or that no layer has one.
+    ///
+    /// ```rust
+    /// # use anymap::layered::LayeredAnyMap;
+    /// struct Timeout(u32);
+    ///
+    /// let mut defaults = LayeredAnyMap::new();
+    /// defaults.set_label("defaults");
+    /// defaults.insert(Timeout(30));
+    /// let mut route = defaults.child();
+    /// route.set_label("route");
+    /// route.insert(Timeout(5));
+    /// let request = route.child();
+    ///
+    /// let explanation = request.explain::<Timeout>();
+    /// assert_eq!(explanation.supplier(), Some(1));
+    /// assert_eq!(explanation.supplier_label(), Some("route"));
+    /// assert!(explanation.to_string().ends_with("supplied by layer 1 (route), shadowing layer 2 \
+    ///                                            (defaults)"));
+    /// assert!(request.explain::<u8>().is_absent());
+    /// ```
+    pub fn explain<T: Any + 'static>(&self) -> Explanation<'_> {
+        Explanation {
+            type_name: type_name::<T>(),
+            layers: self.layers()
+                .map(|layer| (layer.label(), layer.local.contains::<T>()))
+                .collect(),
+        }
+    }
+
+    /// Returns a mutable reference to the value for the type `T` in the local layer.
+    /// Values in parent layers are never made available mutably.
+    #[inline]
+    pub fn get_mut<T: Any + 'static>(&mut self) -> Option<&mut T> {
+        self.local.get_mut::<T>()
+    }
+
+    /// Sets the value for the type `T` in the local layer, shadowing any parent’s value.
+    /// If the local layer already had a value of type `T`, that value is returned.
+    #[inline]
+    pub fn insert<T: Any + 'static>(&mut self, value: T) -> Option<T> {
+        self.local.insert(value)
+    }
+
+    /// Removes the `T` value from the local layer, returning it if there was one.
+    /// A parent’s value, if any, becomes visible again.
+    #[inline]
+    pub fn remove<T: Any + 'static>(&mut self) -> Option<T> {
+        self.local.remove::<T>()
+    }
+
+    /// An iterator over this layer and then each of its parents in turn.
+    fn layers(&self) -> Layers<'_> {
+        Layers {
+            next: Some(self),
+        }
+    }
+}
+
+/// Lookups through `AnyMapLike` fall through to the parents, and changes go to the local layer,
+/// as with the typed methods; the length is that of the types in any layer.
+impl<'a> AnyMapLike for LayeredAnyMap<'a> {
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.layers().any(|layer| layer.local.contains_id(id))
+    }
+
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.layers().filter_map(|layer| layer.local.get_raw(id)).next()
+    }
+
+    #[inline]
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        self.local.get_raw_mut(id)
+    }
+
+    #[inline]
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        self.local.insert_raw(value)
+    }
+
+    #[inline]
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        self.local.remove_raw(id)
+    }
+
+    fn len(&self) -> usize {
+        let mut types: HashSet<TypeId, _> = HashSet::with_hasher(TypeIdBuildHasher);
+        for layer in self.layers() {
+            types.extend(layer.local.data.keys());
+        }
+        types.len()
+    }
+}
+
+/// Iterator over the layers of a `LayeredAnyMap`, from the top down.
+struct Layers<'a> {
+    next: Option<&'a LayeredAnyMap<'a>>,
+}
+
+impl<'a> Iterator for Layers<'a> {
+    type Item = &'a LayeredAnyMap<'a>;
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a LayeredAnyMap<'a>> {
+        self.next.inspect(|layer| {
+            self.next = layer.parent;
+        })
+    }
+}
+
+/// Where the value of a type in a `LayeredAnyMap` comes from, as reported by `explain`.
+///
+/// Layers are numbered from the one explained, which is layer 0, down through its parents.
+/// Displayed, it reads as, for example, “u16: supplied by layer 1 (route), shadowing layer 2”.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Explanation<'a> {
+    type_name: &'static str,
+    /// The label of each layer, and whether it has a value.
+    layers: Vec<(Option<&'a str>, bool)>,
+}
+
+impl<'a> Explanation<'a> {
+    /// Returns the number of the layer which supplies the value, if any does.
+    pub fn supplier(&self) -> Option<usize> {
+        self.layers.iter().position(|&(_, present)| present)
+    }
+
+    /// Returns the label of the layer which supplies the value, if any does and it has a label.
+    pub fn supplier_label(&self) -> Option<&'a str> {
+        self.supplier().and_then(|layer| self.layers[layer].0)
+    }
+
+    /// Returns the numbers of the layers below the supplier which have values of their own,
+    /// shadowed by the supplier's.
+    pub fn shadowed(&self) -> Vec<usize> {
+        self.layers.iter().enumerate()
+            .filter(|&(_, &(_, present))| present)
+            .map(|(layer, _)| layer)
+            .skip(1)
+            .collect()
+    }
+
+    /// Returns true if no layer has a value.
+    #[inline]
+    pub fn is_absent(&self) -> bool {
+        self.supplier().is_none()
+    }
+
+    /// Returns the number of layers searched.
+    #[inline]
+    pub fn layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Writes the number and label of a layer.
+    fn write_layer(&self, f: &mut fmt::Formatter, layer: usize) -> fmt::Result {
+        write!(f, "layer {}", layer)?;
+        match self.layers[layer].0 {
+            Some(label) => write!(f, " ({})", label),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> fmt::Display for Explanation<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.type_name)?;
+        let supplier = match self.supplier() {
+            Some(supplier) => supplier,
+            None => return write!(f, "absent from all {} layers", self.layers.len()),
+        };
+        f.write_str("supplied by ")?;
+        self.write_layer(f, supplier)?;
+        for (i, layer) in self.shadowed().into_iter().enumerate() {
+            f.write_str(if i == 0 { ", shadowing " } else { ", " })?;
+            self.write_layer(f, layer)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_layered() {
+    let mut app = LayeredAnyMap::new();
+    let _ = app.insert(1i32);
+    let _ = app.insert(1u8);
+    let mut route = app.child();
+    let _ = route.insert(2i32);
+    let mut request = route.child();
+    assert_eq!(request.depth(), 2);
+    assert_eq!(request.get::<i32>(), Some(&2));
+    assert_eq!(request.get::<u8>(), Some(&1));
+    assert!(request.get_mut::<i32>().is_none());
+    assert!(!request.contains::<u16>());
+
+    let _ = request.insert(3i32);
+    assert_eq!(request.get::<i32>(), Some(&3));
+    assert_eq!(request.remove::<i32>(), Some(3));
+    assert_eq!(request.get::<i32>(), Some(&2));
+    assert_eq!(request.remove::<u8>(), None);
+    assert!(request.local().is_empty());
+}
+
+#[test]
+fn test_explain() {
+    let mut app = LayeredAnyMap::new();
+    app.set_label("app");
+    let _ = app.insert(1i32);
+    let _ = app.insert(1u8);
+    let mut route = app.child();
+    let _ = route.insert(2i32);
+    let mut request = route.child();
+    request.set_label("request");
+    let _ = request.insert(3i32);
+    assert_eq!((route.label(), request.label()), (None, Some("request")));
+
+    let int = request.explain::<i32>();
+    assert_eq!((int.supplier(), int.supplier_label()), (Some(0), Some("request")));
+    assert_eq!(int.shadowed(), vec![1, 2]);
+    assert_eq!(int.to_string(), format!("{}: supplied by layer 0 (request), shadowing layer 1, \
+                                         layer 2 (app)", type_name::<i32>()));
+    let byte = route.explain::<u8>();
+    assert_eq!((byte.supplier(), byte.supplier_label()), (Some(1), Some("app")));
+    assert!(byte.shadowed().is_empty());
+    let missing = request.explain::<u16>();
+    assert!(missing.is_absent() && missing.supplier_label().is_none());
+    assert_eq!(missing.layers(), 3);
+    assert_eq!(missing.to_string(), "u16: absent from all 3 layers");
+}
@@ -1,111 +1,400 @@
 //! This crate provides the `AnyMap` type, a safe and convenient store for one value of each type.
+//!
+//! Values are stored under their types’ IDs, so a value taken out is always known to be of the
+//! type asked for and is downcast without checking. With the `forbid-unsafe` feature, the crate
+//! instead contains no unsafe code at all, downcasting with the checked methods of `Any` at a
+//! small cost.
+//!
+//! The crate builds on stable Rust. The benchmarks need the unstable `test` crate, and so are
+//! only built with the `bench` feature, on a nightly compiler.
 
 #![warn(unused_qualifications, non_upper_case_globals,
-        variant_size_differences, unused_typecasts,
+        variant_size_differences,
         missing_docs, unused_results)]
-#![allow(unstable)]
+#![cfg_attr(feature = "forbid-unsafe", forbid(unsafe_code))]
+#![cfg_attr(feature = "bench", feature(test))]
 
-#[cfg(test)]
+#[cfg(all(test, feature = "bench"))]
 extern crate test;
-
+#[cfg(feature = "metrics")]
+#[macro_use]
+extern crate metrics;
+#[cfg(feature = "quickcheck")]
+extern crate quickcheck;
+#[cfg(feature = "tracing")]
+#[macro_use]
+extern crate tracing;
+#[cfg(feature = "zeroize")]
+extern crate zeroize;
+
+use std::alloc::Layout;
 use std::any::{Any, TypeId};
-use std::mem::forget;
+use std::fmt;
 use std::collections::HashMap;
 use std::collections::hash_map;
-use std::hash::{Hasher, Writer};
-use std::collections::hash_state::HashState;
-use std::mem::transmute;
-use std::raw::TraitObject;
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut, Index};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+#[cfg(not(feature = "forbid-unsafe"))]
+use any::UncheckedAnyExt;
+use bundle::Bundle;
+use instrument::{Event, Instruments};
+use invoke::{Invoke, InvokeError};
+#[cfg(feature = "stats")]
+pub use instrument::TypeStats;
+
+/// Creates an `AnyMap` containing the given values, each under its own type.
+///
+/// Where two of the values are of the same type, the later one is kept.
+///
+/// ```rust
+/// # #[macro_use] extern crate anymap;
+/// # fn main() {
+/// let data = anymap![42u32, "hello", vec![1u8, 2, 3]];
+/// assert_eq!(data.get::<u32>(), Some(&42));
+/// assert_eq!(data.get::<&str>(), Some(&"hello"));
+/// assert_eq!(data.len(), 3);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! anymap {
+    () => ($crate::AnyMap::new());
+    ($($value:expr),+ $(,)*) => {{
+        let mut map = $crate::AnyMap::new();
+        $(let _ = map.insert($value);)+
+        map
+    }};
+}
+
+/// Declares an enum of references to values of a list of types, with a variant `Other` for
+/// values of any other type, and methods to produce it from the values of an `AnyMap`.
+///
+/// Each variant is given with the type it refers to, and the enum must have exactly one
+/// lifetime parameter. The enum gets two methods: `from_any`, which converts a reference to a
+/// value to the variant for its type, and `iter`, which returns an iterator over the values in a
+/// map, so converted.
+///
+/// ```rust
+/// # #[macro_use] extern crate anymap;
+/// # fn main() {
+/// struct Config { verbose: bool }
+/// struct Stats { requests: u32 }
+///
+/// typed_iter! {
+///     enum Entry<'a> {
+///         Config(Config),
+///         Stats(Stats),
+///     }
+/// }
+///
+/// let data = anymap![Config { verbose: true }, Stats { requests: 3 }, 0u8];
+/// let mut others = 0;
+/// for entry in Entry::iter(&data) {
+///     match entry {
+///         Entry::Config(config) => assert!(config.verbose),
+///         Entry::Stats(stats) => assert_eq!(stats.requests, 3),
+///         Entry::Other(_) => others += 1,
+///     }
+/// }
+/// assert_eq!(others, 1);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! typed_iter {
+    ($(#[$attr:meta])* pub enum $name:ident<$lt:lifetime> { $($variant:ident($t:ty)),+ $(,)* }) => {
+        $(#[$attr])*
+        pub enum $name<$lt> {
+            $($variant(&$lt $t),)+
+            /// A value of a type not listed, with the ID of its type.
+            Other(::std::any::TypeId),
+        }
+
+        typed_iter!(@impl $name<$lt> { $($variant($t)),+ });
+    };
+    ($(#[$attr:meta])* enum $name:ident<$lt:lifetime> { $($variant:ident($t:ty)),+ $(,)* }) => {
+        $(#[$attr])*
+        enum $name<$lt> {
+            $($variant(&$lt $t),)+
+            Other(::std::any::TypeId),
+        }
+
+        typed_iter!(@impl $name<$lt> { $($variant($t)),+ });
+    };
+    (@impl $name:ident<$lt:lifetime> { $($variant:ident($t:ty)),+ }) => {
+        #[allow(dead_code)]
+        impl<$lt> $name<$lt> {
+            /// Converts a reference to a value to the variant for its type.
+            pub fn from_any(value: &$lt dyn (::std::any::Any)) -> $name<$lt> {
+                $(if let Some(value) = value.downcast_ref::<$t>() {
+                    return $name::$variant(value);
+                })+
+                $name::Other(value.type_id())
+            }
+
+            /// Returns an iterator over the values in the map, as variants of this enum.
+            pub fn iter(map: &$lt $crate::AnyMap)
+                        -> ::std::iter::Map<$crate::Iter<$lt>,
+                                            fn(&$lt dyn (::std::any::Any)) -> $name<$lt>> {
+                map.iter().map($name::from_any as fn(&$lt dyn (::std::any::Any)) -> $name<$lt>)
+            }
+        }
+    };
+}
 
-struct TypeIdHasher {
+/// Creates a `TypeSet` containing the given types.
+///
+/// ```rust
+/// # #[macro_use] extern crate anymap;
+/// # fn main() {
+/// let types = type_set![u32, &str];
+/// assert!(types.contains::<u32>() && types.contains::<&str>());
+/// assert_eq!(types.len(), 2);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! type_set {
+    () => ($crate::TypeSet::new());
+    ($($t:ty),+ $(,)*) => ($crate::TypeSet::new()$(.with::<$t>())+);
+}
+
+#[cfg(not(feature = "forbid-unsafe"))]
+pub mod any;
+#[cfg(feature = "quickcheck")]
+pub mod arbitrary;
+pub mod atomic;
+pub mod bounded;
+pub mod budget;
+#[macro_use]
+pub mod bundle;
+pub mod capped;
+pub mod cell;
+pub mod clock;
+pub mod commands;
+pub mod concurrent;
+pub mod cow;
+pub mod crdt;
+pub mod defaults;
+pub mod diff;
+pub mod double_buffered;
+pub mod entity;
+#[macro_use]
+pub mod extensible;
+#[macro_use]
+pub mod facade;
+pub mod frozen;
+pub mod global;
+pub mod handlers;
+mod instrument;
+pub mod invoke;
+pub mod layered;
+pub mod like;
+pub mod lru;
+pub mod observe;
+pub mod ordered;
+pub mod plugin;
+pub mod pool;
+pub mod protect;
+pub mod provide;
+pub mod record;
+#[cfg(feature = "reflect")]
+pub mod reflect;
+pub mod refs;
+pub mod registry;
+pub mod replicate;
+pub mod resolve;
+pub mod restrict;
+pub mod schedule;
+#[cfg(not(feature = "forbid-unsafe"))]
+#[macro_use]
+pub mod scoped;
+#[cfg(feature = "zeroize")]
+pub mod sensitive;
+pub mod shared;
+pub mod slot;
+pub mod snapshot;
+pub mod sync;
+pub mod task_local;
+#[macro_use]
+pub mod testing;
+pub mod thread_local;
+pub mod timed;
+pub mod ttl;
+pub mod vec;
+#[macro_use]
+pub mod view;
+pub mod weak;
+
+/// A hasher for `TypeId`s, which are already well distributed and need no further hashing.
+///
+/// It must not be used for any other keys, as it assumes that what is written is already a hash.
+pub struct TypeIdHasher {
     value: u64,
 }
 
-struct TypeIdState;
+/// A `BuildHasher` creating `TypeIdHasher`s, for maps keyed by `TypeId` as fast as `AnyMap`.
+///
+/// ```rust
+/// # use anymap::TypeIdBuildHasher;
+/// # use std::any::TypeId;
+/// # use std::collections::HashMap;
+/// let mut names = HashMap::with_hasher(TypeIdBuildHasher);
+/// names.insert(TypeId::of::<i32>(), "i32");
+/// assert_eq!(names.get(&TypeId::of::<i32>()), Some(&"i32"));
+/// ```
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TypeIdBuildHasher;
 
-impl HashState for TypeIdState {
+impl BuildHasher for TypeIdBuildHasher {
     type Hasher = TypeIdHasher;
 
-    fn hasher(&self) -> TypeIdHasher {
+    fn build_hasher(&self) -> TypeIdHasher {
         TypeIdHasher { value: 0 }
     }
 }
 
-impl Writer for TypeIdHasher {
+impl Hasher for TypeIdHasher {
     #[inline(always)]
     fn write(&mut self, bytes: &[u8]) {
-        // This expects to receive one and exactly one 64-bit value
-        debug_assert!(bytes.len() == 8);
-        unsafe {
-            std::ptr::copy_nonoverlapping_memory(&mut self.value,
-                                                 transmute(&bytes[0]),
-                                                 1)
+        // A `TypeId` is already a good hash, so it need only be folded down to 64 bits, whether
+        // it arrives as one 64-bit write (and is thus taken as is) or as writes of other lengths.
+        for chunk in bytes.chunks(8) {
+            let word = chunk.iter().rev().fold(0, |word, &byte| word << 8 | byte as u64);
+            self.value = self.value.rotate_left(32) ^ word;
         }
     }
+
+    #[inline(always)]
+    fn write_u64(&mut self, word: u64) {
+        self.value = self.value.rotate_left(32) ^ word;
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 { self.value }
 }
 
-impl Hasher for TypeIdHasher {
-    type Output = u64;
+/// The raw parts of an `AnyMap`: its values, each filed under the ID of its own type.
+pub type RawAnyMap = HashMap<TypeId, Box<dyn Any + 'static>, TypeIdBuildHasher>;
 
-    fn reset(&mut self) { }
+/// A callback invoked with each value evicted from one of the bounded map flavours.
+type EvictionCallback = Box<dyn FnMut(TypeId, Box<dyn Any + 'static>) + 'static>;
 
-    fn finish(&self) -> u64 { self.value }
+/// A panic of a callback, caught by the collection which invoked it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CallbackPanic {
+    /// The type of the entry the callback was invoked with.
+    pub type_id: TypeId,
+    /// The message the callback panicked with, if it was a string.
+    pub message: Option<String>,
 }
 
-/// An extension of `AnyRefExt` allowing unchecked downcasting of trait objects to `&T`.
-trait UncheckedAnyRefExt<'a> {
-    /// Returns a reference to the boxed value, assuming that it is of type `T`. This should only be
-    /// called if you are ABSOLUTELY CERTAIN of `T` as you will get really wacky output if it’s not.
-    unsafe fn downcast_ref_unchecked<T: 'static>(self) -> &'a T;
+/// Returns the message a panic was raised with, if it was a string.
+fn panic_message(payload: &(dyn Any + Send)) -> Option<String> {
+    payload.downcast_ref::<&str>().map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
 }
 
-impl<'a> UncheckedAnyRefExt<'a> for &'a Any {
-    #[inline]
-    unsafe fn downcast_ref_unchecked<T: 'static>(self) -> &'a T {
-        // Get the raw representation of the trait object
-        let to: TraitObject = transmute(self);
+/// Passes an evicted value to the eviction callback, if there is one. A callback which panics
+/// is unregistered, so that whatever state it was left in is never seen, and its panic recorded.
+fn call_on_evict(on_evict: &mut Option<EvictionCallback>, panics: &mut Vec<CallbackPanic>,
+                 id: TypeId, value: Box<dyn Any + 'static>) {
+    let payload = match *on_evict {
+        Some(ref mut callback) => {
+            match panic::catch_unwind(AssertUnwindSafe(|| callback(id, value))) {
+                Ok(()) => return,
+                Err(payload) => payload,
+            }
+        },
+        None => return,
+    };
+    *on_evict = None;
+    panics.push(CallbackPanic { type_id: id, message: panic_message(&*payload) });
+}
+
+/// Returns the name of the type `T`, for use in diagnostics.
+#[inline]
+fn type_name<T: ?Sized>() -> &'static str {
+    std::any::type_name::<T>()
+}
+
+/// The name recorded in type sets for types only ever seen by `TypeId`.
+const UNKNOWN_TYPE: &str = "<unknown type>";
 
-        // Extract the data pointer
-        transmute(to.data)
+/// With debug assertions enabled, checks that a value about to be downcast without checking is
+/// really of type `T`, panicking if not.
+///
+/// The maps only ever file a value under its own type’s ID, so this should never fail; if it
+/// does, a loud panic naming the types beats the memory unsafety which would follow.
+#[cfg(not(feature = "forbid-unsafe"))]
+#[inline]
+fn audit_downcast<T: 'static>(found: TypeId) {
+    if cfg!(debug_assertions) && found != TypeId::of::<T>() {
+        invariant_violated::<T>(found)
     }
 }
 
-/// An extension of `AnyMutRefExt` allowing unchecked downcasting of trait objects to `&mut T`.
-trait UncheckedAnyMutRefExt<'a> {
-    /// Returns a reference to the boxed value, assuming that it is of type `T`. This should only be
-    /// called if you are ABSOLUTELY CERTAIN of `T` as you will get really wacky output if it’s not.
-    unsafe fn downcast_mut_unchecked<T: 'static>(self) -> &'a mut T;
+/// Panics at finding a stored value of type `found` where one of type `T` should have been.
+#[cold]
+fn invariant_violated<T: 'static>(found: TypeId) -> ! {
+    panic!("anymap invariant violated: expected a value of type {}, found one with type ID {:?}",
+           type_name::<T>(), found)
 }
 
-impl<'a> UncheckedAnyMutRefExt<'a> for &'a mut Any {
-    #[inline]
-    unsafe fn downcast_mut_unchecked<T: 'static>(self) -> &'a mut T {
-        // Get the raw representation of the trait object
-        let to: TraitObject = transmute(self);
+/// Downcasts a reference to a stored value, which must be of type `T`.
+#[cfg(not(feature = "forbid-unsafe"))]
+#[inline]
+fn cast_ref<T: 'static>(any: &dyn Any) -> &T {
+    unsafe { UncheckedAnyExt::downcast_ref_unchecked::<T>(any) }
+}
 
-        // Extract the data pointer
-        transmute(to.data)
-    }
+/// Downcasts a mutable reference to a stored value, which must be of type `T`.
+#[cfg(not(feature = "forbid-unsafe"))]
+#[inline]
+fn cast_mut<T: 'static>(any: &mut dyn Any) -> &mut T {
+    unsafe { UncheckedAnyExt::downcast_mut_unchecked::<T>(any) }
 }
 
-/// An extension of `BoxAny` allowing unchecked downcasting of trait objects to `Box<T>`.
-trait UncheckedBoxAny {
-    /// Returns the boxed value, assuming that it is of type `T`. This should only be called if you
-    /// are ABSOLUTELY CERTAIN of `T` as you will get really wacky output if it’s not.
-    unsafe fn downcast_unchecked<T: 'static>(self) -> Box<T>;
+/// Downcasts a stored value, which must be of type `T`.
+#[cfg(not(feature = "forbid-unsafe"))]
+#[inline]
+fn cast_box<T: 'static>(any: Box<dyn Any + 'static>) -> Box<T> {
+    unsafe { UncheckedAnyExt::downcast_unchecked::<T>(any) }
 }
 
-impl UncheckedBoxAny for Box<Any + 'static> {
-    #[inline]
-    unsafe fn downcast_unchecked<T: 'static>(self) -> Box<T> {
-        // Get the raw representation of the trait object
-        let to: TraitObject = *transmute::<&Box<Any>, &TraitObject>(&self);
+/// Downcasts a reference to a stored value, which must be of type `T`.
+#[cfg(feature = "forbid-unsafe")]
+#[inline]
+fn cast_ref<T: 'static>(any: &dyn Any) -> &T {
+    match any.downcast_ref::<T>() {
+        Some(value) => value,
+        None => invariant_violated::<T>(any.type_id()),
+    }
+}
 
-        // Prevent destructor on self being run
-        forget(self);
+/// Downcasts a mutable reference to a stored value, which must be of type `T`.
+#[cfg(feature = "forbid-unsafe")]
+#[inline]
+fn cast_mut<T: 'static>(any: &mut dyn Any) -> &mut T {
+    let found = (*any).type_id();
+    match any.downcast_mut::<T>() {
+        Some(value) => value,
+        None => invariant_violated::<T>(found),
+    }
+}
 
-        // Extract the data pointer
-        transmute(to.data)
+/// Downcasts a stored value, which must be of type `T`.
+#[cfg(feature = "forbid-unsafe")]
+#[inline]
+fn cast_box<T: 'static>(any: Box<dyn Any + 'static>) -> Box<T> {
+    match any.downcast::<T>() {
+        Ok(value) => value,
+        Err(any) => invariant_violated::<T>((*any).type_id()),
     }
 }
 
@@ -121,7 +410,7 @@ impl UncheckedBoxAny for Box<Any + 'static> {
 /// data.remove::<i32>();
 /// assert_eq!(data.get::<i32>(), None);
 ///
-/// #[derive(PartialEq, Show)]
+/// #[derive(PartialEq, Debug)]
 /// struct Foo {
 ///     str: String,
 /// }
@@ -134,33 +423,48 @@ impl UncheckedBoxAny for Box<Any + 'static> {
 /// ```
 ///
 /// Values containing non-static references are not permitted.
-#[stable]
+///
+/// `AnyMap` is neither `UnwindSafe` nor `RefUnwindSafe`, since the values it holds need not be:
+/// a value with interior mutability left half-updated by a panic could otherwise be observed
+/// through a map carried across `catch_unwind`. Wrap the map in `AssertUnwindSafe` where its
+/// values are known to be safe; `ConcurrentAnyMap` is `RefUnwindSafe` already, as its lock is
+/// poisoned by a panic while it is held.
 pub struct AnyMap {
-    data: HashMap<TypeId, Box<Any + 'static>, TypeIdState>,
+    data: HashMap<TypeId, Box<dyn Any + 'static>, TypeIdBuildHasher>,
+    instruments: Instruments,
+    shrink_threshold: Option<f64>,
+}
+
+impl Default for AnyMap {
+    #[inline]
+    fn default() -> AnyMap {
+        AnyMap::new()
+    }
 }
 
 impl AnyMap {
     /// Construct a new `AnyMap`.
     #[inline]
-    #[stable]
     pub fn new() -> AnyMap {
         AnyMap {
-            data: HashMap::with_hash_state(TypeIdState),
+            data: HashMap::with_hasher(TypeIdBuildHasher),
+            instruments: Instruments::new(),
+            shrink_threshold: None,
         }
     }
 
     /// Creates an empty AnyMap with the given initial capacity.
     #[inline]
-    #[stable]
     pub fn with_capcity(capacity: usize) -> AnyMap {
         AnyMap {
-            data: HashMap::with_capacity_and_hash_state(capacity, TypeIdState),
+            data: HashMap::with_capacity_and_hasher(capacity, TypeIdBuildHasher),
+            instruments: Instruments::new(),
+            shrink_threshold: None,
         }
     }
 
     /// Returns the number of elements the collection can hold without reallocating.
     #[inline]
-    #[stable]
     pub fn capacity(&self) -> usize {
         self.data.capacity()
     }
@@ -173,7 +477,6 @@ impl AnyMap {
     ///
     /// Panics if the new allocation size overflows `usize`.
     #[inline]
-    #[stable]
     pub fn reserve(&mut self, additional: usize) {
         self.data.reserve(additional)
     }
@@ -182,145 +485,696 @@ impl AnyMap {
     /// down as much as possible while maintaining the internal rules
     /// and possibly leaving some space in accordance with the resize policy.
     #[inline]
-    #[stable]
     pub fn shrink_to_fit(&mut self) {
         self.data.shrink_to_fit()
     }
 
+    /// Returns the occupancy below which the collection releases its excess capacity, if it
+    /// has been set by `set_shrink_threshold`.
+    #[inline]
+    pub fn shrink_threshold(&self) -> Option<f64> {
+        self.shrink_threshold
+    }
+
+    /// Sets the occupancy, as a fraction of the capacity, below which the collection releases
+    /// its excess capacity, as by `shrink_to_fit`, whenever values are removed from it; or, if
+    /// `None`, the default, keeps the capacity until `shrink_to_fit` is called.
+    ///
+    /// This is for long-lived maps which once held many values and now hold few. With a
+    /// threshold set, `clear` releases the allocated memory rather than keeping it for reuse.
+    /// Values taken out through `drain` or an `Entry` do not count until the next removal.
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// let mut data = AnyMap::with_capcity(100);
+    /// data.set_shrink_threshold(Some(0.25));
+    /// data.insert(1u8);
+    /// data.insert(2u16);
+    /// data.remove::<u16>();
+    /// assert!(data.capacity() < 100);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the threshold is not greater than zero and at most one.
+    pub fn set_shrink_threshold(&mut self, threshold: Option<f64>) {
+        if let Some(threshold) = threshold {
+            assert!(threshold > 0.0 && threshold <= 1.0,
+                    "the shrink threshold must be in the range (0, 1]");
+        }
+        self.shrink_threshold = threshold;
+        self.shrink_if_sparse();
+    }
+
+    /// Releases the excess capacity if the occupancy is below the shrink threshold.
+    fn shrink_if_sparse(&mut self) {
+        if let Some(threshold) = self.shrink_threshold {
+            if (self.data.len() as f64) < self.data.capacity() as f64 * threshold {
+                self.data.shrink_to_fit();
+            }
+        }
+    }
+
     /// An iterator visiting all items in the collection in arbitrary order.
-    /// Iterator element type is `&Any`.
+    /// Iterator element type is `&dyn Any`.
     ///
     /// This is probably not a great deal of use.
     #[inline]
-    #[stable]
-    pub fn iter(&self) -> Iter {
+    pub fn iter(&self) -> Iter<'_> {
         Iter {
             inner: self.data.iter(),
         }
     }
 
     /// An iterator visiting all items in the collection in arbitrary order.
-    /// Iterator element type is `&mut Any`.
+    /// Iterator element type is `&mut dyn Any`.
     ///
     /// This is probably not a great deal of use.
     #[inline]
-    #[stable]
-    pub fn iter_mut(&mut self) -> IterMut {
+    pub fn iter_mut(&mut self) -> IterMut<'_> {
         IterMut {
             inner: self.data.iter_mut(),
         }
     }
 
-    /// An iterator visiting all items in the collection in arbitrary order.
-    /// Creates a consuming iterator, that is, one that moves each item
-    /// out of the map in arbitrary order. The map cannot be used after
-    /// calling this.
+    /// Decomposes the collection into its raw parts, which `from_raw_parts` can reassemble.
     ///
-    /// Iterator element type is `Box<Any>`.
+    /// Any statistics collected for the collection are lost.
     #[inline]
-    #[stable]
-    pub fn into_iter(self) -> IntoIter {
-        IntoIter {
-            inner: self.data.into_iter(),
+    pub fn into_raw_parts(self) -> RawAnyMap {
+        self.data
+    }
+
+    /// Reassembles a collection from its raw parts, checking that every value is filed under the
+    /// ID of its own type. If one is not, the raw parts are handed back.
+    pub fn from_raw_parts(raw: RawAnyMap) -> Result<AnyMap, RawAnyMap> {
+        if raw.iter().all(|(&id, value)| (**value).type_id() == id) {
+            Ok(AnyMap::from_raw_parts_trusted(raw))
+        } else {
+            Err(raw)
         }
     }
 
+    /// Reassembles a collection from its raw parts without checking them.
+    ///
+    /// Not available with the `forbid-unsafe` feature.
+    ///
+    /// # Safety
+    ///
+    /// This is undefined behaviour unless every value is filed under the ID of its own type.
+    #[cfg(not(feature = "forbid-unsafe"))]
+    #[inline]
+    pub unsafe fn from_raw_parts_unchecked(raw: RawAnyMap) -> AnyMap {
+        AnyMap::from_raw_parts_trusted(raw)
+    }
+
+    fn from_raw_parts_trusted(raw: RawAnyMap) -> AnyMap {
+        let mut map = AnyMap {
+            data: raw,
+            instruments: Instruments::new(),
+            shrink_threshold: None,
+        };
+        let len = map.data.len();
+        map.instruments.resized(len);
+        map
+    }
+
+    /// Leaks the collection, so that it and the values in it live for the rest of the program.
+    ///
+    /// This is for maps assembled once, at startup, and read until the process exits: their
+    /// values can then be borrowed for `'static` and passed around freely.
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// let mut config = AnyMap::new();
+    /// config.insert(8080u16);
+    /// let config = config.leak();
+    /// let port: &'static u16 = config.get().unwrap();
+    /// assert_eq!(*port, 8080);
+    /// ```
+    #[inline]
+    pub fn leak(self) -> &'static AnyMap {
+        Box::leak(Box::new(self))
+    }
+
     /// Returns a reference to the value stored in the collection for the type `T`, if it exists.
-    #[stable]
     pub fn get<T: Any + 'static>(&self) -> Option<&T> {
-        self.data.get(&TypeId::of::<T>())
-            .map(|any| unsafe { any.downcast_ref_unchecked::<T>() })
+        let value = self.data.get(&TypeId::of::<T>())
+            .map(|any| cast_ref::<T>(&**any));
+        self.instruments.lookup::<T>(value.is_some());
+        value
+    }
+
+    /// Returns a reference to the value stored in the collection for the type `T`, or an error
+    /// naming the type if there is none.
+    ///
+    /// ```rust
+    /// # use anymap::{AnyMap, MissingType};
+    /// struct Session { user: &'static str }
+    ///
+    /// fn user(extensions: &AnyMap) -> Result<&'static str, MissingType> {
+    ///     Ok(extensions.try_get::<Session>()?.user)
+    /// }
+    ///
+    /// let mut extensions = AnyMap::new();
+    /// assert!(user(&extensions).unwrap_err().to_string().contains("Session"));
+    /// extensions.insert(Session { user: "admin" });
+    /// assert_eq!(user(&extensions), Ok("admin"));
+    /// ```
+    pub fn try_get<T: Any + 'static>(&self) -> Result<&T, MissingType> {
+        self.get::<T>().ok_or(MissingType { name: type_name::<T>() })
     }
 
     /// Returns a mutable reference to the value stored in the collection for the type `T`,
     /// if it exists.
-    #[stable]
     pub fn get_mut<T: Any + 'static>(&mut self) -> Option<&mut T> {
+        self.instruments.lookup::<T>(self.data.contains_key(&TypeId::of::<T>()));
         self.data.get_mut(&TypeId::of::<T>())
-            .map(|any| unsafe { any.downcast_mut_unchecked::<T>() })
+            .map(|any| cast_mut::<T>(&mut **any))
+    }
+
+    /// Returns a pointer to the value stored in the collection for the type `T`, if it exists,
+    /// for code such as a C callback layer which cannot hold a reference.
+    ///
+    /// Getting the pointer is safe; using it is not. These are the rules:
+    ///
+    /// - The pointer stays valid (each value being boxed, the map's growing does not move it)
+    ///   until the value is removed or replaced, or the map is dropped. It is then dangling.
+    /// - It is derived from a shared reference, so it may only be read through, and only while
+    ///   nothing holds a mutable reference to the value. Use `get_mut_ptr` to write.
+    #[inline]
+    pub fn get_ptr<T: Any + 'static>(&self) -> Option<NonNull<T>> {
+        self.get::<T>().map(NonNull::from)
+    }
+
+    /// Returns a pointer to the value stored in the collection for the type `T`, if it exists,
+    /// which may be written through.
+    ///
+    /// The rules are those of `get_ptr`, except that the value may also be written through the
+    /// pointer, while nothing else refers to it; in particular, until the pointer is last
+    /// used, the value must not be reached through the map again, as by `get` or `get_mut`.
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// extern "C" fn bump(counter: *mut u32) {
+    ///     unsafe { *counter += 1 }
+    /// }
+    ///
+    /// let mut data = AnyMap::new();
+    /// data.insert(0u32);
+    /// let counter = data.get_mut_ptr::<u32>().unwrap();
+    /// bump(counter.as_ptr());
+    /// bump(counter.as_ptr());
+    /// assert_eq!(data.get::<u32>(), Some(&2));
+    /// ```
+    #[inline]
+    pub fn get_mut_ptr<T: Any + 'static>(&mut self) -> Option<NonNull<T>> {
+        self.get_mut::<T>().map(NonNull::from)
+    }
+
+    /// Returns an untyped pointer to the value stored in the collection for the type with the
+    /// given ID, if it exists, and the layout of the value, under the rules of `get_ptr`.
+    ///
+    /// This is for code which knows the type only by ID, so the layout is given for copying
+    /// the value's bytes around; it is the caller's job to interpret them.
+    pub fn get_ptr_by_id(&self, id: TypeId) -> Option<(NonNull<u8>, Layout)> {
+        self.data.get(&id).map(|value| {
+            let layout = Layout::for_value::<dyn Any>(&**value);
+            (NonNull::from(&**value).cast::<u8>(), layout)
+        })
+    }
+
+    /// Returns an untyped pointer to the value stored in the collection for the type with the
+    /// given ID, if it exists, and the layout of the value, under the rules of `get_mut_ptr`.
+    pub fn get_mut_ptr_by_id(&mut self, id: TypeId) -> Option<(NonNull<u8>, Layout)> {
+        self.data.get_mut(&id).map(|value| {
+            let layout = Layout::for_value::<dyn Any>(&**value);
+            (NonNull::from(&mut **value).cast::<u8>(), layout)
+        })
     }
 
     /// Sets the value stored in the collection for the type `T`.
     /// If the collection already had a value of type `T`, that value is returned.
     /// Otherwise, `None` is returned.
-    #[stable]
+    ///
+    /// The type is looked up once; where there is an old value, the new one is moved into its
+    /// place, reusing its allocation.
     pub fn insert<T: Any + 'static>(&mut self, value: T) -> Option<T> {
-        self.data.insert(TypeId::of::<T>(), Box::new(value) as Box<Any>)
-            .map(|any| *unsafe { any.downcast_unchecked::<T>() })
+        self.instruments.record::<T>(Event::Insert);
+        let old = match self.data.entry(TypeId::of::<T>()) {
+            hash_map::Entry::Occupied(e) => {
+                Some(mem::replace(cast_mut::<T>(&mut **e.into_mut()), value))
+            },
+            hash_map::Entry::Vacant(e) => {
+                let _ = e.insert(Box::new(value) as Box<dyn Any>);
+                None
+            },
+        };
+        self.instruments.resized(self.data.len());
+        old
+    }
+
+    /// Sets the value stored in the collection for the type `T`, dropping the old value if
+    /// there was one, rather than returning it as `insert` does.
+    pub fn insert_no_return<T: Any + 'static>(&mut self, value: T) {
+        self.instruments.record::<T>(Event::Insert);
+        match self.data.entry(TypeId::of::<T>()) {
+            hash_map::Entry::Occupied(e) => *cast_mut::<T>(&mut **e.into_mut()) = value,
+            hash_map::Entry::Vacant(e) => { let _ = e.insert(Box::new(value) as Box<dyn Any>); },
+        }
+        self.instruments.resized(self.data.len());
     }
 
     /// Removes the `T` value from the collection,
     /// returning it if there was one or `None` if there was not.
-    #[stable]
     pub fn remove<T: Any + 'static>(&mut self) -> Option<T> {
-        self.data.remove(&TypeId::of::<T>())
-            .map(|any| *unsafe { any.downcast_unchecked::<T>() })
+        let value = self.data.remove(&TypeId::of::<T>())
+            .map(|any| *cast_box::<T>(any));
+        if value.is_some() {
+            self.instruments.record::<T>(Event::Remove);
+            self.instruments.resized(self.data.len());
+            self.shrink_if_sparse();
+        }
+        value
+    }
+
+    /// Sets the boxed value stored in the collection for the type `T`, which may be unsized.
+    ///
+    /// Unsized values such as string slices, byte slices and paths can then be stored without
+    /// wrapping them in a sized type:
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// let mut data = AnyMap::new();
+    /// data.insert_boxed::<str>("hello".into());
+    /// data.insert_boxed::<[u8]>(vec![1, 2, 3].into_boxed_slice());
+    /// assert_eq!(data.get_boxed::<str>(), Some("hello"));
+    /// assert_eq!(data.get_boxed::<[u8]>(), Some(&[1, 2, 3][..]));
+    /// ```
+    ///
+    /// And trait object types in particular allow values to be looked up by the interface they
+    /// provide rather than by their concrete type:
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// trait Logger {
+    ///     fn prefix(&self) -> &str;
+    /// }
+    ///
+    /// struct Stderr;
+    /// impl Logger for Stderr {
+    ///     fn prefix(&self) -> &str { "stderr" }
+    /// }
+    ///
+    /// let mut data = AnyMap::new();
+    /// data.insert_boxed(Box::new(Stderr) as Box<dyn Logger>);
+    /// assert_eq!(data.get_boxed::<dyn Logger>().unwrap().prefix(), "stderr");
+    /// ```
+    ///
+    /// If the collection already had a boxed value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    ///
+    /// Boxed values are kept apart from unboxed ones, as values of the type `Box<T>`.
+    #[inline]
+    pub fn insert_boxed<T: ?Sized + 'static>(&mut self, value: Box<T>) -> Option<Box<T>> {
+        self.insert(value)
+    }
+
+    /// Returns a reference to the boxed value stored in the collection for the type `T`,
+    /// if it exists.
+    #[inline]
+    pub fn get_boxed<T: ?Sized + 'static>(&self) -> Option<&T> {
+        self.get::<Box<T>>().map(|value| &**value)
+    }
+
+    /// Returns a mutable reference to the boxed value stored in the collection for the type `T`,
+    /// if it exists.
+    #[inline]
+    pub fn get_boxed_mut<T: ?Sized + 'static>(&mut self) -> Option<&mut T> {
+        self.get_mut::<Box<T>>().map(|value| &mut **value)
+    }
+
+    /// Returns true if the collection contains a boxed value of type `T`.
+    #[inline]
+    pub fn contains_boxed<T: ?Sized + 'static>(&self) -> bool {
+        self.contains::<Box<T>>()
+    }
+
+    /// Removes the boxed `T` value from the collection,
+    /// returning it if there was one or `None` if there was not.
+    #[inline]
+    pub fn remove_boxed<T: ?Sized + 'static>(&mut self) -> Option<Box<T>> {
+        self.remove::<Box<T>>()
+    }
+
+    /// Sets the pinned value stored in the collection for the type `T`, which is never moved
+    /// again: it can only be reached through `Pin`s, and is dropped in place.
+    /// If the collection already had a pinned value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    ///
+    /// Pinned values are kept apart from unpinned ones, as values of the type `Pin<Box<T>>`.
+    pub fn insert_pinned<T: Any + 'static>(&mut self, value: T) -> Option<Pin<Box<T>>> {
+        self.insert(Box::pin(value))
+    }
+
+    /// Returns a pinned reference to the pinned value stored in the collection for the type `T`,
+    /// if it exists.
+    #[inline]
+    pub fn get_pinned<T: Any + 'static>(&self) -> Option<Pin<&T>> {
+        self.get::<Pin<Box<T>>>().map(|pinned| pinned.as_ref())
+    }
+
+    /// Returns a pinned mutable reference to the pinned value stored in the collection for the
+    /// type `T`, if it exists.
+    #[inline]
+    pub fn get_pin_mut<T: Any + 'static>(&mut self) -> Option<Pin<&mut T>> {
+        self.get_mut::<Pin<Box<T>>>().map(|pinned| pinned.as_mut())
+    }
+
+    /// Removes the pinned `T` value from the collection,
+    /// returning it, still pinned, if there was one or `None` if there was not.
+    #[inline]
+    pub fn remove_pinned<T: Any + 'static>(&mut self) -> Option<Pin<Box<T>>> {
+        self.remove::<Pin<Box<T>>>()
+    }
+
+    /// Sets the shared value stored in the collection for the type `T`.
+    /// If the collection already had a shared value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    ///
+    /// Shared values are kept apart from unshared ones, as values of the type `Arc<T>`; they can
+    /// be handed out with `get_arc` as handles which outlive any borrow of the collection.
+    #[inline]
+    pub fn insert_arc<T: Any + 'static>(&mut self, value: Arc<T>) -> Option<Arc<T>> {
+        self.insert(value)
+    }
+
+    /// Returns a new handle to the shared value stored in the collection for the type `T`,
+    /// if it exists.
+    #[inline]
+    pub fn get_arc<T: Any + 'static>(&self) -> Option<Arc<T>> {
+        self.get::<Arc<T>>().cloned()
+    }
+
+    /// Removes the shared `T` value from the collection,
+    /// returning it if there was one or `None` if there was not.
+    #[inline]
+    pub fn remove_arc<T: Any + 'static>(&mut self) -> Option<Arc<T>> {
+        self.remove::<Arc<T>>()
+    }
+
+    /// Sets the value stored in the collection for the type `T` for as long as the returned guard
+    /// lives. When the guard is dropped, including during unwinding, the previous value is
+    /// restored, or the value removed if there was none.
+    ///
+    /// The guard dereferences to the map, so it can still be used in the meantime:
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// let mut data = AnyMap::new();
+    /// data.insert("production");
+    /// {
+    ///     let data = data.scoped_insert("test");
+    ///     assert_eq!(data.get::<&str>(), Some(&"test"));
+    /// }
+    /// assert_eq!(data.get::<&str>(), Some(&"production"));
+    /// ```
+    pub fn scoped_insert<T: Any + 'static>(&mut self, value: T) -> ScopedInsert<'_, T> {
+        let previous = self.insert(value);
+        ScopedInsert {
+            map: self,
+            previous,
+        }
     }
 
     /// Returns true if the collection contains a value of type `T`.
-    #[stable]
     pub fn contains<T: Any + 'static>(&self) -> bool {
         self.data.contains_key(&TypeId::of::<T>())
     }
 
     /// Gets the entry for the given type in the collection for in-place manipulation
-    #[stable]
-    pub fn entry<T: Any + 'static>(&mut self) -> Entry<T> {
+    pub fn entry<T: Any + 'static>(&mut self) -> Entry<'_, T> {
+        let len = self.data.len();
+        let instruments = &mut self.instruments;
         match self.data.entry(TypeId::of::<T>()) {
-            hash_map::Entry::Occupied(e) => Entry::Occupied(OccupiedEntry { entry: e }),
-            hash_map::Entry::Vacant(e) => Entry::Vacant(VacantEntry { entry: e }),
+            hash_map::Entry::Occupied(e) => {
+                Entry::Occupied(OccupiedEntry { entry: e, instruments, len, type_: PhantomData })
+            },
+            hash_map::Entry::Vacant(e) => {
+                Entry::Vacant(VacantEntry { entry: e, instruments, len, type_: PhantomData })
+            },
         }
     }
 
     /// Returns the number of items in the collection.
     #[inline]
-    #[stable]
     pub fn len(&self) -> usize {
         self.data.len()
     }
 
     /// Returns true if there are no items in the collection.
     #[inline]
-    #[stable]
     pub fn is_empty(&self) -> bool {
         self.data.is_empty()
     }
 
     /// Clears the map, returning all items as an iterator.
     ///
-    /// Iterator element type is `Box<Any>`.
+    /// Iterator element type is `Box<dyn Any>`.
     ///
     /// Keeps the allocated memory for reuse.
     #[inline]
-    #[unstable = "matches collection reform specification, waiting for dust to settle"]
-    pub fn drain(&mut self) -> Drain {
+    pub fn drain(&mut self) -> Drain<'_> {
+        self.instruments.resized(0);
         Drain {
             inner: self.data.drain(),
         }
     }
 
-    /// Removes all items from the collection. Keeps the allocated memory for reuse.
+    /// Removes all items from the collection. Keeps the allocated memory for reuse, unless a
+    /// shrink threshold is set.
     #[inline]
-    #[stable]
     pub fn clear(&mut self) {
         self.data.clear();
+        self.instruments.resized(0);
+        self.shrink_if_sparse();
+    }
+
+    /// Moves every value out of `other` into this collection. Where both have a value of the
+    /// same type, `prefer` decides which is kept.
+    pub fn union_with(&mut self, other: AnyMap, prefer: Preference) {
+        for (id, value) in other.data.into_iter() {
+            match self.data.entry(id) {
+                hash_map::Entry::Occupied(mut e) => if prefer == Preference::Theirs {
+                    let _ = e.insert(value);
+                },
+                hash_map::Entry::Vacant(e) => { let _ = e.insert(value); },
+            }
+        }
+        self.instruments.resized(self.data.len());
+    }
+
+    /// Removes the value of every type which `other` has no value of.
+    pub fn intersect_with(&mut self, other: &AnyMap) {
+        self.retain_ids(|id| other.data.contains_key(id));
+    }
+
+    /// Removes the value of every type which `other` has a value of.
+    pub fn subtract(&mut self, other: &AnyMap) {
+        self.retain_ids(|id| !other.data.contains_key(id));
+    }
+
+    /// Returns true if `other` has a value of every type this collection has a value of.
+    pub fn is_subset_of(&self, other: &AnyMap) -> bool {
+        self.data.keys().all(|id| other.data.contains_key(id))
+    }
+
+    /// Checks that the collection has a value of every type in `required`, returning the types
+    /// it lacks if not.
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate anymap;
+    /// # fn main() {
+    /// struct Session;
+    /// struct User;
+    ///
+    /// let requires = type_set![Session, User];
+    /// let mut extensions = anymap![Session];
+    /// assert!(extensions.validate_against(&requires).unwrap_err().types().contains::<User>());
+    /// extensions.insert(User);
+    /// assert!(extensions.validate_against(&requires).is_ok());
+    /// # }
+    /// ```
+    pub fn validate_against(&self, required: &TypeSet) -> Result<(), MissingTypes> {
+        let mut missing = TypeSet::new();
+        for (id, name) in required.iter() {
+            if !self.data.contains_key(&id) {
+                let _ = missing.insert_id(id, name);
+            }
+        }
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(MissingTypes { types: missing })
+        }
+    }
+
+    /// Inserts each of a group of values as an entry of its own, replacing any values of the
+    /// same types already stored.
+    ///
+    /// A struct may be made a `Bundle` by declaring it with the `bundle!` macro.
+    #[inline]
+    pub fn insert_bundle<B: Bundle>(&mut self, bundle: B) {
+        bundle.insert_into(self)
+    }
+
+    /// Calls a function whose parameters are shared or mutable references to values in the
+    /// collection, borrowing each from the collection according to its type.
+    ///
+    /// If the collection lacks a value for any parameter, the function is not called and
+    /// `InvokeError::Missing` names all of the missing types. No two parameters may refer to the
+    /// same type.
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// struct Config { step: u32 }
+    /// struct Stats { count: u32 }
+    ///
+    /// let mut data = AnyMap::new();
+    /// data.insert(Config { step: 2 });
+    /// data.insert(Stats { count: 0 });
+    /// data.invoke(|config: &Config, stats: &mut Stats| stats.count += config.step).unwrap();
+    /// assert_eq!(data.get::<Stats>().unwrap().count, 2);
+    /// ```
+    pub fn invoke<'a, Args, F>(&'a mut self, function: F)
+                               -> Result<<F as Invoke<'a, Args>>::Output, InvokeError>
+    where F: Invoke<'a, Args> {
+        function.invoke(self)
+    }
+
+    /// Sets the boxed value stored in the collection for the type identified by `id`, which must
+    /// be that of the value, returning the old value if there was one.
+    fn insert_by_id(&mut self, id: TypeId, value: Box<dyn Any + 'static>)
+                    -> Option<Box<dyn Any + 'static>> {
+        let old = self.data.insert(id, value);
+        self.instruments.resized(self.data.len());
+        old
+    }
+
+    /// Removes the value of the type identified by `id`, returning it if there was one.
+    fn remove_by_id(&mut self, id: &TypeId) -> Option<Box<dyn Any + 'static>> {
+        let value = self.data.remove(id);
+        if value.is_some() {
+            self.instruments.resized(self.data.len());
+            self.shrink_if_sparse();
+        }
+        value
+    }
+
+    /// Removes the value of every type whose ID fails the predicate.
+    fn retain_ids<F: FnMut(&TypeId) -> bool>(&mut self, mut keep: F) {
+        let doomed: Vec<TypeId> = self.data.keys().filter(|id| !keep(id)).copied().collect();
+        for id in doomed.iter() {
+            let _ = self.data.remove(id);
+        }
+        self.instruments.resized(self.data.len());
+        self.shrink_if_sparse();
+    }
+
+    /// Returns the prefix of the names of the metrics emitted for this map, `anymap` by default.
+    ///
+    /// Only available with the `metrics` feature.
+    #[cfg(feature = "metrics")]
+    #[inline]
+    pub fn metrics_prefix(&self) -> &str {
+        self.instruments.metrics_prefix()
+    }
+
+    /// Sets the prefix of the names of the metrics emitted for this map.
+    ///
+    /// With the `metrics` feature, each map emits the counters `<prefix>.hits`,
+    /// `<prefix>.misses`, `<prefix>.inserts` and `<prefix>.removes`, labelled with the name of the
+    /// type concerned, and contributes its length to the gauge `<prefix>.entries`, which is
+    /// thus the total number of entries in all live maps with that prefix.
+    #[cfg(feature = "metrics")]
+    #[inline]
+    pub fn set_metrics_prefix(&mut self, prefix: &str) {
+        self.instruments.set_metrics_prefix(prefix)
+    }
+
+    /// Returns a snapshot of the access statistics collected so far, one item per type which
+    /// has been looked up, inserted or removed with `get`, `get_mut`, `insert` or `remove`.
+    ///
+    /// Only available with the `stats` feature.
+    #[cfg(feature = "stats")]
+    #[inline]
+    pub fn stats(&self) -> Vec<TypeStats> {
+        self.instruments.stats()
+    }
+
+    /// Forgets the access statistics collected so far.
+    ///
+    /// Only available with the `stats` feature.
+    #[cfg(feature = "stats")]
+    #[inline]
+    pub fn reset_stats(&self) {
+        self.instruments.reset_stats()
+    }
+}
+
+/// Gives the value of the type identified by the `TypeId`, for code which knows types only at
+/// runtime.
+///
+/// # Panics
+///
+/// Panics if the collection has no value of the type.
+impl Index<TypeId> for AnyMap {
+    type Output = dyn Any + 'static;
+
+    fn index(&self, id: TypeId) -> &(dyn Any + 'static) {
+        match self.data.get(&id) {
+            Some(value) => &**value,
+            None => panic!("the map has no value of the type with ID {:?}", id),
+        }
+    }
+}
+
+/// Creates a consuming iterator, that is, one that moves each item
+/// out of the map in arbitrary order. The map cannot be used after
+/// calling this.
+///
+/// Iterator element type is `Box<dyn Any>`.
+impl IntoIterator for AnyMap {
+    type Item = Box<dyn Any + 'static>;
+    type IntoIter = IntoIter;
+
+    #[inline]
+    fn into_iter(self) -> IntoIter {
+        IntoIter {
+            inner: self.data.into_iter(),
+        }
     }
 }
 
 /// A view into a single occupied location in an AnyMap
-#[stable]
 pub struct OccupiedEntry<'a, V: 'a> {
-    entry: hash_map::OccupiedEntry<'a, TypeId, Box<Any + 'static>>,
+    entry: hash_map::OccupiedEntry<'a, TypeId, Box<dyn Any + 'static>>,
+    instruments: &'a mut Instruments,
+    /// The number of entries in the map.
+    len: usize,
+    type_: PhantomData<V>,
 }
 
 /// A view into a single empty location in an AnyMap
-#[stable]
 pub struct VacantEntry<'a, V: 'a> {
-    entry: hash_map::VacantEntry<'a, TypeId, Box<Any + 'static>>,
+    entry: hash_map::VacantEntry<'a, TypeId, Box<dyn Any + 'static>>,
+    instruments: &'a mut Instruments,
+    /// The number of entries in the map.
+    len: usize,
+    type_: PhantomData<V>,
 }
 
 /// A view into a single location in an AnyMap, which may be vacant or occupied
-#[stable]
 pub enum Entry<'a, V: 'a> {
     /// An occupied Entry
     Occupied(OccupiedEntry<'a, V>),
@@ -329,7 +1183,6 @@ pub enum Entry<'a, V: 'a> {
 }
 
 impl<'a, V: 'static + Clone> Entry<'a, V> {
-    #[unstable = "matches collection reform v2 specification, waiting for dust to settle"]
     /// Returns a mutable reference to the entry if occupied, or the VacantEntry if vacant
     pub fn get(self) -> Result<&'a mut V, VacantEntry<'a, V>> {
         match self {
@@ -340,78 +1193,107 @@ impl<'a, V: 'static + Clone> Entry<'a, V> {
 }
 
 impl<'a, V: 'static> OccupiedEntry<'a, V> {
-    #[stable]
     /// Gets a reference to the value in the entry
     pub fn get(&self) -> &V {
-        unsafe { self.entry.get().downcast_ref_unchecked() }
+        cast_ref(&**self.entry.get())
     }
 
-    #[stable]
     /// Gets a mutable reference to the value in the entry
     pub fn get_mut(&mut self) -> &mut V {
-        unsafe { self.entry.get_mut().downcast_mut_unchecked() }
+        cast_mut(&mut **self.entry.get_mut())
     }
 
-    #[stable]
     /// Converts the OccupiedEntry into a mutable reference to the value in the entry
     /// with a lifetime bound to the collection itself
     pub fn into_mut(self) -> &'a mut V {
-        unsafe { self.entry.into_mut().downcast_mut_unchecked() }
+        cast_mut(&mut **self.entry.into_mut())
     }
 
-    #[stable]
     /// Sets the value of the entry, and returns the entry's old value
     pub fn insert(&mut self, value: V) -> V {
-        unsafe { *self.entry.insert(Box::new(value) as Box<Any + 'static>).downcast_unchecked() }
+        self.instruments.record::<V>(Event::Insert);
+        *cast_box(self.entry.insert(Box::new(value) as Box<dyn Any + 'static>))
     }
 
-    #[stable]
     /// Takes the value out of the entry, and returns it
+    ///
+    /// Unlike `AnyMap::remove`, this cannot release excess capacity below the shrink threshold,
+    /// as the entry has no hold on the map once the value is gone; the next removal through the
+    /// map does.
     pub fn remove(self) -> V {
-        unsafe { *self.entry.remove().downcast_unchecked() }
+        self.instruments.record::<V>(Event::Remove);
+        self.instruments.resized(self.len - 1);
+        *cast_box(self.entry.remove())
     }
 }
 
 impl<'a, V: 'static> VacantEntry<'a, V> {
-    #[stable]
     /// Sets the value of the entry with the VacantEntry's key,
     /// and returns a mutable reference to it
     pub fn insert(self, value: V) -> &'a mut V {
-        unsafe { self.entry.insert(Box::new(value) as Box<Any + 'static>).downcast_mut_unchecked() }
+        self.instruments.record::<V>(Event::Insert);
+        self.instruments.resized(self.len + 1);
+        cast_mut(&mut **self.entry.insert(Box::new(value) as Box<dyn Any + 'static>))
+    }
+}
+
+/// A guard restoring the previous value of a type when dropped; see `AnyMap::scoped_insert`.
+pub struct ScopedInsert<'a, T: Any + 'static> {
+    map: &'a mut AnyMap,
+    previous: Option<T>,
+}
+
+impl<'a, T: Any + 'static> Deref for ScopedInsert<'a, T> {
+    type Target = AnyMap;
+
+    #[inline]
+    fn deref(&self) -> &AnyMap {
+        &*self.map
+    }
+}
+
+impl<'a, T: Any + 'static> DerefMut for ScopedInsert<'a, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut AnyMap {
+        &mut *self.map
+    }
+}
+
+impl<'a, T: Any + 'static> Drop for ScopedInsert<'a, T> {
+    fn drop(&mut self) {
+        match self.previous.take() {
+            Some(previous) => { let _ = self.map.insert(previous); },
+            None => { let _ = self.map.remove::<T>(); },
+        }
     }
 }
 
 /// `AnyMap` iterator.
-#[stable]
 #[derive(Clone)]
 pub struct Iter<'a> {
-    inner: hash_map::Iter<'a, TypeId, Box<Any + 'static>>,
+    inner: hash_map::Iter<'a, TypeId, Box<dyn Any + 'static>>,
 }
 
 /// `AnyMap` mutable references iterator.
-#[stable]
 pub struct IterMut<'a> {
-    inner: hash_map::IterMut<'a, TypeId, Box<Any + 'static>>,
+    inner: hash_map::IterMut<'a, TypeId, Box<dyn Any + 'static>>,
 }
 
 /// `AnyMap` draining iterator.
-#[unstable = "matches collection reform specification, waiting for dust to settle"]
 pub struct Drain<'a> {
-    inner: hash_map::Drain<'a, TypeId, Box<Any + 'static>>,
+    inner: hash_map::Drain<'a, TypeId, Box<dyn Any + 'static>>,
 }
 
 /// `AnyMap` move iterator.
-#[stable]
 pub struct IntoIter {
-    inner: hash_map::IntoIter<TypeId, Box<Any + 'static>>,
+    inner: hash_map::IntoIter<TypeId, Box<dyn Any + 'static>>,
 }
 
-#[stable]
 impl<'a> Iterator for Iter<'a> {
-    type Item = &'a Any;
+    type Item = &'a dyn Any;
 
     #[inline]
-    fn next(&mut self) -> Option<&'a Any> {
+    fn next(&mut self) -> Option<&'a dyn Any> {
         self.inner.next().map(|item| &**item.1)
     }
 
@@ -419,12 +1301,11 @@ impl<'a> Iterator for Iter<'a> {
     fn size_hint(&self) -> (usize, Option<usize>) { self.inner.size_hint() }
 }
 
-#[stable]
 impl<'a> Iterator for IterMut<'a> {
-    type Item = &'a mut Any;
+    type Item = &'a mut dyn Any;
 
     #[inline]
-    fn next(&mut self) -> Option<&'a mut Any> {
+    fn next(&mut self) -> Option<&'a mut dyn Any> {
         self.inner.next().map(|item| &mut **item.1)
     }
 
@@ -432,12 +1313,11 @@ impl<'a> Iterator for IterMut<'a> {
     fn size_hint(&self) -> (usize, Option<usize>) { self.inner.size_hint() }
 }
 
-#[stable]
 impl<'a> Iterator for Drain<'a> {
-    type Item = Box<Any + 'static>;
+    type Item = Box<dyn Any + 'static>;
 
     #[inline]
-    fn next(&mut self) -> Option<Box<Any + 'static>> {
+    fn next(&mut self) -> Option<Box<dyn Any + 'static>> {
         self.inner.next().map(|item| item.1)
     }
 
@@ -445,12 +1325,11 @@ impl<'a> Iterator for Drain<'a> {
     fn size_hint(&self) -> (usize, Option<usize>) { self.inner.size_hint() }
 }
 
-#[stable]
 impl Iterator for IntoIter {
-    type Item = Box<Any + 'static>;
+    type Item = Box<dyn Any + 'static>;
 
     #[inline]
-    fn next(&mut self) -> Option<Box<Any + 'static>> {
+    fn next(&mut self) -> Option<Box<dyn Any + 'static>> {
         self.inner.next().map(|item| item.1)
     }
 
@@ -458,33 +1337,234 @@ impl Iterator for IntoIter {
     fn size_hint(&self) -> (usize, Option<usize>) { self.inner.size_hint() }
 }
 
+/// Which of two values of the same type `AnyMap::union_with` keeps.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Preference {
+    /// Keep the value already in the collection.
+    Ours,
+    /// Keep the value from the other collection.
+    Theirs,
+}
+
+/// A set of types, as a companion to `AnyMap`: it can be used to describe which types a map
+/// contains, or should contain.
+///
+/// The name of each type is kept alongside its `TypeId`, for use in diagnostics.
+#[derive(Clone)]
+pub struct TypeSet {
+    types: HashMap<TypeId, &'static str, TypeIdBuildHasher>,
+}
+
+impl Default for TypeSet {
+    #[inline]
+    fn default() -> TypeSet {
+        TypeSet::new()
+    }
+}
+
+impl TypeSet {
+    /// Construct a new, empty `TypeSet`.
+    #[inline]
+    pub fn new() -> TypeSet {
+        TypeSet {
+            types: HashMap::with_hasher(TypeIdBuildHasher),
+        }
+    }
+
+    /// Adds the type `T` to the set, returning true if it was not already present.
+    #[inline]
+    pub fn insert<T: Any + 'static>(&mut self) -> bool {
+        self.insert_id(TypeId::of::<T>(), type_name::<T>())
+    }
+
+    /// Removes the type `T` from the set, returning true if it was present.
+    #[inline]
+    pub fn remove<T: Any + 'static>(&mut self) -> bool {
+        self.types.remove(&TypeId::of::<T>()).is_some()
+    }
+
+    /// Returns true if the set contains the type `T`.
+    #[inline]
+    pub fn contains<T: Any + 'static>(&self) -> bool {
+        self.types.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Returns true if the set contains the type identified by `id`.
+    #[inline]
+    pub fn contains_type_id(&self, id: &TypeId) -> bool {
+        self.types.contains_key(id)
+    }
+
+    /// Returns the number of types in the set.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    /// Returns true if the set contains no types.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    /// Removes all types from the set.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.types.clear()
+    }
+
+    /// An iterator visiting all types in the set in arbitrary order.
+    /// Iterator element type is `(TypeId, &'static str)`, the type’s ID and name.
+    #[inline]
+    pub fn iter(&self) -> Types<'_> {
+        Types {
+            inner: self.types.iter(),
+        }
+    }
+
+    /// Adds the type `T` to the set, returning the set, for building sets in one expression.
+    #[inline]
+    pub fn with<T: Any + 'static>(mut self) -> TypeSet {
+        let _ = self.insert::<T>();
+        self
+    }
+
+    /// Adds a type by ID, keeping the first name seen for it.
+    fn insert_id(&mut self, id: TypeId, name: &'static str) -> bool {
+        match self.types.entry(id) {
+            hash_map::Entry::Occupied(_) => false,
+            hash_map::Entry::Vacant(e) => { let _ = e.insert(name); true },
+        }
+    }
+}
+
+impl fmt::Debug for TypeSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (_, name)) in self.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { "" } else { ", " }, name)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+/// `TypeSet` iterator.
+#[derive(Clone)]
+pub struct Types<'a> {
+    inner: hash_map::Iter<'a, TypeId, &'static str>,
+}
+
+impl<'a> Iterator for Types<'a> {
+    type Item = (TypeId, &'static str);
+
+    #[inline]
+    fn next(&mut self) -> Option<(TypeId, &'static str)> {
+        self.inner.next().map(|(&id, &name)| (id, name))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.inner.size_hint() }
+}
+
+/// The error returned by `AnyMap::try_get`: the type of which the map lacks a value.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MissingType {
+    name: &'static str,
+}
+
+impl MissingType {
+    /// Returns the name of the missing type.
+    #[inline]
+    pub fn type_name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl fmt::Display for MissingType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the map lacks a value of the type {}", self.name)
+    }
+}
+
+impl Error for MissingType {
+    fn description(&self) -> &str {
+        "the map lacks a value of the required type"
+    }
+}
+
+/// The error returned by `AnyMap::validate_against`: the types of which the map lacks values.
+#[derive(Clone, Debug)]
+pub struct MissingTypes {
+    types: TypeSet,
+}
+
+impl MissingTypes {
+    /// Returns the set of the missing types.
+    #[inline]
+    pub fn types(&self) -> &TypeSet {
+        &self.types
+    }
+
+    /// Unwraps the set of the missing types.
+    #[inline]
+    pub fn into_types(self) -> TypeSet {
+        self.types
+    }
+}
+
+impl fmt::Display for MissingTypes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the map lacks values of the types {:?}", self.types)
+    }
+}
+
+impl Error for MissingTypes {
+    fn description(&self) -> &str {
+        "the map lacks values of some required types"
+    }
+}
+
+#[cfg(feature = "bench")]
 #[bench]
 fn bench_insertion(b: &mut ::test::Bencher) {
     b.iter(|| {
         let mut data = AnyMap::new();
-        for _ in range(0, 100) {
+        for _ in 0..100 {
             let _ = data.insert(42i32);
         }
     })
 }
 
+#[cfg(feature = "bench")]
+#[bench]
+fn bench_insertion_no_return(b: &mut ::test::Bencher) {
+    b.iter(|| {
+        let mut data = AnyMap::new();
+        for _ in 0..100 {
+            data.insert_no_return(42i32);
+        }
+    })
+}
+
+#[cfg(feature = "bench")]
 #[bench]
 fn bench_get_missing(b: &mut ::test::Bencher) {
     b.iter(|| {
         let data = AnyMap::new();
-        for _ in range(0, 100) {
+        for _ in 0..100 {
             assert_eq!(data.get(), None::<&i32>);
         }
     })
 }
 
+#[cfg(feature = "bench")]
 #[bench]
 fn bench_get_present(b: &mut ::test::Bencher) {
     b.iter(|| {
         let mut data = AnyMap::new();
         let _ = data.insert(42i32);
         // These inner loops are a feeble attempt to drown the other factors.
-        for _ in range(0, 100) {
+        for _ in 0..100 {
             assert_eq!(data.get(), Some(&42i32));
         }
     })
@@ -492,13 +1572,13 @@ fn bench_get_present(b: &mut ::test::Bencher) {
 
 #[test]
 fn test_entry() {
-    #[derive(Show, PartialEq)] struct A(i32);
-    #[derive(Show, PartialEq)] struct B(i32);
-    #[derive(Show, PartialEq)] struct C(i32);
-    #[derive(Show, PartialEq)] struct D(i32);
-    #[derive(Show, PartialEq)] struct E(i32);
-    #[derive(Show, PartialEq)] struct F(i32);
-    #[derive(Show, PartialEq)] struct J(i32);
+    #[derive(Debug, PartialEq)] struct A(i32);
+    #[derive(Debug, PartialEq)] struct B(i32);
+    #[derive(Debug, PartialEq)] struct C(i32);
+    #[derive(Debug, PartialEq)] struct D(i32);
+    #[derive(Debug, PartialEq)] struct E(i32);
+    #[derive(Debug, PartialEq)] struct F(i32);
+    #[derive(Debug, PartialEq)] struct J(i32);
 
     let mut map: AnyMap = AnyMap::new();
     assert_eq!(map.insert(A(10)), None);
@@ -529,7 +1609,7 @@ fn test_entry() {
             *v = new_v;
         }
     }
-    assert_eq!(map.get().unwrap(), &B(200));
+    assert_eq!(map.get::<B>().unwrap(), &B(200));
     assert_eq!(map.len(), 6);
 
 
@@ -554,3 +1634,368 @@ fn test_entry() {
     assert_eq!(map.get::<J>().unwrap(), &J(1000));
     assert_eq!(map.len(), 6);
 }
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_entry_metrics() {
+    use metrics::{Counter, CounterFn, Gauge, GaugeFn, Histogram, Key, KeyName, Metadata,
+                  Recorder, SharedString, Unit};
+    use std::sync::{Arc, Mutex};
+
+    /// Keeps the total of each counter and gauge by name, across labels.
+    struct Totals(Arc<Mutex<HashMap<String, f64>>>);
+
+    struct Tally(String, Arc<Mutex<HashMap<String, f64>>>);
+
+    impl Tally {
+        fn add(&self, value: f64) {
+            *self.1.lock().unwrap().entry(self.0.clone()).or_insert(0.0) += value;
+        }
+    }
+
+    impl CounterFn for Tally {
+        fn increment(&self, value: u64) { self.add(value as f64) }
+        fn absolute(&self, _: u64) { unimplemented!() }
+    }
+
+    impl GaugeFn for Tally {
+        fn increment(&self, value: f64) { self.add(value) }
+        fn decrement(&self, value: f64) { self.add(-value) }
+        fn set(&self, _: f64) { unimplemented!() }
+    }
+
+    impl Recorder for Totals {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            Counter::from_arc(Arc::new(Tally(key.name().to_string(), self.0.clone())))
+        }
+        fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::from_arc(Arc::new(Tally(key.name().to_string(), self.0.clone())))
+        }
+        fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::noop()
+        }
+    }
+
+    let totals = Arc::new(Mutex::new(HashMap::new()));
+    let total = |name: &str| totals.lock().unwrap().get(name).cloned().unwrap_or(0.0);
+    metrics::with_local_recorder(&Totals(totals.clone()), || {
+        let mut map = AnyMap::new();
+        for value in 1..3u8 {
+            match map.entry::<u8>() {
+                Entry::Occupied(mut entry) => *entry.get_mut() += value,
+                Entry::Vacant(entry) => { let _ = entry.insert(value); },
+            }
+        }
+        if let Entry::Vacant(entry) = map.entry::<u16>() {
+            let _ = entry.insert(1);
+        }
+        assert_eq!((total("anymap.entries"), total("anymap.inserts")), (2.0, 2.0));
+        match map.entry::<u8>() {
+            Entry::Occupied(mut entry) => {
+                assert_eq!(entry.insert(4), 3);
+                assert_eq!(entry.remove(), 4);
+            },
+            Entry::Vacant(_) => unreachable!(),
+        }
+        assert_eq!(total("anymap.entries"), 1.0);
+        assert_eq!((total("anymap.inserts"), total("anymap.removes")), (3.0, 1.0));
+    });
+    assert_eq!(total("anymap.entries"), 0.0);
+}
+
+#[test]
+fn test_scoped_insert() {
+    let mut map = AnyMap::new();
+    let _ = map.insert(1i32);
+    {
+        let mut map = map.scoped_insert(2i32);
+        assert_eq!(map.get::<i32>(), Some(&2));
+        {
+            let mut map = map.scoped_insert(3i32);
+            let map = map.scoped_insert(4u8);
+            assert_eq!(map.get::<i32>(), Some(&3));
+            assert_eq!(map.get::<u8>(), Some(&4));
+        }
+        *map.get_mut::<i32>().unwrap() = 5;
+        assert!(!map.contains::<u8>());
+    }
+    assert_eq!(map.get::<i32>(), Some(&1));
+    assert_eq!(map.len(), 1);
+}
+
+#[test]
+fn test_set_operations() {
+    let mut a = AnyMap::new();
+    let _ = a.insert(1i32);
+    let _ = a.insert(1u8);
+    let mut b = AnyMap::new();
+    let _ = b.insert(2i32);
+    let _ = b.insert(2u16);
+    assert!(!a.is_subset_of(&b));
+
+    let mut union = AnyMap::new();
+    let _ = union.insert(1i32);
+    union.union_with(b, Preference::Theirs);
+    assert_eq!(union.get::<i32>(), Some(&2));
+    union.union_with(a, Preference::Ours);
+    assert_eq!(union.get::<i32>(), Some(&2));
+    assert_eq!(union.len(), 3);
+
+    let mut other = AnyMap::new();
+    let _ = other.insert(0u8);
+    let _ = other.insert(0u16);
+    assert!(other.is_subset_of(&union));
+    union.subtract(&other);
+    assert!(union.contains::<i32>() && union.len() == 1);
+    let _ = other.insert(0i32);
+    other.intersect_with(&union);
+    assert!(other.contains::<i32>() && other.len() == 1);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "anymap invariant violated: expected a value of type u8")]
+fn test_audit_downcast() {
+    let mut map = AnyMap::new();
+    let _ = map.data.insert(TypeId::of::<u8>(), Box::new(1i32) as Box<dyn Any>);
+    let _ = map.get::<u8>();
+}
+
+#[test]
+fn test_type_id_hasher() {
+    fn hash(writes: &[&[u8]]) -> u64 {
+        let mut hasher = TypeIdBuildHasher.build_hasher();
+        for bytes in writes.iter() {
+            hasher.write(bytes);
+        }
+        hasher.finish()
+    }
+
+    assert_eq!(hash(&[&[1, 0, 0, 0, 0, 0, 0, 0]]), 1);
+    assert_eq!(hash(&[&[1, 2, 3]]), 0x030201);
+    assert!(hash(&[&[1; 16]]) != hash(&[&[1; 8], &[2; 8]]));
+    let word: &[u8] = &[1, 2, 3, 4, 5, 6, 7, 8];
+    assert!(hash(&[&[0; 8], word]) != hash(&[word, &[0; 8]]));
+    assert!(hash(&[&[0; 16]]) != hash(&[&[0; 15], &[1]]));
+    let mut types = HashMap::with_hasher(TypeIdBuildHasher);
+    let _ = types.insert(TypeId::of::<u8>(), 1);
+    let _ = types.insert(TypeId::of::<i8>(), 2);
+    assert_eq!(types.get(&TypeId::of::<u8>()), Some(&1));
+    assert_eq!(types.get(&TypeId::of::<i8>()), Some(&2));
+}
+
+#[test]
+fn test_raw_parts() {
+    let mut map = AnyMap::new();
+    let _ = map.insert(1i32);
+    let mut raw = map.into_raw_parts();
+    assert_eq!(raw.len(), 1);
+    let map = AnyMap::from_raw_parts(raw).ok().unwrap();
+    assert_eq!(map.get::<i32>(), Some(&1));
+
+    raw = map.into_raw_parts();
+    let _ = raw.insert(TypeId::of::<u8>(), Box::new(1i32) as Box<dyn Any>);
+    assert_eq!(AnyMap::from_raw_parts(raw).err().unwrap().len(), 2);
+}
+
+#[test]
+fn test_pinned() {
+    use std::cell::Cell;
+    use std::marker::PhantomPinned;
+
+    struct Unmovable(Cell<u8>, PhantomPinned);
+
+    let mut map = AnyMap::new();
+    assert!(map.insert_pinned(Unmovable(Cell::new(1), PhantomPinned)).is_none());
+    let address = &*map.get_pinned::<Unmovable>().unwrap() as *const Unmovable;
+    map.get_pin_mut::<Unmovable>().unwrap().0.set(2);
+    let _ = map.insert(1u8);
+    assert!(!map.contains::<Unmovable>());
+    let pinned = map.remove_pinned::<Unmovable>().unwrap();
+    assert_eq!(pinned.0.get(), 2);
+    assert_eq!(&*pinned as *const Unmovable, address);
+}
+
+#[test]
+fn test_arc() {
+    let mut map = AnyMap::new();
+    assert!(map.insert_arc(Arc::new(1i32)).is_none());
+    let handle = map.get_arc::<i32>().unwrap();
+    assert!(!map.contains::<i32>());
+    assert_eq!(map.remove_arc::<i32>(), Some(Arc::new(1)));
+    assert!(map.get_arc::<i32>().is_none());
+    assert_eq!(Arc::strong_count(&handle), 1);
+}
+
+#[test]
+fn test_boxed() {
+    trait Named {
+        fn name(&self) -> String;
+        fn rename(&mut self, name: &str);
+    }
+
+    struct Thing(String);
+
+    impl Named for Thing {
+        fn name(&self) -> String { self.0.clone() }
+        fn rename(&mut self, name: &str) { self.0 = name.to_string() }
+    }
+
+    let mut map = AnyMap::new();
+    assert!(map.insert_boxed(Box::new(Thing("a".to_string())) as Box<dyn Named>).is_none());
+    assert!(!map.contains::<Thing>());
+    map.get_boxed_mut::<dyn Named>().unwrap().rename("b");
+    assert_eq!(map.get_boxed::<dyn Named>().unwrap().name(), "b");
+    assert_eq!(map.remove_boxed::<dyn Named>().unwrap().name(), "b");
+    assert!(map.get_boxed::<dyn Named>().is_none());
+}
+
+#[test]
+fn test_boxed_unsized() {
+    use std::path::{Path, PathBuf};
+
+    let mut map = AnyMap::new();
+    assert!(map.insert_boxed::<str>("a".into()).is_none());
+    assert!(map.insert_boxed::<Path>(PathBuf::from("/tmp").into_boxed_path()).is_none());
+    assert!(map.contains_boxed::<str>() && !map.contains::<String>());
+    map.get_boxed_mut::<str>().unwrap().make_ascii_uppercase();
+    assert_eq!(map.get_boxed::<str>(), Some("A"));
+    assert_eq!(map.get_boxed::<Path>(), Some(Path::new("/tmp")));
+    assert_eq!(map.remove_boxed::<str>().map(String::from), Some("A".to_string()));
+    assert!(!map.contains_boxed::<str>());
+}
+
+#[test]
+fn test_anymap_macro() {
+    let empty = anymap![];
+    assert!(empty.is_empty());
+    let map = anymap![1u8, 2u16, 3u8,];
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.get::<u8>(), Some(&3));
+}
+
+#[test]
+fn test_typed_iter() {
+    typed_iter! {
+        #[derive(PartialEq, Debug)]
+        enum Number<'a> {
+            Byte(u8),
+            Short(u16),
+        }
+    }
+
+    let map = anymap![1u8, 2u16, 3u32];
+    let mut numbers: Vec<Number> = Number::iter(&map).collect();
+    assert_eq!(numbers.len(), 3);
+    numbers.retain(|number| !matches!(*number, Number::Other(_)));
+    assert!(numbers.contains(&Number::Byte(&1)) && numbers.contains(&Number::Short(&2)));
+    assert_eq!(Number::from_any(&3u32), Number::Other(TypeId::of::<u32>()));
+}
+
+#[test]
+fn test_validate_against() {
+    let required = TypeSet::new().with::<u8>().with::<u16>().with::<u8>();
+    assert_eq!(required.len(), 2);
+    assert!(type_set![].is_empty());
+
+    let mut map = anymap![1u32];
+    let missing = map.validate_against(&required).err().unwrap().into_types();
+    assert!(missing.contains::<u8>() && missing.contains::<u16>() && missing.len() == 2);
+    let _ = map.insert(1u8);
+    let missing = map.validate_against(&type_set![u8, u16, u32]).err().unwrap();
+    assert!(missing.types().contains::<u16>() && missing.types().len() == 1);
+    let _ = map.insert(1u16);
+    assert!(map.validate_against(&required).is_ok());
+}
+
+#[test]
+fn test_try_get() {
+    let mut map = AnyMap::new();
+    let missing = map.try_get::<u8>().err().unwrap();
+    assert_eq!(missing.type_name(), type_name::<u8>());
+    assert!(missing.to_string().contains(missing.type_name()));
+    let _ = map.insert(1u8);
+    assert_eq!(map.try_get::<u8>(), Ok(&1));
+}
+
+#[test]
+fn test_ptr() {
+    let mut map = AnyMap::new();
+    let _ = map.insert(7u64);
+    let _ = map.insert([1u8, 2, 3]);
+    let ptr = map.get_ptr::<u64>().unwrap();
+    for n in 0..100u16 {
+        let _ = map.insert(vec![n; n as usize]);
+        let _ = map.remove::<Vec<u16>>();
+        let _ = map.insert(n as usize);
+    }
+    assert_eq!(ptr, map.get_ptr::<u64>().unwrap());
+    assert!(map.get_ptr::<i8>().is_none() && map.get_mut_ptr_by_id(TypeId::of::<i8>()).is_none());
+
+    let (bytes, layout) = map.get_ptr_by_id(TypeId::of::<[u8; 3]>()).unwrap();
+    assert_eq!((layout.size(), layout.align()), (3, 1));
+    assert_eq!(bytes.cast::<[u8; 3]>(), map.get_ptr::<[u8; 3]>().unwrap());
+    let (bytes, layout) = map.get_mut_ptr_by_id(TypeId::of::<u64>()).unwrap();
+    assert_eq!((bytes.cast::<u64>(), layout), (ptr, Layout::new::<u64>()));
+    let ptr = map.get_mut_ptr::<u64>().unwrap();
+    assert_eq!(ptr.as_ptr() as *const u64, map.get::<u64>().unwrap() as *const u64);
+}
+
+#[test]
+fn test_insert_in_place() {
+    use std::rc::Rc;
+
+    let mut map = AnyMap::new();
+    assert_eq!(map.insert(vec![1u8]), None);
+    let ptr = map.get_ptr::<Vec<u8>>().unwrap();
+    assert_eq!(map.insert(vec![2u8]), Some(vec![1]));
+    assert_eq!(map.get_ptr::<Vec<u8>>(), Some(ptr));
+
+    let dropped = Rc::new(());
+    map.insert_no_return(dropped.clone());
+    assert_eq!(Rc::strong_count(&dropped), 2);
+    map.insert_no_return(Rc::new(()));
+    assert_eq!(Rc::strong_count(&dropped), 1);
+    map.insert_no_return(vec![3u8]);
+    assert_eq!((map.get::<Vec<u8>>(), map.len()), (Some(&vec![3]), 2));
+    assert_eq!(map.get_ptr::<Vec<u8>>(), Some(ptr));
+}
+
+#[test]
+fn test_shrink_threshold() {
+    let mut map = AnyMap::with_capcity(64);
+    let _ = map.insert(1u8);
+    let _ = map.insert(2u16);
+    let _ = map.remove::<u16>();
+    assert!(map.capacity() >= 64);
+    map.set_shrink_threshold(Some(0.5));
+    assert_eq!(map.shrink_threshold(), Some(0.5));
+    assert!(map.capacity() < 64);
+    let _ = map.insert(1u16);
+    let _ = map.insert(1u32);
+    let _ = map.remove::<u64>();
+    assert!(map.contains::<u32>());
+    map.reserve(64);
+    map.clear();
+    assert_eq!(map.capacity(), 0);
+    map.set_shrink_threshold(None);
+    map.reserve(64);
+    map.clear();
+    assert!(map.capacity() >= 64);
+}
+
+#[test]
+fn test_index() {
+    let map = anymap![1u8];
+    assert_eq!(map[TypeId::of::<u8>()].downcast_ref::<u8>(), Some(&1));
+}
+
+#[test]
+#[should_panic(expected = "no value")]
+fn test_index_missing() {
+    let map = anymap![1u8];
+    let _ = &map[TypeId::of::<u16>()];
+}
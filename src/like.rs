@@ -0,0 +1,368 @@
+//! A trait over the map flavours, through which code can use whichever flavour it is given as a
+//! trait object.
+
+use std::any::{Any, TypeId};
+
+use {AnyMap, cast_box, cast_mut, cast_ref};
+
+/// The part of the map API keyed by `TypeId` rather than by type parameter, and so usable as
+/// `&dyn AnyMapLike` or `&mut dyn AnyMapLike`, by framework code which works with whichever
+/// flavour the application chose.
+///
+/// It is implemented by `AnyMap` and by every flavour. A flavour refuses a change it does not
+/// allow, such as one to a frozen entry, or an insertion of a value it cannot check the bounds
+/// of, as into a `SyncAnyMap`; one which does not store its values as they are, such as a
+/// `CellAnyMap` or a `WeakAnyMap`, cannot lend them out through every method either. What each
+/// flavour refuses, and why, is documented on its impl. Typed forms of the methods are provided
+/// on `dyn AnyMapLike`.
+///
+/// Boxed values are inserted with `insert_raw`, for symmetry with the other methods, rather
+/// than `insert_boxed`.
+///
+/// ```rust
+/// # use anymap::AnyMap;
+/// # use anymap::like::AnyMapLike;
+/// # use anymap::observe::ObservedAnyMap;
+/// struct RequestCount(u32);
+///
+/// fn count(extensions: &mut dyn AnyMapLike) {
+///     match extensions.get_mut::<RequestCount>() {
+///         Some(count) => count.0 += 1,
+///         None => { let _ = extensions.insert(RequestCount(1)); },
+///     }
+/// }
+///
+/// let mut plain = AnyMap::new();
+/// let mut observed = ObservedAnyMap::new();
+/// count(&mut plain);
+/// count(&mut observed);
+/// count(&mut observed);
+/// assert_eq!(plain.get::<RequestCount>().unwrap().0, 1);
+/// assert_eq!(observed.get::<RequestCount>().unwrap().0, 2);
+/// ```
+pub trait AnyMapLike {
+    /// Returns true if the collection contains a value of the type with the given ID.
+    fn contains_id(&self, id: TypeId) -> bool;
+
+    /// Returns a reference to the value stored in the collection for the type with the given
+    /// ID, if it exists.
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any>;
+
+    /// Returns a mutable reference to the value stored in the collection for the type with the
+    /// given ID, if it exists and the flavour allows it to be changed.
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any>;
+
+    /// Sets the value stored in the collection for the type of the boxed value, returning the
+    /// old value if there was one, or handing the new value back if the flavour refuses it.
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>>;
+
+    /// Removes the value of the type with the given ID from the collection, returning it if
+    /// there was one and the flavour allows it to be removed.
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>>;
+
+    /// Returns the number of items in the collection.
+    fn len(&self) -> usize;
+
+    /// Returns true if there are no items in the collection.
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a> dyn AnyMapLike + 'a {
+    /// Returns true if the collection contains a value of type `T`.
+    #[inline]
+    pub fn contains<T: Any + 'static>(&self) -> bool {
+        self.contains_id(TypeId::of::<T>())
+    }
+
+    /// Returns a reference to the value stored in the collection for the type `T`, if it exists.
+    #[inline]
+    pub fn get<T: Any + 'static>(&self) -> Option<&T> {
+        self.get_raw(TypeId::of::<T>()).map(cast_ref::<T>)
+    }
+
+    /// Returns a mutable reference to the value stored in the collection for the type `T`,
+    /// if it exists and the flavour allows it to be changed.
+    #[inline]
+    pub fn get_mut<T: Any + 'static>(&mut self) -> Option<&mut T> {
+        self.get_raw_mut(TypeId::of::<T>()).map(cast_mut::<T>)
+    }
+
+    /// Sets the value stored in the collection for the type `T`, returning the old value if
+    /// there was one, or handing the new value back if the flavour refuses it.
+    pub fn insert<T: Any + 'static>(&mut self, value: T) -> Result<Option<T>, T> {
+        match self.insert_raw(Box::new(value)) {
+            Ok(old) => Ok(old.map(|old| *cast_box::<T>(old))),
+            Err(value) => Err(*cast_box::<T>(value)),
+        }
+    }
+
+    /// Removes the `T` value from the collection, returning it if there was one and the flavour
+    /// allows it to be removed.
+    #[inline]
+    pub fn remove<T: Any + 'static>(&mut self) -> Option<T> {
+        self.remove_raw(TypeId::of::<T>()).map(|value| *cast_box::<T>(value))
+    }
+}
+
+impl AnyMapLike for AnyMap {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.data.contains_key(&id)
+    }
+
+    #[inline]
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.data.get(&id).map(|value| &**value)
+    }
+
+    #[inline]
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        self.data.get_mut(&id).map(|value| &mut **value)
+    }
+
+    #[inline]
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        Ok(self.insert_by_id((*value).type_id(), value))
+    }
+
+    #[inline]
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        self.remove_by_id(&id)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+#[test]
+fn test_like() {
+    use budget::BudgetedAnyMap;
+    use capped::CappedAnyMap;
+    use frozen::FrozenAnyMap;
+    use layered::LayeredAnyMap;
+    use lru::LruAnyMap;
+    use observe::ObservedAnyMap;
+    use ordered::OrderedAnyMap;
+    use protect::ProtectedAnyMap;
+    use sync::SyncAnyMap;
+    use testing::SpyAnyMap;
+    use timed::TimedAnyMap;
+    use ttl::TtlAnyMap;
+
+    /// Exercises a map which allows every change.
+    fn exercise(map: &mut dyn AnyMapLike) {
+        assert!(map.is_empty());
+        assert_eq!(map.insert(1u8), Ok(None));
+        assert_eq!(map.insert(2u8), Ok(Some(1)));
+        *map.get_mut::<u8>().unwrap() += 1;
+        assert_eq!(map.insert(1u16), Ok(None));
+        assert!(map.contains::<u16>() && !map.contains::<u32>());
+        assert_eq!((map.get::<u8>(), map.len()), (Some(&3), 2));
+        assert_eq!(map.remove::<u16>(), Some(1));
+        assert_eq!(map.remove::<u16>(), None);
+        assert_eq!(map.len(), 1);
+    }
+
+    let mut root = LayeredAnyMap::new();
+    let _ = root.insert(1i8);
+    let mut layered = root.child();
+    assert_eq!((&layered as &dyn AnyMapLike).get::<i8>(), Some(&1));
+    assert!((&mut layered as &mut dyn AnyMapLike).remove::<i8>().is_none());
+    let _ = root.remove::<i8>();
+    let maps: Vec<Box<dyn AnyMapLike>> = vec![
+        Box::new(AnyMap::new()),
+        Box::new(ObservedAnyMap::new()),
+        Box::new(ProtectedAnyMap::new()),
+        Box::new(SpyAnyMap::new()),
+        Box::new(LayeredAnyMap::new()),
+        Box::new(OrderedAnyMap::new()),
+        Box::new(CappedAnyMap::new(2)),
+        Box::new(LruAnyMap::new(2)),
+        Box::new(TtlAnyMap::new()),
+        Box::new(TimedAnyMap::new()),
+    ];
+    for mut map in maps {
+        exercise(&mut *map);
+    }
+
+    let mut sync = SyncAnyMap::new();
+    let _ = sync.insert(1u8);
+    let sync: &mut dyn AnyMapLike = &mut sync;
+    assert_eq!(sync.insert(2u8), Err(2));
+    *sync.get_mut::<u8>().unwrap() += 1;
+    assert_eq!(sync.remove::<u8>(), Some(2));
+
+    let mut plain = AnyMap::new();
+    let _ = plain.insert(1u8);
+    let mut frozen: FrozenAnyMap = plain.optimize_for_reads();
+    let frozen: &mut dyn AnyMapLike = &mut frozen;
+    assert_eq!((frozen.get::<u8>(), frozen.len()), (Some(&1), 1));
+    assert!(frozen.get_mut::<u8>().is_none() && frozen.remove::<u8>().is_none());
+    assert_eq!(frozen.insert(2u8), Err(2));
+
+    let mut protected = ProtectedAnyMap::new();
+    let _ = protected.insert(1u8);
+    let _ = protected.freeze_entry::<u8>();
+    let protected: &mut dyn AnyMapLike = &mut protected;
+    assert!(protected.get_mut::<u8>().is_none() && protected.remove::<u8>().is_none());
+    assert_eq!(protected.insert(2u8), Err(2));
+
+    let mut capped = CappedAnyMap::new(1);
+    let capped: &mut dyn AnyMapLike = &mut capped;
+    assert_eq!(capped.insert(1u8), Ok(None));
+    assert_eq!(capped.insert(1u16), Err(1));
+
+    let mut lru = LruAnyMap::new(1);
+    let _ = lru.insert(1u8);
+    let lru: &mut dyn AnyMapLike = &mut lru;
+    assert_eq!(lru.insert(1u16), Ok(None));
+    assert!(!lru.contains::<u8>() && lru.contains::<u16>());
+
+    let mut budgeted = BudgetedAnyMap::new(2);
+    let budgeted: &mut dyn AnyMapLike = &mut budgeted;
+    assert_eq!(budgeted.insert(1u16), Ok(None));
+    assert_eq!(budgeted.insert(1u32), Err(1));
+    assert!(budgeted.get_mut::<u16>().is_none());
+    assert_eq!(budgeted.get::<u16>(), Some(&1));
+    assert_eq!(budgeted.remove::<u16>(), Some(1));
+}
+
+#[test]
+fn test_like_wrapping() {
+    use bounded::{BoundedAnyMap, Storable};
+    use cell::CellAnyMap;
+    use clock::ManualClock;
+    use concurrent::ConcurrentAnyMap;
+    use cow::CowAnyMap;
+    use record::{Op, RecordingAnyMap, replay};
+    use registry::TypeRegistry;
+    use shared::SharedAnyMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use ttl::TtlAnyMap;
+    use weak::WeakAnyMap;
+    use UNKNOWN_TYPE;
+
+    let clock = ManualClock::new();
+    let mut ttl = TtlAnyMap::with_clock(clock.clone());
+    let _ = ttl.insert_with_ttl(1u8, Duration::from_secs(1));
+    clock.advance(Duration::from_secs(1));
+    let ttl: &mut dyn AnyMapLike = &mut ttl;
+    assert!(!ttl.contains::<u8>() && ttl.get::<u8>().is_none());
+    assert_eq!(ttl.len(), 1);
+    assert!(ttl.get_mut::<u8>().is_none());
+    assert!(ttl.is_empty());
+
+    enum Marker { }
+    impl Storable<Marker> for u8 { }
+    let mut bounded = BoundedAnyMap::<Marker>::new();
+    let _ = bounded.insert(1u8);
+    let bounded: &mut dyn AnyMapLike = &mut bounded;
+    assert_eq!(bounded.insert(2u8), Err(2));
+    *bounded.get_mut::<u8>().unwrap() += 1;
+    assert_eq!(bounded.remove::<u8>(), Some(2));
+
+    let mut cell = CellAnyMap::new();
+    let _ = cell.insert(1u8);
+    let cell: &mut dyn AnyMapLike = &mut cell;
+    assert!(cell.contains::<u8>() && cell.get::<u8>().is_none());
+    *cell.get_mut::<u8>().unwrap() += 1;
+    assert_eq!(cell.insert(3u8), Err(3));
+    assert_eq!((cell.remove::<u8>(), cell.len()), (Some(2), 0));
+
+    let numbers = Arc::new(vec![1u8]);
+    let mut shared = SharedAnyMap::new();
+    let _ = shared.insert_shared(numbers.clone());
+    let shared: &mut dyn AnyMapLike = &mut shared;
+    assert_eq!(shared.get::<Vec<u8>>(), Some(&vec![1]));
+    shared.get_mut::<Vec<u8>>().unwrap().push(2);
+    assert_eq!(*numbers, vec![1]);
+    assert_eq!(shared.insert(1u16), Err(1));
+    assert_eq!(shared.remove::<Vec<u8>>(), Some(vec![1, 2]));
+
+    let mut weak = WeakAnyMap::new();
+    let _ = weak.insert(&numbers);
+    let weak: &mut dyn AnyMapLike = &mut weak;
+    assert!(weak.contains::<Vec<u8>>() && weak.get::<Vec<u8>>().is_none());
+    assert!(weak.get_mut::<Vec<u8>>().is_none() && weak.remove::<Vec<u8>>().is_none());
+    assert_eq!(weak.insert(vec![2u8]), Err(vec![2]));
+    drop(numbers);
+    assert!(!weak.contains::<Vec<u8>>() && weak.len() == 1);
+
+    let mut concurrent = ConcurrentAnyMap::new();
+    let _ = concurrent.insert(1u8);
+    {
+        let like: &mut dyn AnyMapLike = &mut concurrent;
+        assert!(like.contains::<u8>() && like.get::<u8>().is_none());
+        *like.get_mut::<u8>().unwrap() += 1;
+        assert_eq!(like.insert(1u16), Err(1));
+    }
+    assert_eq!((concurrent.get_cloned::<u8>(), concurrent.version::<u8>()), (Some(2), 2));
+    let like: &mut dyn AnyMapLike = &mut concurrent;
+    assert_eq!((like.remove::<u8>(), like.len()), (Some(2), 0));
+
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<u8>("u8")
+        .codec(|&n: &u8| vec![n], |bytes| if bytes.len() == 1 { Some(bytes[0]) } else { None });
+    let mut recording = RecordingAnyMap::new(&registry);
+    {
+        let like: &mut dyn AnyMapLike = &mut recording;
+        assert_eq!(like.insert(1u8), Ok(None));
+        assert!(like.get_mut::<u8>().is_none());
+        assert_eq!((like.get::<u8>(), like.len()), (Some(&1), 1));
+        assert_eq!(like.insert(1u16), Ok(None));
+        assert_eq!(like.remove::<u16>(), Some(1));
+        assert_eq!(like.remove::<u16>(), None);
+    }
+    assert_eq!(recording.log()[0], Op::Insert { name: "u8".to_string(), data: Some(vec![1]) });
+    assert_eq!(recording.log()[2], Op::Remove { name: UNKNOWN_TYPE.to_string() });
+    assert_eq!(recording.log().len(), 3);
+    let mut replayed = AnyMap::new();
+    assert_eq!(replay(&recording.log()[..1], &registry, &mut replayed), Ok(()));
+    assert_eq!(replayed.get::<u8>(), Some(&1));
+
+    let mut template = SharedAnyMap::new();
+    let _ = template.insert(1u8);
+    let template = CowAnyMap::new(template);
+    let mut child = template.clone();
+    {
+        let like: &mut dyn AnyMapLike = &mut child;
+        assert!(like.get_mut::<u16>().is_none() && like.remove::<u16>().is_none());
+        assert_eq!(like.get::<u8>(), Some(&1));
+    }
+    assert!(child.is_shared());
+    {
+        let like: &mut dyn AnyMapLike = &mut child;
+        *like.get_mut::<u8>().unwrap() += 1;
+        assert_eq!(like.insert(1u16), Err(1));
+    }
+    assert!(!child.is_shared());
+    assert_eq!((template.get::<u8>(), child.get::<u8>()), (Some(&1), Some(&2)));
+    let like: &mut dyn AnyMapLike = &mut child;
+    assert_eq!((like.remove::<u8>(), like.len()), (Some(2), 0));
+    assert_eq!(template.len(), 1);
+}
+
+#[cfg(feature = "zeroize")]
+#[test]
+fn test_like_zeroizing() {
+    use sensitive::ZeroizingAnyMap;
+
+    let mut secrets = ZeroizingAnyMap::new();
+    let _ = secrets.insert(vec![1u8]);
+    {
+        let like: &mut dyn AnyMapLike = &mut secrets;
+        assert_eq!(like.insert(vec![2u8]), Err(vec![2]));
+        like.get_mut::<Vec<u8>>().unwrap().push(2);
+        assert!(like.remove::<Vec<u8>>().is_none());
+        assert_eq!((like.get::<Vec<u8>>(), like.len()), (Some(&vec![1, 2]), 1));
+    }
+    assert_eq!(*secrets.remove::<Vec<u8>>().unwrap(), vec![1, 2]);
+}
@@ -0,0 +1,339 @@
+//! An `AnyMap` flavour bounded in size, evicting the least recently used type when full.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+use instrument::Instruments;
+use like::AnyMapLike;
+use {CallbackPanic, EvictionCallback, TypeIdBuildHasher, UNKNOWN_TYPE, call_on_evict, cast_box,
+     cast_mut, cast_ref, type_name};
+
+/// A value together with the time it was last used.
+struct Slot {
+    value: Box<dyn Any + 'static>,
+    /// The name of the type of the value, for diagnostics.
+    name: &'static str,
+    last_used: u64,
+}
+
+/// A collection containing zero or one values for any given type, holding at most a fixed number
+/// of values: inserting a new type into a full map evicts the type which was least recently
+/// inserted or accessed, among the types of the lowest eviction priority set by `set_priority`.
+///
+/// Evicted values are dropped, unless a callback has been registered with `on_evict` to take
+/// them.
+///
+/// ```rust
+/// # use anymap::lru::LruAnyMap;
+/// let mut cache = LruAnyMap::new(2);
+/// cache.insert(1i32);
+/// cache.insert(2u8);
+/// cache.get::<i32>();
+/// cache.insert(3u16);
+/// assert!(cache.contains::<i32>());
+/// assert!(!cache.contains::<u8>());
+/// ```
+pub struct LruAnyMap {
+    data: HashMap<TypeId, Slot, TypeIdBuildHasher>,
+    max_entries: usize,
+    clock: u64,
+    on_evict: Option<EvictionCallback>,
+    evict_panics: Vec<CallbackPanic>,
+    priorities: HashMap<TypeId, u32, TypeIdBuildHasher>,
+    instruments: Instruments,
+}
+
+impl LruAnyMap {
+    /// Construct a new `LruAnyMap` holding at most `max_entries` values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_entries` is zero.
+    #[inline]
+    pub fn new(max_entries: usize) -> LruAnyMap {
+        assert!(max_entries > 0, "an LruAnyMap must be able to hold at least one entry");
+        LruAnyMap {
+            data: HashMap::with_capacity_and_hasher(max_entries, TypeIdBuildHasher),
+            max_entries,
+            clock: 0,
+            on_evict: None,
+            evict_panics: Vec::new(),
+            priorities: HashMap::with_hasher(TypeIdBuildHasher),
+            instruments: Instruments::new(),
+        }
+    }
+
+    /// Registers a callback to be given each value evicted to make room, replacing any callback
+    /// registered before. Values removed explicitly are not passed to it.
+    ///
+    /// A callback which panics does not unwind through the map: the panic is caught, the
+    /// callback unregistered, and the panic recorded, to be collected with `take_evict_panics`.
+    pub fn on_evict<F: FnMut(TypeId, Box<dyn Any + 'static>) + 'static>(&mut self, callback: F) {
+        self.on_evict = Some(Box::new(callback) as EvictionCallback);
+    }
+
+    /// Returns the panics of eviction callbacks caught since the last call, in the order they
+    /// happened, leaving none recorded.
+    #[inline]
+    pub fn take_evict_panics(&mut self) -> Vec<CallbackPanic> {
+        std::mem::take(&mut self.evict_panics)
+    }
+
+    /// Sets the eviction priority of the type `T`, zero by default. Values of the types of the
+    /// lowest priority are evicted first, the least recently used first among them, so giving
+    /// a type which is expensive to recompute a higher priority protects it from eviction for
+    /// as long as there are values of lower priority to evict instead.
+    pub fn set_priority<T: Any + 'static>(&mut self, priority: u32) {
+        let id = TypeId::of::<T>();
+        if priority == 0 {
+            let _ = self.priorities.remove(&id);
+        } else {
+            let _ = self.priorities.insert(id, priority);
+        }
+    }
+
+    /// Returns the eviction priority of the type `T`.
+    #[inline]
+    pub fn priority<T: Any + 'static>(&self) -> u32 {
+        self.priorities.get(&TypeId::of::<T>()).cloned().unwrap_or(0)
+    }
+
+    /// Sets the prefix of the names of the metrics emitted for this map, `anymap` by default.
+    ///
+    /// With the `metrics` feature, each eviction increments the counter `<prefix>.evictions`,
+    /// labelled with the name of the type evicted.
+    #[cfg(feature = "metrics")]
+    #[inline]
+    pub fn set_metrics_prefix(&mut self, prefix: &str) {
+        self.instruments.set_metrics_prefix(prefix)
+    }
+
+    /// Returns the maximum number of values the map will hold.
+    #[inline]
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    /// Changes the maximum number of values the map will hold, evicting the least recently used
+    /// values if there are now too many.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_entries` is zero.
+    pub fn set_max_entries(&mut self, max_entries: usize) {
+        assert!(max_entries > 0, "an LruAnyMap must be able to hold at least one entry");
+        self.max_entries = max_entries;
+        while self.data.len() > max_entries {
+            self.evict();
+        }
+    }
+
+    /// Returns a reference to the value stored in the collection for the type `T`, if it exists,
+    /// marking it as the most recently used.
+    pub fn get<T: Any + 'static>(&mut self) -> Option<&T> {
+        let now = self.tick();
+        self.data.get_mut(&TypeId::of::<T>()).map(|slot| {
+            slot.last_used = now;
+            cast_ref::<T>(&*slot.value)
+        })
+    }
+
+    /// Returns a mutable reference to the value stored in the collection for the type `T`,
+    /// if it exists, marking it as the most recently used.
+    pub fn get_mut<T: Any + 'static>(&mut self) -> Option<&mut T> {
+        let now = self.tick();
+        self.data.get_mut(&TypeId::of::<T>()).map(|slot| {
+            slot.last_used = now;
+            cast_mut::<T>(&mut *slot.value)
+        })
+    }
+
+    /// Returns a reference to the value stored in the collection for the type `T`, if it exists,
+    /// without marking it as used.
+    pub fn peek<T: Any + 'static>(&self) -> Option<&T> {
+        self.data.get(&TypeId::of::<T>())
+            .map(|slot| cast_ref::<T>(&*slot.value))
+    }
+
+    /// Returns true if the collection contains a value of type `T`. This does not count as a use.
+    #[inline]
+    pub fn contains<T: Any + 'static>(&self) -> bool {
+        self.data.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Sets the value stored in the collection for the type `T`, marking it as the most recently
+    /// used. If the collection is full and has no `T` yet, the least recently used value is
+    /// evicted to make room.
+    ///
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    #[inline]
+    pub fn insert<T: Any + 'static>(&mut self, value: T) -> Option<T> {
+        self.insert_any(TypeId::of::<T>(), type_name::<T>(), Box::new(value) as Box<dyn Any>)
+            .map(|any| *cast_box::<T>(any))
+    }
+
+    /// Removes the `T` value from the collection,
+    /// returning it if there was one or `None` if there was not.
+    pub fn remove<T: Any + 'static>(&mut self) -> Option<T> {
+        self.data.remove(&TypeId::of::<T>())
+            .map(|slot| *cast_box::<T>(slot.value))
+    }
+
+    /// Returns the number of items in the collection.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns true if there are no items in the collection.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Removes all items from the collection. Keeps the allocated memory for reuse.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    fn insert_any(&mut self, id: TypeId, name: &'static str, value: Box<dyn Any + 'static>)
+                  -> Option<Box<dyn Any + 'static>> {
+        if self.data.len() >= self.max_entries && !self.data.contains_key(&id) {
+            self.evict();
+        }
+        let slot = Slot {
+            value,
+            name,
+            last_used: self.tick(),
+        };
+        self.data.insert(id, slot).map(|slot| slot.value)
+    }
+
+    /// Advances the clock, returning the new time.
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Evicts the least recently used value of the lowest priority, if there are any values.
+    fn evict(&mut self) {
+        let mut oldest: Option<(TypeId, (u32, u64))> = None;
+        for (&id, slot) in self.data.iter() {
+            let rank = (self.priorities.get(&id).cloned().unwrap_or(0), slot.last_used);
+            match oldest {
+                Some((_, oldest_rank)) if oldest_rank <= rank => (),
+                _ => oldest = Some((id, rank)),
+            }
+        }
+        if let Some((id, _)) = oldest {
+            let slot = self.data.remove(&id).unwrap();
+            self.instruments.evicted(id, slot.name);
+            call_on_evict(&mut self.on_evict, &mut self.evict_panics, id, slot.value);
+        }
+    }
+}
+
+/// Lookups through `AnyMapLike` by shared reference are peeks, which do not count as uses;
+/// mutable lookups and insertions mark the value as the most recently used, and insertions
+/// evict to make room, as with the typed methods.
+impl AnyMapLike for LruAnyMap {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.data.contains_key(&id)
+    }
+
+    #[inline]
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.data.get(&id).map(|slot| &*slot.value)
+    }
+
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        let now = self.tick();
+        self.data.get_mut(&id).map(|slot| {
+            slot.last_used = now;
+            &mut *slot.value
+        })
+    }
+
+    #[inline]
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        Ok(self.insert_any((*value).type_id(), UNKNOWN_TYPE, value))
+    }
+
+    #[inline]
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        self.data.remove(&id).map(|slot| slot.value)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+#[test]
+fn test_lru() {
+    let mut map = LruAnyMap::new(2);
+    assert_eq!(map.insert(1i32), None);
+    assert_eq!(map.insert(1u8), None);
+    assert_eq!(map.insert(2i32), Some(1));
+    assert_eq!(map.get::<u8>(), Some(&1));
+    assert_eq!(map.len(), 2);
+
+    // i32 is now the least recently used; peeking at it doesn’t change that.
+    assert_eq!(map.peek::<i32>(), Some(&2));
+    assert_eq!(map.insert(1u16), None);
+    assert_eq!(map.len(), 2);
+    assert!(!map.contains::<i32>());
+    assert!(map.contains::<u8>() && map.contains::<u16>());
+
+    *map.get_mut::<u8>().unwrap() = 2;
+    map.set_max_entries(1);
+    assert_eq!(map.peek::<u8>(), Some(&2));
+    assert_eq!(map.remove::<u8>(), Some(2));
+    assert!(map.is_empty());
+}
+
+#[test]
+fn test_on_evict() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let evicted = Rc::new(RefCell::new(Vec::new()));
+    let log = evicted.clone();
+    let mut map = LruAnyMap::new(1);
+    map.on_evict(move |id, value| log.borrow_mut().push((id, *value.downcast::<i32>().unwrap())));
+    let _ = map.insert(1i32);
+    let _ = map.insert(2i32);
+    let _ = map.insert(3u8);
+    let _ = map.remove::<u8>();
+    assert_eq!(*evicted.borrow(), vec![(TypeId::of::<i32>(), 2)]);
+
+    map.on_evict(|_, _| panic!("bad callback"));
+    let _ = map.insert(4u8);
+    let _ = map.insert(5u16);
+    let _ = map.insert(6u32);
+    assert!(map.contains::<u32>() && map.len() == 1);
+    assert_eq!(map.take_evict_panics(), vec![CallbackPanic {
+        type_id: TypeId::of::<u8>(),
+        message: Some("bad callback".to_string()),
+    }]);
+    assert!(map.take_evict_panics().is_empty());
+}
+
+#[test]
+fn test_lru_priority() {
+    let mut map = LruAnyMap::new(2);
+    map.set_priority::<i32>(1);
+    assert_eq!((map.priority::<i32>(), map.priority::<u8>()), (1, 0));
+    let _ = map.insert(1i32);
+    let _ = map.insert(1u8);
+    let _ = map.insert(1u16);
+    assert!(map.contains::<i32>() && !map.contains::<u8>());
+    map.set_priority::<i32>(0);
+    let _ = map.insert(1u32);
+    assert!(!map.contains::<i32>() && map.contains::<u16>());
+}
@@ -0,0 +1,727 @@
+//! An `AnyMap` wrapper which notifies registered hooks whenever its contents change.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::collections::hash_map;
+use std::future::Future;
+use std::ops::Deref;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::task::{Context, Poll, Waker};
+
+use {AnyMap, TypeIdBuildHasher, TypeSet, UNKNOWN_TYPE, cast_ref, panic_message, type_name};
+use invoke::{Access, Invoke, InvokeError};
+use like::AnyMapLike;
+
+/// A hook invoked with the type and value of an entry, returning false once it wants to be
+/// unregistered.
+type Hook = Box<dyn FnMut(TypeId, &dyn Any) -> bool + 'static>;
+
+/// A handle to a hook registered on an `ObservedAnyMap`, used to unregister it again.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct HookHandle(usize);
+
+/// An `AnyMap` which invokes registered hooks whenever a value is inserted, replaced or removed.
+///
+/// All of the non-mutating `AnyMap` methods are available through `Deref`; the mutating methods
+/// are reimplemented here so that no change can slip past the hooks.
+///
+/// Each type slot also carries a version number, bumped on every insertion, removal and mutable
+/// access, which makes for cheap change detection (“recompute only if the version moved”).
+/// The same changes are collected into a set which can be drained in batches with
+/// `take_changed`.
+///
+/// A hook which panics does not unwind through the map, which is left as if the hook had
+/// returned: the panic is caught, the hook unregistered, and the panic recorded, to be
+/// collected with `take_hook_panics`.
+///
+/// ```rust
+/// # use anymap::observe::ObservedAnyMap;
+/// # use std::cell::Cell;
+/// # use std::rc::Rc;
+/// let mut data = ObservedAnyMap::new();
+/// let changes = Rc::new(Cell::new(0));
+/// let counter = changes.clone();
+/// let handle = data.on_insert(move |_, _| counter.set(counter.get() + 1));
+/// data.insert(42i32);
+/// data.insert(43i32);
+/// assert_eq!(changes.get(), 2);
+/// data.unhook(handle);
+/// data.insert(44i32);
+/// assert_eq!(changes.get(), 2);
+/// ```
+pub struct ObservedAnyMap {
+    map: AnyMap,
+    insert_hooks: Vec<(HookHandle, Hook)>,
+    remove_hooks: Vec<(HookHandle, Hook)>,
+    next_hook: usize,
+    tracking: Tracking,
+    hook_panics: Vec<HookPanic>,
+}
+
+impl Default for ObservedAnyMap {
+    #[inline]
+    fn default() -> ObservedAnyMap {
+        ObservedAnyMap::new()
+    }
+}
+
+impl ObservedAnyMap {
+    /// Construct a new `ObservedAnyMap` with no hooks registered.
+    #[inline]
+    pub fn new() -> ObservedAnyMap {
+        ObservedAnyMap::from_map(AnyMap::new())
+    }
+
+    /// Wrap an existing `AnyMap`. Values already in it are not reported to any hooks.
+    #[inline]
+    pub fn from_map(map: AnyMap) -> ObservedAnyMap {
+        ObservedAnyMap {
+            map,
+            insert_hooks: Vec::new(),
+            remove_hooks: Vec::new(),
+            next_hook: 0,
+            tracking: Tracking {
+                versions: HashMap::with_hasher(TypeIdBuildHasher),
+                changed: TypeSet::new(),
+            },
+            hook_panics: Vec::new(),
+        }
+    }
+
+    /// Unwraps the underlying `AnyMap`, dropping all hooks.
+    #[inline]
+    pub fn into_inner(self) -> AnyMap {
+        self.map
+    }
+
+    /// Registers a hook invoked with the new value whenever a value is inserted, including when
+    /// it replaces an existing value of the same type.
+    pub fn on_insert<F: FnMut(TypeId, &dyn Any) + 'static>(&mut self, mut hook: F) -> HookHandle {
+        let handle = self.next_handle();
+        let hook = move |id, value: &dyn Any| { hook(id, value); true };
+        self.insert_hooks.push((handle, Box::new(hook) as Hook));
+        handle
+    }
+
+    /// Registers a hook invoked with the old value whenever a value is removed from the map.
+    pub fn on_remove<F: FnMut(TypeId, &dyn Any) + 'static>(&mut self, mut hook: F) -> HookHandle {
+        let handle = self.next_handle();
+        let hook = move |id, value: &dyn Any| { hook(id, value); true };
+        self.remove_hooks.push((handle, Box::new(hook) as Hook));
+        handle
+    }
+
+    /// Subscribes to changes of the `T` entry.
+    ///
+    /// Every subsequent insertion of a `T` sends a clone of the new value to the returned
+    /// `Subscription`, and every removal sends `Update::Removed`. The subscription unregisters
+    /// itself the next time the entry changes after it has been dropped.
+    ///
+    /// Updates can be awaited with `changed`, from a task on any executor. As the map is not
+    /// `Send`, the blocking `recv` is of use only on another thread than the map's: on its own
+    /// thread, nothing could ever send the update it waits for.
+    pub fn subscribe<T: Any + Clone + 'static>(&mut self) -> Subscription<T> {
+        let (tx, rx) = channel();
+        let waker = Arc::new(Mutex::new(None));
+        let notifier = Rc::new(Notifier { updates: Some(tx), waker: waker.clone() });
+        let removals = notifier.clone();
+        let id = TypeId::of::<T>();
+        let handle = self.next_handle();
+        self.insert_hooks.push((handle, Box::new(move |changed, value: &dyn Any| {
+            changed != id || notifier.send(Update::Set(cast_ref::<T>(value).clone()))
+        }) as Hook));
+        self.remove_hooks.push((handle, Box::new(move |changed, _: &dyn Any| {
+            changed != id || removals.send(Update::Removed)
+        }) as Hook));
+        Subscription { updates: rx, waker }
+    }
+
+    /// Unregisters a hook, returning true if it was still registered.
+    pub fn unhook(&mut self, handle: HookHandle) -> bool {
+        let before = self.insert_hooks.len() + self.remove_hooks.len();
+        self.insert_hooks.retain(|&(h, _)| h != handle);
+        self.remove_hooks.retain(|&(h, _)| h != handle);
+        before != self.insert_hooks.len() + self.remove_hooks.len()
+    }
+
+    /// Returns the panics of hooks caught since the last call, in the order they happened,
+    /// leaving none recorded.
+    #[inline]
+    pub fn take_hook_panics(&mut self) -> Vec<HookPanic> {
+        std::mem::take(&mut self.hook_panics)
+    }
+
+    /// Returns the current version of the `T` slot.
+    ///
+    /// This starts at zero and increases every time a `T` is inserted or removed or mutable
+    /// access to the `T` is taken; it is never reset, even by removing the value.
+    #[inline]
+    pub fn version<T: Any + 'static>(&self) -> u64 {
+        self.tracking.version(&TypeId::of::<T>())
+    }
+
+    /// Returns the set of types which have changed (in the sense that their version has been
+    /// bumped) since the last call, leaving the change set empty.
+    #[inline]
+    pub fn take_changed(&mut self) -> TypeSet {
+        std::mem::take(&mut self.tracking.changed)
+    }
+
+    /// Returns a mutable reference to the value stored in the collection for the type `T`,
+    /// if it exists.
+    ///
+    /// This bumps the version of the `T` slot whether or not the value is then modified, but
+    /// hooks are *not* invoked for changes made through this reference; use `insert` if the
+    /// change needs to be observed.
+    #[inline]
+    pub fn get_mut<T: Any + 'static>(&mut self) -> Option<&mut T> {
+        let value = self.map.get_mut::<T>();
+        if value.is_some() {
+            self.tracking.touch(TypeId::of::<T>(), type_name::<T>());
+        }
+        value
+    }
+
+    /// Sets the value stored in the collection for the type `T`, invoking the insert hooks.
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    pub fn insert<T: Any + 'static>(&mut self, value: T) -> Option<T> {
+        self.tracking.touch(TypeId::of::<T>(), type_name::<T>());
+        fire(&mut self.insert_hooks, &mut self.remove_hooks, &mut self.hook_panics,
+             TypeId::of::<T>(), &value);
+        self.map.insert(value)
+    }
+
+    /// Removes the `T` value from the collection, invoking the remove hooks,
+    /// returning it if there was one or `None` if there was not.
+    pub fn remove<T: Any + 'static>(&mut self) -> Option<T> {
+        let value = self.map.remove::<T>();
+        if let Some(ref value) = value {
+            self.tracking.touch(TypeId::of::<T>(), type_name::<T>());
+            fire(&mut self.remove_hooks, &mut self.insert_hooks, &mut self.hook_panics,
+                 TypeId::of::<T>(), value);
+        }
+        value
+    }
+
+    /// Removes all items from the collection, invoking the remove hooks for each of them.
+    /// Keeps the allocated memory for reuse.
+    pub fn clear(&mut self) {
+        for (id, value) in self.map.data.drain() {
+            self.tracking.touch(id, UNKNOWN_TYPE);
+            fire(&mut self.remove_hooks, &mut self.insert_hooks, &mut self.hook_panics,
+                 id, &*value);
+        }
+        self.map.instruments.resized(0);
+        self.map.shrink_if_sparse();
+    }
+
+    /// Calls a function with its parameters borrowed from the map, as by `AnyMap::invoke`, if
+    /// any value referred to by a `Changed` parameter has changed since the function was last
+    /// run with the same `LastRun`, returning `None` if it has not. A function with no
+    /// `Changed` parameters is always run.
+    ///
+    /// This bumps the version of every value referred to by a mutable parameter if the function
+    /// is run, but hooks are *not* invoked for the changes it makes.
+    ///
+    /// ```rust
+    /// # use anymap::invoke::Changed;
+    /// # use anymap::observe::{LastRun, ObservedAnyMap};
+    /// struct Input(u32);
+    /// struct Output(u32);
+    ///
+    /// let mut data = ObservedAnyMap::new();
+    /// data.insert(Input(1));
+    /// data.insert(Output(0));
+    /// let mut last_run = LastRun::new();
+    /// let double = |data: &mut ObservedAnyMap, last_run: &mut LastRun| {
+    ///     data.invoke_if_changed(last_run, |input: Changed<Input>, output: &mut Output| {
+    ///         output.0 = input.0 * 2;
+    ///     }).unwrap().is_some()
+    /// };
+    /// assert!(double(&mut data, &mut last_run));
+    /// assert!(!double(&mut data, &mut last_run));
+    /// data.get_mut::<Input>().unwrap().0 = 5;
+    /// assert!(double(&mut data, &mut last_run));
+    /// assert_eq!(data.get::<Output>().unwrap().0, 10);
+    /// ```
+    pub fn invoke_if_changed<'a, Args, F>(&'a mut self, last_run: &mut LastRun, function: F)
+        -> Result<Option<<F as Invoke<'a, Args>>::Output>, InvokeError>
+    where F: Invoke<'a, Args> {
+        let params = <F as Invoke<'a, Args>>::params();
+        let watched: Vec<(TypeId, u64)> = params.iter()
+            .filter(|&&(_, _, access)| access == Access::Changed)
+            .map(|&(id, _, _)| (id, self.tracking.version(&id)))
+            .collect();
+        if !last_run.is_due(&watched) {
+            return Ok(None);
+        }
+        let output = function.invoke(&mut self.map)?;
+        for &(id, name, access) in params.iter() {
+            if access == Access::Mut {
+                self.tracking.touch(id, name);
+            }
+        }
+        last_run.seen = Some(watched.into_iter().collect());
+        Ok(Some(output))
+    }
+
+    fn next_handle(&mut self) -> HookHandle {
+        self.next_hook += 1;
+        HookHandle(self.next_hook)
+    }
+}
+
+/// The change tracking state of an `ObservedAnyMap`.
+struct Tracking {
+    versions: HashMap<TypeId, u64, TypeIdBuildHasher>,
+    changed: TypeSet,
+}
+
+impl Tracking {
+    /// Returns the current version of a type slot.
+    fn version(&self, id: &TypeId) -> u64 {
+        self.versions.get(id).map_or(0, |&version| version)
+    }
+
+    /// Records a change to a type slot, bumping its version.
+    fn touch(&mut self, id: TypeId, name: &'static str) {
+        match self.versions.entry(id) {
+            hash_map::Entry::Occupied(mut e) => *e.get_mut() += 1,
+            hash_map::Entry::Vacant(e) => { let _ = e.insert(1); },
+        }
+        if name != UNKNOWN_TYPE {
+            // A real name always wins over the placeholder.
+            let _ = self.changed.types.insert(id, name);
+        } else {
+            let _ = self.changed.insert_id(id, name);
+        }
+    }
+}
+
+/// The versions of the values a function run by `ObservedAnyMap::invoke_if_changed` watches
+/// for changes, as they were when it last ran.
+#[derive(Clone, Debug)]
+pub struct LastRun {
+    seen: Option<HashMap<TypeId, u64, TypeIdBuildHasher>>,
+}
+
+impl Default for LastRun {
+    #[inline]
+    fn default() -> LastRun {
+        LastRun::new()
+    }
+}
+
+impl LastRun {
+    /// Construct a new `LastRun` for a function which has not yet run, and so is due to.
+    #[inline]
+    pub fn new() -> LastRun {
+        LastRun {
+            seen: None,
+        }
+    }
+
+    /// Returns true if the function has run.
+    #[inline]
+    pub fn has_run(&self) -> bool {
+        self.seen.is_some()
+    }
+
+    /// Returns true if the function has not run, or has no watched values, or if any of them
+    /// has changed since it last ran.
+    fn is_due(&self, watched: &[(TypeId, u64)]) -> bool {
+        match self.seen {
+            None => true,
+            Some(ref seen) => watched.is_empty() || watched.iter()
+                .any(|&(id, version)| seen.get(&id) != Some(&version)),
+        }
+    }
+}
+
+/// A panic of a hook, caught by the `ObservedAnyMap` which invoked it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HookPanic {
+    /// The handle of the hook, which has been unregistered.
+    pub handle: HookHandle,
+    /// The type of the entry the hook was invoked with.
+    pub type_id: TypeId,
+    /// The message the hook panicked with, if it was a string.
+    pub message: Option<String>,
+}
+
+/// Invokes each hook in turn with the given entry, dropping those which ask to be unregistered,
+/// along with any of the other kind registered under the same handle, and those which panic,
+/// whose panics are recorded.
+fn fire(hooks: &mut Vec<(HookHandle, Hook)>, others: &mut Vec<(HookHandle, Hook)>,
+        panics: &mut Vec<HookPanic>, id: TypeId, value: &dyn Any) {
+    let mut i = 0;
+    while i < hooks.len() {
+        // A hook which panics is dropped, so that whatever state it was left in is never seen.
+        let hook = &mut hooks[i].1;
+        match panic::catch_unwind(AssertUnwindSafe(|| (**hook)(id, value))) {
+            Ok(true) => i += 1,
+            Ok(false) => {
+                let handle = hooks.remove(i).0;
+                others.retain(|&(h, _)| h != handle);
+            },
+            Err(payload) => {
+                let message = panic_message(&*payload);
+                panics.push(HookPanic { handle: hooks.remove(i).0, type_id: id, message });
+            },
+        }
+    }
+}
+
+/// A change to the entry watched by a `Subscription`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Update<T> {
+    /// A value was inserted, possibly replacing an old one; this is a clone of the new value.
+    Set(T),
+    /// The value was removed from the map.
+    Removed,
+}
+
+/// The waker of the task awaiting the next update of a subscription, if there is one.
+type WakerSlot = Arc<Mutex<Option<Waker>>>;
+
+/// The sending half of a subscription, shared by its hooks, which wakes the task awaiting the
+/// subscription after each update and once the map has dropped the hooks.
+struct Notifier<T> {
+    updates: Option<Sender<Update<T>>>,
+    waker: WakerSlot,
+}
+
+impl<T> Notifier<T> {
+    /// Sends an update, returning false if the subscription has been dropped.
+    fn send(&self, update: Update<T>) -> bool {
+        let sent = self.updates.as_ref().is_some_and(|updates| updates.send(update).is_ok());
+        self.wake();
+        sent
+    }
+
+    fn wake(&self) {
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Notifier<T> {
+    fn drop(&mut self) {
+        // Disconnect before waking, so that the woken task sees that no more updates will come.
+        self.updates = None;
+        self.wake();
+    }
+}
+
+/// A handle yielding the changes made to one entry of an `ObservedAnyMap`.
+///
+/// This can be used either as a stream, awaiting every update in order with `changed` or
+/// receiving them with `recv` or by iteration, or as a watch, looking only at the most recent
+/// update with `latest`. `recv` and iteration block the thread, and so wait forever on the
+/// thread which owns the map.
+pub struct Subscription<T> {
+    updates: Receiver<Update<T>>,
+    waker: WakerSlot,
+}
+
+impl<T> Subscription<T> {
+    /// Returns a future resolving to the next update, or to `None` once the map has been
+    /// dropped and all updates have been received.
+    #[inline]
+    pub fn changed(&mut self) -> NextUpdate<'_, T> {
+        NextUpdate { subscription: self }
+    }
+
+    /// Blocks until the next update arrives, returning `None` once the map has been dropped and
+    /// all updates have been received.
+    pub fn recv(&self) -> Option<Update<T>> {
+        self.updates.recv().ok()
+    }
+
+    /// Returns the next update if there is one pending, without blocking.
+    pub fn try_recv(&self) -> Option<Update<T>> {
+        self.updates.try_recv().ok()
+    }
+
+    /// Discards all pending updates but the most recent, returning it.
+    /// Returns `None` if nothing has changed since the last call.
+    pub fn latest(&self) -> Option<Update<T>> {
+        let mut latest = None;
+        while let Some(update) = self.try_recv() {
+            latest = Some(update);
+        }
+        latest
+    }
+}
+
+impl<T> Iterator for Subscription<T> {
+    type Item = Update<T>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Update<T>> {
+        self.recv()
+    }
+}
+
+/// The next update of a `Subscription`, as returned by `Subscription::changed`.
+pub struct NextUpdate<'a, T: 'a> {
+    subscription: &'a mut Subscription<T>,
+}
+
+impl<'a, T> Future for NextUpdate<'a, T> {
+    type Output = Option<Update<T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Update<T>>> {
+        // The waker is registered before looking, so that an update sent in between wakes it.
+        *self.subscription.waker.lock().unwrap() = Some(cx.waker().clone());
+        match self.subscription.updates.try_recv() {
+            Ok(update) => Poll::Ready(Some(update)),
+            Err(TryRecvError::Empty) => Poll::Pending,
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+        }
+    }
+}
+
+/// Changes through `AnyMapLike` bump versions and invoke hooks as the typed methods do.
+impl AnyMapLike for ObservedAnyMap {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.map.contains_id(id)
+    }
+
+    #[inline]
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.map.get_raw(id)
+    }
+
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        if self.map.contains_id(id) {
+            self.tracking.touch(id, UNKNOWN_TYPE);
+        }
+        self.map.get_raw_mut(id)
+    }
+
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        let id = (*value).type_id();
+        self.tracking.touch(id, UNKNOWN_TYPE);
+        fire(&mut self.insert_hooks, &mut self.remove_hooks, &mut self.hook_panics,
+             id, &*value);
+        self.map.insert_raw(value)
+    }
+
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        let value = self.map.remove_raw(id);
+        if let Some(ref value) = value {
+            self.tracking.touch(id, UNKNOWN_TYPE);
+            fire(&mut self.remove_hooks, &mut self.insert_hooks, &mut self.hook_panics,
+                 id, &**value);
+        }
+        value
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+impl Deref for ObservedAnyMap {
+    type Target = AnyMap;
+
+    #[inline]
+    fn deref(&self) -> &AnyMap {
+        &self.map
+    }
+}
+
+#[test]
+fn test_hooks() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut map = ObservedAnyMap::new();
+    let inserts = log.clone();
+    let insert_hook = map.on_insert(move |id, value| {
+        assert_eq!(id, TypeId::of::<i32>());
+        inserts.borrow_mut().push(("insert", *value.downcast_ref::<i32>().unwrap()));
+    });
+    let removes = log.clone();
+    let _ = map.on_remove(move |_, value| {
+        removes.borrow_mut().push(("remove", *value.downcast_ref::<i32>().unwrap()));
+    });
+
+    assert_eq!(map.insert(1i32), None);
+    assert_eq!(map.insert(2i32), Some(1));
+    assert_eq!(map.remove::<i32>(), Some(2));
+    assert_eq!(map.remove::<i32>(), None);
+    assert!(map.unhook(insert_hook));
+    assert!(!map.unhook(insert_hook));
+    assert_eq!(map.insert(3i32), None);
+    map.clear();
+    assert!(map.is_empty());
+
+    assert_eq!(*log.borrow(), vec![("insert", 1), ("insert", 2), ("remove", 2), ("remove", 3)]);
+
+    let mut sparse = AnyMap::new();
+    let _ = sparse.insert(1u8);
+    let _ = sparse.insert(1u16);
+    sparse.set_shrink_threshold(Some(0.5));
+    let mut map = ObservedAnyMap::from_map(sparse);
+    map.clear();
+    assert_eq!(map.capacity(), 0);
+}
+
+#[test]
+fn test_hook_panics() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let mut map = ObservedAnyMap::new();
+    let calls = Rc::new(Cell::new(0));
+    let counter = calls.clone();
+    let _ = map.on_insert(move |_, _| counter.set(counter.get() + 1));
+    let failing = map.on_insert(|_, value| if value.is::<u8>() { panic!("no bytes") });
+    let _ = map.on_remove(|_, _| panic!("{} removals", 0));
+    let _ = map.insert(1u16);
+    let _ = map.insert(1u8);
+    let _ = map.insert(2u8);
+    map.clear();
+
+    assert_eq!((calls.get(), map.len(), map.version::<u8>()), (3, 0, 3));
+    let panics = map.take_hook_panics();
+    assert_eq!(panics.len(), 2);
+    assert_eq!((panics[0].handle, panics[0].type_id), (failing, TypeId::of::<u8>()));
+    assert_eq!(panics[0].message.as_deref(), Some("no bytes"));
+    assert_eq!(panics[1].message.as_deref(), Some("0 removals"));
+    assert!(!map.unhook(failing) && map.take_hook_panics().is_empty());
+}
+
+#[test]
+fn test_subscribe() {
+    let mut map = ObservedAnyMap::new();
+    let ints = map.subscribe::<i32>();
+    let strings = map.subscribe::<String>();
+    let _ = map.insert(1i32);
+    let _ = map.insert("ignored".to_string());
+    let _ = map.insert(2i32);
+    let _ = map.remove::<i32>();
+    assert_eq!(ints.try_recv(), Some(Update::Set(1)));
+    assert_eq!(ints.latest(), Some(Update::Removed));
+    assert_eq!(ints.latest(), None);
+
+    drop(strings);
+    let _ = map.insert("unsubscribes".to_string());
+    assert_eq!((map.insert_hooks.len(), map.remove_hooks.len()), (1, 1));
+    drop(map);
+    assert_eq!(ints.recv(), None);
+}
+
+#[test]
+fn test_subscribe_changed() {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::{Context, Poll, Wake, Waker};
+
+    struct Flag(AtomicBool);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Flag>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    let flag = Arc::new(Flag(AtomicBool::new(false)));
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+    let mut map = ObservedAnyMap::new();
+    let mut ints = map.subscribe::<i32>();
+    {
+        let mut changed = ints.changed();
+        assert_eq!(Pin::new(&mut changed).poll(&mut cx), Poll::Pending);
+        let _ = map.insert(1i32);
+        assert!(flag.0.swap(false, Ordering::SeqCst));
+        assert_eq!(Pin::new(&mut changed).poll(&mut cx), Poll::Ready(Some(Update::Set(1))));
+    }
+    let mut changed = ints.changed();
+    assert_eq!(Pin::new(&mut changed).poll(&mut cx), Poll::Pending);
+    drop(map);
+    assert!(flag.0.load(Ordering::SeqCst));
+    assert_eq!(Pin::new(&mut changed).poll(&mut cx), Poll::Ready(None));
+}
+
+#[test]
+fn test_versions() {
+    let mut map = ObservedAnyMap::new();
+    assert_eq!(map.version::<i32>(), 0);
+    let _ = map.insert(1i32);
+    assert_eq!(map.version::<i32>(), 1);
+    let _ = map.get::<i32>();
+    assert_eq!(map.version::<i32>(), 1);
+    let _ = map.get_mut::<i32>();
+    assert_eq!(map.version::<i32>(), 2);
+    let _ = map.remove::<i32>();
+    assert_eq!(map.version::<i32>(), 3);
+    let _ = map.get_mut::<i32>();
+    let _ = map.remove::<i32>();
+    assert_eq!(map.version::<i32>(), 3);
+    assert_eq!(map.version::<u8>(), 0);
+}
+
+#[test]
+fn test_take_changed() {
+    let mut map = ObservedAnyMap::new();
+    let _ = map.insert(1i32);
+    let _ = map.insert(2u8);
+    let _ = map.remove::<u8>();
+    let changed = map.take_changed();
+    assert_eq!(changed.len(), 2);
+    assert!(changed.contains::<i32>() && changed.contains::<u8>());
+    assert!(map.take_changed().is_empty());
+
+    let _ = map.get::<i32>();
+    assert!(map.take_changed().is_empty());
+    let _ = map.get_mut::<i32>();
+    assert!(map.take_changed().contains::<i32>());
+}
+
+#[test]
+fn test_invoke_if_changed() {
+    use invoke::Changed;
+
+    let mut map = ObservedAnyMap::new();
+    let _ = map.insert(1u8);
+    let _ = map.insert(0u16);
+    let mut last_run = LastRun::new();
+    let sum = |map: &mut ObservedAnyMap, last_run: &mut LastRun| {
+        map.invoke_if_changed(last_run, |a: Changed<u8>, b: Changed<i8>, total: &mut u16| {
+            *total += *a as u16 + *b as u16;
+        })
+    };
+    assert_eq!(sum(&mut map, &mut last_run), Err(InvokeError::Missing(vec![type_name::<i8>()])));
+    assert!(!last_run.has_run());
+    let _ = map.insert(2i8);
+    assert_eq!(sum(&mut map, &mut last_run), Ok(Some(())));
+    assert!(last_run.has_run());
+    assert_eq!((map.get::<u16>(), map.version::<u16>()), (Some(&3), 2));
+    assert_eq!(sum(&mut map, &mut last_run), Ok(None));
+    let _ = map.insert(3i8);
+    assert_eq!(sum(&mut map, &mut last_run), Ok(Some(())));
+    let _ = map.get_mut::<u16>();
+    assert_eq!(sum(&mut map, &mut last_run), Ok(None));
+    assert_eq!(map.get::<u16>(), Some(&7));
+
+    let mut always = LastRun::new();
+    for _ in 0..2 {
+        assert_eq!(map.invoke_if_changed(&mut always, |n: &u8| *n), Ok(Some(1)));
+    }
+}
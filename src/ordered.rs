@@ -0,0 +1,257 @@
+//! An `AnyMap` flavour which remembers the order its values were inserted in, and drops them in
+//! that order or its reverse.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::slice;
+
+use {TypeIdBuildHasher, cast_box, cast_mut, cast_ref};
+use like::AnyMapLike;
+
+/// The order in which an `OrderedAnyMap` drops its values.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DropOrder {
+    /// The value inserted first is dropped first.
+    Insertion,
+    /// The value inserted last is dropped first, as with local variables; the default.
+    ReverseInsertion,
+}
+
+/// A collection containing zero or one values for any given type, which remembers the order in
+/// which they were inserted, and drops them in a well-defined order when it is cleared or
+/// dropped itself.
+///
+/// By default values are dropped in the reverse of the order in which they were inserted, so
+/// that a value inserted after another, which may depend upon it, is dropped first. Replacing a
+/// value keeps its place in the order.
+///
+/// ```rust
+/// # use anymap::ordered::OrderedAnyMap;
+/// # use std::cell::RefCell;
+/// # use std::rc::Rc;
+/// struct Noisy(&'static str, Rc<RefCell<Vec<&'static str>>>);
+///
+/// impl Drop for Noisy {
+///     fn drop(&mut self) { self.1.borrow_mut().push(self.0) }
+/// }
+///
+/// struct Runtime(Noisy);
+/// struct Logger(Noisy);
+///
+/// let log = Rc::new(RefCell::new(Vec::new()));
+/// let mut services = OrderedAnyMap::new();
+/// services.insert(Runtime(Noisy("runtime", log.clone())));
+/// services.insert(Logger(Noisy("logger", log.clone())));
+/// drop(services);
+/// assert_eq!(*log.borrow(), vec!["logger", "runtime"]);
+/// ```
+pub struct OrderedAnyMap {
+    data: HashMap<TypeId, Box<dyn Any + 'static>, TypeIdBuildHasher>,
+    order: Vec<TypeId>,
+    drop_order: DropOrder,
+}
+
+impl Default for OrderedAnyMap {
+    #[inline]
+    fn default() -> OrderedAnyMap {
+        OrderedAnyMap::new()
+    }
+}
+
+impl OrderedAnyMap {
+    /// Construct a new, empty `OrderedAnyMap`, which drops its values in reverse insertion
+    /// order.
+    #[inline]
+    pub fn new() -> OrderedAnyMap {
+        OrderedAnyMap {
+            data: HashMap::with_hasher(TypeIdBuildHasher),
+            order: Vec::new(),
+            drop_order: DropOrder::ReverseInsertion,
+        }
+    }
+
+    /// Returns the order in which the collection drops its values.
+    #[inline]
+    pub fn drop_order(&self) -> DropOrder {
+        self.drop_order
+    }
+
+    /// Sets the order in which the collection drops its values.
+    #[inline]
+    pub fn set_drop_order(&mut self, drop_order: DropOrder) {
+        self.drop_order = drop_order;
+    }
+
+    /// Returns a reference to the value stored in the collection for the type `T`, if it exists.
+    pub fn get<T: Any + 'static>(&self) -> Option<&T> {
+        self.data.get(&TypeId::of::<T>())
+            .map(|any| cast_ref::<T>(&**any))
+    }
+
+    /// Returns a mutable reference to the value stored in the collection for the type `T`,
+    /// if it exists.
+    pub fn get_mut<T: Any + 'static>(&mut self) -> Option<&mut T> {
+        self.data.get_mut(&TypeId::of::<T>())
+            .map(|any| cast_mut::<T>(&mut **any))
+    }
+
+    /// Returns true if the collection contains a value of type `T`.
+    #[inline]
+    pub fn contains<T: Any + 'static>(&self) -> bool {
+        self.data.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Sets the value stored in the collection for the type `T`.
+    /// If the collection already had a value of type `T`, that value is returned, and the new
+    /// value takes its place in the order. Otherwise, `None` is returned, and the new value
+    /// comes last.
+    pub fn insert<T: Any + 'static>(&mut self, value: T) -> Option<T> {
+        let old = self.data.insert(TypeId::of::<T>(), Box::new(value) as Box<dyn Any>)
+            .map(|any| *cast_box::<T>(any));
+        if old.is_none() {
+            self.order.push(TypeId::of::<T>());
+        }
+        old
+    }
+
+    /// Removes the `T` value from the collection,
+    /// returning it if there was one or `None` if there was not.
+    pub fn remove<T: Any + 'static>(&mut self) -> Option<T> {
+        let id = TypeId::of::<T>();
+        let value = self.data.remove(&id).map(|any| *cast_box::<T>(any));
+        if value.is_some() {
+            let _ = self.order.iter().position(|&other| other == id)
+                .map(|i| self.order.remove(i));
+        }
+        value
+    }
+
+    /// Gets an iterator over the IDs of the types of the values in the collection, in the order
+    /// in which they were inserted.
+    #[inline]
+    pub fn type_ids(&self) -> TypeIds<'_> {
+        TypeIds {
+            inner: self.order.iter(),
+        }
+    }
+
+    /// Returns the number of items in the collection.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns true if there are no items in the collection.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Removes all items from the collection, dropping them in the collection's drop order.
+    /// Keeps the allocated memory for reuse.
+    pub fn clear(&mut self) {
+        if self.drop_order == DropOrder::ReverseInsertion {
+            self.order.reverse();
+        }
+        for id in self.order.drain(..) {
+            drop(self.data.remove(&id));
+        }
+    }
+}
+
+impl AnyMapLike for OrderedAnyMap {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.data.contains_key(&id)
+    }
+
+    #[inline]
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.data.get(&id).map(|any| &**any)
+    }
+
+    #[inline]
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        self.data.get_mut(&id).map(|any| &mut **any)
+    }
+
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        let id = (*value).type_id();
+        let old = self.data.insert(id, value);
+        if old.is_none() {
+            self.order.push(id);
+        }
+        Ok(old)
+    }
+
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        let value = self.data.remove(&id);
+        if value.is_some() {
+            let _ = self.order.iter().position(|&other| other == id)
+                .map(|i| self.order.remove(i));
+        }
+        value
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl Drop for OrderedAnyMap {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// `OrderedAnyMap` iterator over type IDs, in insertion order.
+#[derive(Clone)]
+pub struct TypeIds<'a> {
+    inner: slice::Iter<'a, TypeId>,
+}
+
+impl<'a> Iterator for TypeIds<'a> {
+    type Item = TypeId;
+
+    #[inline]
+    fn next(&mut self) -> Option<TypeId> {
+        self.inner.next().copied()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.inner.size_hint() }
+}
+
+#[test]
+fn test_ordered() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Noisy(u8, Rc<RefCell<Vec<u8>>>);
+    impl Drop for Noisy {
+        fn drop(&mut self) { self.1.borrow_mut().push(self.0) }
+    }
+    struct A(#[allow(dead_code)] Noisy);
+    struct B(#[allow(dead_code)] Noisy);
+    struct C(#[allow(dead_code)] Noisy);
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut map = OrderedAnyMap::new();
+    assert!(map.insert(A(Noisy(1, log.clone()))).is_none());
+    assert!(map.insert(B(Noisy(2, log.clone()))).is_none());
+    assert!(map.insert(C(Noisy(3, log.clone()))).is_none());
+    drop(map.insert(A(Noisy(4, log.clone()))));
+    drop(map.remove::<B>());
+    assert_eq!(map.type_ids().collect::<Vec<_>>(), vec![TypeId::of::<A>(), TypeId::of::<C>()]);
+    map.set_drop_order(DropOrder::Insertion);
+    map.clear();
+    assert!(map.is_empty() && map.type_ids().next().is_none());
+
+    assert!(map.insert(B(Noisy(5, log.clone()))).is_none());
+    assert!(map.insert(C(Noisy(6, log.clone()))).is_none());
+    map.set_drop_order(DropOrder::ReverseInsertion);
+    drop(map);
+    assert_eq!(*log.borrow(), vec![1, 2, 4, 3, 6, 5]);
+}
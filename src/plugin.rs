@@ -0,0 +1,118 @@
+//! Values computed from a context on first access and cached in an `AnyMap` thereafter, as with
+//! the plugins of web frameworks which parse a request’s body or query string only if asked to.
+
+use std::any::Any;
+use std::marker::PhantomData;
+
+use AnyMap;
+
+/// A key type for a value computed from a context of type `C`, such as a request, and cached in
+/// an `AnyMap` by `AnyMap::get_computed`.
+///
+/// The key type itself is never stored, so it is usually an empty enum or struct.
+pub trait Plugin<C: ?Sized>: Any + 'static {
+    /// The type of the computed value.
+    type Value: Any + 'static;
+
+    /// The type of the error returned when the value cannot be computed.
+    type Error;
+
+    /// Computes the value from the context.
+    fn eval(context: &mut C) -> Result<Self::Value, Self::Error>;
+}
+
+/// The cached value of the plugin `P`, stored under a type of its own so that plugins with the
+/// same value type do not collide.
+struct Computed<P: ?Sized, V>(V, PhantomData<fn() -> P>);
+
+impl AnyMap {
+    /// Returns a mutable reference to the value of the plugin `P`, computing it from `context`
+    /// and caching it in the collection if it has not been already.
+    ///
+    /// The context type is inferred, so it can be written `_`. If the computation fails, the
+    /// error is returned and nothing is cached, so the next call tries again.
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// # use anymap::plugin::Plugin;
+    /// struct Request { query: &'static str, parses: u32 }
+    ///
+    /// enum Query { }
+    ///
+    /// impl Plugin<Request> for Query {
+    ///     type Value = Vec<(String, String)>;
+    ///     type Error = ();
+    ///
+    ///     fn eval(request: &mut Request) -> Result<Vec<(String, String)>, ()> {
+    ///         request.parses += 1;
+    ///         request.query.split('&').map(|pair| {
+    ///             let mut parts = pair.splitn(2, '=');
+    ///             match (parts.next(), parts.next()) {
+    ///                 (Some(key), Some(value)) => Ok((key.to_string(), value.to_string())),
+    ///                 _ => Err(()),
+    ///             }
+    ///         }).collect()
+    ///     }
+    /// }
+    ///
+    /// let mut request = Request { query: "page=2&sort=name", parses: 0 };
+    /// let mut extensions = AnyMap::new();
+    /// assert_eq!(extensions.get_computed::<Query, _>(&mut request).unwrap().len(), 2);
+    /// assert_eq!(extensions.get_computed::<Query, _>(&mut request).unwrap()[0].1, "2");
+    /// assert_eq!(request.parses, 1);
+    /// ```
+    pub fn get_computed<P, C>(&mut self, context: &mut C) -> Result<&mut P::Value, P::Error>
+    where P: Plugin<C>, C: ?Sized {
+        if !self.contains::<Computed<P, P::Value>>() {
+            let value = P::eval(context)?;
+            let _ = self.insert(Computed::<P, P::Value>(value, PhantomData));
+        }
+        Ok(&mut self.get_mut::<Computed<P, P::Value>>().unwrap().0)
+    }
+
+    /// Returns a reference to the value of the plugin `P`, if it has been computed.
+    #[inline]
+    pub fn computed<P, C>(&self) -> Option<&P::Value> where P: Plugin<C>, C: ?Sized {
+        self.get::<Computed<P, P::Value>>().map(|computed| &computed.0)
+    }
+
+    /// Removes the cached value of the plugin `P`, returning it if there was one, so that the
+    /// next call to `get_computed` computes it afresh.
+    #[inline]
+    pub fn forget_computed<P, C>(&mut self) -> Option<P::Value> where P: Plugin<C>, C: ?Sized {
+        self.remove::<Computed<P, P::Value>>().map(|computed| computed.0)
+    }
+}
+
+#[test]
+fn test_plugin() {
+    enum Double { }
+    impl Plugin<u32> for Double {
+        type Value = u32;
+        type Error = &'static str;
+        fn eval(n: &mut u32) -> Result<u32, &'static str> {
+            *n += 1;
+            if *n == 1 { Err("unlucky") } else { Ok(*n * 2) }
+        }
+    }
+
+    enum Triple { }
+    impl Plugin<u32> for Triple {
+        type Value = u32;
+        type Error = ();
+        fn eval(n: &mut u32) -> Result<u32, ()> { Ok(*n * 3) }
+    }
+
+    let mut context = 0u32;
+    let mut map = AnyMap::new();
+    assert_eq!(map.get_computed::<Double, _>(&mut context), Err("unlucky"));
+    assert!(map.is_empty());
+    *map.get_computed::<Double, _>(&mut context).unwrap() += 1;
+    assert_eq!(map.get_computed::<Double, _>(&mut context), Ok(&mut 5));
+    assert_eq!(map.get_computed::<Triple, _>(&mut context), Ok(&mut 6));
+    assert_eq!(map.computed::<Double, u32>(), Some(&5));
+    assert_eq!(context, 2);
+    assert_eq!(map.forget_computed::<Double, u32>(), Some(5));
+    assert_eq!(map.computed::<Double, u32>(), None);
+    assert!(!map.contains::<u32>());
+}
@@ -0,0 +1,142 @@
+//! A pool of `AnyMap`s, for reusing their allocations rather than making a fresh map for each of
+//! many short-lived contexts, such as requests.
+
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+
+use AnyMap;
+
+/// A pool of empty `AnyMap`s, which hands them out through guards and takes them back, cleared
+/// but keeping their allocated capacity, when the guards are dropped.
+///
+/// `AnyMap`s cannot be sent between threads, so neither can a pool of them; a server would keep
+/// one per thread.
+///
+/// ```rust
+/// # use anymap::pool::AnyMapPool;
+/// let pool = AnyMapPool::new();
+/// for id in 0..3u32 {
+///     let mut context = pool.get();
+///     assert!(context.is_empty());
+///     context.insert(id);
+/// }
+/// assert_eq!(pool.idle(), 1);
+/// ```
+pub struct AnyMapPool {
+    idle: RefCell<Vec<AnyMap>>,
+    max_idle: usize,
+}
+
+impl Default for AnyMapPool {
+    #[inline]
+    fn default() -> AnyMapPool {
+        AnyMapPool::new()
+    }
+}
+
+impl AnyMapPool {
+    /// Construct a new, empty pool, which keeps every map given back to it.
+    #[inline]
+    pub fn new() -> AnyMapPool {
+        AnyMapPool::with_max_idle(usize::MAX)
+    }
+
+    /// Construct a new, empty pool, which keeps at most `max_idle` maps not in use and drops any
+    /// more given back to it.
+    #[inline]
+    pub fn with_max_idle(max_idle: usize) -> AnyMapPool {
+        AnyMapPool {
+            idle: RefCell::new(Vec::new()),
+            max_idle,
+        }
+    }
+
+    /// Takes an empty map from the pool, or makes a new one if the pool has none, returning it
+    /// in a guard which gives it back to the pool when dropped.
+    pub fn get(&self) -> PooledAnyMap<'_> {
+        let map = self.idle.borrow_mut().pop().unwrap_or_default();
+        PooledAnyMap {
+            pool: self,
+            map: Some(map),
+        }
+    }
+
+    /// Returns the number of maps in the pool which are not in use.
+    #[inline]
+    pub fn idle(&self) -> usize {
+        self.idle.borrow().len()
+    }
+
+    /// Drops every map in the pool which is not in use, freeing their memory.
+    #[inline]
+    pub fn clear(&self) {
+        self.idle.borrow_mut().clear()
+    }
+
+    /// Clears a map and keeps it, if there is room.
+    fn give_back(&self, mut map: AnyMap) {
+        let mut idle = self.idle.borrow_mut();
+        if idle.len() < self.max_idle {
+            map.clear();
+            idle.push(map);
+        }
+    }
+}
+
+/// A map taken from an `AnyMapPool`, given back to it when dropped.
+pub struct PooledAnyMap<'a> {
+    pool: &'a AnyMapPool,
+    map: Option<AnyMap>,
+}
+
+impl<'a> PooledAnyMap<'a> {
+    /// Takes the map out of the guard, so that it is not given back to the pool.
+    #[inline]
+    pub fn into_inner(mut self) -> AnyMap {
+        self.map.take().unwrap()
+    }
+}
+
+impl<'a> Deref for PooledAnyMap<'a> {
+    type Target = AnyMap;
+
+    #[inline]
+    fn deref(&self) -> &AnyMap {
+        self.map.as_ref().unwrap()
+    }
+}
+
+impl<'a> DerefMut for PooledAnyMap<'a> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut AnyMap {
+        self.map.as_mut().unwrap()
+    }
+}
+
+impl<'a> Drop for PooledAnyMap<'a> {
+    fn drop(&mut self) {
+        if let Some(map) = self.map.take() {
+            self.pool.give_back(map);
+        }
+    }
+}
+
+#[test]
+fn test_pool() {
+    let pool = AnyMapPool::with_max_idle(1);
+    {
+        let _discarded = pool.get();
+        let mut kept = pool.get();
+        kept.reserve(32);
+        let _ = kept.insert(1u8);
+    }
+    assert_eq!(pool.idle(), 1);
+    let reused = pool.get();
+    assert!(reused.is_empty() && reused.capacity() >= 32);
+    assert_eq!(pool.idle(), 0);
+    let _ = reused.into_inner();
+    assert_eq!(pool.idle(), 0);
+    drop(pool.get());
+    pool.clear();
+    assert_eq!(pool.idle(), 0);
+}
@@ -0,0 +1,224 @@
+//! An `AnyMap` wrapper in which individual entries can be frozen against change.
+
+use std::any::{Any, TypeId};
+use std::error::Error;
+use std::fmt;
+use std::ops::Deref;
+
+use {AnyMap, TypeSet, type_name};
+use like::AnyMapLike;
+
+/// An `AnyMap` whose entries can be frozen one type at a time, after which the entry can still
+/// be read but no longer inserted, removed or borrowed mutably.
+///
+/// This protects the values a framework depends on, such as its logger, from being replaced by
+/// code it hands the map to. Freezing cannot be undone, short of unwrapping the map with
+/// `into_inner`. All of the non-mutating `AnyMap` methods are available through `Deref`; the
+/// mutating methods are reimplemented here to check for frozen entries.
+///
+/// ```rust
+/// # use anymap::protect::ProtectedAnyMap;
+/// struct Logger(&'static str);
+///
+/// let mut data = ProtectedAnyMap::new();
+/// data.insert(Logger("framework")).ok().unwrap();
+/// data.freeze_entry::<Logger>();
+///
+/// let middleware = |data: &mut ProtectedAnyMap| data.insert(Logger("mine")).is_ok();
+/// assert!(!middleware(&mut data));
+/// assert_eq!(data.get::<Logger>().unwrap().0, "framework");
+/// ```
+pub struct ProtectedAnyMap {
+    map: AnyMap,
+    frozen: TypeSet,
+}
+
+impl Default for ProtectedAnyMap {
+    #[inline]
+    fn default() -> ProtectedAnyMap {
+        ProtectedAnyMap::new()
+    }
+}
+
+impl ProtectedAnyMap {
+    /// Construct a new `ProtectedAnyMap` with no entries frozen.
+    #[inline]
+    pub fn new() -> ProtectedAnyMap {
+        ProtectedAnyMap::from_map(AnyMap::new())
+    }
+
+    /// Wrap an existing `AnyMap`, with no entries frozen.
+    #[inline]
+    pub fn from_map(map: AnyMap) -> ProtectedAnyMap {
+        ProtectedAnyMap {
+            map,
+            frozen: TypeSet::new(),
+        }
+    }
+
+    /// Unwraps the underlying `AnyMap`, thawing every entry.
+    #[inline]
+    pub fn into_inner(self) -> AnyMap {
+        self.map
+    }
+
+    /// Freezes the entry for the type `T`, whether or not it has a value, returning false if it
+    /// was frozen already.
+    #[inline]
+    pub fn freeze_entry<T: Any + 'static>(&mut self) -> bool {
+        self.frozen.insert::<T>()
+    }
+
+    /// Returns true if the entry for the type `T` is frozen.
+    #[inline]
+    pub fn is_frozen<T: Any + 'static>(&self) -> bool {
+        self.frozen.contains::<T>()
+    }
+
+    /// Returns the set of the types whose entries are frozen.
+    #[inline]
+    pub fn frozen(&self) -> &TypeSet {
+        &self.frozen
+    }
+
+    /// Returns a mutable reference to the value stored in the collection for the type `T`,
+    /// if it exists, failing if the entry is frozen.
+    pub fn get_mut<T: Any + 'static>(&mut self) -> Result<Option<&mut T>, FrozenEntry> {
+        self.check::<T>()?;
+        Ok(self.map.get_mut::<T>())
+    }
+
+    /// Sets the value stored in the collection for the type `T`.
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    ///
+    /// If the entry is frozen, the value is handed back instead, with the error.
+    pub fn insert<T: Any + 'static>(&mut self, value: T) -> Result<Option<T>, (T, FrozenEntry)> {
+        if let Err(frozen) = self.check::<T>() {
+            return Err((value, frozen));
+        }
+        Ok(self.map.insert(value))
+    }
+
+    /// Removes the `T` value from the collection,
+    /// returning it if there was one or `None` if there was not,
+    /// failing if the entry is frozen.
+    pub fn remove<T: Any + 'static>(&mut self) -> Result<Option<T>, FrozenEntry> {
+        self.check::<T>()?;
+        Ok(self.map.remove::<T>())
+    }
+
+    /// Removes all items from the collection but those whose entries are frozen.
+    pub fn clear(&mut self) {
+        let frozen = &self.frozen;
+        self.map.retain_ids(|id| frozen.types.contains_key(id));
+    }
+
+    fn check<T: Any + 'static>(&self) -> Result<(), FrozenEntry> {
+        if self.frozen.types.contains_key(&TypeId::of::<T>()) {
+            Err(FrozenEntry { name: type_name::<T>() })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Changes to frozen entries through `AnyMapLike` are refused.
+impl AnyMapLike for ProtectedAnyMap {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.map.contains_id(id)
+    }
+
+    #[inline]
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.map.get_raw(id)
+    }
+
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        if self.frozen.types.contains_key(&id) {
+            return None;
+        }
+        self.map.get_raw_mut(id)
+    }
+
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        if self.frozen.types.contains_key(&(*value).type_id()) {
+            return Err(value);
+        }
+        self.map.insert_raw(value)
+    }
+
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        if self.frozen.types.contains_key(&id) {
+            return None;
+        }
+        self.map.remove_raw(id)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+impl Deref for ProtectedAnyMap {
+    type Target = AnyMap;
+
+    #[inline]
+    fn deref(&self) -> &AnyMap {
+        &self.map
+    }
+}
+
+/// The error of changing a frozen entry of a `ProtectedAnyMap`: the type of the entry.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FrozenEntry {
+    name: &'static str,
+}
+
+impl FrozenEntry {
+    /// Returns the name of the type of the frozen entry.
+    #[inline]
+    pub fn type_name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl fmt::Display for FrozenEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the entry for the type {} is frozen", self.name)
+    }
+}
+
+impl Error for FrozenEntry {
+    fn description(&self) -> &str {
+        "the entry is frozen"
+    }
+}
+
+#[test]
+fn test_protect() {
+    let mut map = ProtectedAnyMap::new();
+    assert_eq!(map.insert(1u8), Ok(None));
+    assert_eq!(map.insert(1u16), Ok(None));
+    assert!(map.freeze_entry::<u8>() && !map.freeze_entry::<u8>());
+    assert!(map.freeze_entry::<i8>());
+    assert!(map.is_frozen::<u8>() && !map.is_frozen::<u16>());
+    assert_eq!(map.frozen().len(), 2);
+
+    let frozen = FrozenEntry { name: type_name::<u8>() };
+    assert_eq!(map.insert(2u8), Err((2, frozen)));
+    assert_eq!(map.insert(2i8), Err((2, FrozenEntry { name: type_name::<i8>() })));
+    assert_eq!(map.remove::<u8>(), Err(frozen));
+    assert_eq!(map.get_mut::<u8>().err(), Some(frozen));
+    assert_eq!(frozen.to_string(), "the entry for the type u8 is frozen");
+    assert_eq!(map.get::<u8>(), Some(&1));
+
+    *map.get_mut::<u16>().unwrap().unwrap() += 1;
+    assert_eq!(map.insert(3u16), Ok(Some(2)));
+    map.clear();
+    assert_eq!((map.len(), map.get::<u8>()), (1, Some(&1)));
+    assert!(map.into_inner().remove::<u8>().is_some());
+}
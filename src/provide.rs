@@ -0,0 +1,224 @@
+//! A "give me a `T` if you have one" interface, after the standard library's experimental
+//! `Provider` design, through which an `AnyMap`, or anything embedding one, can serve generic
+//! code which does not depend on the concrete type.
+//!
+//! A `Provider` is asked for a value by `request_ref` or `request_value`, which pass it a
+//! `Request` for the one type wanted; the provider offers what it has, and the first offer of
+//! the right type satisfies the request.
+
+use std::any::{Any, TypeId};
+
+use AnyMap;
+
+/// A type which can provide values of various types on request.
+pub trait Provider {
+    /// Offers values to the request; see `Request` for the ways of offering them.
+    fn provide<'a>(&'a self, request: &mut Request<'a>);
+}
+
+/// A type which provides values from an `AnyMap` it embeds, and so implements `Provider`.
+///
+/// ```rust
+/// # use anymap::AnyMap;
+/// # use anymap::provide::{self, ProvideFromMap, Request};
+/// struct HttpRequest {
+///     path: &'static str,
+///     extensions: AnyMap,
+/// }
+///
+/// impl ProvideFromMap for HttpRequest {
+///     fn provider_map(&self) -> &AnyMap { &self.extensions }
+///
+///     fn provide_also<'a>(&'a self, request: &mut Request<'a>) {
+///         request.provide_ref::<&'static str>(&self.path);
+///     }
+/// }
+///
+/// struct UserId(u32);
+///
+/// let mut request = HttpRequest { path: "/", extensions: AnyMap::new() };
+/// request.extensions.insert(UserId(7));
+/// assert_eq!(provide::request_ref::<UserId, _>(&request).unwrap().0, 7);
+/// assert_eq!(provide::request_ref::<&str, _>(&request), Some(&"/"));
+/// ```
+pub trait ProvideFromMap {
+    /// Returns the map to provide values from.
+    fn provider_map(&self) -> &AnyMap;
+
+    /// Offers values not in the map to a request which the map could not satisfy.
+    #[inline]
+    fn provide_also<'a>(&'a self, request: &mut Request<'a>) {
+        let _ = request;
+    }
+}
+
+impl<P: ProvideFromMap + ?Sized> Provider for P {
+    fn provide<'a>(&'a self, request: &mut Request<'a>) {
+        if let Some(value) = self.provider_map().data.get(&request.wanted) {
+            let _ = request.provide_any(&**value);
+        }
+        if !request.is_satisfied() {
+            self.provide_also(request);
+        }
+    }
+}
+
+impl ProvideFromMap for AnyMap {
+    #[inline]
+    fn provider_map(&self) -> &AnyMap {
+        self
+    }
+}
+
+/// A request for a reference to a value of one type, or for a value of it, passed to
+/// `Provider::provide`.
+///
+/// Offers of other types, and of references to a request for a value or the other way round,
+/// are ignored, as are offers once the request is satisfied.
+pub struct Request<'a> {
+    wanted: TypeId,
+    by_value: bool,
+    reference: Option<&'a dyn Any>,
+    value: Option<Box<dyn Any + 'static>>,
+}
+
+impl<'a> Request<'a> {
+    fn new<T: Any + 'static>(by_value: bool) -> Request<'a> {
+        Request {
+            wanted: TypeId::of::<T>(),
+            by_value,
+            reference: None,
+            value: None,
+        }
+    }
+
+    /// Returns true if the request is for a reference to a `T`, and not yet satisfied.
+    #[inline]
+    pub fn would_be_satisfied_by_ref_of<T: Any + 'static>(&self) -> bool {
+        !self.by_value && self.wants::<T>()
+    }
+
+    /// Returns true if the request is for a `T` by value, and not yet satisfied.
+    #[inline]
+    pub fn would_be_satisfied_by_value_of<T: Any + 'static>(&self) -> bool {
+        self.by_value && self.wants::<T>()
+    }
+
+    /// Returns true if a value has been provided.
+    #[inline]
+    pub fn is_satisfied(&self) -> bool {
+        self.reference.is_some() || self.value.is_some()
+    }
+
+    /// Offers a reference to a `T`.
+    pub fn provide_ref<T: Any + 'static>(&mut self, value: &'a T) -> &mut Request<'a> {
+        self.provide_ref_with(|| value)
+    }
+
+    /// Offers a reference to a `T`, calling the function for it only if it is wanted.
+    pub fn provide_ref_with<T, F>(&mut self, fulfil: F) -> &mut Request<'a>
+    where T: Any + 'static, F: FnOnce() -> &'a T {
+        if self.would_be_satisfied_by_ref_of::<T>() {
+            self.reference = Some(fulfil());
+        }
+        self
+    }
+
+    /// Offers a reference to a value of whatever type it is.
+    pub fn provide_any(&mut self, value: &'a dyn Any) -> &mut Request<'a> {
+        if !self.by_value && !self.is_satisfied() && (*value).type_id() == self.wanted {
+            self.reference = Some(value);
+        }
+        self
+    }
+
+    /// Offers a `T` by value.
+    pub fn provide_value<T: Any + 'static>(&mut self, value: T) -> &mut Request<'a> {
+        self.provide_value_with(|| value)
+    }
+
+    /// Offers a `T` by value, calling the function for it only if it is wanted.
+    pub fn provide_value_with<T, F>(&mut self, fulfil: F) -> &mut Request<'a>
+    where T: Any + 'static, F: FnOnce() -> T {
+        if self.would_be_satisfied_by_value_of::<T>() {
+            self.value = Some(Box::new(fulfil()));
+        }
+        self
+    }
+
+    fn wants<T: Any + 'static>(&self) -> bool {
+        !self.is_satisfied() && TypeId::of::<T>() == self.wanted
+    }
+}
+
+/// Requests a reference to a `T` from a provider, returning it if it has one.
+///
+/// ```rust
+/// # use anymap::AnyMap;
+/// # use anymap::provide::{self, Provider};
+/// fn port(provider: &dyn Provider) -> u16 {
+///     provide::request_ref::<u16, _>(provider).copied().unwrap_or(80)
+/// }
+///
+/// let mut config = AnyMap::new();
+/// assert_eq!(port(&config), 80);
+/// config.insert(8080u16);
+/// assert_eq!(port(&config), 8080);
+/// ```
+pub fn request_ref<T, P>(provider: &P) -> Option<&T>
+where T: Any + 'static, P: Provider + ?Sized {
+    let mut request = Request::new::<T>(false);
+    provider.provide(&mut request);
+    request.reference.and_then(|value| value.downcast_ref::<T>())
+}
+
+/// Requests a `T` by value from a provider, returning it if it has one.
+///
+/// An `AnyMap` provides only references to its values; types embedding one can provide values
+/// as well through `ProvideFromMap::provide_also`.
+pub fn request_value<T, P>(provider: &P) -> Option<T>
+where T: Any + 'static, P: Provider + ?Sized {
+    let mut request = Request::new::<T>(true);
+    provider.provide(&mut request);
+    request.value.and_then(|value| value.downcast::<T>().ok()).map(|value| *value)
+}
+
+#[test]
+fn test_provide() {
+    struct Both(u8);
+
+    impl Provider for Both {
+        fn provide<'a>(&'a self, request: &mut Request<'a>) {
+            let _ = request.provide_ref(&self.0)
+                .provide_value(self.0 as u16)
+                .provide_value(1u16)
+                .provide_value_with(|| -> u32 { panic!("not wanted") });
+        }
+    }
+
+    let both = Both(2);
+    assert_eq!(request_ref::<u8, _>(&both), Some(&2));
+    assert_eq!(request_value::<u16, _>(&both), Some(2));
+    assert_eq!(request_value::<u8, _>(&both), None);
+    assert_eq!(request_ref::<u16, _>(&both as &dyn Provider), None);
+
+    struct Wrapper {
+        map: AnyMap,
+    }
+
+    impl ProvideFromMap for Wrapper {
+        fn provider_map(&self) -> &AnyMap { &self.map }
+
+        fn provide_also<'a>(&'a self, request: &mut Request<'a>) {
+            let _ = request.provide_value(self.map.len()).provide_ref(&0u8);
+        }
+    }
+
+    let mut wrapper = Wrapper { map: AnyMap::new() };
+    assert_eq!(request_ref::<u8, _>(&wrapper), Some(&0));
+    let _ = wrapper.map.insert(5u8);
+    assert_eq!(request_ref::<u8, _>(&wrapper), Some(&5));
+    assert_eq!(request_value::<usize, _>(&wrapper), Some(1));
+    assert_eq!(request_value::<u8, _>(&wrapper.map), None);
+    assert_eq!(request_ref::<u16, _>(&wrapper.map), None);
+}
@@ -0,0 +1,330 @@
+//! An `AnyMap` wrapper which logs every mutation, so that it can be replayed later.
+
+use std::any::{Any, TypeId};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::Deref;
+
+use {AnyMap, UNKNOWN_TYPE, type_name};
+use like::AnyMapLike;
+use registry::TypeRegistry;
+use replicate::{self, Sink};
+
+/// One mutation of a map, as recorded by `RecordingAnyMap`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Op {
+    /// A value was inserted, possibly replacing an old one.
+    Insert {
+        /// The registered name of the type, or its compiler-generated name if unregistered.
+        name: String,
+        /// The value, encoded with its registered codec, or `None` if it had none.
+        data: Option<Vec<u8>>,
+    },
+    /// A value was removed.
+    Remove {
+        /// The registered name of the type, or its compiler-generated name if unregistered.
+        name: String,
+    },
+    /// All values were removed.
+    Clear,
+}
+
+/// An `AnyMap` which keeps a log of every mutation made to it.
+///
+/// Each entry of the log names the type concerned and, for insertions, carries the value encoded
+/// with the codec registered for its type in a `TypeRegistry`. Replaying the log onto an empty
+/// map with `replay` reproduces the state of this one, provided every type inserted had a codec.
+///
+/// Mutable references into the map cannot be tracked, so instead of `get_mut` there is
+/// `modify`, which logs the value as it is afterwards.
+///
+/// In replication mode, made with `replicating`, each mutation is instead sent to a sink as it
+/// is made, for `replicate::apply` to apply to a follower map, and nothing is logged.
+///
+/// All of the non-mutating `AnyMap` methods are available through `Deref`.
+pub struct RecordingAnyMap<'r> {
+    map: AnyMap,
+    registry: &'r TypeRegistry,
+    log: Vec<Op>,
+    sink: Option<Box<dyn Sink + 'r>>,
+    error: Option<io::Error>,
+}
+
+impl<'r> RecordingAnyMap<'r> {
+    /// Construct a new, empty `RecordingAnyMap` encoding values with the given registry.
+    #[inline]
+    pub fn new(registry: &'r TypeRegistry) -> RecordingAnyMap<'r> {
+        RecordingAnyMap {
+            map: AnyMap::new(),
+            registry,
+            log: Vec::new(),
+            sink: None,
+            error: None,
+        }
+    }
+
+    /// Construct a new, empty `RecordingAnyMap` encoding values with the given registry, which
+    /// sends each mutation to `sink` rather than logging it.
+    ///
+    /// Should a message fail to send, no more are sent, as the follower would miss it, and the
+    /// error is kept for `replication_error`; the follower must then be resynchronised, say from
+    /// a snapshot.
+    #[inline]
+    pub fn replicating<S: Sink + 'r>(registry: &'r TypeRegistry, sink: S) -> RecordingAnyMap<'r> {
+        RecordingAnyMap {
+            sink: Some(Box::new(sink)),
+            ..RecordingAnyMap::new(registry)
+        }
+    }
+
+    /// Returns the error with which a mutation failed to be sent to the sink, if one has.
+    #[inline]
+    pub fn replication_error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Returns the mutations recorded so far.
+    #[inline]
+    pub fn log(&self) -> &[Op] {
+        &self.log[..]
+    }
+
+    /// Takes the mutations recorded so far, leaving the log empty.
+    #[inline]
+    pub fn take_log(&mut self) -> Vec<Op> {
+        std::mem::take(&mut self.log)
+    }
+
+    /// Unwraps the underlying `AnyMap`, discarding the log.
+    #[inline]
+    pub fn into_inner(self) -> AnyMap {
+        self.map
+    }
+
+    /// Sets the value stored in the collection for the type `T`, logging the insertion.
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    pub fn insert<T: Any + 'static>(&mut self, value: T) -> Option<T> {
+        self.log_insert(&value);
+        self.map.insert(value)
+    }
+
+    /// Applies a change to the value stored in the collection for the type `T`, if it exists,
+    /// logging the value as it is afterwards. Returns the result of the change.
+    pub fn modify<T: Any + 'static, R, F: FnOnce(&mut T) -> R>(&mut self, f: F) -> Option<R> {
+        let result = self.map.get_mut::<T>().map(f);
+        if let Some(value) = self.map.get::<T>() {
+            let op = insert_op(self.registry, value);
+            self.record(op);
+        }
+        result
+    }
+
+    /// Removes the `T` value from the collection, logging the removal,
+    /// returning it if there was one or `None` if there was not.
+    pub fn remove<T: Any + 'static>(&mut self) -> Option<T> {
+        let value = self.map.remove::<T>();
+        if value.is_some() {
+            let name = name_of::<T>(self.registry);
+            self.record(Op::Remove { name });
+        }
+        value
+    }
+
+    /// Removes all items from the collection, logging the fact.
+    /// Keeps the allocated memory for reuse.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.record(Op::Clear);
+    }
+
+    fn log_insert<T: Any + 'static>(&mut self, value: &T) {
+        let op = insert_op(self.registry, value);
+        self.record(op);
+    }
+
+    /// Logs a mutation, or sends it to the sink in replication mode.
+    fn record(&mut self, op: Op) {
+        match self.sink {
+            Some(ref mut sink) => if self.error.is_none() {
+                self.error = sink.send(replicate::encode(&op)).err();
+            },
+            None => self.log.push(op),
+        }
+    }
+}
+
+impl<'r> Deref for RecordingAnyMap<'r> {
+    type Target = AnyMap;
+
+    #[inline]
+    fn deref(&self) -> &AnyMap {
+        &self.map
+    }
+}
+
+/// Raw insertions and removals are logged just as typed ones are, but a type inserted raw is
+/// logged under its registered name alone, as its compiler-generated one is not known. Mutable
+/// references cannot be tracked, so `get_raw_mut` is refused, as `get_mut` is replaced by
+/// `modify`.
+impl<'r> AnyMapLike for RecordingAnyMap<'r> {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.map.contains_id(id)
+    }
+
+    #[inline]
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.map.get_raw(id)
+    }
+
+    #[inline]
+    fn get_raw_mut(&mut self, _: TypeId) -> Option<&mut dyn Any> {
+        None
+    }
+
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        let op = Op::Insert {
+            name: name_of_id(self.registry, (*value).type_id()),
+            data: self.registry.encode(&*value),
+        };
+        self.record(op);
+        self.map.insert_raw(value)
+    }
+
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        let value = self.map.remove_raw(id);
+        if value.is_some() {
+            let name = name_of_id(self.registry, id);
+            self.record(Op::Remove { name });
+        }
+        value
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+/// Returns the name by which the type `T` is logged.
+fn name_of<T: Any + 'static>(registry: &TypeRegistry) -> String {
+    registry.name_of(&TypeId::of::<T>()).unwrap_or(type_name::<T>()).to_string()
+}
+
+/// Returns the name by which the type with the given ID is logged.
+fn name_of_id(registry: &TypeRegistry, id: TypeId) -> String {
+    registry.name_of(&id).unwrap_or(UNKNOWN_TYPE).to_string()
+}
+
+fn insert_op<T: Any + 'static>(registry: &TypeRegistry, value: &T) -> Op {
+    Op::Insert {
+        name: name_of::<T>(registry),
+        data: registry.encode(value),
+    }
+}
+
+/// Applies a log of mutations to a map, decoding values with the given registry.
+///
+/// Replaying stops at the first mutation which cannot be applied, leaving the map with all of
+/// the mutations before it applied.
+pub fn replay(log: &[Op], registry: &TypeRegistry, map: &mut AnyMap) -> Result<(), ReplayError> {
+    for op in log.iter() {
+        match *op {
+            Op::Insert { ref name, data: Some(ref data) } => {
+                match registry.decode(&name[..], &data[..]) {
+                    Some((id, value)) => { let _ = map.insert_by_id(id, value); },
+                    None => return Err(ReplayError::Undecodable(name.clone())),
+                }
+            },
+            Op::Insert { ref name, data: None } => {
+                return Err(ReplayError::Unrecorded(name.clone()));
+            },
+            Op::Remove { ref name } => {
+                match registry.type_id_of(&name[..]) {
+                    Some(id) => { let _ = map.remove_by_id(&id); },
+                    None => return Err(ReplayError::UnknownType(name.clone())),
+                }
+            },
+            Op::Clear => map.clear(),
+        }
+    }
+    Ok(())
+}
+
+/// The reasons a mutation may fail to replay.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ReplayError {
+    /// The value inserted could not be recorded, as its type had no codec.
+    Unrecorded(String),
+    /// No type with a codec is registered under the name, or the codec rejected the data.
+    Undecodable(String),
+    /// No type is registered under the name.
+    UnknownType(String),
+    /// The replication message is not an encoded mutation.
+    Malformed,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::Unrecorded(ref name) =>
+                write!(f, "the inserted {} was not recorded, as it has no codec", name),
+            ReplayError::Undecodable(ref name) =>
+                write!(f, "the inserted {} could not be decoded", name),
+            ReplayError::UnknownType(ref name) =>
+                write!(f, "no type is registered as {}", name),
+            ReplayError::Malformed => write!(f, "the message is not an encoded mutation"),
+        }
+    }
+}
+
+impl Error for ReplayError {
+    fn description(&self) -> &str {
+        match *self {
+            ReplayError::Unrecorded(_) => "an inserted value was not recorded",
+            ReplayError::Undecodable(_) => "an inserted value could not be decoded",
+            ReplayError::UnknownType(_) => "a type name is not registered",
+            ReplayError::Malformed => "a message is not an encoded mutation",
+        }
+    }
+}
+
+#[test]
+fn test_record_replay() {
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<u8>("u8")
+        .codec(|&n: &u8| vec![n], |bytes| if bytes.len() == 1 { Some(bytes[0]) } else { None });
+    let _ = registry.register::<()>("unit").codec(|_: &()| vec![], |_| Some(()));
+    let _ = registry.register::<i8>("i8");
+
+    let mut map = RecordingAnyMap::new(&registry);
+    let _ = map.insert(1u8);
+    let _ = map.insert(());
+    let _ = map.modify(|n: &mut u8| *n += 1);
+    let _ = map.remove::<()>();
+    assert_eq!(map.log().len(), 4);
+
+    let mut replayed = AnyMap::new();
+    assert_eq!(replay(map.log(), &registry, &mut replayed), Ok(()));
+    assert_eq!(replayed.get::<u8>(), Some(&2));
+    assert!(!replayed.contains::<()>());
+
+    assert_eq!(map.take_log().len(), 4);
+    map.clear();
+    let _ = map.insert(1i8);
+    let log = map.take_log();
+    assert_eq!(log[0], Op::Clear);
+    assert_eq!(replay(&log[..], &registry, &mut replayed),
+               Err(ReplayError::Unrecorded("i8".to_string())));
+    assert!(replayed.is_empty());
+    assert!(map.log().is_empty());
+
+    let _ = map.insert(1u8);
+    let _ = map.remove::<u8>();
+    replayed.set_shrink_threshold(Some(0.5));
+    assert_eq!(replay(map.log(), &registry, &mut replayed), Ok(()));
+    assert_eq!(replayed.capacity(), 0);
+}
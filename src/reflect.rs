@@ -0,0 +1,343 @@
+//! Field-level reflection for the values of an `AnyMap`, so that tools such as admin consoles can
+//! walk from a map to its values to their fields without knowing the types involved.
+//!
+//! Only available with the `reflect` feature.
+
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, hash_map};
+use std::marker::PhantomData;
+use std::slice;
+
+use {AnyMap, TypeIdBuildHasher, cast_mut, cast_ref};
+
+/// Reaches one field of values of some type known only at runtime.
+trait Access: Send + Sync {
+    fn get<'a>(&self, value: &'a dyn Any) -> &'a dyn Any;
+
+    fn get_mut<'a>(&self, value: &'a mut dyn Any) -> &'a mut dyn Any;
+}
+
+/// Reaches a field of type `F` of values of type `T`.
+struct Accessor<T, F> {
+    get: fn(&T) -> &F,
+    get_mut: fn(&mut T) -> &mut F,
+}
+
+impl<T: Any + 'static, F: Any + 'static> Access for Accessor<T, F> {
+    fn get<'a>(&self, value: &'a dyn Any) -> &'a dyn Any {
+        (self.get)(cast_ref::<T>(value))
+    }
+
+    fn get_mut<'a>(&self, value: &'a mut dyn Any) -> &'a mut dyn Any {
+        (self.get_mut)(cast_mut::<T>(value))
+    }
+}
+
+/// One field of a registered type.
+struct Field {
+    name: &'static str,
+    type_id: TypeId,
+    access: Box<dyn Access>,
+}
+
+/// What is known of one registered type.
+struct TypeInfo {
+    name: &'static str,
+    fields: Vec<Field>,
+}
+
+impl TypeInfo {
+    fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+/// A registry of the fields of types, each reached by a getter returning `&dyn Any`.
+///
+/// Types are registered by name, with their fields, and the values of an `AnyMap` can then be
+/// listed and their fields read and changed by name. The registry is `Send` and `Sync`, so one
+/// can be built at startup and shared.
+///
+/// ```rust
+/// # use anymap::AnyMap;
+/// # use anymap::reflect::Reflection;
+/// struct Config { verbose: bool, retries: u32 }
+///
+/// let mut reflection = Reflection::new();
+/// reflection.register::<Config>("config")
+///     .field("verbose", |c| &c.verbose, |c| &mut c.verbose)
+///     .field("retries", |c| &c.retries, |c| &mut c.retries);
+///
+/// let mut data = AnyMap::new();
+/// data.insert(Config { verbose: false, retries: 3 });
+///
+/// for (name, value) in reflection.walk(&data) {
+///     assert_eq!(name, "config");
+///     let fields: Vec<&str> = reflection.fields(value).map(|(field, _)| field).collect();
+///     assert_eq!(fields, vec!["verbose", "retries"]);
+/// }
+///
+/// *reflection.field_in_mut(&mut data, "config", "retries").unwrap()
+///     .downcast_mut::<u32>().unwrap() = 5;
+/// assert_eq!(data.get::<Config>().unwrap().retries, 5);
+/// ```
+pub struct Reflection {
+    types: HashMap<TypeId, TypeInfo, TypeIdBuildHasher>,
+    names: HashMap<&'static str, TypeId>,
+}
+
+impl Default for Reflection {
+    #[inline]
+    fn default() -> Reflection {
+        Reflection::new()
+    }
+}
+
+impl Reflection {
+    /// Construct a new, empty `Reflection`.
+    #[inline]
+    pub fn new() -> Reflection {
+        Reflection {
+            types: HashMap::with_hasher(TypeIdBuildHasher),
+            names: HashMap::new(),
+        }
+    }
+
+    /// Registers the type `T` under the given name, returning a handle with which to record its
+    /// fields. Registering a type again replaces the name and forgets the fields recorded before.
+    ///
+    /// # Panics
+    ///
+    /// Panics if another type is registered under the name.
+    pub fn register<T: Any + 'static>(&mut self, name: &'static str) -> Fields<'_, T> {
+        let id = TypeId::of::<T>();
+        match self.names.get(name) {
+            Some(&other) if other != id => panic!("a type is already registered as {}", name),
+            _ => (),
+        }
+        if let Some(old) = self.types.insert(id, TypeInfo { name, fields: Vec::new() }) {
+            let _ = self.names.remove(old.name);
+        }
+        let _ = self.names.insert(name, id);
+        Fields {
+            info: self.types.get_mut(&id).unwrap(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns true if the type `T` is registered.
+    #[inline]
+    pub fn is_registered<T: Any + 'static>(&self) -> bool {
+        self.types.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Returns the name under which the type identified by `id` is registered.
+    #[inline]
+    pub fn name_of(&self, id: &TypeId) -> Option<&'static str> {
+        self.types.get(id).map(|info| info.name)
+    }
+
+    /// Returns the ID of the type registered under the given name.
+    #[inline]
+    pub fn type_id_of(&self, name: &str) -> Option<TypeId> {
+        self.names.get(name).copied()
+    }
+
+    /// Returns the names of the fields of the type identified by `id`, in the order they were
+    /// recorded, or `None` if the type is not registered.
+    pub fn field_names(&self, id: &TypeId) -> Option<FieldNames<'_>> {
+        self.types.get(id).map(|info| FieldNames { inner: info.fields.iter() })
+    }
+
+    /// Returns the ID of the type of the named field of the type identified by `id`.
+    pub fn field_type_id(&self, id: &TypeId, field: &str) -> Option<TypeId> {
+        self.types.get(id).and_then(|info| info.field(field)).map(|field| field.type_id)
+    }
+
+    /// Returns the named field of a value, or `None` if its type is not registered or has no
+    /// such field.
+    pub fn field<'a>(&self, value: &'a dyn Any, name: &str) -> Option<&'a dyn Any> {
+        self.types.get(&value.type_id())
+            .and_then(|info| info.field(name))
+            .map(|field| field.access.get(value))
+    }
+
+    /// Returns the named field of a value mutably, or `None` if its type is not registered or
+    /// has no such field.
+    pub fn field_mut<'a>(&self, value: &'a mut dyn Any, name: &str) -> Option<&'a mut dyn Any> {
+        self.types.get(&(*value).type_id())
+            .and_then(|info| info.field(name))
+            .map(move |field| field.access.get_mut(value))
+    }
+
+    /// Gets an iterator over the names and values of the fields of a value, in the order they
+    /// were recorded. It is empty if the value's type is not registered.
+    ///
+    /// Iterator element type is `(&'static str, &dyn Any)`.
+    pub fn fields<'a>(&'a self, value: &'a dyn Any) -> FieldValues<'a> {
+        let fields: &[Field] = match self.types.get(&value.type_id()) {
+            Some(info) => &info.fields[..],
+            None => &[],
+        };
+        FieldValues {
+            value,
+            inner: fields.iter(),
+        }
+    }
+
+    /// Gets an iterator over the values in `map` whose types are registered, with the names of
+    /// their types, in arbitrary order.
+    ///
+    /// Iterator element type is `(&'static str, &dyn Any)`.
+    pub fn walk<'a>(&'a self, map: &'a AnyMap) -> Walk<'a> {
+        Walk {
+            reflection: self,
+            inner: map.data.values(),
+        }
+    }
+
+    /// Returns the named field of the value in `map` of the type registered under `type_name`.
+    pub fn field_in<'a>(&self, map: &'a AnyMap, type_name: &str, field: &str)
+                        -> Option<&'a dyn Any> {
+        let id = self.type_id_of(type_name)?;
+        map.data.get(&id).and_then(|value| self.field(&**value, field))
+    }
+
+    /// Returns the named field of the value in `map` of the type registered under `type_name`
+    /// mutably, so that it can be changed in place.
+    pub fn field_in_mut<'a>(&self, map: &'a mut AnyMap, type_name: &str, field: &str)
+                            -> Option<&'a mut dyn Any> {
+        let id = self.type_id_of(type_name)?;
+        map.data.get_mut(&id).and_then(|value| self.field_mut(&mut **value, field))
+    }
+}
+
+/// A handle for recording the fields of a type registered in a `Reflection`.
+pub struct Fields<'a, T> {
+    info: &'a mut TypeInfo,
+    marker: PhantomData<T>,
+}
+
+impl<'a, T: Any + 'static> Fields<'a, T> {
+    /// Records a field of the type, with how to reach it, which is usually just
+    /// `|value| &value.field` and `|value| &mut value.field`. Recording a field of the same name
+    /// again replaces it, keeping its place in the order.
+    pub fn field<F: Any + 'static>(self, name: &'static str, get: fn(&T) -> &F,
+                                   get_mut: fn(&mut T) -> &mut F) -> Fields<'a, T> {
+        let field = Field {
+            name,
+            type_id: TypeId::of::<F>(),
+            access: Box::new(Accessor { get, get_mut }),
+        };
+        match self.info.fields.iter().position(|other| other.name == name) {
+            Some(i) => self.info.fields[i] = field,
+            None => self.info.fields.push(field),
+        }
+        self
+    }
+}
+
+/// `Reflection` iterator over the names of the fields of a type.
+#[derive(Clone)]
+pub struct FieldNames<'a> {
+    inner: slice::Iter<'a, Field>,
+}
+
+impl<'a> Iterator for FieldNames<'a> {
+    type Item = &'static str;
+
+    #[inline]
+    fn next(&mut self) -> Option<&'static str> {
+        self.inner.next().map(|field| field.name)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.inner.size_hint() }
+}
+
+/// `Reflection` iterator over the fields of a value.
+#[derive(Clone)]
+pub struct FieldValues<'a> {
+    value: &'a dyn Any,
+    inner: slice::Iter<'a, Field>,
+}
+
+impl<'a> Iterator for FieldValues<'a> {
+    type Item = (&'static str, &'a dyn Any);
+
+    #[inline]
+    fn next(&mut self) -> Option<(&'static str, &'a dyn Any)> {
+        let value = self.value;
+        self.inner.next().map(|field| (field.name, field.access.get(value)))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.inner.size_hint() }
+}
+
+/// `Reflection` iterator over the values of registered types in a map.
+pub struct Walk<'a> {
+    reflection: &'a Reflection,
+    inner: hash_map::Values<'a, TypeId, Box<dyn Any + 'static>>,
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = (&'static str, &'a dyn Any);
+
+    fn next(&mut self) -> Option<(&'static str, &'a dyn Any)> {
+        for value in self.inner.by_ref() {
+            if let Some(name) = self.reflection.name_of(&(**value).type_id()) {
+                return Some((name, &**value));
+            }
+        }
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { (0, self.inner.size_hint().1) }
+}
+
+#[test]
+fn test_reflect() {
+    struct Point { x: i32, y: i32 }
+    struct Label(String);
+
+    let mut reflection = Reflection::new();
+    let _ = reflection.register::<Point>("point")
+        .field("x", |p| &p.x, |p| &mut p.x)
+        .field("y", |p| &p.y, |p| &mut p.y)
+        .field("x", |p| &p.x, |p| &mut p.x);
+    let _ = reflection.register::<Label>("label").field("text", |l| &l.0, |l| &mut l.0);
+    assert!(reflection.is_registered::<Point>() && !reflection.is_registered::<u8>());
+    let names: Vec<&str> = reflection.field_names(&TypeId::of::<Point>()).unwrap().collect();
+    assert_eq!(names, vec!["x", "y"]);
+    assert_eq!(reflection.field_type_id(&TypeId::of::<Label>(), "text"),
+               Some(TypeId::of::<String>()));
+    assert!(reflection.field_names(&TypeId::of::<u8>()).is_none());
+
+    let mut map = AnyMap::new();
+    let _ = map.insert(Point { x: 1, y: 2 });
+    let _ = map.insert(Label("origin".to_string()));
+    let _ = map.insert(0u8);
+    let mut walked: Vec<&str> = reflection.walk(&map).map(|(name, _)| name).collect();
+    walked.sort();
+    assert_eq!(walked, vec!["label", "point"]);
+
+    let point = map.get::<Point>().unwrap() as &dyn Any;
+    let fields: Vec<i32> = reflection.fields(point)
+        .map(|(_, value)| *value.downcast_ref::<i32>().unwrap())
+        .collect();
+    assert_eq!(fields, vec![1, 2]);
+    assert!(reflection.field(point, "z").is_none());
+    assert_eq!(reflection.fields(&0u8).count(), 0);
+
+    reflection.field_in_mut(&mut map, "label", "text").unwrap()
+        .downcast_mut::<String>().unwrap().push('!');
+    assert_eq!(reflection.field_in(&map, "label", "text").unwrap().downcast_ref::<String>(),
+               Some(&"origin!".to_string()));
+    assert!(reflection.field_in(&map, "unregistered", "text").is_none());
+
+    let _ = reflection.register::<Point>("vector");
+    assert_eq!(reflection.type_id_of("point"), None);
+    assert_eq!(reflection.fields(map.get::<Point>().unwrap()).count(), 0);
+}
@@ -0,0 +1,155 @@
+//! A map of borrowed values, for passing a bundle of borrowed context around without boxing.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+use {TypeIdBuildHasher, cast_mut, cast_ref};
+
+/// A reference held by an `AnyRefMap`, either shared or mutable.
+pub enum Borrowed<'a, T: 'a> {
+    /// A shared reference.
+    Shared(&'a T),
+    /// A mutable reference.
+    Mut(&'a mut T),
+}
+
+/// A type-erased reference.
+enum Ref<'a> {
+    Shared(&'a dyn Any),
+    Mut(&'a mut dyn Any),
+}
+
+impl<'a> Ref<'a> {
+    fn downcast<T: Any + 'static>(self) -> Borrowed<'a, T> {
+        match self {
+            Ref::Shared(any) => Borrowed::Shared(cast_ref::<T>(any)),
+            Ref::Mut(any) => Borrowed::Mut(cast_mut::<T>(any)),
+        }
+    }
+}
+
+/// A collection containing zero or one references for any given type, borrowed for `'a`.
+///
+/// Unlike an `AnyMap`, it does not own its values, so they need not be `'static`; only their
+/// types must be, so that they have `TypeId`s. This can bundle up borrowed context to be passed
+/// into a function:
+///
+/// ```rust
+/// # use anymap::refs::AnyRefMap;
+/// fn handle(context: &mut AnyRefMap) {
+///     let name = context.get::<String>().unwrap().clone();
+///     context.get_mut::<Vec<String>>().unwrap().push(name);
+/// }
+///
+/// let name = format!("request");
+/// let mut log: Vec<String> = Vec::new();
+/// {
+///     let mut context = AnyRefMap::new();
+///     context.insert(&name);
+///     context.insert_mut(&mut log);
+///     handle(&mut context);
+/// }
+/// assert_eq!(log, vec![name]);
+/// ```
+pub struct AnyRefMap<'a> {
+    data: HashMap<TypeId, Ref<'a>, TypeIdBuildHasher>,
+}
+
+impl<'a> Default for AnyRefMap<'a> {
+    #[inline]
+    fn default() -> AnyRefMap<'a> {
+        AnyRefMap::new()
+    }
+}
+
+impl<'a> AnyRefMap<'a> {
+    /// Construct a new, empty `AnyRefMap`.
+    #[inline]
+    pub fn new() -> AnyRefMap<'a> {
+        AnyRefMap {
+            data: HashMap::with_hasher(TypeIdBuildHasher),
+        }
+    }
+
+    /// Returns a reference to the value referred to for the type `T`, if there is one.
+    pub fn get<T: Any + 'static>(&self) -> Option<&T> {
+        self.data.get(&TypeId::of::<T>()).map(|r| match *r {
+            Ref::Shared(any) => cast_ref::<T>(any),
+            Ref::Mut(ref any) => cast_ref::<T>(&**any),
+        })
+    }
+
+    /// Returns a mutable reference to the value referred to for the type `T`, if there is one
+    /// and it was inserted with `insert_mut`.
+    pub fn get_mut<T: Any + 'static>(&mut self) -> Option<&mut T> {
+        match self.data.get_mut(&TypeId::of::<T>()) {
+            Some(&mut Ref::Mut(ref mut any)) => Some(cast_mut::<T>(&mut **any)),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the collection refers to a value of type `T`.
+    #[inline]
+    pub fn contains<T: Any + 'static>(&self) -> bool {
+        self.data.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Sets the reference for the type `T` to a shared reference.
+    /// If the collection already had a reference for the type `T`, that reference is returned.
+    /// Otherwise, `None` is returned.
+    pub fn insert<T: Any + 'static>(&mut self, value: &'a T) -> Option<Borrowed<'a, T>> {
+        self.data.insert(TypeId::of::<T>(), Ref::Shared(value as &dyn Any)).map(|r| r.downcast())
+    }
+
+    /// Sets the reference for the type `T` to a mutable reference.
+    /// If the collection already had a reference for the type `T`, that reference is returned.
+    /// Otherwise, `None` is returned.
+    pub fn insert_mut<T: Any + 'static>(&mut self, value: &'a mut T) -> Option<Borrowed<'a, T>> {
+        self.data.insert(TypeId::of::<T>(), Ref::Mut(value as &mut dyn Any)).map(|r| r.downcast())
+    }
+
+    /// Removes the reference for the type `T` from the collection,
+    /// returning it if there was one or `None` if there was not.
+    pub fn remove<T: Any + 'static>(&mut self) -> Option<Borrowed<'a, T>> {
+        self.data.remove(&TypeId::of::<T>()).map(|r| r.downcast())
+    }
+
+    /// Returns the number of references in the collection.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns true if there are no references in the collection.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Removes all references from the collection. Keeps the allocated memory for reuse.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+}
+
+#[test]
+fn test_ref_map() {
+    let one = 1i32;
+    let mut two = 2u8;
+    {
+        let mut map = AnyRefMap::new();
+        assert!(map.insert(&one).is_none());
+        assert!(map.insert_mut(&mut two).is_none());
+        assert_eq!(map.get::<i32>(), Some(&1));
+        assert!(map.get_mut::<i32>().is_none());
+        *map.get_mut::<u8>().unwrap() = 3;
+        assert_eq!(map.get::<u8>(), Some(&3));
+        match map.remove::<u8>() {
+            Some(Borrowed::Mut(two)) => *two = 4,
+            _ => panic!("expected a mutable reference"),
+        }
+        assert_eq!(map.len(), 1);
+    }
+    assert_eq!(two, 4);
+}
@@ -0,0 +1,888 @@
+//! A registry of per-type metadata, through which type-erased values can be handled generically.
+//!
+//! A `TypeRegistry` is the one place in which what is known about a type is recorded, for every
+//! map and every feature: diffs, recordings and test assertions all consult the registry they
+//! are given. A process which needs only one can use the one returned by `shared`.
+
+use std::any::{Any, TypeId};
+use std::borrow::Cow;
+use std::collections::{HashMap, hash_map};
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::{OnceLock, RwLock};
+
+use {AnyMap, TypeIdBuildHasher, cast_box, cast_mut, cast_ref, type_name};
+use crdt::Merge;
+
+/// Encodes a value as bytes, if it is of the right type.
+type Encoder = Box<dyn Fn(&dyn Any) -> Option<Vec<u8>> + Send + Sync + 'static>;
+
+/// Decodes a boxed value from bytes.
+type Decoder = Box<dyn Fn(&[u8]) -> Option<Box<dyn Any + 'static>> + Send + Sync + 'static>;
+
+/// Upgrades encoded bytes from one version of a type's layout to the next.
+type Migration = Box<dyn Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync + 'static>;
+
+/// Compares two values, which are equal only if both are of the right type.
+type Comparer = Box<dyn Fn(&dyn Any, &dyn Any) -> bool + Send + Sync + 'static>;
+
+/// Clones a value into a new box, if it is of the right type.
+type Cloner = Box<dyn Fn(&dyn Any) -> Option<Box<dyn Any + 'static>> + Send + Sync + 'static>;
+
+/// Merges a value into another of its type, giving it back if it or the other is not of the type.
+type Merger = Box<dyn Fn(&mut dyn Any, Box<dyn Any + 'static>) -> Result<(), Box<dyn Any + 'static>>
+                  + Send + Sync + 'static>;
+
+/// Converts a value of another type into a boxed value of the type it is registered for.
+type Converter = Box<dyn Fn(&dyn Any) -> Box<dyn Any + 'static> + Send + Sync + 'static>;
+
+/// Casts values of one type to the trait object type `U`.
+trait Upcast<U: ?Sized> {
+    fn upcast<'a>(&self, value: &'a dyn Any) -> &'a U;
+    fn upcast_mut<'a>(&self, value: &'a mut dyn Any) -> &'a mut U;
+}
+
+/// Casts values of the type `T` to the trait object type `U`.
+struct Upcaster<T, U: ?Sized> {
+    upcast: fn(&T) -> &U,
+    upcast_mut: fn(&mut T) -> &mut U,
+}
+
+impl<T: Any + 'static, U: ?Sized + 'static> Upcast<U> for Upcaster<T, U> {
+    fn upcast<'a>(&self, value: &'a dyn Any) -> &'a U {
+        (self.upcast)(cast_ref::<T>(value))
+    }
+
+    fn upcast_mut<'a>(&self, value: &'a mut dyn Any) -> &'a mut U {
+        (self.upcast_mut)(cast_mut::<T>(value))
+    }
+}
+
+/// A type whose values can be visited by a visitor of type `V`, usually a trait object type
+/// with a method for each type it can visit, which `accept` calls.
+///
+/// See `TypeRegistry::visit`.
+pub trait Accept<V: ?Sized> {
+    /// Calls the visitor's method for this type with this value.
+    fn accept(&self, visitor: &mut V);
+}
+
+/// A visitor of the values of an `AnyMap`, as by `TypeRegistry::visit`.
+pub trait Visit {
+    /// Visits a value whose type has no `accept` registered for this visitor.
+    ///
+    /// By default this does nothing.
+    fn visit_unknown(&mut self, value: &dyn Any) {
+        let _ = value;
+    }
+}
+
+/// Passes a value known to be of type `T` to a visitor.
+fn accept<T: Accept<V> + Any + 'static, V: ?Sized>(value: &dyn Any, visitor: &mut V) {
+    cast_ref::<T>(value).accept(visitor)
+}
+
+/// A type-erased `Box<dyn Upcast<U>>` or `fn(&dyn Any, &mut V)`.
+type Erased = Box<dyn Any + Send + Sync + 'static>;
+
+/// What is known of one registered type.
+struct TypeInfo {
+    name: &'static str,
+    size: usize,
+    align: usize,
+    needs_drop: bool,
+    encode: Option<Encoder>,
+    decode: Option<Decoder>,
+    /// The version of the layout the codec encodes and decodes.
+    version: u32,
+    /// Upgrades to the next version, keyed by the version upgraded from, in the order they were
+    /// registered.
+    migrations: Vec<(u32, Migration)>,
+    eq: Option<Comparer>,
+    clone: Option<Cloner>,
+    merge: Option<Merger>,
+    /// Each a `Box<Upcast<U>>`, keyed by the ID of the trait object type `U`.
+    casts: HashMap<TypeId, Erased, TypeIdBuildHasher>,
+    /// Each a `fn(&dyn Any, &mut V)`, keyed by the ID of the visitor type `V`.
+    acceptors: HashMap<TypeId, Erased, TypeIdBuildHasher>,
+    /// Conversions from other types, keyed by the ID of the type converted from, in the order
+    /// they were registered.
+    conversions: Vec<(TypeId, Converter)>,
+}
+
+impl TypeInfo {
+    fn caster<U: ?Sized + 'static>(&self) -> Option<&dyn Upcast<U>> {
+        self.casts.get(&TypeId::of::<U>())
+            .map(|caster| {
+                &**cast_ref::<Box<dyn Upcast<U> + Send + Sync>>(&**caster) as &dyn Upcast<U>
+            })
+    }
+}
+
+/// A registry of per-type metadata.
+///
+/// Each registered type is given a stable name, which unlike its `TypeId` or compiler-generated
+/// name can be relied upon across builds, and optionally the functions needed to handle its
+/// values without knowing the type statically, such as a codec for converting them to and from
+/// bytes. Its size and alignment, and whether its values need dropping, are recorded too.
+///
+/// The registry is `Send` and `Sync`, so one can be shared by the whole of a program, and every
+/// function recorded in it must be `Send` and `Sync` in turn.
+///
+/// ```rust
+/// # use anymap::registry::TypeRegistry;
+/// # use std::any::TypeId;
+/// let mut registry = TypeRegistry::new();
+/// registry.register::<String>("string")
+///     .codec(|s: &String| s.clone().into_bytes(),
+///            |bytes| String::from_utf8(bytes.to_vec()).ok());
+/// let bytes = registry.encode(&format!("hello")).unwrap();
+/// let (id, value) = registry.decode("string", &bytes).unwrap();
+/// assert_eq!(id, TypeId::of::<String>());
+/// assert_eq!(value.downcast_ref::<String>().unwrap(), "hello");
+/// ```
+pub struct TypeRegistry {
+    types: HashMap<TypeId, TypeInfo, TypeIdBuildHasher>,
+    names: HashMap<&'static str, TypeId>,
+}
+
+impl Default for TypeRegistry {
+    #[inline]
+    fn default() -> TypeRegistry {
+        TypeRegistry::new()
+    }
+}
+
+impl TypeRegistry {
+    /// Construct a new, empty `TypeRegistry`.
+    #[inline]
+    pub fn new() -> TypeRegistry {
+        TypeRegistry {
+            types: HashMap::with_hasher(TypeIdBuildHasher),
+            names: HashMap::new(),
+        }
+    }
+
+    /// Registers the type `T` under the given stable name, returning a `Registration` through
+    /// which more can be recorded about it.
+    ///
+    /// Registering a type again keeps what was recorded before.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is already taken by another type, or the type is already registered
+    /// under another name.
+    pub fn register<T: Any + 'static>(&mut self, name: &'static str) -> Registration<'_, T> {
+        let id = TypeId::of::<T>();
+        match self.names.get(name) {
+            Some(&existing) if existing != id => {
+                panic!("the type name {:?} is already registered to another type", name)
+            },
+            _ => (),
+        }
+        if !self.types.contains_key(&id) {
+            let _ = self.names.insert(name, id);
+            let _ = self.types.insert(id, TypeInfo {
+                name,
+                size: size_of::<T>(),
+                align: align_of::<T>(),
+                needs_drop: mem::needs_drop::<T>(),
+                encode: None,
+                decode: None,
+                version: 0,
+                migrations: Vec::new(),
+                eq: None,
+                clone: None,
+                merge: None,
+                casts: HashMap::with_hasher(TypeIdBuildHasher),
+                acceptors: HashMap::with_hasher(TypeIdBuildHasher),
+                conversions: Vec::new(),
+            });
+        }
+        let info = self.types.get_mut(&id).unwrap();
+        if info.name != name {
+            panic!("the type {} is already registered as {:?}", type_name::<T>(), info.name)
+        }
+        Registration {
+            info,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns true if the type `T` has been registered.
+    #[inline]
+    pub fn is_registered<T: Any + 'static>(&self) -> bool {
+        self.types.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Returns the name under which the type identified by `id` is registered.
+    #[inline]
+    pub fn name_of(&self, id: &TypeId) -> Option<&'static str> {
+        self.types.get(id).map(|info| info.name)
+    }
+
+    /// Returns the ID of the type registered under the given name.
+    #[inline]
+    pub fn type_id_of(&self, name: &str) -> Option<TypeId> {
+        self.names.get(name).copied()
+    }
+
+    /// Returns the size in bytes of the type identified by `id`, if it is registered.
+    #[inline]
+    pub fn size_of(&self, id: &TypeId) -> Option<usize> {
+        self.types.get(id).map(|info| info.size)
+    }
+
+    /// Returns the alignment in bytes of the type identified by `id`, if it is registered.
+    #[inline]
+    pub fn align_of(&self, id: &TypeId) -> Option<usize> {
+        self.types.get(id).map(|info| info.align)
+    }
+
+    /// Returns whether dropping a value of the type identified by `id` runs any code, if the type
+    /// is registered. Values of types for which it does not can be forgotten instead of dropped.
+    #[inline]
+    pub fn needs_drop(&self, id: &TypeId) -> Option<bool> {
+        self.types.get(id).map(|info| info.needs_drop)
+    }
+
+    /// Returns true if values of the type identified by `id` can be encoded and decoded.
+    #[inline]
+    pub fn has_codec(&self, id: &TypeId) -> bool {
+        self.types.get(id).is_some_and(|info| info.encode.is_some())
+    }
+
+    /// Returns the version of the layout of values of the type identified by `id` encoded by its
+    /// codec, if it is registered.
+    #[inline]
+    pub fn version_of(&self, id: &TypeId) -> Option<u32> {
+        self.types.get(id).map(|info| info.version)
+    }
+
+    /// Returns true if values of the type identified by `id` can be compared.
+    #[inline]
+    pub fn is_comparable(&self, id: &TypeId) -> bool {
+        self.types.get(id).is_some_and(|info| info.eq.is_some())
+    }
+
+    /// Returns true if values of the type identified by `id` can be cloned.
+    #[inline]
+    pub fn is_cloneable(&self, id: &TypeId) -> bool {
+        self.types.get(id).is_some_and(|info| info.clone.is_some())
+    }
+
+    /// Returns true if values of the type identified by `id` can be merged.
+    #[inline]
+    pub fn is_mergeable(&self, id: &TypeId) -> bool {
+        self.types.get(id).is_some_and(|info| info.merge.is_some())
+    }
+
+    /// Encodes a value with its type’s registered codec.
+    ///
+    /// Returns `None` if the type of the value has no codec.
+    pub fn encode(&self, value: &dyn Any) -> Option<Vec<u8>> {
+        self.types.get(&value.type_id())
+            .and_then(|info| info.encode.as_ref())
+            .and_then(|encode| (*encode)(value))
+    }
+
+    /// Decodes a value of the type registered under the given name with its registered codec,
+    /// returning the type’s ID along with the value.
+    ///
+    /// Returns `None` if no type with a codec is registered under the name, or if the codec
+    /// rejects the bytes.
+    pub fn decode(&self, name: &str, bytes: &[u8]) -> Option<(TypeId, Box<dyn Any + 'static>)> {
+        self.type_id_of(name)
+            .and_then(|id| self.types[&id].decode.as_ref().map(|decode| (id, decode)))
+            .and_then(|(id, decode)| (*decode)(bytes).map(|value| (id, value)))
+    }
+
+    /// Upgrades bytes encoding a value of the type registered under the given name in the
+    /// `version` of its layout to the current version, with its registered migrations, one
+    /// version at a time. Bytes already in the current version are returned as they are.
+    ///
+    /// Returns `None` if no type is registered under the name, if `version` is newer than the
+    /// current one, if a migration from one of the versions in between is missing, or if one
+    /// rejects the bytes.
+    pub fn migrate<'a>(&self, name: &str, version: u32, bytes: &'a [u8])
+                       -> Option<Cow<'a, [u8]>> {
+        let info = &self.types[&self.type_id_of(name)?];
+        if version > info.version {
+            return None;
+        }
+        let mut bytes = Cow::Borrowed(bytes);
+        for from in version..info.version {
+            let (_, migrate) = info.migrations.iter().find(|&&(v, _)| v == from)?;
+            bytes = Cow::Owned((*migrate)(&bytes)?);
+        }
+        Some(bytes)
+    }
+
+    /// Compares two values of the same type with its registered equality.
+    ///
+    /// Returns `None` if the values are of different types, or their type has no registered
+    /// equality.
+    pub fn eq_values(&self, a: &dyn Any, b: &dyn Any) -> Option<bool> {
+        let id = a.type_id();
+        if id != b.type_id() {
+            return None;
+        }
+        self.types.get(&id)
+            .and_then(|info| info.eq.as_ref())
+            .map(|eq| (*eq)(a, b))
+    }
+
+    /// Clones a value with its type’s registered clone.
+    ///
+    /// Returns `None` if the type of the value has no registered clone.
+    pub fn clone_value(&self, value: &dyn Any) -> Option<Box<dyn Any + 'static>> {
+        self.types.get(&value.type_id())
+            .and_then(|info| info.clone.as_ref())
+            .and_then(|clone| (*clone)(value))
+    }
+
+    /// Merges the value `theirs` into `ours`, of the same type, with its registered merge.
+    ///
+    /// Gives `theirs` back if the values are of different types, or their type has no
+    /// registered merge.
+    pub fn merge_values(&self, ours: &mut dyn Any, theirs: Box<dyn Any + 'static>)
+                        -> Result<(), Box<dyn Any + 'static>> {
+        match self.types.get(&(*ours).type_id()).and_then(|info| info.merge.as_ref()) {
+            Some(merge) => (*merge)(ours, theirs),
+            None => Err(theirs),
+        }
+    }
+
+    /// Casts a value to the trait object type `U`, if its type is registered as implementing it.
+    pub fn cast<'a, U: ?Sized + 'static>(&self, value: &'a dyn Any) -> Option<&'a U> {
+        self.types.get(&value.type_id())
+            .and_then(|info| info.caster::<U>())
+            .map(|caster| caster.upcast(value))
+    }
+
+    /// Casts a value mutably to the trait object type `U`, if its type is registered as
+    /// implementing it.
+    pub fn cast_mut<'a, U: ?Sized + 'static>(&self, value: &'a mut dyn Any) -> Option<&'a mut U> {
+        let id = (*value).type_id();
+        match self.types.get(&id).and_then(|info| info.caster::<U>()) {
+            Some(caster) => Some(caster.upcast_mut(value)),
+            None => None,
+        }
+    }
+
+    /// Finds a value in `map` whose type is registered as implementing the trait object type
+    /// `U`, returning it as a `U`. If there are several, which is found is unspecified.
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// # use anymap::registry::TypeRegistry;
+    /// trait Logger {
+    ///     fn prefix(&self) -> &str;
+    /// }
+    ///
+    /// struct Stderr;
+    /// impl Logger for Stderr {
+    ///     fn prefix(&self) -> &str { "stderr" }
+    /// }
+    ///
+    /// let mut registry = TypeRegistry::new();
+    /// registry.register::<Stderr>("stderr").implements::<dyn Logger>(|s| s, |s| s);
+    /// let mut data = AnyMap::new();
+    /// data.insert(Stderr);
+    /// assert_eq!(registry.query::<dyn Logger>(&data).unwrap().prefix(), "stderr");
+    /// ```
+    pub fn query<'a, U: ?Sized + 'static>(&'a self, map: &'a AnyMap) -> Option<&'a U> {
+        self.iter_as::<U>(map).next()
+    }
+
+    /// Finds a value in `map` whose type is registered as implementing the trait object type
+    /// `U`, returning it mutably as a `U`. If there are several, which is found is unspecified.
+    pub fn query_mut<'a, U: ?Sized + 'static>(&self, map: &'a mut AnyMap) -> Option<&'a mut U> {
+        map.data.values_mut().filter_map(|value| self.cast_mut::<U>(&mut **value)).next()
+    }
+
+    /// Returns an iterator over every value in `map` whose type is registered as implementing
+    /// the trait object type `U`, as a `U`, in no particular order.
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// # use anymap::registry::TypeRegistry;
+    /// trait Inspect {
+    ///     fn report(&self) -> String;
+    /// }
+    ///
+    /// struct Hits(u32);
+    /// impl Inspect for Hits {
+    ///     fn report(&self) -> String { format!("{} hits", self.0) }
+    /// }
+    ///
+    /// struct Misses(u32);
+    /// impl Inspect for Misses {
+    ///     fn report(&self) -> String { format!("{} misses", self.0) }
+    /// }
+    ///
+    /// let mut registry = TypeRegistry::new();
+    /// registry.register::<Hits>("hits").implements::<dyn Inspect>(|s| s, |s| s);
+    /// registry.register::<Misses>("misses").implements::<dyn Inspect>(|s| s, |s| s);
+    /// let mut data = AnyMap::new();
+    /// data.insert(Hits(3));
+    /// data.insert(Misses(1));
+    /// data.insert(0u8);
+    /// let mut reports: Vec<String> = registry.iter_as::<dyn Inspect>(&data)
+    ///     .map(|value| value.report())
+    ///     .collect();
+    /// reports.sort();
+    /// assert_eq!(reports, vec!["1 misses".to_string(), "3 hits".to_string()]);
+    /// ```
+    pub fn iter_as<'a, U: ?Sized + 'static>(&'a self, map: &'a AnyMap) -> IterAs<'a, U> {
+        IterAs {
+            registry: self,
+            inner: map.data.values(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the value of type `T` in `map`, or failing that one converted from the value of
+    /// the first type with a conversion to `T` registered of which `map` has a value.
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// # use anymap::registry::TypeRegistry;
+    /// struct Config { host: String, port: u16 }
+    ///
+    /// #[derive(Clone, PartialEq, Debug)]
+    /// struct Port(u16);
+    ///
+    /// let mut registry = TypeRegistry::new();
+    /// registry.register::<Port>("port").converts_from(|config: &Config| Port(config.port));
+    /// let mut data = AnyMap::new();
+    /// data.insert(Config { host: format!("localhost"), port: 8080 });
+    /// assert_eq!(registry.get_converted::<Port>(&data).unwrap().into_owned(), Port(8080));
+    /// data.insert(Port(80));
+    /// assert_eq!(*registry.get_converted::<Port>(&data).unwrap(), Port(80));
+    /// ```
+    pub fn get_converted<'a, T: Clone + Any + 'static>(&self, map: &'a AnyMap)
+                                                       -> Option<Cow<'a, T>> {
+        if let Some(value) = map.get::<T>() {
+            return Some(Cow::Borrowed(value));
+        }
+        let info = self.types.get(&TypeId::of::<T>())?;
+        for (from, convert) in info.conversions.iter() {
+            if let Some(value) = map.data.get(from) {
+                return Some(Cow::Owned(*cast_box::<T>((**convert)(&**value))));
+            }
+        }
+        None
+    }
+
+    /// Visits every value in `map`, in no particular order, passing each whose type has an
+    /// `accept` registered for visitors of type `V` to that, and the rest to
+    /// `Visit::visit_unknown`.
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// # use anymap::registry::{Accept, TypeRegistry, Visit};
+    /// # use std::any::Any;
+    /// struct Width(u32);
+    /// struct Title(&'static str);
+    ///
+    /// trait Printer: Visit {
+    ///     fn visit_width(&mut self, width: &Width);
+    ///     fn visit_title(&mut self, title: &Title);
+    /// }
+    ///
+    /// impl Accept<dyn Printer> for Width {
+    ///     fn accept(&self, printer: &mut (dyn Printer + 'static)) { printer.visit_width(self) }
+    /// }
+    ///
+    /// impl Accept<dyn Printer> for Title {
+    ///     fn accept(&self, printer: &mut (dyn Printer + 'static)) { printer.visit_title(self) }
+    /// }
+    ///
+    /// struct Lines(Vec<String>);
+    ///
+    /// impl Visit for Lines {
+    ///     fn visit_unknown(&mut self, _: &dyn Any) { self.0.push(format!("?")) }
+    /// }
+    ///
+    /// impl Printer for Lines {
+    ///     fn visit_width(&mut self, width: &Width) { self.0.push(format!("{}px", width.0)) }
+    ///     fn visit_title(&mut self, title: &Title) { self.0.push(title.0.to_string()) }
+    /// }
+    ///
+    /// let mut registry = TypeRegistry::new();
+    /// registry.register::<Width>("width").accepts::<dyn Printer>();
+    /// registry.register::<Title>("title").accepts::<dyn Printer>();
+    /// let mut data = AnyMap::new();
+    /// data.insert(Width(80));
+    /// data.insert(Title("home"));
+    /// data.insert(0u8);
+    ///
+    /// let mut lines = Lines(Vec::new());
+    /// registry.visit::<dyn Printer>(&data, &mut lines);
+    /// lines.0.sort();
+    /// assert_eq!(lines.0, vec![format!("80px"), format!("?"), format!("home")]);
+    /// ```
+    pub fn visit<V: ?Sized + Visit + 'static>(&self, map: &AnyMap, visitor: &mut V) {
+        let visitor_id = TypeId::of::<V>();
+        for (id, value) in map.data.iter() {
+            match self.types.get(id).and_then(|info| info.acceptors.get(&visitor_id)) {
+                Some(accept) => (*cast_ref::<fn(&dyn Any, &mut V)>(&**accept))(&**value, visitor),
+                None => visitor.visit_unknown(&**value),
+            }
+        }
+    }
+}
+
+static SHARED: OnceLock<RwLock<TypeRegistry>> = OnceLock::new();
+
+/// Returns the process-wide `TypeRegistry`, which is empty until types are registered in it.
+///
+/// Libraries can register their types here when they are set up, so that maps and features
+/// throughout the program share what is known of them.
+///
+/// ```rust
+/// # use anymap::registry;
+/// # use std::any::TypeId;
+/// registry::shared().write().unwrap().register::<u32>("u32").cloneable();
+/// let registry = registry::shared().read().unwrap();
+/// assert!(registry.is_cloneable(&TypeId::of::<u32>()));
+/// assert_eq!(registry.size_of(&TypeId::of::<u32>()), Some(4));
+/// ```
+pub fn shared() -> &'static RwLock<TypeRegistry> {
+    SHARED.get_or_init(|| RwLock::new(TypeRegistry::new()))
+}
+
+/// Iterator over the values of an `AnyMap` implementing the trait object type `U`, as returned
+/// by `TypeRegistry::iter_as`.
+pub struct IterAs<'a, U: ?Sized> {
+    registry: &'a TypeRegistry,
+    inner: hash_map::Values<'a, TypeId, Box<dyn Any + 'static>>,
+    marker: PhantomData<fn(&'a U)>,
+}
+
+impl<'a, U: ?Sized + 'static> Iterator for IterAs<'a, U> {
+    type Item = &'a U;
+
+    fn next(&mut self) -> Option<&'a U> {
+        for value in self.inner.by_ref() {
+            if let Some(value) = self.registry.cast::<U>(&**value) {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { (0, self.inner.size_hint().1) }
+}
+
+/// A handle for recording metadata about a type registered in a `TypeRegistry`.
+pub struct Registration<'a, T> {
+    info: &'a mut TypeInfo,
+    marker: PhantomData<T>,
+}
+
+impl<'a, T: Any + 'static> Registration<'a, T> {
+    /// Records how values of the type are converted to and from bytes.
+    ///
+    /// The decoder may return `None` to reject malformed input.
+    pub fn codec<E, D>(self, encode: E, decode: D) -> Registration<'a, T>
+    where E: Fn(&T) -> Vec<u8> + Send + Sync + 'static,
+          D: Fn(&[u8]) -> Option<T> + Send + Sync + 'static {
+        self.info.encode = Some(Box::new(move |value: &dyn Any| {
+            value.downcast_ref::<T>().map(&encode)
+        }) as Encoder);
+        self.info.decode = Some(Box::new(move |bytes: &[u8]| {
+            decode(bytes).map(|value| Box::new(value) as Box<dyn Any>)
+        }) as Decoder);
+        self
+    }
+
+    /// Records the version of the layout the codec encodes and decodes, which is 0 by default.
+    ///
+    /// Snapshots record the version of each value, so that when the layout changes, values
+    /// encoded in older versions can be upgraded with the migrations recorded by `migrates`.
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// # use anymap::registry::TypeRegistry;
+    /// struct Session { user: String, admin: bool }
+    ///
+    /// // Version 0 held just the user's name.
+    /// let mut old = TypeRegistry::new();
+    /// old.register::<String>("session")
+    ///     .codec(|user| user.clone().into_bytes(),
+    ///            |bytes| String::from_utf8(bytes.to_vec()).ok());
+    /// let mut data = AnyMap::new();
+    /// data.insert(format!("alice"));
+    /// let snapshot = old.export(&data).unwrap();
+    ///
+    /// // Version 1 has a leading flag byte.
+    /// let mut registry = TypeRegistry::new();
+    /// registry.register::<Session>("session")
+    ///     .codec(|session| {
+    ///         let mut bytes = vec![session.admin as u8];
+    ///         bytes.extend(session.user.as_bytes());
+    ///         bytes
+    ///     }, |bytes| Some(Session {
+    ///         user: String::from_utf8(bytes.get(1..)?.to_vec()).ok()?,
+    ///         admin: bytes[0] != 0,
+    ///     }))
+    ///     .version(1)
+    ///     .migrates(0, |bytes| Some([&[0][..], bytes].concat()));
+    ///
+    /// let mut restored = AnyMap::new();
+    /// registry.import(&mut restored, &snapshot).unwrap();
+    /// let session = restored.get::<Session>().unwrap();
+    /// assert_eq!((&session.user[..], session.admin), ("alice", false));
+    /// ```
+    #[inline]
+    pub fn version(self, version: u32) -> Registration<'a, T> {
+        self.info.version = version;
+        self
+    }
+
+    /// Records how bytes encoding a value of the type in the version `from` of its layout are
+    /// upgraded to the version after it, replacing any migration from that version recorded
+    /// before. Values several versions old are upgraded by each migration in turn.
+    ///
+    /// The migration may return `None` to reject malformed input.
+    pub fn migrates<F>(self, from: u32, migrate: F) -> Registration<'a, T>
+    where F: Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync + 'static {
+        let migration = Box::new(migrate) as Migration;
+        match self.info.migrations.iter().position(|&(v, _)| v == from) {
+            Some(i) => self.info.migrations[i].1 = migration,
+            None => self.info.migrations.push((from, migration)),
+        }
+        self
+    }
+}
+
+impl<'a, T: Any + 'static> Registration<'a, T> {
+    /// Records how a value of the type is merged with another, the function being given the
+    /// value to keep and the value to merge into it. See `AnyMap::merge_crdt`.
+    pub fn merges_with<F>(self, merge: F) -> Registration<'a, T>
+    where F: Fn(&mut T, T) + Send + Sync + 'static {
+        self.info.merge = Some(Box::new(move |ours: &mut dyn Any, theirs: Box<dyn Any>| {
+            if !theirs.is::<T>() {
+                return Err(theirs);
+            }
+            match ours.downcast_mut::<T>() {
+                Some(ours) => {
+                    merge(ours, *cast_box::<T>(theirs));
+                    Ok(())
+                },
+                None => Err(theirs),
+            }
+        }) as Merger);
+        self
+    }
+
+    /// Records that the type implements the trait object type `U`, along with how to cast
+    /// references to it, which is usually just `|value| value` in both cases. Values of the type
+    /// can then be found by `TypeRegistry::query` and cast by `TypeRegistry::cast`.
+    pub fn implements<U: ?Sized + 'static>(self, upcast: fn(&T) -> &U,
+                                           upcast_mut: fn(&mut T) -> &mut U)
+                                           -> Registration<'a, T> {
+        let caster = Box::new(Upcaster {
+            upcast,
+            upcast_mut,
+        }) as Box<dyn Upcast<U> + Send + Sync>;
+        let _ = self.info.casts.insert(TypeId::of::<U>(), Box::new(caster) as Erased);
+        self
+    }
+
+    /// Records how a value of the type can be made from one of the type `U`, for
+    /// `TypeRegistry::get_converted` to fall back on. Several conversions may be recorded, and
+    /// are tried in the order they were; recording one from `U` again replaces it.
+    pub fn converts_from<U, F>(self, convert: F) -> Registration<'a, T>
+    where U: Any + 'static, F: Fn(&U) -> T + Send + Sync + 'static {
+        let converter = Box::new(move |value: &dyn Any| {
+            Box::new(convert(cast_ref::<U>(value))) as Box<dyn Any>
+        }) as Converter;
+        let from = TypeId::of::<U>();
+        match self.info.conversions.iter().position(|&(id, _)| id == from) {
+            Some(i) => self.info.conversions[i].1 = converter,
+            None => self.info.conversions.push((from, converter)),
+        }
+        self
+    }
+
+    /// Records that values of the type can be visited by visitors of type `V`, with its
+    /// implementation of `Accept<V>`. See `TypeRegistry::visit`.
+    pub fn accepts<V: ?Sized + 'static>(self) -> Registration<'a, T> where T: Accept<V> {
+        let accept = accept::<T, V> as fn(&dyn Any, &mut V);
+        let _ = self.info.acceptors.insert(TypeId::of::<V>(), Box::new(accept) as Erased);
+        self
+    }
+}
+
+impl<'a, T: PartialEq + Any + 'static> Registration<'a, T> {
+    /// Records that values of the type are compared with its `PartialEq` implementation.
+    pub fn comparable(self) -> Registration<'a, T> {
+        self.info.eq = Some(Box::new(|a: &dyn Any, b: &dyn Any| {
+            match (a.downcast_ref::<T>(), b.downcast_ref::<T>()) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            }
+        }) as Comparer);
+        self
+    }
+}
+
+impl<'a, T: Merge + Any + 'static> Registration<'a, T> {
+    /// Records that values of the type are merged with its `Merge` implementation.
+    #[inline]
+    pub fn mergeable(self) -> Registration<'a, T> {
+        self.merges_with(T::merge)
+    }
+}
+
+impl<'a, T: Clone + Any + 'static> Registration<'a, T> {
+    /// Records that values of the type are cloned with its `Clone` implementation.
+    pub fn cloneable(self) -> Registration<'a, T> {
+        self.info.clone = Some(Box::new(|value: &dyn Any| {
+            value.downcast_ref::<T>().map(|value| Box::new(value.clone()) as Box<dyn Any>)
+        }) as Cloner);
+        self
+    }
+}
+
+#[test]
+fn test_registry() {
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<u8>("u8")
+        .codec(|&n: &u8| vec![n], |bytes| if bytes.len() == 1 { Some(bytes[0]) } else { None });
+    let _ = registry.register::<()>("unit");
+    let _ = registry.register::<u8>("u8");
+
+    assert!(registry.is_registered::<u8>() && !registry.is_registered::<i8>());
+    assert_eq!(registry.name_of(&TypeId::of::<()>()), Some("unit"));
+    assert_eq!(registry.type_id_of("u8"), Some(TypeId::of::<u8>()));
+    assert_eq!(registry.encode(&7u8), Some(vec![7]));
+    assert_eq!(registry.encode(&()), None);
+    assert_eq!(registry.encode(&7i8), None);
+    assert_eq!(registry.decode("u8", &[7]).unwrap().1.downcast_ref::<u8>(), Some(&7));
+    assert!(registry.decode("u8", &[]).is_none());
+    assert!(registry.decode("unit", &[]).is_none());
+
+    let _ = registry.register::<u8>("u8").comparable().cloneable();
+    assert_eq!(registry.eq_values(&1u8, &1u8), Some(true));
+    assert_eq!(registry.eq_values(&1u8, &2u8), Some(false));
+    assert_eq!(registry.eq_values(&1u8, &()), None);
+    assert_eq!(registry.eq_values(&(), &()), None);
+    assert_eq!(registry.clone_value(&7u8).unwrap().downcast_ref::<u8>(), Some(&7));
+    assert!(registry.clone_value(&()).is_none());
+}
+
+#[test]
+fn test_metadata() {
+    fn assert_send_sync<T: Send + Sync>(_: &T) { }
+
+    let mut registry = TypeRegistry::new();
+    assert_send_sync(&registry);
+    let _ = registry.register::<u16>("u16").cloneable();
+    let _ = registry.register::<String>("string").comparable();
+    let (short, string) = (TypeId::of::<u16>(), TypeId::of::<String>());
+    assert_eq!((registry.size_of(&short), registry.align_of(&short)), (Some(2), Some(2)));
+    assert_eq!(registry.needs_drop(&short), Some(false));
+    assert_eq!(registry.needs_drop(&string), Some(true));
+    assert_eq!(registry.size_of(&TypeId::of::<u8>()), None);
+    assert!(registry.is_cloneable(&short) && !registry.is_cloneable(&string));
+    assert!(registry.is_comparable(&string) && !registry.has_codec(&string));
+}
+
+#[test]
+fn test_casts() {
+    use std::fmt::Debug;
+
+    trait Counter {
+        fn count(&self) -> usize;
+        fn increment(&mut self);
+    }
+
+    impl Counter for u8 {
+        fn count(&self) -> usize { *self as usize }
+        fn increment(&mut self) { *self += 1 }
+    }
+
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<u8>("u8")
+        .implements::<dyn Counter>(|n| n, |n| n)
+        .implements::<dyn Debug>(|n| n, |n| n);
+    let _ = registry.register::<i8>("i8").implements::<dyn Debug>(|n| n, |n| n);
+    assert_eq!(registry.cast::<dyn Counter>(&1u8).map(|c| c.count()), Some(1));
+    assert!(registry.cast::<dyn Counter>(&1i8).is_none());
+    assert_eq!(registry.cast::<dyn Debug>(&1i8).map(|n| format!("{:?}", n)), Some("1".to_string()));
+
+    let mut map = AnyMap::new();
+    assert!(registry.query::<dyn Counter>(&map).is_none());
+    let _ = map.insert(1i8);
+    let _ = map.insert(2u8);
+    registry.query_mut::<dyn Counter>(&mut map).unwrap().increment();
+    assert_eq!(map.get::<u8>(), Some(&3));
+    assert_eq!(registry.query::<dyn Counter>(&map).unwrap().count(), 3);
+
+    let _ = map.insert(());
+    let mut shown: Vec<String> = registry.iter_as::<dyn Debug>(&map)
+        .map(|n| format!("{:?}", n))
+        .collect();
+    shown.sort();
+    assert_eq!(shown, vec![format!("1"), format!("3")]);
+    assert_eq!(registry.iter_as::<dyn Counter>(&map).count(), 1);
+}
+
+#[test]
+fn test_converted() {
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<u32>("u32")
+        .converts_from(|&n: &u8| n as u32)
+        .converts_from(|&n: &u16| n as u32)
+        .converts_from(|&n: &u8| n as u32 * 10);
+    let mut map = AnyMap::new();
+    assert!(registry.get_converted::<u32>(&map).is_none());
+    assert!(registry.get_converted::<i32>(&map).is_none());
+    let _ = map.insert(2u16);
+    assert_eq!(registry.get_converted::<u32>(&map).map(|n| *n), Some(2));
+    let _ = map.insert(1u8);
+    assert_eq!(registry.get_converted::<u32>(&map).map(|n| *n), Some(10));
+    let _ = map.insert(3u32);
+    match registry.get_converted::<u32>(&map) {
+        Some(Cow::Borrowed(&n)) => assert_eq!(n, 3),
+        _ => panic!("the stored value should have been borrowed"),
+    }
+}
+
+#[test]
+fn test_visit() {
+    struct Sum(u32, usize);
+
+    impl Visit for Sum {
+        fn visit_unknown(&mut self, _: &dyn Any) {
+            self.1 += 1;
+        }
+    }
+
+    impl Accept<Sum> for u8 {
+        fn accept(&self, sum: &mut Sum) { sum.0 += *self as u32 }
+    }
+
+    impl Accept<Sum> for u16 {
+        fn accept(&self, sum: &mut Sum) { sum.0 += *self as u32 * 100 }
+    }
+
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<u8>("u8").accepts::<Sum>();
+    let _ = registry.register::<u16>("u16").accepts::<Sum>();
+    let _ = registry.register::<i8>("i8");
+    let mut map = AnyMap::new();
+    let _ = map.insert(1u8);
+    let _ = map.insert(2u16);
+    let _ = map.insert(3i8);
+    let _ = map.insert(());
+
+    let mut sum = Sum(0, 0);
+    registry.visit(&map, &mut sum);
+    assert_eq!((sum.0, sum.1), (201, 2));
+}
@@ -0,0 +1,213 @@
+//! Replication of the mutations of a `RecordingAnyMap` to a follower map, as messages sent over
+//! a sink such as a channel or a connection to another process.
+
+use std::io;
+use std::sync::mpsc;
+
+use AnyMap;
+use record::{Op, ReplayError, replay};
+use registry::TypeRegistry;
+
+/// The tag of a message inserting a value.
+const INSERT: u8 = 0;
+
+/// The tag of a message inserting a value which could not be encoded.
+const INSERT_UNRECORDED: u8 = 1;
+
+/// The tag of a message removing a value.
+const REMOVE: u8 = 2;
+
+/// The tag of a message removing all values.
+const CLEAR: u8 = 3;
+
+/// A destination for replication messages, each the encoding of one mutation.
+///
+/// `RecordingAnyMap::replicating` sends each mutation of the map to a sink as it is made, in
+/// order; on the other end, `apply` applies the messages to a follower map.
+pub trait Sink {
+    /// Sends one message, failing if it could not be delivered.
+    fn send(&mut self, message: Vec<u8>) -> io::Result<()>;
+}
+
+impl<S: Sink + ?Sized> Sink for &mut S {
+    #[inline]
+    fn send(&mut self, message: Vec<u8>) -> io::Result<()> {
+        (**self).send(message)
+    }
+}
+
+impl Sink for Vec<Vec<u8>> {
+    #[inline]
+    fn send(&mut self, message: Vec<u8>) -> io::Result<()> {
+        self.push(message);
+        Ok(())
+    }
+}
+
+impl Sink for mpsc::Sender<Vec<u8>> {
+    #[inline]
+    fn send(&mut self, message: Vec<u8>) -> io::Result<()> {
+        mpsc::Sender::send(self, message).map_err(|_| disconnected())
+    }
+}
+
+impl Sink for mpsc::SyncSender<Vec<u8>> {
+    #[inline]
+    fn send(&mut self, message: Vec<u8>) -> io::Result<()> {
+        mpsc::SyncSender::send(self, message).map_err(|_| disconnected())
+    }
+}
+
+/// The error of sending on a channel whose receiver is gone.
+fn disconnected() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "the receiver has disconnected")
+}
+
+/// Encodes a mutation as a message.
+pub fn encode(op: &Op) -> Vec<u8> {
+    let mut message = Vec::new();
+    match *op {
+        Op::Insert { ref name, data: Some(ref data) } => {
+            message.push(INSERT);
+            write_bytes(&mut message, name.as_bytes());
+            write_bytes(&mut message, data);
+        },
+        Op::Insert { ref name, data: None } => {
+            message.push(INSERT_UNRECORDED);
+            write_bytes(&mut message, name.as_bytes());
+        },
+        Op::Remove { ref name } => {
+            message.push(REMOVE);
+            write_bytes(&mut message, name.as_bytes());
+        },
+        Op::Clear => message.push(CLEAR),
+    }
+    message
+}
+
+/// Decodes a message made by `encode`, returning `None` if it is not one.
+pub fn decode(message: &[u8]) -> Option<Op> {
+    let (&tag, mut rest) = message.split_first()?;
+    let op = match tag {
+        INSERT => {
+            let name = read_name(&mut rest)?;
+            let data = read_bytes(&mut rest)?.to_vec();
+            Op::Insert { name, data: Some(data) }
+        },
+        INSERT_UNRECORDED => Op::Insert { name: read_name(&mut rest)?, data: None },
+        REMOVE => Op::Remove { name: read_name(&mut rest)? },
+        CLEAR => Op::Clear,
+        _ => return None,
+    };
+    if !rest.is_empty() {
+        return None;
+    }
+    Some(op)
+}
+
+/// Applies a message made by `encode` to a follower map, decoding values with the given
+/// registry, as `replay` applies a logged mutation.
+///
+/// ```rust
+/// # use anymap::AnyMap;
+/// # use anymap::record::RecordingAnyMap;
+/// # use anymap::registry::TypeRegistry;
+/// # use anymap::replicate;
+/// use std::sync::mpsc;
+///
+/// let mut registry = TypeRegistry::new();
+/// registry.register::<String>("user")
+///     .codec(|s| s.clone().into_bytes(), |bytes| String::from_utf8(bytes.to_vec()).ok());
+///
+/// let (sender, receiver) = mpsc::channel();
+/// let mut leader = RecordingAnyMap::replicating(&registry, sender);
+/// leader.insert(format!("alice"));
+///
+/// let mut follower = AnyMap::new();
+/// for message in receiver.try_iter() {
+///     replicate::apply(&message, &registry, &mut follower).unwrap();
+/// }
+/// assert_eq!(follower.get::<String>().unwrap(), "alice");
+/// ```
+pub fn apply(message: &[u8], registry: &TypeRegistry, map: &mut AnyMap)
+             -> Result<(), ReplayError> {
+    let op = decode(message).ok_or(ReplayError::Malformed)?;
+    replay(&[op], registry, map)
+}
+
+/// Writes length-prefixed bytes.
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// Reads length-prefixed bytes from the front of `rest`.
+fn read_bytes<'a>(rest: &mut &'a [u8]) -> Option<&'a [u8]> {
+    if rest.len() < 4 {
+        return None;
+    }
+    let (len, tail) = rest.split_at(4);
+    let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    if tail.len() < len {
+        return None;
+    }
+    let (bytes, tail) = tail.split_at(len);
+    *rest = tail;
+    Some(bytes)
+}
+
+/// Reads a length-prefixed type name from the front of `rest`.
+fn read_name(rest: &mut &[u8]) -> Option<String> {
+    String::from_utf8(read_bytes(rest)?.to_vec()).ok()
+}
+
+#[test]
+fn test_replicate() {
+    use record::RecordingAnyMap;
+
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<u8>("u8")
+        .codec(|&n: &u8| vec![n], |bytes| if bytes.len() == 1 { Some(bytes[0]) } else { None });
+    let _ = registry.register::<u16>("u16")
+        .codec(|&n: &u16| n.to_le_bytes().to_vec(), |bytes| {
+            if bytes.len() == 2 { Some(u16::from_le_bytes([bytes[0], bytes[1]])) } else { None }
+        });
+
+    let ops = vec![
+        Op::Insert { name: "u8".to_string(), data: Some(vec![1]) },
+        Op::Insert { name: "i8".to_string(), data: None },
+        Op::Remove { name: "u8".to_string() },
+        Op::Clear,
+    ];
+    for op in &ops {
+        assert_eq!(decode(&encode(op)).as_ref(), Some(op));
+    }
+    assert_eq!(decode(&encode(&ops[0])[..5]), None);
+    assert_eq!(decode(&[CLEAR, 0]), None);
+
+    let mut messages = Vec::new();
+    {
+        let mut leader = RecordingAnyMap::replicating(&registry, &mut messages);
+        let _ = leader.insert(1u8);
+        let _ = leader.insert(2u16);
+        let _ = leader.modify(|n: &mut u8| *n += 1);
+        let _ = leader.remove::<u16>();
+        assert!(leader.log().is_empty() && leader.replication_error().is_none());
+    }
+    assert_eq!(messages.len(), 4);
+    let mut follower = AnyMap::new();
+    for message in &messages {
+        assert_eq!(apply(message, &registry, &mut follower), Ok(()));
+    }
+    assert_eq!((follower.get::<u8>(), follower.get::<u16>()), (Some(&2), None));
+    assert_eq!(apply(b"", &registry, &mut follower), Err(ReplayError::Malformed));
+
+    let (sender, receiver) = mpsc::channel();
+    drop(receiver);
+    let mut leader = RecordingAnyMap::replicating(&registry, sender);
+    let _ = leader.insert(1u8);
+    let _ = leader.insert(2u8);
+    assert_eq!(leader.replication_error().map(|error| error.kind()),
+               Some(io::ErrorKind::BrokenPipe));
+    assert_eq!(leader.get::<u8>(), Some(&2));
+}
@@ -0,0 +1,236 @@
+//! A dependency-injection container built on an `AnyMap`, constructing values on demand.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+use {AnyMap, TypeIdBuildHasher, cast_ref, type_name};
+
+/// Constructs a boxed value from the values it depends upon.
+type Constructor = Box<dyn Fn(&AnyMap) -> Box<dyn Any + 'static> + 'static>;
+
+/// How to construct the value of one type.
+struct Provider {
+    construct: Constructor,
+    /// The types of the values the constructor reads, with their names.
+    dependencies: Vec<(TypeId, &'static str)>,
+}
+
+/// A container which constructs values from registered constructors when they are first asked
+/// for, caching them for later.
+///
+/// A constructor is given an `AnyMap` from which to take the values it needs; each type it reads
+/// must be declared with `depends_on`, so that `resolve` can construct those first, and theirs
+/// before them, and so on. Values may also be inserted directly, for configuration and the like.
+///
+/// The values constructed so far are held in an ordinary `AnyMap`, available through `Deref` and
+/// `DerefMut`. Removing a value from it means that it will be constructed again when it is next
+/// resolved.
+///
+/// ```rust
+/// # use anymap::resolve::Resolver;
+/// struct Config { url: &'static str }
+/// struct Database { url: &'static str }
+/// struct Repository { database_url: &'static str }
+///
+/// let mut resolver = Resolver::new();
+/// resolver.insert(Config { url: "postgres://localhost" });
+/// resolver.provide(|map| Database { url: map.get::<Config>().unwrap().url })
+///     .depends_on::<Config>();
+/// resolver.provide(|map| Repository { database_url: map.get::<Database>().unwrap().url })
+///     .depends_on::<Database>();
+///
+/// assert_eq!(resolver.resolve::<Repository>().unwrap().database_url, "postgres://localhost");
+/// assert!(resolver.contains::<Database>());
+/// ```
+pub struct Resolver {
+    map: AnyMap,
+    providers: HashMap<TypeId, Provider, TypeIdBuildHasher>,
+}
+
+impl Default for Resolver {
+    #[inline]
+    fn default() -> Resolver {
+        Resolver::new()
+    }
+}
+
+impl Resolver {
+    /// Construct a new `Resolver` with no values or constructors.
+    #[inline]
+    pub fn new() -> Resolver {
+        Resolver {
+            map: AnyMap::new(),
+            providers: HashMap::with_hasher(TypeIdBuildHasher),
+        }
+    }
+
+    /// Registers the constructor for values of type `T`, replacing any registered before.
+    ///
+    /// The types of the values it reads must then be declared on the returned handle.
+    pub fn provide<T, F>(&mut self, construct: F) -> Provision<'_, T>
+    where T: Any + 'static, F: Fn(&AnyMap) -> T + 'static {
+        let provider = Provider {
+            construct: Box::new(move |map: &AnyMap| Box::new(construct(map)) as Box<dyn Any>)
+                as Constructor,
+            dependencies: Vec::new(),
+        };
+        let _ = self.providers.insert(TypeId::of::<T>(), provider);
+        Provision {
+            provider: self.providers.get_mut(&TypeId::of::<T>()).unwrap(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns true if a constructor for values of type `T` is registered.
+    #[inline]
+    pub fn provides<T: Any + 'static>(&self) -> bool {
+        self.providers.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Returns the value of type `T`, constructing it if it has not been yet, along with
+    /// everything it depends upon.
+    ///
+    /// A value already in the map, whether constructed before or inserted directly, is returned
+    /// as it is, and its constructor is not consulted.
+    pub fn resolve<T: Any + 'static>(&mut self) -> Result<&T, ResolveError> {
+        let mut stack = Vec::new();
+        self.resolve_id(TypeId::of::<T>(), type_name::<T>(), &mut stack)?;
+        Ok(cast_ref::<T>(&*self.map.data[&TypeId::of::<T>()]))
+    }
+
+    /// Unwraps the map of the values constructed or inserted so far, discarding the constructors.
+    #[inline]
+    pub fn into_inner(self) -> AnyMap {
+        self.map
+    }
+
+    /// Makes sure that the map has a value with the given type ID, constructing it if need be.
+    ///
+    /// `stack` holds the types being constructed further up, by which cycles are detected.
+    fn resolve_id(&mut self, id: TypeId, name: &'static str,
+                  stack: &mut Vec<(TypeId, &'static str)>) -> Result<(), ResolveError> {
+        if self.map.data.contains_key(&id) {
+            return Ok(());
+        }
+        if let Some(start) = stack.iter().position(|&(on_stack, _)| on_stack == id) {
+            let mut cycle: Vec<&'static str> = stack[start..].iter().map(|&(_, n)| n).collect();
+            cycle.push(name);
+            return Err(ResolveError::Cycle(cycle));
+        }
+        let dependencies = match self.providers.get(&id) {
+            Some(provider) => provider.dependencies.clone(),
+            None => return Err(ResolveError::Unprovided(name)),
+        };
+        stack.push((id, name));
+        for &(dependency, dependency_name) in dependencies.iter() {
+            self.resolve_id(dependency, dependency_name, stack)?;
+        }
+        let _ = stack.pop();
+        let value = (*self.providers[&id].construct)(&self.map);
+        let _ = self.map.insert_by_id(id, value);
+        Ok(())
+    }
+}
+
+impl Deref for Resolver {
+    type Target = AnyMap;
+
+    #[inline]
+    fn deref(&self) -> &AnyMap {
+        &self.map
+    }
+}
+
+impl DerefMut for Resolver {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut AnyMap {
+        &mut self.map
+    }
+}
+
+/// A handle for declaring the dependencies of a constructor registered with `Resolver::provide`.
+pub struct Provision<'a, T> {
+    provider: &'a mut Provider,
+    marker: PhantomData<T>,
+}
+
+impl<'a, T: Any + 'static> Provision<'a, T> {
+    /// Declares that the constructor reads the value of type `D`, which will be resolved first.
+    pub fn depends_on<D: Any + 'static>(self) -> Provision<'a, T> {
+        self.provider.dependencies.push((TypeId::of::<D>(), type_name::<D>()));
+        self
+    }
+}
+
+/// The reasons a value may fail to be resolved.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ResolveError {
+    /// The named type, the one asked for or one of its dependencies, has neither a value nor a
+    /// constructor.
+    Unprovided(&'static str),
+    /// The named types depend upon each other in a cycle, the first and last being the same.
+    Cycle(Vec<&'static str>),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ResolveError::Unprovided(name) =>
+                write!(f, "the type {} has neither a value nor a constructor", name),
+            ResolveError::Cycle(ref names) => write!(f, "the types depend upon each other in a \
+                                                      cycle: {}", names.join(" -> ")),
+        }
+    }
+}
+
+impl Error for ResolveError {
+    fn description(&self) -> &str {
+        match *self {
+            ResolveError::Unprovided(_) => "a type has neither a value nor a constructor",
+            ResolveError::Cycle(_) => "types depend upon each other in a cycle",
+        }
+    }
+}
+
+#[test]
+fn test_resolver() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let constructed = Rc::new(Cell::new(0));
+    let counter = constructed.clone();
+    let mut resolver = Resolver::new();
+    let _ = resolver.insert(2u8);
+    let _ = resolver.provide(move |map| {
+        counter.set(counter.get() + 1);
+        *map.get::<u8>().unwrap() as u16 * 10
+    }).depends_on::<u8>();
+    let _ = resolver.provide(|map| *map.get::<u16>().unwrap() as u32 + 1).depends_on::<u16>();
+    assert!(resolver.provides::<u32>() && !resolver.provides::<u64>());
+
+    assert_eq!(resolver.resolve::<u32>(), Ok(&21));
+    assert_eq!(resolver.resolve::<u16>(), Ok(&20));
+    assert_eq!(constructed.get(), 1);
+    let _ = resolver.remove::<u16>();
+    assert_eq!(resolver.resolve::<u16>(), Ok(&20));
+    assert_eq!(constructed.get(), 2);
+}
+
+#[test]
+fn test_resolve_errors() {
+    let mut resolver = Resolver::new();
+    let _ = resolver.provide(|_| 1i8).depends_on::<i16>();
+    let _ = resolver.provide(|_| 1i16).depends_on::<i32>();
+    let _ = resolver.provide(|_| 1i32).depends_on::<i16>();
+    let _ = resolver.provide(|_| 1i64).depends_on::<u8>();
+    assert_eq!(resolver.resolve::<u8>(), Err(ResolveError::Unprovided(type_name::<u8>())));
+    assert_eq!(resolver.resolve::<i64>(), Err(ResolveError::Unprovided(type_name::<u8>())));
+    assert_eq!(resolver.resolve::<i8>(), Err(ResolveError::Cycle(vec![type_name::<i16>(),
+                                                                      type_name::<i32>(),
+                                                                      type_name::<i16>()])));
+    assert!(resolver.is_empty());
+}
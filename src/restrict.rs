@@ -0,0 +1,155 @@
+//! Views of an `AnyMap` restricted to a fixed list of types, checked at compile time.
+
+use std::any::Any;
+use std::marker::PhantomData;
+
+use AnyMap;
+
+/// Implemented by tuples of up to eight types for each type `T` they contain, `I` being one of
+/// the markers `At0` to `At7` for its position, so that the impls cannot overlap.
+///
+/// `I` is inferred, so where type parameters are given explicitly it can be written `_`; it need
+/// only be named for a type listed more than once.
+pub trait Contains<T, I> { }
+
+/// The position of the first type in a tuple; see `Contains`.
+pub enum At0 { }
+
+/// The position of the second type in a tuple; see `Contains`.
+pub enum At1 { }
+
+/// The position of the third type in a tuple; see `Contains`.
+pub enum At2 { }
+
+/// The position of the fourth type in a tuple; see `Contains`.
+pub enum At3 { }
+
+/// The position of the fifth type in a tuple; see `Contains`.
+pub enum At4 { }
+
+/// The position of the sixth type in a tuple; see `Contains`.
+pub enum At5 { }
+
+/// The position of the seventh type in a tuple; see `Contains`.
+pub enum At6 { }
+
+/// The position of the eighth type in a tuple; see `Contains`.
+pub enum At7 { }
+
+macro_rules! impl_contains {
+    ($($t:ident $at:ident),+) => {
+        impl_contains!(@each ($($t),+) $($t $at),+);
+    };
+    (@each $list:tt $($t:ident $at:ident),+) => {
+        $(impl_contains!(@one $list $t $at);)+
+    };
+    (@one ($($list:ident),+) $t:ident $at:ident) => {
+        impl<$($list),+> Contains<$t, $at> for ($($list,)+) { }
+    };
+}
+
+impl_contains!(A At0);
+impl_contains!(A At0, B At1);
+impl_contains!(A At0, B At1, C At2);
+impl_contains!(A At0, B At1, C At2, D At3);
+impl_contains!(A At0, B At1, C At2, D At3, E At4);
+impl_contains!(A At0, B At1, C At2, D At3, E At4, F At5);
+impl_contains!(A At0, B At1, C At2, D At3, E At4, F At5, G At6);
+impl_contains!(A At0, B At1, C At2, D At3, E At4, F At5, G At6, H At7);
+
+/// A mutable view of an `AnyMap` through which only the types in the tuple `L` can be reached,
+/// as returned by `AnyMap::restrict`.
+///
+/// Asking for any other type is a compile-time error, so code given a restricted map, such as a
+/// plugin, can be seen at a glance to touch nothing else in the map.
+///
+/// ```rust
+/// # use anymap::AnyMap;
+/// # use anymap::restrict::RestrictedAnyMap;
+/// struct Config { level: u8 }
+/// struct Log(Vec<String>);
+/// struct Secret;
+///
+/// fn plugin(mut context: RestrictedAnyMap<(Config, Log)>) {
+///     let level = context.get::<Config, _>().unwrap().level;
+///     context.insert(Log(vec![format!("level {}", level)]));
+///     // context.get::<Secret, _>() would not compile.
+/// }
+///
+/// let mut data = AnyMap::new();
+/// data.insert(Config { level: 2 });
+/// data.insert(Secret);
+/// plugin(data.restrict());
+/// assert_eq!(data.get::<Log>().unwrap().0, vec![format!("level 2")]);
+/// ```
+pub struct RestrictedAnyMap<'a, L> {
+    map: &'a mut AnyMap,
+    marker: PhantomData<fn() -> L>,
+}
+
+impl AnyMap {
+    /// Returns a mutable view of the collection through which only the types in the tuple `L`
+    /// can be reached.
+    #[inline]
+    pub fn restrict<L>(&mut self) -> RestrictedAnyMap<'_, L> {
+        RestrictedAnyMap {
+            map: self,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, L> RestrictedAnyMap<'a, L> {
+    /// Returns a reference to the value stored in the map for the type `T`, if it exists.
+    #[inline]
+    pub fn get<T: Any + 'static, I>(&self) -> Option<&T> where L: Contains<T, I> {
+        self.map.get::<T>()
+    }
+
+    /// Returns a mutable reference to the value stored in the map for the type `T`, if it
+    /// exists.
+    #[inline]
+    pub fn get_mut<T: Any + 'static, I>(&mut self) -> Option<&mut T> where L: Contains<T, I> {
+        self.map.get_mut::<T>()
+    }
+
+    /// Returns true if the map contains a value of type `T`.
+    #[inline]
+    pub fn contains<T: Any + 'static, I>(&self) -> bool where L: Contains<T, I> {
+        self.map.contains::<T>()
+    }
+
+    /// Sets the value stored in the map for the type `T`.
+    /// If the map already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    #[inline]
+    pub fn insert<T: Any + 'static, I>(&mut self, value: T) -> Option<T>
+    where L: Contains<T, I> {
+        self.map.insert(value)
+    }
+
+    /// Removes the `T` value from the map,
+    /// returning it if there was one or `None` if there was not.
+    #[inline]
+    pub fn remove<T: Any + 'static, I>(&mut self) -> Option<T> where L: Contains<T, I> {
+        self.map.remove::<T>()
+    }
+}
+
+#[test]
+fn test_restrict() {
+    let mut map = AnyMap::new();
+    let _ = map.insert(1u8);
+    let _ = map.insert(1i8);
+    {
+        let mut restricted = map.restrict::<(u8, u16, u8)>();
+        assert_eq!(restricted.get::<u8, At0>(), Some(&1));
+        assert!(!restricted.contains::<u16, _>());
+        assert_eq!(restricted.insert(2u16), None);
+        *restricted.get_mut::<u16, _>().unwrap() += 1;
+        assert_eq!(restricted.remove::<u8, At2>(), Some(1));
+    }
+    assert_eq!(map.get::<u16>(), Some(&3));
+    assert_eq!(map.get::<i8>(), Some(&1));
+    assert!(!map.contains::<u8>());
+}
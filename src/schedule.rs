@@ -0,0 +1,314 @@
+//! Running batches of functions over a `SyncAnyMap`, in parallel where their accesses allow.
+
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, hash_map};
+use std::thread;
+
+use {TypeIdBuildHasher, cast_mut, cast_ref, type_name};
+use invoke::InvokeError;
+use sync::SyncAnyMap;
+
+/// One parameter of a system: the type it refers to, and whether mutably.
+struct Access {
+    id: TypeId,
+    name: &'static str,
+    write: bool,
+}
+
+/// A value borrowed from the map for one parameter of a system.
+enum Slot<'a> {
+    Shared(&'a (dyn Any + Send + Sync + 'static)),
+    Mut(&'a mut (dyn Any + Send + Sync + 'static)),
+}
+
+impl<'a> Slot<'a> {
+    fn into_ref(self) -> &'a dyn Any {
+        match self {
+            Slot::Shared(value) => value,
+            Slot::Mut(value) => value,
+        }
+    }
+
+    fn into_mut(self) -> &'a mut dyn Any {
+        match self {
+            Slot::Mut(value) => value,
+            Slot::Shared(_) => unreachable!(),
+        }
+    }
+}
+
+/// A function prepared to be run by a `Schedule`.
+pub struct System {
+    access: Vec<Access>,
+    run: Runner,
+    stage: usize,
+}
+
+/// A system's function, adapted to take its parameters as slots.
+type Runner = Box<dyn FnMut(Vec<Slot>) + Send + 'static>;
+
+impl System {
+    /// Returns true if the two systems cannot run at once, as one writes a type the other uses.
+    fn conflicts_with(&self, other: &System) -> bool {
+        self.access.iter().any(|a| other.access.iter().any(|b| {
+            a.id == b.id && (a.write || b.write)
+        }))
+    }
+
+    /// Checks that the system refers to no type twice, and that the map has every type it does.
+    fn check(&self, map: &SyncAnyMap) -> Result<(), InvokeError> {
+        for (i, access) in self.access.iter().enumerate() {
+            if self.access[..i].iter().any(|other| other.id == access.id) {
+                return Err(InvokeError::Conflict(access.name));
+            }
+        }
+        let missing: Vec<&'static str> = self.access.iter()
+            .filter(|access| !map.data.contains_key(&access.id))
+            .map(|access| access.name)
+            .collect();
+        if missing.is_empty() { Ok(()) } else { Err(InvokeError::Missing(missing)) }
+    }
+}
+
+/// A function which can be added to a `Schedule`: one whose parameters, of which there may be up
+/// to four, are each a shared or mutable reference to a value in a `SyncAnyMap`. `Args` is the
+/// tuple of the parameter types, with `'static` lifetimes.
+pub trait IntoSystem<Args> {
+    /// Prepares the function to be run by a `Schedule`.
+    fn into_system(self) -> System;
+}
+
+macro_rules! access {
+    (mut; $t:ty) => (Access { id: TypeId::of::<$t>(), name: type_name::<$t>(), write: true });
+    (; $t:ty) => (Access { id: TypeId::of::<$t>(), name: type_name::<$t>(), write: false });
+}
+
+macro_rules! borrow {
+    (mut; $slot:expr, $t:ty) => (cast_mut::<$t>($slot.into_mut()));
+    (; $slot:expr, $t:ty) => (cast_ref::<$t>($slot.into_ref()));
+}
+
+/// Implements `IntoSystem` for functions of the given parameter types, each shared or mutable.
+macro_rules! impl_into_system {
+    (@expand [$($done:tt)*]) => {
+        impl_into_system!(@emit $($done)*);
+    };
+    (@expand [$($done:tt)*] $t:ident $value:ident $($rest:ident)*) => {
+        impl_into_system!(@expand [$($done)* (() $t $value)] $($rest)*);
+        impl_into_system!(@expand [$($done)* ((mut) $t $value)] $($rest)*);
+    };
+    (@emit $((($($m:tt)*) $t:ident $value:ident))+) => {
+        impl<F, $($t: Any + Send + Sync + 'static),+> IntoSystem<($(&'static $($m)* $t,)+)> for F
+        where F: FnMut($(&$($m)* $t),+) + Send + 'static {
+            fn into_system(self) -> System {
+                let mut function = self;
+                System {
+                    access: vec![$(access!($($m)*; $t)),+],
+                    run: Box::new(move |slots: Vec<Slot>| {
+                        let mut slots = slots.into_iter();
+                        $(let $value = borrow!($($m)*; slots.next().unwrap(), $t);)+
+                        function($($value),+)
+                    }),
+                    stage: 0,
+                }
+            }
+        }
+    };
+    ($($t:ident $value:ident),+) => {
+        impl_into_system!(@expand [] $($t $value)+);
+    };
+}
+
+impl_into_system!(A a);
+impl_into_system!(A a, B b);
+impl_into_system!(A a, B b, C c);
+impl_into_system!(A a, B b, C c, D d);
+
+/// A batch of functions, called systems, to be run over a `SyncAnyMap`.
+///
+/// Each system's parameters are shared or mutable references to values in the map, just as with
+/// `AnyMap::invoke`, so which types it reads and which it writes are known. Systems are run in
+/// the order they were added, except that those which do not conflict may run at once on
+/// different threads: the schedule is divided into stages, each system going in the stage after
+/// the last stage with a system added before it which writes a type it uses or uses a type it
+/// writes.
+///
+/// ```rust
+/// # use anymap::schedule::Schedule;
+/// # use anymap::sync::SyncAnyMap;
+/// struct Step(u32);
+/// struct Position(u32);
+/// struct Frames(u32);
+///
+/// let mut data = SyncAnyMap::new();
+/// data.insert(Step(2));
+/// data.insert(Position(0));
+/// data.insert(Frames(0));
+///
+/// let mut schedule = Schedule::new();
+/// schedule.add(|step: &Step, position: &mut Position| position.0 += step.0);
+/// schedule.add(|frames: &mut Frames| frames.0 += 1);
+/// schedule.add(|position: &Position, frames: &Frames| assert_eq!(position.0, frames.0 * 2));
+/// assert_eq!(schedule.stages(), 2);
+///
+/// schedule.run(&mut data).unwrap();
+/// schedule.run(&mut data).unwrap();
+/// assert_eq!(data.get::<Position>().unwrap().0, 4);
+/// ```
+pub struct Schedule {
+    systems: Vec<System>,
+    stages: usize,
+}
+
+impl Default for Schedule {
+    #[inline]
+    fn default() -> Schedule {
+        Schedule::new()
+    }
+}
+
+impl Schedule {
+    /// Construct a new, empty `Schedule`.
+    #[inline]
+    pub fn new() -> Schedule {
+        Schedule {
+            systems: Vec::new(),
+            stages: 0,
+        }
+    }
+
+    /// Adds a system to the end of the schedule.
+    pub fn add<Args, S: IntoSystem<Args>>(&mut self, system: S) -> &mut Schedule {
+        let mut system = system.into_system();
+        system.stage = self.systems.iter()
+            .filter(|earlier| earlier.conflicts_with(&system))
+            .map(|earlier| earlier.stage + 1)
+            .max()
+            .unwrap_or(0);
+        if system.stage >= self.stages {
+            self.stages = system.stage + 1;
+        }
+        self.systems.push(system);
+        self
+    }
+
+    /// Returns the number of systems in the schedule.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.systems.len()
+    }
+
+    /// Returns true if there are no systems in the schedule.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.systems.is_empty()
+    }
+
+    /// Returns the number of stages the systems are divided into, in each of which the systems
+    /// run at once.
+    #[inline]
+    pub fn stages(&self) -> usize {
+        self.stages
+    }
+
+    /// Runs every system once, stage by stage.
+    ///
+    /// If the map lacks a value for a parameter of any system, or a system refers to the same
+    /// type twice, no system is run and the error is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a system does.
+    pub fn run(&mut self, map: &mut SyncAnyMap) -> Result<(), InvokeError> {
+        for system in self.systems.iter() {
+            system.check(map)?;
+        }
+        for stage in 0..self.stages {
+            let systems = self.systems.iter_mut().filter(|system| system.stage == stage).collect();
+            run_stage(systems, map);
+        }
+        Ok(())
+    }
+}
+
+/// Runs systems known not to conflict, borrowing their parameters from the map, each system but
+/// the last on a thread of its own.
+fn run_stage(systems: Vec<&mut System>, map: &mut SyncAnyMap) {
+    // Where each type is used: by which system, as which parameter.
+    let mut users: HashMap<TypeId, Vec<(usize, usize)>, TypeIdBuildHasher> =
+        HashMap::with_hasher(TypeIdBuildHasher);
+    for (i, system) in systems.iter().enumerate() {
+        for (p, access) in system.access.iter().enumerate() {
+            match users.entry(access.id) {
+                hash_map::Entry::Occupied(e) => e.into_mut().push((i, p)),
+                hash_map::Entry::Vacant(e) => { let _ = e.insert(vec![(i, p)]); },
+            }
+        }
+    }
+
+    let mut slots: Vec<Vec<Option<Slot>>> = systems.iter()
+        .map(|system| system.access.iter().map(|_| None).collect())
+        .collect();
+    for (id, value) in map.data.iter_mut() {
+        let users = match users.get(id) {
+            Some(users) => users,
+            None => continue,
+        };
+        match users.iter().find(|&&(i, p)| systems[i].access[p].write) {
+            // No other system in the stage uses a type one writes.
+            Some(&(i, p)) => slots[i][p] = Some(Slot::Mut(&mut **value)),
+            None => {
+                let value = &**value;
+                for &(i, p) in users.iter() {
+                    slots[i][p] = Some(Slot::Shared(value));
+                }
+            },
+        }
+    }
+
+    let mut work: Vec<(&mut System, Vec<Slot>)> = systems.into_iter()
+        .zip(slots.into_iter().map(|slots| slots.into_iter().map(|slot| slot.unwrap()).collect()))
+        .collect();
+    let last = match work.pop() {
+        Some(last) => last,
+        None => return,
+    };
+    thread::scope(|scope| {
+        for (system, slots) in work.into_iter() {
+            let _ = scope.spawn(move || (*system.run)(slots));
+        }
+        let (system, slots) = last;
+        (*system.run)(slots);
+    });
+}
+
+#[test]
+fn test_schedule() {
+    use std::sync::Mutex;
+
+    let mut map = SyncAnyMap::new();
+    let _ = map.insert(1u8);
+    let _ = map.insert(0u16);
+    let _ = map.insert(0u32);
+    let _ = map.insert(Mutex::new(Vec::<u16>::new()));
+
+    let mut schedule = Schedule::new();
+    let _ = schedule
+        .add(|n: &u8, total: &mut u16| *total += *n as u16)
+        .add(|n: &u8, total: &mut u32| *total += *n as u32)
+        .add(|n: &mut u16, log: &Mutex<Vec<u16>>| {
+            log.lock().unwrap().push(*n);
+            *n *= 10;
+        })
+        .add(|n: &u32, m: &u16, log: &Mutex<Vec<u16>>| log.lock().unwrap().push(*m + *n as u16));
+    assert_eq!(schedule.len(), 4);
+    assert_eq!(schedule.stages(), 3);
+    assert_eq!(schedule.run(&mut map), Ok(()));
+    assert_eq!(map.get::<u16>(), Some(&10));
+    assert_eq!(*map.get::<Mutex<Vec<u16>>>().unwrap().lock().unwrap(), vec![1, 11]);
+
+    let _ = schedule.add(|_: &u8, _: &mut i64, _: &i8| ());
+    assert_eq!(schedule.run(&mut map),
+               Err(InvokeError::Missing(vec![type_name::<i64>(), type_name::<i8>()])));
+    assert_eq!(map.get::<u32>(), Some(&1));
+}
@@ -0,0 +1,201 @@
+//! A map owning values which may borrow from their surroundings, confined to a scope.
+//!
+//! Not available with the `forbid-unsafe` feature.
+
+use std::any::{Any, TypeId};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use TypeIdBuildHasher;
+
+/// A type which may be stored in a `ScopedAnyMap<'s>`, borrowing for at most `'s`.
+///
+/// Use the `scoped_type!` macro, which implements it correctly, rather than implementing it by
+/// hand.
+///
+/// # Safety
+///
+/// `Static` must be the same type with every lifetime replaced by `'static`, so that its
+/// `TypeId` identifies this type; getting this wrong lets a value be taken out of the map as a
+/// different type.
+pub unsafe trait ScopedType<'s>: Sized + 's {
+    /// This type, with every lifetime replaced by `'static`.
+    type Static: Any + 'static;
+}
+
+unsafe impl<'s, T: Any + 'static> ScopedType<'s> for &'s T {
+    type Static = &'static T;
+}
+
+unsafe impl<'s, T: Any + 'static> ScopedType<'s> for &'s mut T {
+    type Static = &'static mut T;
+}
+
+/// Implements `ScopedType` for a type, so that its values may be stored in a `ScopedAnyMap`.
+///
+/// It takes either a type with no lifetime parameters, or the name of a type with exactly one
+/// lifetime parameter (and no type parameters) followed by that parameter:
+///
+/// ```rust
+/// # #[macro_use] extern crate anymap;
+/// struct Config;
+/// struct Request<'a> {
+///     path: &'a str,
+/// }
+/// scoped_type!(Config);
+/// scoped_type!(Request<'a>);
+/// # fn main() { }
+/// ```
+#[macro_export]
+macro_rules! scoped_type {
+    ($name:ident < $lt:lifetime >) => {
+        unsafe impl<$lt> $crate::scoped::ScopedType<$lt> for $name<$lt> {
+            type Static = $name<'static>;
+        }
+    };
+    ($t:ty) => {
+        unsafe impl<'s> $crate::scoped::ScopedType<'s> for $t {
+            type Static = $t;
+        }
+    };
+}
+
+/// Any value at all, whose type is recorded alongside instead.
+trait Erased { }
+
+impl<T> Erased for T { }
+
+/// A collection containing zero or one values for any given type, where the values may borrow
+/// from their surroundings for `'s`.
+///
+/// The lifetime prevents the map from outliving anything its values borrow. Types are admitted
+/// by implementing `ScopedType` for them with `scoped_type!`, which is implemented already for
+/// references to `'static` types.
+///
+/// ```rust
+/// # #[macro_use] extern crate anymap;
+/// # use anymap::scoped;
+/// struct User<'a> {
+///     name: &'a str,
+/// }
+/// scoped_type!(User<'a>);
+///
+/// # fn main() {
+/// let name = format!("alice");
+/// let greeting = scoped::scope(|map| {
+///     map.insert(User { name: &name[..] });
+///     format!("hello, {}", map.get::<User>().unwrap().name)
+/// });
+/// assert_eq!(greeting, "hello, alice");
+/// # }
+/// ```
+pub struct ScopedAnyMap<'s> {
+    data: HashMap<TypeId, Box<dyn Erased + 's>, TypeIdBuildHasher>,
+    // `'s` must be invariant, or a value could be put in at one lifetime and taken out at
+    // another.
+    marker: PhantomData<Cell<&'s ()>>,
+}
+
+/// Calls `f` with a new, empty `ScopedAnyMap`, which is dropped when it returns.
+pub fn scope<'s, R, F: FnOnce(&mut ScopedAnyMap<'s>) -> R>(f: F) -> R {
+    f(&mut ScopedAnyMap::new())
+}
+
+impl<'s> Default for ScopedAnyMap<'s> {
+    #[inline]
+    fn default() -> ScopedAnyMap<'s> {
+        ScopedAnyMap::new()
+    }
+}
+
+impl<'s> ScopedAnyMap<'s> {
+    /// Construct a new, empty `ScopedAnyMap`.
+    #[inline]
+    pub fn new() -> ScopedAnyMap<'s> {
+        ScopedAnyMap {
+            data: HashMap::with_hasher(TypeIdBuildHasher),
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the value stored in the collection for the type `T`, if it exists.
+    pub fn get<T: ScopedType<'s>>(&self) -> Option<&T> {
+        self.data.get(&TypeId::of::<T::Static>())
+            .map(|erased| unsafe { &*(&**erased as *const (dyn Erased + 's) as *const T) })
+    }
+
+    /// Returns a mutable reference to the value stored in the collection for the type `T`,
+    /// if it exists.
+    pub fn get_mut<T: ScopedType<'s>>(&mut self) -> Option<&mut T> {
+        self.data.get_mut(&TypeId::of::<T::Static>())
+            .map(|erased| unsafe { &mut *(&mut **erased as *mut (dyn Erased + 's) as *mut T) })
+    }
+
+    /// Returns true if the collection contains a value of type `T`.
+    #[inline]
+    pub fn contains<T: ScopedType<'s>>(&self) -> bool {
+        self.data.contains_key(&TypeId::of::<T::Static>())
+    }
+
+    /// Sets the value stored in the collection for the type `T`.
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    pub fn insert<T: ScopedType<'s>>(&mut self, value: T) -> Option<T> {
+        self.data.insert(TypeId::of::<T::Static>(), Box::new(value) as Box<dyn Erased + 's>)
+            .map(|erased| unsafe { unbox::<T>(erased) })
+    }
+
+    /// Removes the `T` value from the collection,
+    /// returning it if there was one or `None` if there was not.
+    pub fn remove<T: ScopedType<'s>>(&mut self) -> Option<T> {
+        self.data.remove(&TypeId::of::<T::Static>()).map(|erased| unsafe { unbox::<T>(erased) })
+    }
+
+    /// Returns the number of items in the collection.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns true if there are no items in the collection.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Removes all items from the collection. Keeps the allocated memory for reuse.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+}
+
+/// Unboxes a value stored under the ID of `T::Static`, which must thus be of type `T`.
+unsafe fn unbox<'s, T: ScopedType<'s>>(erased: Box<dyn Erased + 's>) -> T {
+    *Box::from_raw(Box::into_raw(erased) as *mut T)
+}
+
+#[cfg(test)]
+scoped_type!(u8);
+
+#[test]
+fn test_scoped() {
+    struct Borrowing<'a>(&'a mut Vec<u8>);
+    scoped_type!(Borrowing<'a>);
+
+    let mut log = Vec::new();
+    let message = "hi".to_string();
+    let len = scope(|map| {
+        assert!(map.insert(Borrowing(&mut log)).is_none());
+        assert!(map.insert(&message).is_none());
+        assert!(map.insert(1u8).is_none());
+        let byte = *map.get::<u8>().unwrap();
+        map.get_mut::<Borrowing>().unwrap().0.push(byte);
+        assert_eq!(map.remove::<&String>().map(|s| &s[..]), Some("hi"));
+        assert!(!map.contains::<&String>());
+        map.len()
+    });
+    assert_eq!(len, 2);
+    assert_eq!(log, vec![1]);
+}
@@ -0,0 +1,222 @@
+//! An `AnyMap` flavour for secrets, which are zeroized rather than left lingering in freed memory.
+//!
+//! Only available with the `zeroize` feature.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use zeroize::{Zeroize, Zeroizing};
+
+use {AnyMap, TypeIdBuildHasher, cast_box, cast_mut};
+use instrument::Event;
+use like::AnyMapLike;
+
+/// Zeroizes a stored value, which must be of type `T`.
+fn zeroize_value<T: Zeroize + 'static>(value: &mut dyn Any) {
+    cast_mut::<T>(value).zeroize()
+}
+
+/// An `AnyMap` holding only values which implement `Zeroize`, such as decrypted credentials.
+///
+/// Every value left in the map is zeroized before it is dropped, whether it is cleared or dropped
+/// along with the map. A value removed is handed back still in its box, as a `ZeroizingBox`,
+/// which zeroizes it in place when it is dropped, so that no copy of it is left behind in freed
+/// memory. A value replaced is overwritten in its box by the new one, and handed back wrapped in
+/// `Zeroizing`. Values are boxed, so the map growing never leaves copies of them behind either.
+///
+/// All of the non-mutating `AnyMap` methods are available through `Deref`.
+///
+/// ```rust
+/// # use anymap::sensitive::ZeroizingAnyMap;
+/// let mut secrets = ZeroizingAnyMap::new();
+/// secrets.insert(format!("hunter2"));
+/// assert_eq!(&secrets.get::<String>().unwrap()[..], "hunter2");
+/// let password = secrets.remove::<String>().unwrap();
+/// assert_eq!(&password[..], "hunter2");
+/// // The string is zeroized in its box as `password` goes out of scope.
+/// ```
+pub struct ZeroizingAnyMap {
+    map: AnyMap,
+    zeroizers: HashMap<TypeId, fn(&mut dyn Any), TypeIdBuildHasher>,
+}
+
+impl Default for ZeroizingAnyMap {
+    #[inline]
+    fn default() -> ZeroizingAnyMap {
+        ZeroizingAnyMap::new()
+    }
+}
+
+impl ZeroizingAnyMap {
+    /// Construct a new, empty `ZeroizingAnyMap`.
+    #[inline]
+    pub fn new() -> ZeroizingAnyMap {
+        ZeroizingAnyMap {
+            map: AnyMap::new(),
+            zeroizers: HashMap::with_hasher(TypeIdBuildHasher),
+        }
+    }
+
+    /// Returns a mutable reference to the value stored in the collection for the type `T`,
+    /// if it exists.
+    #[inline]
+    pub fn get_mut<T: Zeroize + Any + 'static>(&mut self) -> Option<&mut T> {
+        self.map.get_mut::<T>()
+    }
+
+    /// Sets the value stored in the collection for the type `T`.
+    /// If the collection already had a value of type `T`, that value is returned, to be zeroized
+    /// when it is dropped. Otherwise, `None` is returned.
+    pub fn insert<T: Zeroize + Any + 'static>(&mut self, value: T) -> Option<Zeroizing<T>> {
+        let _ = self.zeroizers.insert(TypeId::of::<T>(), zeroize_value::<T> as fn(&mut dyn Any));
+        self.map.insert(value).map(Zeroizing::new)
+    }
+
+    /// Removes the `T` value from the collection, returning it in the box it was stored in, to
+    /// be zeroized there when it is dropped, if there was one or `None` if there was not.
+    pub fn remove<T: Zeroize + Any + 'static>(&mut self) -> Option<ZeroizingBox<T>> {
+        let _ = self.zeroizers.remove(&TypeId::of::<T>());
+        let value = self.map.remove_by_id(&TypeId::of::<T>())?;
+        self.map.instruments.record::<T>(Event::Remove);
+        Some(ZeroizingBox { value: cast_box::<T>(value) })
+    }
+
+    /// Zeroizes and removes all items from the collection. Keeps the allocated memory for reuse.
+    pub fn clear(&mut self) {
+        self.zeroize_all();
+        self.zeroizers.clear();
+        self.map.clear();
+    }
+
+    fn zeroize_all(&mut self) {
+        for (id, value) in self.map.data.iter_mut() {
+            (self.zeroizers[id])(&mut **value);
+        }
+    }
+}
+
+impl Deref for ZeroizingAnyMap {
+    type Target = AnyMap;
+
+    #[inline]
+    fn deref(&self) -> &AnyMap {
+        &self.map
+    }
+}
+
+/// `insert_raw` is refused, as whether a boxed value implements `Zeroize` cannot be checked.
+/// `remove_raw` is refused too, as the value could only be handed back in a bare box, which
+/// would not zeroize it when dropped; `remove` hands it back in a `ZeroizingBox` instead.
+impl AnyMapLike for ZeroizingAnyMap {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.map.contains_id(id)
+    }
+
+    #[inline]
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.map.get_raw(id)
+    }
+
+    #[inline]
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        self.map.get_raw_mut(id)
+    }
+
+    #[inline]
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        Err(value)
+    }
+
+    #[inline]
+    fn remove_raw(&mut self, _: TypeId) -> Option<Box<dyn Any>> {
+        None
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+impl Drop for ZeroizingAnyMap {
+    fn drop(&mut self) {
+        self.zeroize_all();
+    }
+}
+
+/// A value removed from a `ZeroizingAnyMap`, still in the box it was stored in, which zeroizes
+/// it in place when it is dropped.
+///
+/// Moving the value out of the box, as by cloning and dropping it, is the only way to use it
+/// by value without leaving a copy behind.
+pub struct ZeroizingBox<T: Zeroize> {
+    value: Box<T>,
+}
+
+impl<T: Zeroize> Deref for ZeroizingBox<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Zeroize> DerefMut for ZeroizingBox<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: Zeroize> Drop for ZeroizingBox<T> {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+#[test]
+fn test_zeroizing() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct Secret(u32, Rc<Cell<u32>>);
+
+    impl Zeroize for Secret {
+        fn zeroize(&mut self) {
+            self.0 = 0;
+            self.1.set(self.1.get() + 1);
+        }
+    }
+
+    let zeroized = Rc::new(Cell::new(0));
+    let mut map = ZeroizingAnyMap::new();
+    assert!(map.insert(Secret(1, zeroized.clone())).is_none());
+    {
+        let old = map.insert(Secret(2, zeroized.clone())).unwrap();
+        assert_eq!(old.0, 1);
+        assert_eq!(zeroized.get(), 0);
+    }
+    assert_eq!(zeroized.get(), 1);
+    assert_eq!(map.get::<Secret>().unwrap().0, 2);
+
+    let _ = map.insert(vec![1u8, 2, 3]);
+    map.clear();
+    assert_eq!(zeroized.get(), 2);
+    assert!(map.is_empty());
+
+    let _ = map.insert(Secret(3, zeroized.clone()));
+    let stored = map.get_ptr::<Secret>().unwrap();
+    let removed = map.remove::<Secret>().unwrap();
+    assert!(::std::ptr::eq(&*removed, stored.as_ptr()));
+    assert_eq!((removed.0, zeroized.get()), (3, 2));
+    drop(removed);
+    assert_eq!(zeroized.get(), 3);
+
+    let _ = map.insert(Secret(4, zeroized.clone()));
+    drop(map);
+    assert_eq!(zeroized.get(), 4);
+}
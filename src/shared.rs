@@ -0,0 +1,413 @@
+//! An `AnyMap` flavour whose values may be shared rather than owned, and are only copied when
+//! they are first mutated.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::mem;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use {TypeIdBuildHasher, cast_box, cast_mut, cast_ref};
+use like::AnyMapLike;
+
+/// A value which is either borrowed for the life of the program, shared, or owned.
+#[derive(Debug)]
+pub enum SharedValue<T: 'static> {
+    /// A value borrowed for the life of the program.
+    Borrowed(&'static T),
+    /// A value shared through an `Arc`.
+    Shared(Arc<T>),
+    /// An owned value.
+    Owned(T),
+}
+
+impl<T: Clone + 'static> SharedValue<T> {
+    /// Returns true if the value is owned.
+    #[inline]
+    pub fn is_owned(&self) -> bool {
+        matches!(*self, SharedValue::Owned(_))
+    }
+
+    /// Returns the value, cloning it unless it is owned.
+    pub fn into_owned(self) -> T {
+        match self {
+            SharedValue::Borrowed(value) => value.clone(),
+            SharedValue::Shared(value) => (*value).clone(),
+            SharedValue::Owned(value) => value,
+        }
+    }
+
+    /// Returns the value shared through an `Arc`, moving it into one if it is owned.
+    pub fn into_shared(self) -> SharedValue<T> {
+        match self {
+            SharedValue::Owned(value) => SharedValue::Shared(Arc::new(value)),
+            value => value,
+        }
+    }
+
+    /// Returns a mutable reference to the value, cloning it to make it owned first if need be.
+    pub fn to_mut(&mut self) -> &mut T {
+        if !self.is_owned() {
+            let owned = match *self {
+                SharedValue::Borrowed(value) => value.clone(),
+                SharedValue::Shared(ref value) => (**value).clone(),
+                SharedValue::Owned(_) => unreachable!(),
+            };
+            *self = SharedValue::Owned(owned);
+        }
+        match *self {
+            SharedValue::Owned(ref mut value) => value,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<T: 'static> Deref for SharedValue<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match *self {
+            SharedValue::Borrowed(value) => value,
+            SharedValue::Shared(ref value) => value,
+            SharedValue::Owned(ref value) => value,
+        }
+    }
+}
+
+impl<T: Clone + 'static> Clone for SharedValue<T> {
+    fn clone(&self) -> SharedValue<T> {
+        match *self {
+            SharedValue::Borrowed(value) => SharedValue::Borrowed(value),
+            SharedValue::Shared(ref value) => SharedValue::Shared(value.clone()),
+            SharedValue::Owned(ref value) => SharedValue::Owned(value.clone()),
+        }
+    }
+}
+
+/// A `SharedValue<T>`, with ways of cloning it, of sharing it and of getting at its value.
+struct Slot {
+    value: Box<dyn Any + 'static>,
+    clone: fn(&dyn Any) -> Box<dyn Any + 'static>,
+    clone_owned: fn(&dyn Any) -> Box<dyn Any + 'static>,
+    share: fn(Box<dyn Any + 'static>) -> Box<dyn Any + 'static>,
+    deref: fn(&dyn Any) -> &dyn Any,
+    to_mut: fn(&mut dyn Any) -> &mut dyn Any,
+    into_owned: fn(Box<dyn Any + 'static>) -> Box<dyn Any + 'static>,
+}
+
+impl Slot {
+    fn new<T: Clone + 'static>(value: SharedValue<T>) -> Slot {
+        Slot {
+            value: Box::new(value) as Box<dyn Any>,
+            clone: clone_value::<T>,
+            clone_owned: clone_owned::<T>,
+            share: share_value::<T>,
+            deref: deref_value::<T>,
+            to_mut: value_to_mut::<T>,
+            into_owned: into_owned::<T>,
+        }
+    }
+
+    /// Makes a new slot with the same functions.
+    fn with_value(&self, value: Box<dyn Any + 'static>) -> Slot {
+        Slot {
+            value,
+            clone: self.clone,
+            clone_owned: self.clone_owned,
+            share: self.share,
+            deref: self.deref,
+            to_mut: self.to_mut,
+            into_owned: self.into_owned,
+        }
+    }
+}
+
+/// Clones a `SharedValue<T>`.
+fn clone_value<T: Clone + 'static>(value: &dyn Any) -> Box<dyn Any + 'static> {
+    Box::new(cast_ref::<SharedValue<T>>(value).clone()) as Box<dyn Any>
+}
+
+/// Clones the value of a `SharedValue<T>` into an owned one.
+fn clone_owned<T: Clone + 'static>(value: &dyn Any) -> Box<dyn Any + 'static> {
+    Box::new(SharedValue::Owned((**cast_ref::<SharedValue<T>>(value)).clone())) as Box<dyn Any>
+}
+
+/// Moves the value of a `SharedValue<T>` into an `Arc` if it is owned.
+fn share_value<T: Clone + 'static>(value: Box<dyn Any + 'static>) -> Box<dyn Any + 'static> {
+    Box::new(cast_box::<SharedValue<T>>(value).into_shared()) as Box<dyn Any>
+}
+
+/// Returns the value of a `SharedValue<T>`.
+fn deref_value<T: Clone + 'static>(value: &dyn Any) -> &dyn Any {
+    &**cast_ref::<SharedValue<T>>(value)
+}
+
+/// Returns the value of a `SharedValue<T>` mutably, cloning it first if it is not owned.
+fn value_to_mut<T: Clone + 'static>(value: &mut dyn Any) -> &mut dyn Any {
+    cast_mut::<SharedValue<T>>(value).to_mut()
+}
+
+/// Returns the value of a `SharedValue<T>`, cloning it unless it is owned.
+fn into_owned<T: Clone + 'static>(value: Box<dyn Any + 'static>) -> Box<dyn Any + 'static> {
+    Box::new(cast_box::<SharedValue<T>>(value).into_owned()) as Box<dyn Any>
+}
+
+/// A collection containing zero or one values for any given type, where each value may be
+/// borrowed for the life of the program or shared through an `Arc` instead of owned.
+///
+/// Borrowed and shared values are cloned to become owned the first time they are accessed
+/// mutably, so a map can start out with application defaults and `fork` copies of it into each
+/// request cheaply, paying for copies only of the values which are actually changed.
+///
+/// ```rust
+/// # use anymap::shared::SharedAnyMap;
+/// # use std::sync::Arc;
+/// let mut defaults = SharedAnyMap::new();
+/// defaults.insert_shared(Arc::new(vec![1, 2, 3]));
+/// let mut request = defaults.fork();
+/// assert!(!request.is_owned::<Vec<i32>>());
+/// request.get_mut::<Vec<i32>>().unwrap().push(4);
+/// assert!(request.is_owned::<Vec<i32>>());
+/// assert_eq!(defaults.get::<Vec<i32>>().unwrap().len(), 3);
+/// ```
+pub struct SharedAnyMap {
+    data: HashMap<TypeId, Slot, TypeIdBuildHasher>,
+}
+
+impl Default for SharedAnyMap {
+    #[inline]
+    fn default() -> SharedAnyMap {
+        SharedAnyMap::new()
+    }
+}
+
+impl SharedAnyMap {
+    /// Construct a new, empty `SharedAnyMap`.
+    #[inline]
+    pub fn new() -> SharedAnyMap {
+        SharedAnyMap {
+            data: HashMap::with_hasher(TypeIdBuildHasher),
+        }
+    }
+
+    /// Returns a copy of the collection, sharing its borrowed and shared values and cloning its
+    /// owned ones.
+    pub fn fork(&self) -> SharedAnyMap {
+        let mut data = HashMap::with_capacity_and_hasher(self.data.len(), TypeIdBuildHasher);
+        for (&id, slot) in self.data.iter() {
+            let _ = data.insert(id, slot.with_value((slot.clone)(&*slot.value)));
+        }
+        SharedAnyMap {
+            data,
+        }
+    }
+
+    /// Returns a copy of the collection sharing all of its values, moving each owned value into
+    /// an `Arc` first, so that no value is cloned now.
+    ///
+    /// The values so moved are no longer owned by this collection either, and are cloned if it
+    /// mutates them while they are still shared with the copy.
+    ///
+    /// ```rust
+    /// # use anymap::shared::SharedAnyMap;
+    /// let mut state = SharedAnyMap::new();
+    /// state.insert(vec![0u8; 1024]);
+    /// let worker = state.clone_shallow();
+    /// assert!(!state.is_owned::<Vec<u8>>() && !worker.is_owned::<Vec<u8>>());
+    /// assert!(state.clone_deep().is_owned::<Vec<u8>>());
+    /// ```
+    pub fn clone_shallow(&mut self) -> SharedAnyMap {
+        for slot in self.data.values_mut() {
+            let value = mem::replace(&mut slot.value, Box::new(()) as Box<dyn Any>);
+            slot.value = (slot.share)(value);
+        }
+        self.fork()
+    }
+
+    /// Returns a copy of the collection cloning all of its values, so that every value of the
+    /// copy is owned.
+    pub fn clone_deep(&self) -> SharedAnyMap {
+        let mut data = HashMap::with_capacity_and_hasher(self.data.len(), TypeIdBuildHasher);
+        for (&id, slot) in self.data.iter() {
+            let _ = data.insert(id, slot.with_value((slot.clone_owned)(&*slot.value)));
+        }
+        SharedAnyMap {
+            data,
+        }
+    }
+
+    /// Returns a reference to the value stored in the collection for the type `T`, if it exists.
+    pub fn get<T: Clone + Any + 'static>(&self) -> Option<&T> {
+        self.data.get(&TypeId::of::<T>()).map(|slot| &**cast_ref::<SharedValue<T>>(&*slot.value))
+    }
+
+    /// Returns a mutable reference to the value stored in the collection for the type `T`,
+    /// if it exists, cloning it first if it is not owned.
+    pub fn get_mut<T: Clone + Any + 'static>(&mut self) -> Option<&mut T> {
+        self.data.get_mut(&TypeId::of::<T>())
+            .map(|slot| cast_mut::<SharedValue<T>>(&mut *slot.value).to_mut())
+    }
+
+    /// Returns true if the collection contains a value of type `T`.
+    #[inline]
+    pub fn contains<T: Clone + Any + 'static>(&self) -> bool {
+        self.data.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Returns true if the collection contains an owned value of type `T`.
+    pub fn is_owned<T: Clone + Any + 'static>(&self) -> bool {
+        self.data.get(&TypeId::of::<T>())
+            .is_some_and(|slot| cast_ref::<SharedValue<T>>(&*slot.value).is_owned())
+    }
+
+    /// Sets the value stored in the collection for the type `T` to an owned value.
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    #[inline]
+    pub fn insert<T: Clone + Any + 'static>(&mut self, value: T) -> Option<SharedValue<T>> {
+        self.insert_value(SharedValue::Owned(value))
+    }
+
+    /// Sets the value stored in the collection for the type `T` to a value borrowed for the life
+    /// of the program.
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    #[inline]
+    pub fn insert_static<T: Clone + Any + 'static>(&mut self, value: &'static T)
+                                                   -> Option<SharedValue<T>> {
+        self.insert_value(SharedValue::Borrowed(value))
+    }
+
+    /// Sets the value stored in the collection for the type `T` to a shared value.
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    #[inline]
+    pub fn insert_shared<T: Clone + Any + 'static>(&mut self, value: Arc<T>)
+                                                   -> Option<SharedValue<T>> {
+        self.insert_value(SharedValue::Shared(value))
+    }
+
+    /// Sets the value stored in the collection for the type `T`.
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    pub fn insert_value<T: Clone + Any + 'static>(&mut self, value: SharedValue<T>)
+                                                  -> Option<SharedValue<T>> {
+        self.data.insert(TypeId::of::<T>(), Slot::new(value))
+            .map(|slot| *cast_box::<SharedValue<T>>(slot.value))
+    }
+
+    /// Removes the `T` value from the collection,
+    /// returning it if there was one or `None` if there was not.
+    pub fn remove<T: Clone + Any + 'static>(&mut self) -> Option<SharedValue<T>> {
+        self.data.remove(&TypeId::of::<T>()).map(|slot| *cast_box::<SharedValue<T>>(slot.value))
+    }
+
+    /// Returns the number of items in the collection.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns true if there are no items in the collection.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Removes all items from the collection. Keeps the allocated memory for reuse.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+}
+
+/// Values are lent out through `AnyMapLike` as the typed methods lend them, cloning a value
+/// which is not owned before lending it mutably, and are removed as owned values, cloned if need
+/// be. Values inserted through it are refused, as a value can only be stored along with ways of
+/// cloning it, which cannot be made without knowing its type.
+impl AnyMapLike for SharedAnyMap {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.data.contains_key(&id)
+    }
+
+    #[inline]
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.data.get(&id).map(|slot| (slot.deref)(&*slot.value))
+    }
+
+    #[inline]
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        self.data.get_mut(&id).map(|slot| (slot.to_mut)(&mut *slot.value))
+    }
+
+    #[inline]
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        Err(value)
+    }
+
+    #[inline]
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        self.data.remove(&id).map(|slot| (slot.into_owned)(slot.value))
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl Clone for SharedAnyMap {
+    /// Forks the collection; see `fork`.
+    #[inline]
+    fn clone(&self) -> SharedAnyMap {
+        self.fork()
+    }
+}
+
+#[test]
+fn test_shared() {
+    static GREETING: &str = "hello";
+
+    let mut defaults = SharedAnyMap::new();
+    let numbers = Arc::new(vec![1i32, 2]);
+    assert!(defaults.insert_shared(numbers.clone()).is_none());
+    assert!(defaults.insert_static(&GREETING).is_none());
+    assert!(defaults.insert(1u8).is_none());
+
+    let mut fork = defaults.fork();
+    assert_eq!(Arc::strong_count(&numbers), 3);
+    assert!(fork.is_owned::<u8>() && !fork.is_owned::<Vec<i32>>());
+    *fork.get_mut::<u8>().unwrap() = 2;
+    fork.get_mut::<Vec<i32>>().unwrap().push(3);
+    assert!(fork.is_owned::<Vec<i32>>());
+    assert_eq!(Arc::strong_count(&numbers), 2);
+    assert_eq!(fork.get::<Vec<i32>>(), Some(&vec![1, 2, 3]));
+    assert_eq!(defaults.get::<Vec<i32>>(), Some(&vec![1, 2]));
+    assert_eq!(defaults.get::<u8>(), Some(&1));
+
+    assert_eq!(*fork.remove::<&str>().unwrap(), "hello");
+    assert_eq!(fork.remove::<u8>().unwrap().into_owned(), 2);
+    assert_eq!(fork.len(), 1);
+}
+
+#[test]
+fn test_shallow_and_deep_clones() {
+    let mut map = SharedAnyMap::new();
+    assert!(map.insert(vec![1u8]).is_none());
+    let numbers = Arc::new(2u16);
+    assert!(map.insert_shared(numbers.clone()).is_none());
+
+    let deep = map.clone_deep();
+    assert!(deep.is_owned::<Vec<u8>>() && deep.is_owned::<u16>());
+    assert_eq!(Arc::strong_count(&numbers), 2);
+
+    let mut shallow = map.clone_shallow();
+    assert!(!map.is_owned::<Vec<u8>>() && !shallow.is_owned::<Vec<u8>>());
+    assert_eq!(Arc::strong_count(&numbers), 3);
+    shallow.get_mut::<Vec<u8>>().unwrap().push(2);
+    assert_eq!(map.get::<Vec<u8>>(), Some(&vec![1]));
+    assert_eq!(shallow.get::<Vec<u8>>(), Some(&vec![1, 2]));
+    assert_eq!(deep.get::<u16>(), Some(&2));
+}
@@ -0,0 +1,161 @@
+//! A single type-erased value, for where a whole map would be more than is needed.
+
+use std::any::{Any, TypeId};
+use std::error::Error;
+use std::fmt;
+
+use {cast_box, cast_mut, cast_ref, type_name};
+
+/// A slot holding at most one value, of any type.
+///
+/// A value is taken out of the slot by its type, as with an `AnyMap`; asking for the wrong
+/// type is reported with the names of both types.
+///
+/// ```rust
+/// # use anymap::slot::{AnySlot, SlotError};
+/// let mut slot = AnySlot::new();
+/// slot.put(42u32);
+/// assert_eq!(slot.get::<u32>(), Ok(&42));
+/// match slot.take::<String>() {
+///     Err(SlotError::Mismatch { .. }) => (),
+///     _ => unreachable!(),
+/// }
+/// assert_eq!(slot.take::<u32>(), Ok(42));
+/// assert_eq!(slot.take::<u32>(), Err(SlotError::Empty));
+/// ```
+pub struct AnySlot {
+    value: Option<(Box<dyn Any + 'static>, &'static str)>,
+}
+
+impl Default for AnySlot {
+    #[inline]
+    fn default() -> AnySlot {
+        AnySlot::new()
+    }
+}
+
+impl AnySlot {
+    /// Construct a new, empty `AnySlot`.
+    #[inline]
+    pub fn new() -> AnySlot {
+        AnySlot {
+            value: None,
+        }
+    }
+
+    /// Returns a reference to the value in the slot, if it is of type `T`.
+    pub fn get<T: Any + 'static>(&self) -> Result<&T, SlotError> {
+        self.check::<T>()?;
+        Ok(cast_ref::<T>(&*self.value.as_ref().unwrap().0))
+    }
+
+    /// Returns a mutable reference to the value in the slot, if it is of type `T`.
+    pub fn get_mut<T: Any + 'static>(&mut self) -> Result<&mut T, SlotError> {
+        self.check::<T>()?;
+        Ok(cast_mut::<T>(&mut *self.value.as_mut().unwrap().0))
+    }
+
+    /// Takes the value out of the slot, if it is of type `T`, leaving the slot empty. A value
+    /// of another type is left in the slot.
+    pub fn take<T: Any + 'static>(&mut self) -> Result<T, SlotError> {
+        self.check::<T>()?;
+        Ok(*cast_box::<T>(self.value.take().unwrap().0))
+    }
+
+    /// Puts a value in the slot, returning the value it held before, of whatever type, if any.
+    pub fn put<T: Any + 'static>(&mut self, value: T) -> Option<Box<dyn Any + 'static>> {
+        self.value.replace((Box::new(value) as Box<dyn Any>, type_name::<T>()))
+            .map(|(value, _)| value)
+    }
+
+    /// Returns true if the slot holds a value of type `T`.
+    #[inline]
+    pub fn holds<T: Any + 'static>(&self) -> bool {
+        self.check::<T>().is_ok()
+    }
+
+    /// Returns the name of the type of the value in the slot, if there is one.
+    #[inline]
+    pub fn type_name(&self) -> Option<&'static str> {
+        self.value.as_ref().map(|&(_, name)| name)
+    }
+
+    /// Returns true if the slot is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.value.is_none()
+    }
+
+    /// Takes whatever value is in the slot out of it, leaving it empty.
+    #[inline]
+    pub fn take_any(&mut self) -> Option<Box<dyn Any + 'static>> {
+        self.value.take().map(|(value, _)| value)
+    }
+
+    /// Checks that the slot holds a value of type `T`.
+    fn check<T: Any + 'static>(&self) -> Result<(), SlotError> {
+        match self.value {
+            None => Err(SlotError::Empty),
+            Some((ref value, _)) if (**value).type_id() == TypeId::of::<T>() => Ok(()),
+            Some((_, found)) => Err(SlotError::Mismatch {
+                expected: type_name::<T>(),
+                found,
+            }),
+        }
+    }
+}
+
+/// The reasons a value may not be had from an `AnySlot`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SlotError {
+    /// The slot is empty.
+    Empty,
+    /// The slot holds a value of another type.
+    Mismatch {
+        /// The name of the type asked for.
+        expected: &'static str,
+        /// The name of the type of the value in the slot.
+        found: &'static str,
+    },
+}
+
+impl fmt::Display for SlotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SlotError::Empty => write!(f, "the slot is empty"),
+            SlotError::Mismatch { expected, found } =>
+                write!(f, "expected a value of the type {} in the slot, found one of the type {}",
+                       expected, found),
+        }
+    }
+}
+
+impl Error for SlotError {
+    fn description(&self) -> &str {
+        match *self {
+            SlotError::Empty => "the slot is empty",
+            SlotError::Mismatch { .. } => "the slot holds a value of another type",
+        }
+    }
+}
+
+#[test]
+fn test_slot() {
+    let mut slot = AnySlot::new();
+    assert!(slot.is_empty() && slot.type_name().is_none());
+    assert_eq!(slot.get::<u8>(), Err(SlotError::Empty));
+    assert!(slot.put(1u8).is_none());
+    *slot.get_mut::<u8>().unwrap() += 1;
+    assert!(slot.holds::<u8>() && !slot.holds::<u16>());
+    assert_eq!(slot.type_name(), Some(type_name::<u8>()));
+    assert_eq!(slot.take::<u16>(), Err(SlotError::Mismatch {
+        expected: type_name::<u16>(),
+        found: type_name::<u8>(),
+    }));
+    let old = slot.put(3u16).unwrap();
+    assert_eq!(old.downcast_ref::<u8>(), Some(&2));
+    assert_eq!(slot.take::<u16>(), Ok(3));
+    assert!(slot.is_empty());
+    let _ = slot.put(());
+    assert!(slot.take_any().is_some() && slot.is_empty());
+}
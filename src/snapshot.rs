@@ -0,0 +1,1122 @@
+//! Binary snapshots of the values of an `AnyMap`, encoded with the codecs registered in a
+//! `TypeRegistry`, and optionally encrypted so that they are confidential and tamper-evident.
+//!
+//! A snapshot is a header followed by one record per value, each naming the value's type by its
+//! registered name and giving the version of the type's layout it was encoded in, so that older
+//! values can be migrated as they are read, and a final record counting them. In an encrypted
+//! snapshot each record is sealed on its own, authenticated along with the header and its
+//! position, so that records cannot be altered, reordered, dropped or spliced in from another
+//! snapshot unnoticed. The values may also be compressed, each on its own.
+//!
+//! Each record of a value carries a CRC-32 checksum of its contents, and the final record one of
+//! the whole snapshot before it, so that corruption of a snapshot at rest is reported on import,
+//! naming the type of the corrupt value where it can, rather than decoding garbage.
+//!
+//! Snapshots can be written to an `io::Write` and read from an `io::Read` one record at a time,
+//! so that a large map is never serialized into memory as a whole.
+
+use std::any::{Any, TypeId};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use {AnyMap, TypeSet};
+use registry::TypeRegistry;
+
+/// The bytes every snapshot starts with.
+const MAGIC: [u8; 4] = *b"AMSN";
+
+/// The version of the format written.
+const VERSION: u8 = 3;
+
+/// The header flag marking an encrypted snapshot.
+const ENCRYPTED: u8 = 1;
+
+/// The header flag marking a snapshot whose values are compressed.
+const COMPRESSED: u8 = 2;
+
+/// The length of the header: the magic bytes, the version, the flags and the ID of the
+/// compression.
+const HEADER_LEN: usize = 7;
+
+/// The tag of the record ending a snapshot.
+const END: u8 = 0;
+
+/// The tag of a record holding a value.
+const ENTRY: u8 = 1;
+
+/// An authenticated encryption algorithm with associated data, such as ChaCha20-Poly1305 or
+/// AES-GCM, along with its key, used to seal the records of encrypted snapshots.
+///
+/// This crate implements no cryptography itself; implement this for a type wrapping a cipher
+/// from a cryptography crate. `seal` must use a fresh nonce each time it is called, and include
+/// it in the sealed bytes.
+///
+/// ```rust,ignore
+/// use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+/// use chacha20poly1305::aead::{Aead, AeadCore, OsRng, Payload};
+///
+/// struct Cipher(XChaCha20Poly1305);
+///
+/// impl anymap::snapshot::Aead for Cipher {
+///     fn seal(&self, plaintext: &[u8], associated: &[u8]) -> Vec<u8> {
+///         let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+///         let payload = Payload { msg: plaintext, aad: associated };
+///         let mut sealed = nonce.to_vec();
+///         sealed.extend(self.0.encrypt(&nonce, payload).unwrap());
+///         sealed
+///     }
+///
+///     fn open(&self, sealed: &[u8], associated: &[u8]) -> Option<Vec<u8>> {
+///         if sealed.len() < 24 {
+///             return None;
+///         }
+///         let (nonce, ciphertext) = sealed.split_at(24);
+///         let payload = Payload { msg: ciphertext, aad: associated };
+///         self.0.decrypt(XNonce::from_slice(nonce), payload).ok()
+///     }
+/// }
+/// ```
+pub trait Aead {
+    /// Encrypts `plaintext` and authenticates it along with `associated`, returning the sealed
+    /// bytes, including the nonce and the authentication tag.
+    fn seal(&self, plaintext: &[u8], associated: &[u8]) -> Vec<u8>;
+
+    /// Decrypts sealed bytes, returning `None` if they are not authentic or were sealed with
+    /// different associated data.
+    fn open(&self, sealed: &[u8], associated: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// A compression algorithm, such as zstd or LZ4, with which the values in a snapshot are
+/// compressed, each on its own.
+///
+/// This crate implements no compression itself; implement this for a type calling on a
+/// compression crate.
+///
+/// ```rust,ignore
+/// struct Lz4;
+///
+/// impl anymap::snapshot::Compression for Lz4 {
+///     fn id(&self) -> u8 { 1 }
+///
+///     fn compress(&self, data: &[u8]) -> Vec<u8> {
+///         lz4_flex::compress_prepend_size(data)
+///     }
+///
+///     fn decompress(&self, data: &[u8]) -> Option<Vec<u8>> {
+///         lz4_flex::decompress_size_prepended(data).ok()
+///     }
+/// }
+/// ```
+pub trait Compression {
+    /// A nonzero number identifying the algorithm, recorded in snapshots compressed with it so
+    /// that they are not read with another.
+    fn id(&self) -> u8;
+
+    /// Compresses a value.
+    fn compress(&self, data: &[u8]) -> Vec<u8>;
+
+    /// Decompresses a value, returning `None` if the data is not valid.
+    fn decompress(&self, data: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// The CRC-32 lookup table, for the reflected IEEE polynomial.
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Returns the CRC-32 checksum of some bytes, as used by zlib and PNG.
+fn crc32(bytes: &[u8]) -> u32 {
+    crc32_update(0, bytes)
+}
+
+/// Returns the CRC-32 checksum of some bytes following those with checksum `crc`.
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!crc, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// One record of a snapshot: a value, given by the registered name of its type, the version of
+/// the type's layout and its encoded bytes, or the end, given by the number of values and the
+/// checksum of everything before it.
+enum Record<'a> {
+    Entry(&'a str, u32, &'a [u8]),
+    End(u64, u32),
+}
+
+impl<'a> Record<'a> {
+    fn write(&self, out: &mut Vec<u8>) {
+        match *self {
+            Record::Entry(name, version, data) => {
+                out.push(ENTRY);
+                let start = out.len();
+                write_bytes(out, name.as_bytes());
+                out.extend_from_slice(&version.to_le_bytes());
+                write_bytes(out, data);
+                let checksum = crc32(&out[start..]);
+                out.extend_from_slice(&checksum.to_le_bytes());
+            },
+            Record::End(count, checksum) => {
+                out.push(END);
+                out.extend_from_slice(&count.to_le_bytes());
+                out.extend_from_slice(&checksum.to_le_bytes());
+            },
+        }
+    }
+
+    fn read(reader: &mut Reader<'a>) -> Result<Record<'a>, SnapshotError> {
+        match reader.take(1)?[0] {
+            ENTRY => {
+                let start = reader.bytes;
+                let name = reader.bytes()?;
+                let version = reader.u32()?;
+                let data = reader.bytes()?;
+                let contents = &start[..start.len() - reader.bytes.len()];
+                if reader.u32()? != crc32(contents) {
+                    let name = String::from_utf8_lossy(name).into_owned();
+                    return Err(SnapshotError::CorruptEntry(name));
+                }
+                let name = ::std::str::from_utf8(name).map_err(|_| SnapshotError::Malformed)?;
+                Ok(Record::Entry(name, version, data))
+            },
+            END => Ok(Record::End(reader.u64()?, reader.u32()?)),
+            _ => Err(SnapshotError::Malformed),
+        }
+    }
+}
+
+/// Writes length-prefixed bytes.
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// Reads the parts of a snapshot from a buffer.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() < len {
+            return Err(SnapshotError::Malformed);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        let mut word = [0; 4];
+        word.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(word))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        let mut word = [0; 8];
+        word.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(word))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], SnapshotError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+/// Returns the header of a snapshot written with the given options.
+fn header(options: &Options) -> [u8; HEADER_LEN] {
+    let mut flags = 0;
+    if options.aead.is_some() {
+        flags |= ENCRYPTED;
+    }
+    let compression = match options.compression {
+        Some(compression) => {
+            flags |= COMPRESSED;
+            compression.id()
+        },
+        None => 0,
+    };
+    [MAGIC[0], MAGIC[1], MAGIC[2], MAGIC[3], VERSION, flags, compression]
+}
+
+/// Returns the data authenticated along with the record at `index` of an encrypted snapshot.
+fn associated(header: &[u8], index: u64) -> Vec<u8> {
+    let mut associated = header.to_vec();
+    associated.extend_from_slice(&index.to_le_bytes());
+    associated
+}
+
+/// Reads the header of a snapshot, returning its flags and the ID of its compression.
+fn read_header(reader: &mut Reader) -> Result<(u8, u8), SnapshotError> {
+    let header = reader.take(HEADER_LEN).map_err(|_| SnapshotError::Malformed)?;
+    if header[..4] != MAGIC {
+        return Err(SnapshotError::Malformed);
+    }
+    if header[4] != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(header[4]));
+    }
+    Ok((header[5], header[6]))
+}
+
+/// Checks that a snapshot with the given flags and compression ID can be read with `options`,
+/// returning the compression its values are to be decompressed with, if they are compressed.
+fn compression<'a>(flags: u8, id: u8, options: &Options<'a>)
+                   -> Result<Option<&'a dyn Compression>, SnapshotError> {
+    match (flags & ENCRYPTED != 0, options.aead.is_some()) {
+        (true, false) => return Err(SnapshotError::Encrypted),
+        (false, true) => return Err(SnapshotError::NotEncrypted),
+        _ => (),
+    }
+    match options.compression {
+        _ if flags & COMPRESSED == 0 => Ok(None),
+        Some(compression) if compression.id() == id => Ok(Some(compression)),
+        _ => Err(SnapshotError::Compressed(id)),
+    }
+}
+
+/// Writes the record at `index` of a snapshot, compressing its value and sealing it as the
+/// options say.
+fn write_record(out: &mut Vec<u8>, header: &[u8], index: u64, record: Record,
+                options: &Options) {
+    let compressed;
+    let record = match (record, options.compression) {
+        (Record::Entry(name, version, data), Some(compression)) => {
+            compressed = compression.compress(data);
+            Record::Entry(name, version, &compressed)
+        },
+        (record, _) => record,
+    };
+    match options.aead {
+        Some(aead) => {
+            let mut plaintext = Vec::new();
+            record.write(&mut plaintext);
+            write_bytes(out, &aead.seal(&plaintext, &associated(header, index)));
+        },
+        None => record.write(out),
+    }
+}
+
+/// Converts an error reading a snapshot, a stream ending early making the snapshot truncated.
+fn read_error(error: io::Error) -> SnapshotError {
+    match error.kind() {
+        io::ErrorKind::UnexpectedEof => SnapshotError::Malformed,
+        kind => SnapshotError::Io(kind),
+    }
+}
+
+/// Reads exactly `len` bytes from a stream onto the end of `buf`, allocating no more than the
+/// stream holds, should a corrupt length be huge.
+fn read_into<R: Read + ?Sized>(reader: &mut R, buf: &mut Vec<u8>, len: usize)
+                               -> Result<(), SnapshotError> {
+    let read = Read::take(reader, len as u64).read_to_end(buf).map_err(read_error)?;
+    if read < len {
+        return Err(SnapshotError::Malformed);
+    }
+    Ok(())
+}
+
+/// Reads length-prefixed bytes from a stream onto the end of `buf`, along with their length.
+fn read_bytes_into<R: Read + ?Sized>(reader: &mut R, buf: &mut Vec<u8>)
+                                     -> Result<(), SnapshotError> {
+    let start = buf.len();
+    read_into(reader, buf, 4)?;
+    let mut len = [0; 4];
+    len.copy_from_slice(&buf[start..]);
+    read_into(reader, buf, u32::from_le_bytes(len) as usize)
+}
+
+/// Reads one record of a snapshot from a stream onto the end of `buf`, as it is stored: sealed,
+/// if `sealed`, or otherwise as `Record::write` lays it out.
+fn read_raw<R: Read + ?Sized>(reader: &mut R, buf: &mut Vec<u8>, sealed: bool)
+                              -> Result<(), SnapshotError> {
+    if sealed {
+        return read_bytes_into(reader, buf);
+    }
+    read_into(reader, buf, 1)?;
+    match buf[buf.len() - 1] {
+        ENTRY => {
+            read_bytes_into(reader, buf)?;
+            read_into(reader, buf, 4)?;
+            read_bytes_into(reader, buf)?;
+            read_into(reader, buf, 4)
+        },
+        END => read_into(reader, buf, 12),
+        _ => Err(SnapshotError::Malformed),
+    }
+}
+
+/// How a snapshot is written and read: whether its values are compressed, whether it is
+/// encrypted, and which values are written. By default it is neither compressed nor encrypted,
+/// and holds every value.
+///
+/// Compression and encryption are chosen for each export, and are recorded in the snapshot, so
+/// that reading it with options not matching those it was written with fails rather than
+/// misreading it.
+#[derive(Clone, Copy, Default)]
+pub struct Options<'a> {
+    compression: Option<&'a dyn Compression>,
+    aead: Option<&'a dyn Aead>,
+    types: Option<&'a TypeSet>,
+}
+
+impl<'a> Options<'a> {
+    /// Construct options for a snapshot which is neither compressed nor encrypted.
+    #[inline]
+    pub fn new() -> Options<'a> {
+        Options {
+            compression: None,
+            aead: None,
+            types: None,
+        }
+    }
+
+    /// Compresses the encoded value in each record with `compression`.
+    #[inline]
+    pub fn compressed(self, compression: &'a dyn Compression) -> Options<'a> {
+        Options { compression: Some(compression), ..self }
+    }
+
+    /// Seals each record with `aead`.
+    #[inline]
+    pub fn encrypted(self, aead: &'a dyn Aead) -> Options<'a> {
+        Options { aead: Some(aead), ..self }
+    }
+
+    /// Writes only the values of the types in `types`, leaving out the rest, which need not
+    /// even be registered. This has no effect on reading.
+    #[inline]
+    pub fn only(self, types: &'a TypeSet) -> Options<'a> {
+        Options { types: Some(types), ..self }
+    }
+}
+
+impl TypeRegistry {
+    /// Encodes every value in `map` with its type's registered codec, as a snapshot which
+    /// `import` can restore.
+    ///
+    /// Fails if a value's type is not registered or has no codec.
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// # use anymap::registry::TypeRegistry;
+    /// let mut registry = TypeRegistry::new();
+    /// registry.register::<String>("string")
+    ///     .codec(|s| s.clone().into_bytes(), |bytes| String::from_utf8(bytes.to_vec()).ok());
+    ///
+    /// let mut session = AnyMap::new();
+    /// session.insert(format!("alice"));
+    /// let snapshot = registry.export(&session).unwrap();
+    ///
+    /// let mut restored = AnyMap::new();
+    /// registry.import(&mut restored, &snapshot).unwrap();
+    /// assert_eq!(restored.get::<String>().unwrap(), "alice");
+    /// ```
+    #[inline]
+    pub fn export(&self, map: &AnyMap) -> Result<Vec<u8>, SnapshotError> {
+        self.export_with(map, &Options::new())
+    }
+
+    /// Encodes every value in `map` as `export` does, sealing each record with `aead`, so that
+    /// the snapshot can be read only with the same key, and any tampering with it is detected.
+    #[inline]
+    pub fn export_encrypted(&self, map: &AnyMap, aead: &dyn Aead)
+                            -> Result<Vec<u8>, SnapshotError> {
+        self.export_with(map, &Options::new().encrypted(aead))
+    }
+
+    /// Encodes the values in `map` of the types in `types` as `export` does, leaving out the
+    /// rest, such as transient handles which must never be persisted.
+    ///
+    /// ```rust
+    /// # #[macro_use] extern crate anymap;
+    /// # use anymap::AnyMap;
+    /// # use anymap::registry::TypeRegistry;
+    /// # fn main() {
+    /// struct Connection;
+    ///
+    /// let mut registry = TypeRegistry::new();
+    /// registry.register::<String>("user")
+    ///     .codec(|s| s.clone().into_bytes(), |bytes| String::from_utf8(bytes.to_vec()).ok());
+    ///
+    /// let mut session = AnyMap::new();
+    /// session.insert(format!("alice"));
+    /// session.insert(Connection);
+    /// assert!(registry.export(&session).is_err());
+    /// let snapshot = registry.export_only(&session, &type_set![String]).unwrap();
+    ///
+    /// let mut restored = AnyMap::new();
+    /// registry.import(&mut restored, &snapshot).unwrap();
+    /// assert_eq!(restored.len(), 1);
+    /// # }
+    /// ```
+    #[inline]
+    pub fn export_only(&self, map: &AnyMap, types: &TypeSet) -> Result<Vec<u8>, SnapshotError> {
+        self.export_with(map, &Options::new().only(types))
+    }
+
+    /// Encodes the values in `map` as `export` does, compressing and encrypting the snapshot and
+    /// choosing the values as `options` say. Each value is compressed and each record sealed on
+    /// its own, so that the snapshot is never buffered a second time to be compressed or
+    /// encrypted.
+    pub fn export_with(&self, map: &AnyMap, options: &Options)
+                       -> Result<Vec<u8>, SnapshotError> {
+        let mut out = Vec::new();
+        self.export_to(map, &mut out, options)?;
+        Ok(out)
+    }
+
+    /// Writes a snapshot of `map` to `writer`, as `export_with` makes it, encoding and writing
+    /// one value at a time, so that no more than one record is ever held in memory.
+    ///
+    /// Fails before writing anything if a value's type is not registered or has no codec. An
+    /// I/O error may leave part of a snapshot written, which import rejects as truncated.
+    ///
+    /// ```rust
+    /// # use anymap::AnyMap;
+    /// # use anymap::registry::TypeRegistry;
+    /// # use anymap::snapshot::Options;
+    /// # use std::io::Cursor;
+    /// let mut registry = TypeRegistry::new();
+    /// registry.register::<u32>("u32")
+    ///     .codec(|n| n.to_le_bytes().to_vec(), |bytes| Some(u32::from_le_bytes(
+    ///         [bytes[0], bytes[1], bytes[2], bytes[3]])));
+    ///
+    /// let mut counters = AnyMap::new();
+    /// counters.insert(7u32);
+    /// let mut file = Cursor::new(Vec::new());
+    /// registry.export_to(&counters, &mut file, &Options::new()).unwrap();
+    ///
+    /// file.set_position(0);
+    /// let values = registry.read_from(file, &Options::new()).unwrap()
+    ///     .collect::<Result<Vec<_>, _>>().unwrap();
+    /// assert_eq!(values[0].1.downcast_ref::<u32>(), Some(&7));
+    /// ```
+    pub fn export_to<W: Write + ?Sized>(&self, map: &AnyMap, writer: &mut W, options: &Options)
+                                        -> Result<(), SnapshotError> {
+        let values = self.names_all(map, options.types)?;
+        let header = header(options);
+        let write_error = |error: io::Error| SnapshotError::Io(error.kind());
+        writer.write_all(&header).map_err(write_error)?;
+        let mut checksum = crc32_update(0, &header);
+        let mut record = Vec::new();
+        for (index, &(name, version, value)) in values.iter().enumerate() {
+            let data = self.encode(value).ok_or(SnapshotError::NoCodec(name))?;
+            record.clear();
+            let entry = Record::Entry(name, version, &data);
+            write_record(&mut record, &header, index as u64, entry, options);
+            writer.write_all(&record).map_err(write_error)?;
+            checksum = crc32_update(checksum, &record);
+        }
+        let count = values.len() as u64;
+        record.clear();
+        write_record(&mut record, &header, count, Record::End(count, checksum), options);
+        writer.write_all(&record).map_err(write_error)
+    }
+
+    /// Decodes the values in a snapshot made by `export` with their types' registered codecs,
+    /// inserting them into `map`, replacing any values of the same types.
+    ///
+    /// Values encoded in older versions of their types' layouts are first upgraded with the
+    /// types' registered migrations; see `Registration::version`.
+    ///
+    /// If the snapshot cannot be read in full, `map` is left untouched.
+    #[inline]
+    pub fn import(&self, map: &mut AnyMap, snapshot: &[u8]) -> Result<(), SnapshotError> {
+        self.import_with(map, snapshot, &Options::new())
+    }
+
+    /// Decodes the values in a snapshot made by `export_encrypted` with the same key, inserting
+    /// them into `map` as `import` does.
+    ///
+    /// Fails with `SnapshotError::Tampered` if the snapshot was sealed with another key or has
+    /// been altered in any way, and with `SnapshotError::NotEncrypted` if it is not encrypted.
+    #[inline]
+    pub fn import_encrypted(&self, map: &mut AnyMap, snapshot: &[u8], aead: &dyn Aead)
+                            -> Result<(), SnapshotError> {
+        self.import_with(map, snapshot, &Options::new().encrypted(aead))
+    }
+
+    /// Decodes the values in a snapshot made by `export_with`, inserting them into `map` as
+    /// `import` does.
+    ///
+    /// The snapshot must be encrypted if and only if `options` give a key. If it is compressed,
+    /// `options` must give the compression it was compressed with; if it is not, any
+    /// compression given is ignored.
+    pub fn import_with(&self, map: &mut AnyMap, snapshot: &[u8], options: &Options)
+                       -> Result<(), SnapshotError> {
+        let mut rest = snapshot;
+        let values = self.read_from(&mut rest, options)?.collect::<Result<Vec<_>, _>>()?;
+        if !rest.is_empty() {
+            return Err(SnapshotError::Malformed);
+        }
+        insert_all(map, values);
+        Ok(())
+    }
+
+    /// Reads a snapshot made by `export_to` from `reader`, inserting its values into `map` as
+    /// `import_with` does, once the whole snapshot has been read. Nothing after the snapshot is
+    /// read.
+    pub fn import_from<R: Read>(&self, map: &mut AnyMap, reader: R, options: &Options)
+                                -> Result<(), SnapshotError> {
+        let values = self.read_from(reader, options)?.collect::<Result<Vec<_>, _>>()?;
+        insert_all(map, values);
+        Ok(())
+    }
+
+    /// Starts reading a snapshot made by `export_to` from `reader`, returning an iterator which
+    /// reads and decodes its values one at a time, as `import_with` would with `options`.
+    ///
+    /// Reading is unbuffered; wrap a file or socket in a `BufReader` to read it in larger
+    /// chunks.
+    pub fn read_from<'a, R: Read>(&'a self, mut reader: R, options: &Options<'a>)
+                                  -> Result<SnapshotReader<'a, R>, SnapshotError> {
+        let mut header = [0; HEADER_LEN];
+        reader.read_exact(&mut header).map_err(read_error)?;
+        let (flags, id) = read_header(&mut Reader { bytes: &header })?;
+        Ok(SnapshotReader {
+            registry: self,
+            compression: compression(flags, id, options)?,
+            aead: options.aead,
+            reader,
+            header,
+            index: 0,
+            checksum: crc32_update(0, &header),
+            record: Vec::new(),
+            done: false,
+        })
+    }
+
+    /// Returns the registered name and version of the type of each value in `map`, or of those
+    /// of the types in `types` if it is given, along with the value, checking that each can be
+    /// encoded.
+    fn names_all<'a>(&self, map: &'a AnyMap, types: Option<&TypeSet>)
+                     -> Result<Vec<(&'static str, u32, &'a dyn Any)>, SnapshotError> {
+        map.data.iter().filter(|&(id, _)| {
+            types.is_none_or(|types| types.types.contains_key(id))
+        }).map(|(id, value)| {
+            let name = match self.name_of(id) {
+                Some(name) => name,
+                None => return Err(SnapshotError::Unregistered(*id)),
+            };
+            if !self.has_codec(id) {
+                return Err(SnapshotError::NoCodec(name));
+            }
+            Ok((name, self.version_of(id).unwrap(), &**value))
+        }).collect()
+    }
+
+    /// Decodes the value in one record, migrating it from the given version first.
+    fn decode_entry(&self, name: &str, version: u32, data: &[u8])
+                    -> Result<Value, SnapshotError> {
+        if self.type_id_of(name).is_none() {
+            return Err(SnapshotError::UnknownType(name.to_string()));
+        }
+        let data = self.migrate(name, version, data)
+            .ok_or_else(|| SnapshotError::Unmigratable(name.to_string(), version))?;
+        self.decode(name, &data).ok_or_else(|| SnapshotError::Undecodable(name.to_string()))
+    }
+}
+
+/// A decoded value, along with the ID of its type.
+type Value = (TypeId, Box<dyn Any + 'static>);
+
+/// An iterator over the values in a snapshot, reading and decoding them one at a time from an
+/// `io::Read`, made by `TypeRegistry::read_from`.
+///
+/// Each item is a value along with the ID of its type, or the error ending the iteration. It
+/// ends once the final record of the snapshot has been read and checked, so a snapshot which is
+/// cut short yields `SnapshotError::Malformed` rather than ending early.
+pub struct SnapshotReader<'a, R> {
+    registry: &'a TypeRegistry,
+    compression: Option<&'a dyn Compression>,
+    aead: Option<&'a dyn Aead>,
+    reader: R,
+    header: [u8; HEADER_LEN],
+    index: u64,
+    checksum: u32,
+    record: Vec<u8>,
+    done: bool,
+}
+
+impl<'a, R: Read> SnapshotReader<'a, R> {
+    /// Returns the stream, positioned after the last record read.
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads the next record, returning its value, or `None` at the end of the snapshot.
+    fn next_value(&mut self) -> Result<Option<Value>, SnapshotError> {
+        let before = self.checksum;
+        self.record.clear();
+        read_raw(&mut self.reader, &mut self.record, self.aead.is_some())?;
+        self.checksum = crc32_update(self.checksum, &self.record);
+        let opened;
+        let plaintext = match self.aead {
+            Some(aead) => {
+                opened = aead.open(&self.record[4..], &associated(&self.header, self.index))
+                    .ok_or(SnapshotError::Tampered)?;
+                &opened[..]
+            },
+            None => &self.record[..],
+        };
+        let mut reader = Reader { bytes: plaintext };
+        let record = Record::read(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(SnapshotError::Malformed);
+        }
+        match record {
+            Record::Entry(name, version, data) => {
+                let decompressed;
+                let data = match self.compression {
+                    Some(compression) => {
+                        decompressed = compression.decompress(data)
+                            .ok_or(SnapshotError::Malformed)?;
+                        &decompressed[..]
+                    },
+                    None => data,
+                };
+                let value = self.registry.decode_entry(name, version, data)?;
+                self.index += 1;
+                Ok(Some(value))
+            },
+            Record::End(count, checksum) => {
+                if count != self.index {
+                    return Err(SnapshotError::Malformed);
+                }
+                if checksum != before {
+                    return Err(SnapshotError::Corrupt);
+                }
+                Ok(None)
+            },
+        }
+    }
+}
+
+impl<'a, R: Read> Iterator for SnapshotReader<'a, R> {
+    type Item = Result<Value, SnapshotError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.next_value();
+        self.done = !matches!(next, Ok(Some(_)));
+        next.transpose()
+    }
+}
+
+/// Inserts decoded values into a map.
+fn insert_all(map: &mut AnyMap, values: Vec<Value>) {
+    for (id, value) in values {
+        let _ = map.insert_by_id(id, value);
+    }
+}
+
+/// The reasons a snapshot may fail to be made or restored.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SnapshotError {
+    /// A type in the map is not registered.
+    Unregistered(TypeId),
+    /// The named type is in the map, but has no registered codec.
+    NoCodec(&'static str),
+    /// The bytes are not a snapshot, or are truncated.
+    Malformed,
+    /// The snapshot was written in a version of the format which this version of the crate
+    /// cannot read.
+    UnsupportedVersion(u8),
+    /// The snapshot is encrypted, and must be read with `import_encrypted`.
+    Encrypted,
+    /// The snapshot is not encrypted, but was expected to be.
+    NotEncrypted,
+    /// The snapshot's values are compressed with the algorithm of the given ID, which was not
+    /// given for reading it.
+    Compressed(u8),
+    /// The snapshot was sealed with another key, or has been tampered with.
+    Tampered,
+    /// The record of a value of the named type fails its checksum. The name is as read from the
+    /// corrupt record, so may itself be garbled.
+    CorruptEntry(String),
+    /// The snapshot fails its overall checksum, though each of its values passes its own.
+    Corrupt,
+    /// Reading or writing the snapshot failed with an I/O error of the given kind.
+    Io(io::ErrorKind),
+    /// The snapshot holds a value of a type of the given name, which is not registered.
+    UnknownType(String),
+    /// The codec of the named type rejected its value in the snapshot.
+    Undecodable(String),
+    /// The value of the named type in the snapshot is in the given version of the type's
+    /// layout, which is newer than the registered one, or from which no chain of migrations is
+    /// registered, or whose migration rejected it.
+    Unmigratable(String, u32),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::Unregistered(id) => write!(f, "the type {:?} is not registered", id),
+            SnapshotError::NoCodec(name) => write!(f, "the type {} has no codec", name),
+            SnapshotError::Malformed => write!(f, "the bytes are not a valid snapshot"),
+            SnapshotError::UnsupportedVersion(version) =>
+                write!(f, "snapshot format version {} is not supported", version),
+            SnapshotError::Encrypted => write!(f, "the snapshot is encrypted"),
+            SnapshotError::NotEncrypted => write!(f, "the snapshot is not encrypted"),
+            SnapshotError::Compressed(id) =>
+                write!(f, "the snapshot is compressed with the unavailable algorithm {}", id),
+            SnapshotError::Tampered =>
+                write!(f, "the snapshot was sealed with another key or has been tampered with"),
+            SnapshotError::CorruptEntry(ref name) =>
+                write!(f, "the value of the type {} in the snapshot is corrupt", name),
+            SnapshotError::Corrupt => write!(f, "the snapshot is corrupt"),
+            SnapshotError::Io(kind) => write!(f, "an I/O error occurred: {}", kind),
+            SnapshotError::UnknownType(ref name) =>
+                write!(f, "no type is registered as {}", name),
+            SnapshotError::Undecodable(ref name) =>
+                write!(f, "the value of the type {} could not be decoded", name),
+            SnapshotError::Unmigratable(ref name, version) =>
+                write!(f, "the value of the type {} could not be migrated from version {}",
+                       name, version),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn description(&self) -> &str {
+        match *self {
+            SnapshotError::Unregistered(_) => "a type is not registered",
+            SnapshotError::NoCodec(_) => "a type has no codec",
+            SnapshotError::Malformed => "the bytes are not a valid snapshot",
+            SnapshotError::UnsupportedVersion(_) => "the snapshot format version is not supported",
+            SnapshotError::Encrypted => "the snapshot is encrypted",
+            SnapshotError::NotEncrypted => "the snapshot is not encrypted",
+            SnapshotError::Compressed(_) => "the snapshot's compression is unavailable",
+            SnapshotError::Tampered => "the snapshot has been tampered with",
+            SnapshotError::CorruptEntry(_) => "a value in the snapshot is corrupt",
+            SnapshotError::Corrupt => "the snapshot is corrupt",
+            SnapshotError::Io(_) => "an I/O error occurred",
+            SnapshotError::UnknownType(_) => "a type name is not registered",
+            SnapshotError::Undecodable(_) => "a value could not be decoded",
+            SnapshotError::Unmigratable(..) => "a value could not be migrated",
+        }
+    }
+}
+
+#[test]
+fn test_snapshot() {
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<u8>("u8")
+        .codec(|&n: &u8| vec![n], |bytes| if bytes.len() == 1 { Some(bytes[0]) } else { None });
+    let _ = registry.register::<String>("string")
+        .codec(|s: &String| s.clone().into_bytes(),
+               |bytes| String::from_utf8(bytes.to_vec()).ok());
+    let _ = registry.register::<i8>("i8");
+
+    let mut map = AnyMap::new();
+    let _ = map.insert(1u8);
+    let _ = map.insert("hello".to_string());
+    let snapshot = registry.export(&map).unwrap();
+    let mut restored = AnyMap::new();
+    let _ = restored.insert(2u8);
+    let _ = restored.insert(3u16);
+    assert_eq!(registry.import(&mut restored, &snapshot), Ok(()));
+    assert_eq!((restored.get::<u8>(), restored.get::<u16>()), (Some(&1), Some(&3)));
+    assert_eq!(restored.get::<String>().map(|s| &s[..]), Some("hello"));
+
+    let mut empty = AnyMap::new();
+    assert_eq!(registry.import(&mut empty, &snapshot[..snapshot.len() - 1]),
+               Err(SnapshotError::Malformed));
+    assert_eq!(registry.import(&mut empty, b"nonsense"), Err(SnapshotError::Malformed));
+    assert!(empty.is_empty());
+
+    let _ = map.insert(1i8);
+    assert_eq!(registry.export(&map), Err(SnapshotError::NoCodec("i8")));
+    let _ = map.insert(1i16);
+    let _ = map.remove::<i8>();
+    assert_eq!(registry.export(&map), Err(SnapshotError::Unregistered(TypeId::of::<i16>())));
+    let partial = registry.export_only(&map, &TypeSet::new().with::<u8>().with::<u32>()).unwrap();
+    let mut restored = AnyMap::new();
+    assert_eq!(registry.import(&mut restored, &partial), Ok(()));
+    assert_eq!((restored.get::<u8>(), restored.len()), (Some(&1), 1));
+    let none = registry.export_only(&map, &TypeSet::new()).unwrap();
+    assert_eq!(registry.import(&mut restored, &none), Ok(()));
+    assert_eq!(restored.len(), 1);
+
+    let mut other = TypeRegistry::new();
+    let _ = other.register::<u8>("u8")
+        .codec(|&n: &u8| vec![n], |bytes| if bytes.len() == 1 { Some(bytes[0]) } else { None });
+    assert_eq!(other.import(&mut empty, &snapshot),
+               Err(SnapshotError::UnknownType("string".to_string())));
+}
+
+#[test]
+fn test_encrypted_snapshot() {
+    /// A stand-in for a real cipher, which "encrypts" by XOR and "authenticates" with a sum.
+    /// It is of course not secure.
+    struct Toy(u8);
+
+    impl Toy {
+        fn tag(&self, bytes: &[u8], associated: &[u8]) -> u8 {
+            bytes.iter().chain(associated).fold(self.0, |tag, &byte| tag.rotate_left(3) ^ byte)
+        }
+    }
+
+    impl Aead for Toy {
+        fn seal(&self, plaintext: &[u8], associated: &[u8]) -> Vec<u8> {
+            let mut sealed: Vec<u8> = plaintext.iter().map(|&byte| byte ^ self.0).collect();
+            let tag = self.tag(&sealed, associated);
+            sealed.push(tag);
+            sealed
+        }
+
+        fn open(&self, sealed: &[u8], associated: &[u8]) -> Option<Vec<u8>> {
+            let (&tag, ciphertext) = sealed.split_last()?;
+            if tag != self.tag(ciphertext, associated) {
+                return None;
+            }
+            Some(ciphertext.iter().map(|&byte| byte ^ self.0).collect())
+        }
+    }
+
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<u8>("u8")
+        .codec(|&n: &u8| vec![n], |bytes| if bytes.len() == 1 { Some(bytes[0]) } else { None });
+    let _ = registry.register::<u16>("u16")
+        .codec(|&n: &u16| n.to_le_bytes().to_vec(), |bytes| {
+            if bytes.len() == 2 { Some(u16::from_le_bytes([bytes[0], bytes[1]])) } else { None }
+        });
+
+    let mut map = AnyMap::new();
+    let _ = map.insert(1u8);
+    let _ = map.insert(0x4142u16);
+    let key = Toy(0x5a);
+    let snapshot = registry.export_encrypted(&map, &key).unwrap();
+    assert!(!snapshot.windows(3).any(|window| window == b"u16"));
+
+    let mut restored = AnyMap::new();
+    assert_eq!(registry.import(&mut restored, &snapshot), Err(SnapshotError::Encrypted));
+    assert_eq!(registry.import_encrypted(&mut restored, &registry.export(&map).unwrap(), &key),
+               Err(SnapshotError::NotEncrypted));
+    assert_eq!(registry.import_encrypted(&mut restored, &snapshot, &Toy(0x5b)),
+               Err(SnapshotError::Tampered));
+    let mut tampered = snapshot.clone();
+    let last = tampered.len() - 3;
+    tampered[last] ^= 1;
+    assert_eq!(registry.import_encrypted(&mut restored, &tampered, &key),
+               Err(SnapshotError::Tampered));
+    assert!(restored.is_empty());
+
+    assert_eq!(registry.import_encrypted(&mut restored, &snapshot, &key), Ok(()));
+    assert_eq!((restored.get::<u8>(), restored.get::<u16>()), (Some(&1), Some(&0x4142)));
+}
+
+#[test]
+fn test_compressed_snapshot() {
+    /// Run-length encoding, as pairs of a count and a byte.
+    struct Rle;
+
+    impl Compression for Rle {
+        fn id(&self) -> u8 { 7 }
+
+        fn compress(&self, data: &[u8]) -> Vec<u8> {
+            let mut out: Vec<u8> = Vec::new();
+            for &byte in data {
+                match out.len() {
+                    len if len >= 2 && out[len - 1] == byte && out[len - 2] < 255 =>
+                        out[len - 2] += 1,
+                    _ => out.extend_from_slice(&[1, byte]),
+                }
+            }
+            out
+        }
+
+        fn decompress(&self, data: &[u8]) -> Option<Vec<u8>> {
+            if !data.len().is_multiple_of(2) {
+                return None;
+            }
+            Some(data.chunks(2).flat_map(|pair| vec![pair[1]; pair[0] as usize]).collect())
+        }
+    }
+
+    struct Other;
+
+    impl Compression for Other {
+        fn id(&self) -> u8 { 8 }
+        fn compress(&self, data: &[u8]) -> Vec<u8> { data.to_vec() }
+        fn decompress(&self, data: &[u8]) -> Option<Vec<u8>> { Some(data.to_vec()) }
+    }
+
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<String>("string")
+        .codec(|s: &String| s.clone().into_bytes(),
+               |bytes| String::from_utf8(bytes.to_vec()).ok());
+
+    let mut map = AnyMap::new();
+    let _ = map.insert("a".repeat(200));
+    let options = Options::new().compressed(&Rle);
+    let snapshot = registry.export_with(&map, &options).unwrap();
+    assert!(snapshot.len() < registry.export(&map).unwrap().len());
+
+    let mut restored = AnyMap::new();
+    assert_eq!(registry.import(&mut restored, &snapshot), Err(SnapshotError::Compressed(7)));
+    assert_eq!(registry.import_with(&mut restored, &snapshot, &Options::new().compressed(&Other)),
+               Err(SnapshotError::Compressed(7)));
+    assert!(restored.is_empty());
+    assert_eq!(registry.import_with(&mut restored, &snapshot, &options), Ok(()));
+    assert_eq!(restored.get::<String>(), map.get::<String>());
+
+    let plain = registry.export(&map).unwrap();
+    assert_eq!(registry.import_with(&mut restored, &plain, &options), Ok(()));
+}
+
+#[test]
+fn test_corrupt_snapshot() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<u8>("u8")
+        .codec(|&n: &u8| vec![n], |bytes| if bytes.len() == 1 { Some(bytes[0]) } else { None });
+    let _ = registry.register::<String>("string")
+        .codec(|s: &String| s.clone().into_bytes(),
+               |bytes| String::from_utf8(bytes.to_vec()).ok());
+
+    let mut map = AnyMap::new();
+    let _ = map.insert("hello".to_string());
+    let snapshot = registry.export(&map).unwrap();
+    let mut restored = AnyMap::new();
+
+    let mut corrupt = snapshot.clone();
+    let value = corrupt.windows(5).position(|window| window == b"hello").unwrap();
+    corrupt[value] = b'j';
+    assert_eq!(registry.import(&mut restored, &corrupt),
+               Err(SnapshotError::CorruptEntry("string".to_string())));
+
+    let mut corrupt = snapshot.clone();
+    corrupt[5] ^= 4;
+    assert_eq!(registry.import(&mut restored, &corrupt), Err(SnapshotError::Corrupt));
+
+    let mut corrupt = snapshot.clone();
+    let last = corrupt.len() - 1;
+    corrupt[last] ^= 1;
+    assert_eq!(registry.import(&mut restored, &corrupt), Err(SnapshotError::Corrupt));
+    assert!(restored.is_empty());
+}
+
+#[test]
+fn test_migrated_snapshot() {
+    let mut old = TypeRegistry::new();
+    let _ = old.register::<u8>("n").codec(|&n: &u8| vec![n], |bytes| bytes.first().copied());
+    let mut map = AnyMap::new();
+    let _ = map.insert(5u8);
+    let snapshot = old.export(&map).unwrap();
+
+    // Version 1 widened the value to two bytes, and version 2 doubled it.
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<u16>("n")
+        .codec(|&n: &u16| n.to_le_bytes().to_vec(),
+               |bytes| if bytes.len() == 2 { Some(u16::from_le_bytes([bytes[0], bytes[1]])) }
+                       else { None })
+        .version(2)
+        .migrates(1, |bytes| {
+            let n = u16::from_le_bytes([*bytes.first()?, *bytes.get(1)?]) * 2;
+            Some(n.to_le_bytes().to_vec())
+        })
+        .migrates(0, |bytes| Some(vec![*bytes.first()?, 0]));
+    assert_eq!(registry.version_of(&TypeId::of::<u16>()), Some(2));
+    let mut restored = AnyMap::new();
+    assert_eq!(registry.import(&mut restored, &snapshot), Ok(()));
+    assert_eq!(restored.get::<u16>(), Some(&10));
+    let current = registry.export(&restored).unwrap();
+    assert_eq!(registry.import(&mut restored, &current), Ok(()));
+    assert_eq!(restored.get::<u16>(), Some(&10));
+    assert_eq!(old.import(&mut AnyMap::new(), &current),
+               Err(SnapshotError::Unmigratable("n".to_string(), 2)));
+
+    let mut gap = TypeRegistry::new();
+    let _ = gap.register::<u16>("n").version(2).migrates(1, |bytes| Some(bytes.to_vec()));
+    assert_eq!(gap.import(&mut AnyMap::new(), &snapshot),
+               Err(SnapshotError::Unmigratable("n".to_string(), 0)));
+    assert_eq!(gap.migrate("n", 1, b"as is").as_ref().map(|bytes| &bytes[..]),
+               Some(&b"as is"[..]));
+    assert!(gap.migrate("m", 0, b"").is_none());
+}
+
+#[test]
+fn test_streaming_snapshot() {
+    /// A writer which fails once it has been given `room` bytes.
+    struct Full {
+        room: usize,
+    }
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.room == 0 {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "full"));
+            }
+            let len = buf.len().min(self.room);
+            self.room -= len;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<u8>("u8")
+        .codec(|&n: &u8| vec![n], |bytes| if bytes.len() == 1 { Some(bytes[0]) } else { None });
+    let _ = registry.register::<String>("string")
+        .codec(|s: &String| s.clone().into_bytes(),
+               |bytes| String::from_utf8(bytes.to_vec()).ok());
+
+    let mut map = AnyMap::new();
+    let _ = map.insert(1u8);
+    let _ = map.insert("hello".to_string());
+    let mut stream = Vec::new();
+    assert_eq!(registry.export_to(&map, &mut stream, &Options::new()), Ok(()));
+    assert_eq!(stream, registry.export(&map).unwrap());
+    assert_eq!(registry.export_to(&map, &mut Full { room: 10 }, &Options::new()),
+               Err(SnapshotError::Io(io::ErrorKind::WriteZero)));
+
+    stream.extend_from_slice(b"next");
+    let mut values = registry.read_from(&stream[..], &Options::new()).unwrap();
+    let mut ids = vec![values.next().unwrap().unwrap().0, values.next().unwrap().unwrap().0];
+    ids.sort();
+    let mut expected = vec![TypeId::of::<u8>(), TypeId::of::<String>()];
+    expected.sort();
+    assert_eq!(ids, expected);
+    assert!(values.next().is_none() && values.next().is_none());
+    assert_eq!(values.into_inner(), b"next");
+
+    let truncated = &stream[..stream.len() - 10];
+    let mut values = registry.read_from(truncated, &Options::new()).unwrap();
+    assert!(values.next().unwrap().is_ok() && values.next().unwrap().is_ok());
+    assert_eq!(values.next().unwrap().err(), Some(SnapshotError::Malformed));
+    assert!(values.next().is_none());
+
+    let mut restored = AnyMap::new();
+    assert_eq!(registry.import_from(&mut restored, truncated, &Options::new()),
+               Err(SnapshotError::Malformed));
+    assert!(restored.is_empty());
+    assert_eq!(registry.import_from(&mut restored, &stream[..], &Options::new()), Ok(()));
+    assert_eq!(restored.get::<u8>(), Some(&1));
+    assert_eq!(registry.import(&mut restored, &stream), Err(SnapshotError::Malformed));
+}
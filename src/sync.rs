@@ -0,0 +1,145 @@
+//! An `AnyMap` flavour holding only values which are `Send` and `Sync`, and so is itself both.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+use {TypeIdBuildHasher, cast_box, cast_mut, cast_ref};
+use like::AnyMapLike;
+
+/// A collection containing zero or one values for any given type, each of which must be `Send`
+/// and `Sync`, so that the collection can be shared between threads.
+///
+/// ```rust
+/// # use anymap::sync::SyncAnyMap;
+/// # use std::sync::Arc;
+/// # use std::thread;
+/// let mut data = SyncAnyMap::new();
+/// data.insert(42u64);
+/// let data = Arc::new(data);
+/// let shared = data.clone();
+/// let answer = thread::spawn(move || *shared.get::<u64>().unwrap()).join().unwrap();
+/// assert_eq!(answer, 42);
+/// ```
+pub struct SyncAnyMap {
+    pub(crate) data: HashMap<TypeId, Box<dyn Any + Send + Sync + 'static>, TypeIdBuildHasher>,
+}
+
+impl Default for SyncAnyMap {
+    #[inline]
+    fn default() -> SyncAnyMap {
+        SyncAnyMap::new()
+    }
+}
+
+impl SyncAnyMap {
+    /// Construct a new, empty `SyncAnyMap`.
+    #[inline]
+    pub fn new() -> SyncAnyMap {
+        SyncAnyMap {
+            data: HashMap::with_hasher(TypeIdBuildHasher),
+        }
+    }
+
+    /// Returns a reference to the value stored in the collection for the type `T`, if it exists.
+    pub fn get<T: Any + Send + Sync + 'static>(&self) -> Option<&T> {
+        self.data.get(&TypeId::of::<T>())
+            .map(|any| cast_ref::<T>(&**any))
+    }
+
+    /// Returns a mutable reference to the value stored in the collection for the type `T`,
+    /// if it exists.
+    pub fn get_mut<T: Any + Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        self.data.get_mut(&TypeId::of::<T>())
+            .map(|any| cast_mut::<T>(&mut **any))
+    }
+
+    /// Returns true if the collection contains a value of type `T`.
+    #[inline]
+    pub fn contains<T: Any + Send + Sync + 'static>(&self) -> bool {
+        self.data.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Sets the value stored in the collection for the type `T`.
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    pub fn insert<T: Any + Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.data.insert(TypeId::of::<T>(), Box::new(value) as Box<dyn Any + Send + Sync>)
+            .map(|any| *cast_box::<T>(any))
+    }
+
+    /// Removes the `T` value from the collection,
+    /// returning it if there was one or `None` if there was not.
+    pub fn remove<T: Any + Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.data.remove(&TypeId::of::<T>())
+            .map(|any| *cast_box::<T>(any))
+    }
+
+    /// Returns the number of items in the collection.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns true if there are no items in the collection.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Removes all items from the collection. Keeps the allocated memory for reuse.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+}
+
+/// Values inserted through `AnyMapLike` are refused, as they cannot be checked to be `Send` and
+/// `Sync`.
+impl AnyMapLike for SyncAnyMap {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.data.contains_key(&id)
+    }
+
+    #[inline]
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.data.get(&id).map(|value| &**value as &dyn Any)
+    }
+
+    #[inline]
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        self.data.get_mut(&id).map(|value| &mut **value as &mut dyn Any)
+    }
+
+    #[inline]
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        Err(value)
+    }
+
+    #[inline]
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        self.data.remove(&id).map(|value| value as Box<dyn Any>)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+#[test]
+fn test_sync() {
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let mut map = SyncAnyMap::new();
+    assert_send_sync(&map);
+    assert_eq!(map.insert(1i32), None);
+    assert_eq!(map.insert(2i32), Some(1));
+    *map.get_mut::<i32>().unwrap() += 1;
+    assert_eq!(map.get::<i32>(), Some(&3));
+    assert!(map.contains::<i32>() && !map.contains::<u8>());
+    assert_eq!(map.len(), 1);
+    assert_eq!(map.remove::<i32>(), Some(3));
+    assert!(map.is_empty());
+}
@@ -0,0 +1,208 @@
+//! A per-task `AnyMap` for asynchronous code, for context such as the ID of the request a task
+//! is handling, which follows the task across `.await`s whichever thread polls it.
+//!
+//! A future is given a map with `scope`, and while it is being polled the map is the current
+//! one, reached by the functions here. This works with any executor, Tokio's included, as
+//! `tokio::task_local!` does: the map is swapped into a thread-local slot for each poll and out
+//! again afterwards. As with the `thread_local` module, none of these functions may be called
+//! from within a closure passed to another of them, which would panic.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use AnyMap;
+
+thread_local!(static CURRENT: RefCell<Option<AnyMap>> = const { RefCell::new(None) });
+
+/// Wraps a future so that `map` is the current map whenever it is polled.
+///
+/// Scopes may be nested, the innermost map being current.
+///
+/// ```rust,edition2018
+/// # use anymap::AnyMap;
+/// # use anymap::task_local;
+/// # use std::future::Future;
+/// # use std::task::{Context, Poll, Waker};
+/// # fn block_on<F: Future>(future: F) -> F::Output {
+/// #     let mut future = Box::pin(future);
+/// #     let mut cx = Context::from_waker(Waker::noop());
+/// #     loop {
+/// #         if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+/// #             return output;
+/// #         }
+/// #     }
+/// # }
+/// struct RequestId(u64);
+///
+/// async fn log(message: &str) -> String {
+///     let id = task_local::with_current(|id: &RequestId| id.0).unwrap();
+///     format!("[{}] {}", id, message)
+/// }
+///
+/// let mut context = AnyMap::new();
+/// context.insert(RequestId(7));
+/// let line = block_on(task_local::scope(context, async {
+///     log("started").await
+/// }));
+/// assert_eq!(line, "[7] started");
+/// ```
+#[inline]
+pub fn scope<F: Future>(map: AnyMap, future: F) -> Scope<F> {
+    Scope {
+        map: Some(map),
+        future: Box::pin(future),
+    }
+}
+
+/// A future with a map, which is current whenever it is polled; made by `scope`.
+pub struct Scope<F: Future> {
+    map: Option<AnyMap>,
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Scope<F> {
+    /// Returns a reference to the future's map, if it is not being polled.
+    #[inline]
+    pub fn map(&self) -> Option<&AnyMap> {
+        self.map.as_ref()
+    }
+
+    /// Returns a mutable reference to the future's map, if it is not being polled.
+    #[inline]
+    pub fn map_mut(&mut self) -> Option<&mut AnyMap> {
+        self.map.as_mut()
+    }
+}
+
+impl<F: Future> Future for Scope<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        let this = &mut *self;
+        let _enter = Enter::new(&mut this.map);
+        this.future.as_mut().poll(cx)
+    }
+}
+
+/// Makes a scope's map current while it lives, putting it back, and restoring the map which was
+/// current before, when dropped, even if the future panics.
+struct Enter<'a> {
+    map: &'a mut Option<AnyMap>,
+    previous: Option<AnyMap>,
+}
+
+impl<'a> Enter<'a> {
+    fn new(map: &'a mut Option<AnyMap>) -> Enter<'a> {
+        let previous = CURRENT.with(|current| current.replace(map.take()));
+        Enter { map, previous }
+    }
+}
+
+impl<'a> Drop for Enter<'a> {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        *self.map = CURRENT.with(|current| mem::replace(&mut *current.borrow_mut(), previous));
+    }
+}
+
+/// Calls a function with the current task's map.
+///
+/// # Panics
+///
+/// Panics if called outside a future wrapped by `scope`.
+pub fn with_map_current<R, F: FnOnce(&mut AnyMap) -> R>(f: F) -> R {
+    CURRENT.with(|current| match *current.borrow_mut() {
+        Some(ref mut map) => f(map),
+        None => panic!("the task-local map was used outside of a task_local::scope"),
+    })
+}
+
+/// Returns true if called within a future wrapped by `scope`, and so there is a current map.
+pub fn is_in_scope() -> bool {
+    CURRENT.with(|current| current.borrow().is_some())
+}
+
+/// Calls a function with the current task's value of type `T`, returning its result, or `None`
+/// if the task has no `T`.
+///
+/// # Panics
+///
+/// Panics if called outside a future wrapped by `scope`.
+pub fn with_current<T: Any + 'static, R, F: FnOnce(&T) -> R>(f: F) -> Option<R> {
+    with_map_current(|map| map.get::<T>().map(f))
+}
+
+/// Calls a function with a mutable reference to the current task's value of type `T`, returning
+/// its result, or `None` if the task has no `T`.
+///
+/// # Panics
+///
+/// Panics if called outside a future wrapped by `scope`.
+pub fn with_current_mut<T: Any + 'static, R, F: FnOnce(&mut T) -> R>(f: F) -> Option<R> {
+    with_map_current(|map| map.get_mut::<T>().map(f))
+}
+
+/// Sets the current task's value of type `T`, returning the old value if there was one.
+///
+/// # Panics
+///
+/// Panics if called outside a future wrapped by `scope`.
+pub fn insert_current<T: Any + 'static>(value: T) -> Option<T> {
+    with_map_current(|map| map.insert(value))
+}
+
+/// Removes the current task's value of type `T`, returning it if there was one.
+///
+/// # Panics
+///
+/// Panics if called outside a future wrapped by `scope`.
+pub fn remove_current<T: Any + 'static>() -> Option<T> {
+    with_map_current(|map| map.remove::<T>())
+}
+
+#[test]
+fn test_task_local() {
+    use std::task::Waker;
+
+    /// A future which counts a `u8` in the current map each time it is polled, and is ready
+    /// the third time.
+    struct Steps;
+
+    impl Future for Steps {
+        type Output = u8;
+
+        fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<u8> {
+            let n = with_current_mut(|n: &mut u8| { *n += 1; *n }).unwrap_or_else(|| {
+                let _ = insert_current(1u8);
+                1
+            });
+            if n == 3 { Poll::Ready(n) } else { Poll::Pending }
+        }
+    }
+
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut outer = AnyMap::new();
+    let _ = outer.insert(1u16);
+    let mut a = scope(AnyMap::new(), Steps);
+    let mut b = scope(outer, Steps);
+    assert!(!is_in_scope());
+    assert!(Pin::new(&mut a).poll(&mut cx).is_pending());
+    assert!(Pin::new(&mut b).poll(&mut cx).is_pending());
+    assert!(Pin::new(&mut a).poll(&mut cx).is_pending());
+    assert_eq!(Pin::new(&mut a).poll(&mut cx), Poll::Ready(3));
+    assert_eq!(a.map().unwrap().get::<u8>(), Some(&3));
+    assert_eq!(b.map().unwrap().get::<u8>(), Some(&1));
+    assert_eq!(b.map_mut().unwrap().remove::<u16>(), Some(1));
+    assert!(!is_in_scope());
+
+    let mut nested = scope(AnyMap::new(), scope(AnyMap::new(), Steps));
+    assert!(Pin::new(&mut nested).poll(&mut cx).is_pending());
+    assert!(nested.map().unwrap().is_empty());
+
+    let unwound = ::std::panic::catch_unwind(remove_current::<u8>);
+    assert!(unwound.is_err());
+}
@@ -0,0 +1,428 @@
+//! Assertions for tests of code using `AnyMap`s, which name the types concerned on failure.
+
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+
+use {AnyMap, TypeSet, UNKNOWN_TYPE, type_name};
+use like::AnyMapLike;
+use registry::TypeRegistry;
+
+/// Asserts that a map contains values of all of the given types.
+///
+/// On failure, the panic message lists the types which are missing.
+///
+/// ```rust
+/// # #[macro_use] extern crate anymap;
+/// # fn main() {
+/// let mut data = anymap::AnyMap::new();
+/// data.insert(1i32);
+/// data.insert("hello");
+/// assert_contains_types!(data, i32, &str);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! assert_contains_types {
+    ($map:expr, $($t:ty),+) => {{
+        let mut expected = $crate::TypeSet::new();
+        $(let _ = expected.insert::<$t>();)+
+        $crate::testing::assert_contains(&$map, &expected);
+    }}
+}
+
+/// Asserts that a map contains values of none of the given types.
+///
+/// On failure, the panic message lists the types which are present.
+#[macro_export]
+macro_rules! assert_lacks_types {
+    ($map:expr, $($t:ty),+) => {{
+        let mut unexpected = $crate::TypeSet::new();
+        $(let _ = unexpected.insert::<$t>();)+
+        $crate::testing::assert_lacks(&$map, &unexpected);
+    }}
+}
+
+/// Asserts that two maps hold values of the same types, and that those values are equal
+/// according to the equality registered for their types in a `TypeRegistry`.
+///
+/// On failure, the panic message lists the types only in one map or the other and the types
+/// whose values differ.
+#[macro_export]
+macro_rules! assert_maps_eq {
+    ($registry:expr, $left:expr, $right:expr) => {
+        $crate::testing::assert_maps_eq(&$registry, &$left, &$right)
+    }
+}
+
+/// Panics unless `map` contains values of all of the types in `expected`.
+///
+/// This is the function behind `assert_contains_types!`.
+pub fn assert_contains(map: &AnyMap, expected: &TypeSet) {
+    let missing = filter(expected, |id| !map.data.contains_key(&id));
+    if !missing.is_empty() {
+        panic!("assertion failed: the map (with {} entries) is missing the types {:?}",
+               map.len(), missing);
+    }
+}
+
+/// Panics if `map` contains a value of any of the types in `unexpected`.
+///
+/// This is the function behind `assert_lacks_types!`.
+pub fn assert_lacks(map: &AnyMap, unexpected: &TypeSet) {
+    let present = filter(unexpected, |id| map.data.contains_key(&id));
+    if !present.is_empty() {
+        panic!("assertion failed: the map contains the types {:?}", present);
+    }
+}
+
+/// Panics unless `left` and `right` hold equal values of the same types, comparing values with
+/// the equality registered for their types in `registry`.
+///
+/// This is the function behind `assert_maps_eq!`.
+///
+/// # Panics
+///
+/// Also panics if a type in both maps has no registered equality.
+pub fn assert_maps_eq(registry: &TypeRegistry, left: &AnyMap, right: &AnyMap) {
+    let mut only_left = TypeSet::new();
+    let mut only_right = TypeSet::new();
+    let mut different = TypeSet::new();
+    for (&id, value) in left.data.iter() {
+        let name = registry.name_of(&id).unwrap_or(UNKNOWN_TYPE);
+        match right.data.get(&id) {
+            None => { let _ = only_left.insert_id(id, name); },
+            Some(other) => match registry.eq_values(&**value, &**other) {
+                Some(true) => (),
+                Some(false) => { let _ = different.insert_id(id, name); },
+                None => panic!("cannot compare maps: the type {} has no registered equality",
+                               name),
+            },
+        }
+    }
+    for &id in right.data.keys() {
+        if !left.data.contains_key(&id) {
+            let _ = only_right.insert_id(id, registry.name_of(&id).unwrap_or(UNKNOWN_TYPE));
+        }
+    }
+    if !(only_left.is_empty() && only_right.is_empty() && different.is_empty()) {
+        panic!("assertion failed: maps differ\n  only in left: {:?}\n  only in right: {:?}\n  \
+                different values: {:?}", only_left, only_right, different);
+    }
+}
+
+/// The ways a `SpyAnyMap` records an entry being accessed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AccessKind {
+    /// By `get`.
+    Get,
+    /// By `get_mut`.
+    GetMut,
+    /// By `insert`.
+    Insert,
+    /// By `remove`.
+    Remove,
+}
+
+impl AccessKind {
+    /// Returns true if the access could read the value.
+    #[inline]
+    pub fn is_read(self) -> bool {
+        self == AccessKind::Get || self == AccessKind::GetMut
+    }
+
+    /// Returns true if the access could change the value.
+    #[inline]
+    pub fn is_write(self) -> bool {
+        self != AccessKind::Get
+    }
+}
+
+/// One access to an entry of a `SpyAnyMap`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Access {
+    /// The type of the entry.
+    pub type_id: TypeId,
+    /// The name of the type of the entry.
+    pub name: &'static str,
+    /// How the entry was accessed.
+    pub kind: AccessKind,
+}
+
+/// An `AnyMap` which records every access to its entries, whether or not they have values, for
+/// tests to check which types the code under test reads and writes.
+///
+/// ```rust
+/// # use anymap::testing::SpyAnyMap;
+/// struct Config { verbose: bool }
+/// struct Log(Vec<&'static str>);
+///
+/// fn middleware(data: &mut SpyAnyMap) {
+///     if data.get::<Config>().map_or(false, |config| config.verbose) {
+///         data.insert(Log(vec!["verbose"]));
+///     }
+/// }
+///
+/// let mut data = SpyAnyMap::new();
+/// data.insert(Config { verbose: true });
+/// data.clear_log();
+/// middleware(&mut data);
+/// data.assert_read::<Config>();
+/// data.assert_never_written::<Config>();
+/// data.assert_written::<Log>();
+/// ```
+pub struct SpyAnyMap {
+    map: AnyMap,
+    log: RefCell<Vec<Access>>,
+}
+
+impl Default for SpyAnyMap {
+    #[inline]
+    fn default() -> SpyAnyMap {
+        SpyAnyMap::new()
+    }
+}
+
+impl SpyAnyMap {
+    /// Construct a new, empty `SpyAnyMap`.
+    #[inline]
+    pub fn new() -> SpyAnyMap {
+        SpyAnyMap::from_map(AnyMap::new())
+    }
+
+    /// Wrap an existing `AnyMap`, with nothing recorded.
+    #[inline]
+    pub fn from_map(map: AnyMap) -> SpyAnyMap {
+        SpyAnyMap {
+            map,
+            log: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Unwraps the underlying `AnyMap`, discarding the record.
+    #[inline]
+    pub fn into_inner(self) -> AnyMap {
+        self.map
+    }
+
+    /// Returns a reference to the underlying `AnyMap`, through which accesses are not recorded.
+    #[inline]
+    pub fn inner(&self) -> &AnyMap {
+        &self.map
+    }
+
+    /// Returns a reference to the value stored in the collection for the type `T`, if it exists.
+    pub fn get<T: Any + 'static>(&self) -> Option<&T> {
+        self.record::<T>(AccessKind::Get);
+        self.map.get::<T>()
+    }
+
+    /// Returns a mutable reference to the value stored in the collection for the type `T`,
+    /// if it exists.
+    pub fn get_mut<T: Any + 'static>(&mut self) -> Option<&mut T> {
+        self.record::<T>(AccessKind::GetMut);
+        self.map.get_mut::<T>()
+    }
+
+    /// Sets the value stored in the collection for the type `T`.
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    pub fn insert<T: Any + 'static>(&mut self, value: T) -> Option<T> {
+        self.record::<T>(AccessKind::Insert);
+        self.map.insert(value)
+    }
+
+    /// Removes the `T` value from the collection,
+    /// returning it if there was one or `None` if there was not.
+    pub fn remove<T: Any + 'static>(&mut self) -> Option<T> {
+        self.record::<T>(AccessKind::Remove);
+        self.map.remove::<T>()
+    }
+
+    /// Returns every access recorded, in order.
+    pub fn accesses(&self) -> Vec<Access> {
+        self.log.borrow().clone()
+    }
+
+    /// Forgets every access recorded, as after setting up the map for the code under test.
+    pub fn clear_log(&mut self) {
+        self.log.get_mut().clear();
+    }
+
+    /// Returns the set of the types accessed in any way matching the predicate.
+    pub fn touched<F: Fn(AccessKind) -> bool>(&self, kind: F) -> TypeSet {
+        let mut touched = TypeSet::new();
+        for access in self.log.borrow().iter().filter(|access| kind(access.kind)) {
+            let _ = touched.insert_id(access.type_id, access.name);
+        }
+        touched
+    }
+
+    /// Panics unless the entry for the type `T` has been read.
+    pub fn assert_read<T: Any + 'static>(&self) {
+        if !self.was::<T, _>(AccessKind::is_read) {
+            panic!("assertion failed: the type {} was never read", type_name::<T>());
+        }
+    }
+
+    /// Panics if the entry for the type `T` has been read.
+    pub fn assert_never_read<T: Any + 'static>(&self) {
+        if self.was::<T, _>(AccessKind::is_read) {
+            panic!("assertion failed: the type {} was read", type_name::<T>());
+        }
+    }
+
+    /// Panics unless the entry for the type `T` has been inserted, removed or borrowed mutably.
+    pub fn assert_written<T: Any + 'static>(&self) {
+        if !self.was::<T, _>(AccessKind::is_write) {
+            panic!("assertion failed: the type {} was never written", type_name::<T>());
+        }
+    }
+
+    /// Panics if the entry for the type `T` has been inserted, removed or borrowed mutably.
+    pub fn assert_never_written<T: Any + 'static>(&self) {
+        if self.was::<T, _>(AccessKind::is_write) {
+            panic!("assertion failed: the type {} was written", type_name::<T>());
+        }
+    }
+
+    /// Panics if any entry of a type not in `allowed` has been accessed at all.
+    ///
+    /// On failure, the panic message lists the types accessed which were not allowed.
+    pub fn assert_only_touched(&self, allowed: &TypeSet) {
+        let touched = self.touched(|_| true);
+        let extra = filter(&touched, |id| !allowed.types.contains_key(&id));
+        if !extra.is_empty() {
+            panic!("assertion failed: the types {:?} were accessed", extra);
+        }
+    }
+
+    fn record<T: Any + 'static>(&self, kind: AccessKind) {
+        self.record_id(TypeId::of::<T>(), type_name::<T>(), kind);
+    }
+
+    fn record_id(&self, type_id: TypeId, name: &'static str, kind: AccessKind) {
+        self.log.borrow_mut().push(Access {
+            type_id,
+            name,
+            kind,
+        });
+    }
+
+    fn was<T: Any + 'static, F: Fn(AccessKind) -> bool>(&self, kind: F) -> bool {
+        let id = TypeId::of::<T>();
+        self.log.borrow().iter().any(|access| access.type_id == id && kind(access.kind))
+    }
+}
+
+/// Accesses through `AnyMapLike` are recorded too, with a placeholder for the type's name.
+impl AnyMapLike for SpyAnyMap {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.map.contains_id(id)
+    }
+
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.record_id(id, UNKNOWN_TYPE, AccessKind::Get);
+        self.map.get_raw(id)
+    }
+
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        self.record_id(id, UNKNOWN_TYPE, AccessKind::GetMut);
+        self.map.get_raw_mut(id)
+    }
+
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        self.record_id((*value).type_id(), UNKNOWN_TYPE, AccessKind::Insert);
+        self.map.insert_raw(value)
+    }
+
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        self.record_id(id, UNKNOWN_TYPE, AccessKind::Remove);
+        self.map.remove_raw(id)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+/// Returns the types in `types` whose IDs pass the predicate.
+fn filter<F: Fn(TypeId) -> bool>(types: &TypeSet, predicate: F) -> TypeSet {
+    let mut filtered = TypeSet::new();
+    for (id, name) in types.iter() {
+        if predicate(id) {
+            let _ = filtered.insert_id(id, name);
+        }
+    }
+    filtered
+}
+
+#[test]
+fn test_assertions() {
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<i32>("i32").comparable();
+    let mut left = AnyMap::new();
+    let _ = left.insert(1i32);
+    let _ = left.insert(());
+    assert_contains_types!(left, i32, ());
+    assert_lacks_types!(left, u8, u16);
+
+    let mut right = AnyMap::new();
+    let _ = right.insert(1i32);
+    let _ = left.remove::<()>();
+    assert_maps_eq!(registry, left, right);
+}
+
+#[test]
+#[should_panic(expected = "only in right: {u8}")]
+fn test_assert_maps_eq_failure() {
+    let mut registry = TypeRegistry::new();
+    let _ = registry.register::<u8>("u8");
+    let mut right = AnyMap::new();
+    let _ = right.insert(1u8);
+    assert_maps_eq!(registry, AnyMap::new(), right);
+}
+
+#[test]
+fn test_spy() {
+    let mut map = SpyAnyMap::new();
+    let _ = map.insert(1u8);
+    let _ = map.insert(1u16);
+    let _ = map.get::<u32>();
+    assert_eq!(map.accesses().len(), 3);
+    assert_eq!(map.accesses()[2], Access {
+        type_id: TypeId::of::<u32>(),
+        name: type_name::<u32>(),
+        kind: AccessKind::Get,
+    });
+    map.clear_log();
+    assert!(map.accesses().is_empty());
+
+    *map.get_mut::<u8>().unwrap() += 1;
+    assert_eq!(map.get::<u16>(), Some(&1));
+    assert_eq!(map.remove::<i8>(), None);
+    map.assert_read::<u8>();
+    map.assert_written::<u8>();
+    map.assert_read::<u16>();
+    map.assert_never_written::<u16>();
+    map.assert_written::<i8>();
+    map.assert_never_read::<i8>();
+    map.assert_never_read::<u32>();
+    assert_eq!(map.touched(AccessKind::is_write).len(), 2);
+    let mut allowed = TypeSet::new();
+    let _ = allowed.insert::<u8>();
+    let _ = allowed.insert::<u16>();
+    let _ = allowed.insert::<i8>();
+    map.assert_only_touched(&allowed);
+    assert_eq!(map.inner().get::<u8>(), Some(&2));
+    assert_eq!(map.accesses().len(), 3);
+}
+
+#[test]
+#[should_panic(expected = "the type u16 was written")]
+fn test_spy_failure() {
+    let mut map = SpyAnyMap::new();
+    let _ = map.insert(1u16);
+    map.assert_never_written::<u16>();
+}
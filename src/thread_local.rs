@@ -0,0 +1,109 @@
+//! A per-thread `AnyMap`, for context such as the ID of the request a thread is handling.
+//!
+//! Each thread has a map of its own, created empty when the thread first uses it. Its values are
+//! reached through closures, as with the standard library's thread-local values; none of these
+//! functions may be called from within a closure passed to another of them, which would panic.
+
+use std::any::Any;
+use std::cell::RefCell;
+
+use AnyMap;
+
+thread_local!(static MAP: RefCell<AnyMap> = RefCell::new(AnyMap::new()));
+
+/// Calls a function with this thread's map.
+pub fn with_map<R, F: FnOnce(&mut AnyMap) -> R>(f: F) -> R {
+    MAP.with(|map| f(&mut map.borrow_mut()))
+}
+
+/// Calls a function with this thread's value of type `T`, returning its result, or `None` if
+/// the thread has no `T`.
+///
+/// ```rust
+/// # use anymap::thread_local;
+/// struct RequestId(u64);
+///
+/// thread_local::set(RequestId(7));
+/// assert_eq!(thread_local::with(|id: &RequestId| id.0), Some(7));
+/// std::thread::spawn(|| {
+///     assert!(!thread_local::contains::<RequestId>());
+/// }).join().unwrap();
+/// ```
+pub fn with<T: Any + 'static, R, F: FnOnce(&T) -> R>(f: F) -> Option<R> {
+    with_map(|map| map.get::<T>().map(f))
+}
+
+/// Calls a function with a mutable reference to this thread's value of type `T`, returning its
+/// result, or `None` if the thread has no `T`.
+pub fn with_mut<T: Any + 'static, R, F: FnOnce(&mut T) -> R>(f: F) -> Option<R> {
+    with_map(|map| map.get_mut::<T>().map(f))
+}
+
+/// Returns true if this thread has a value of type `T`.
+pub fn contains<T: Any + 'static>() -> bool {
+    with_map(|map| map.contains::<T>())
+}
+
+/// Sets this thread's value of type `T`, returning the old value if there was one.
+pub fn set<T: Any + 'static>(value: T) -> Option<T> {
+    with_map(|map| map.insert(value))
+}
+
+/// Removes this thread's value of type `T`, returning it if there was one.
+pub fn remove<T: Any + 'static>() -> Option<T> {
+    with_map(|map| map.remove::<T>())
+}
+
+/// Sets this thread's value of type `T` for the duration of a call to a function, restoring
+/// the previous value afterwards, even if the function panics.
+///
+/// ```rust
+/// # use anymap::thread_local;
+/// struct Depth(u32);
+///
+/// thread_local::set(Depth(0));
+/// thread_local::scoped(Depth(1), || {
+///     assert_eq!(thread_local::with(|depth: &Depth| depth.0), Some(1));
+/// });
+/// assert_eq!(thread_local::with(|depth: &Depth| depth.0), Some(0));
+/// ```
+pub fn scoped<T: Any + 'static, R, F: FnOnce() -> R>(value: T, f: F) -> R {
+    let _restore = Restore::<T> { previous: set(value) };
+    f()
+}
+
+/// Restores this thread's previous value of type `T` when dropped.
+struct Restore<T: Any + 'static> {
+    previous: Option<T>,
+}
+
+impl<T: Any + 'static> Drop for Restore<T> {
+    fn drop(&mut self) {
+        match self.previous.take() {
+            Some(previous) => { let _ = set(previous); },
+            None => { let _ = remove::<T>(); },
+        }
+    }
+}
+
+#[test]
+fn test_thread_local() {
+    use std::thread;
+
+    assert_eq!(set(1u8), None);
+    assert_eq!(with_mut(|n: &mut u8| { *n += 1; *n }), Some(2));
+    assert_eq!(with(|n: &u16| *n), None);
+    let result = thread::spawn(|| {
+        scoped(5u8, || with(|n: &u8| *n))
+    }).join().unwrap();
+    assert_eq!(result, Some(5));
+
+    let restored = thread::spawn(|| {
+        let _ = set(1u16);
+        let unwound = ::std::panic::catch_unwind(|| scoped(2u16, || panic!("unwinding")));
+        unwound.is_err() && with(|n: &u16| *n) == Some(1)
+    }).join().unwrap();
+    assert!(restored);
+    assert_eq!(remove::<u8>(), Some(2));
+    assert!(with_map(|map| map.is_empty()));
+}
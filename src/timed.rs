@@ -0,0 +1,254 @@
+//! An `AnyMap` flavour recording when each value was inserted and last modified.
+
+use std::any::{Any, TypeId};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::time::Instant;
+use std::vec;
+
+use {TypeIdBuildHasher, cast_box, cast_mut, cast_ref};
+use clock::{Clock, SystemClock};
+use like::AnyMapLike;
+
+/// A value together with when it was inserted and last modified.
+struct Slot {
+    value: Box<dyn Any + 'static>,
+    inserted: Instant,
+    modified: Instant,
+}
+
+/// A collection containing zero or one values for any given type, which records when each value
+/// was inserted and when it was last modified.
+///
+/// A value counts as modified when it is inserted, when it is borrowed mutably with `get_mut`,
+/// whether or not it is then changed, and when it is marked so with `touch`.
+///
+/// Time is measured by a `Clock`, which is the system clock unless another is supplied:
+///
+/// ```rust
+/// # use anymap::clock::ManualClock;
+/// # use anymap::timed::TimedAnyMap;
+/// # use std::time::Duration;
+/// let clock = ManualClock::new();
+/// let mut cache = TimedAnyMap::with_clock(clock.clone());
+/// cache.insert(vec![1u8, 2, 3]);
+/// let inserted = cache.inserted_at::<Vec<u8>>().unwrap();
+/// clock.advance(Duration::from_secs(30));
+/// cache.get_mut::<Vec<u8>>().unwrap().push(4);
+/// assert_eq!(cache.last_modified::<Vec<u8>>().unwrap() - inserted, Duration::from_secs(30));
+/// ```
+pub struct TimedAnyMap {
+    data: HashMap<TypeId, Slot, TypeIdBuildHasher>,
+    clock: Box<dyn Clock + 'static>,
+}
+
+impl Default for TimedAnyMap {
+    #[inline]
+    fn default() -> TimedAnyMap {
+        TimedAnyMap::new()
+    }
+}
+
+impl TimedAnyMap {
+    /// Construct a new, empty `TimedAnyMap` using the system clock.
+    #[inline]
+    pub fn new() -> TimedAnyMap {
+        TimedAnyMap::with_clock(SystemClock)
+    }
+
+    /// Construct a new, empty `TimedAnyMap` measuring time with the given clock.
+    #[inline]
+    pub fn with_clock<C: Clock + 'static>(clock: C) -> TimedAnyMap {
+        TimedAnyMap {
+            data: HashMap::with_hasher(TypeIdBuildHasher),
+            clock: Box::new(clock) as Box<dyn Clock>,
+        }
+    }
+
+    /// Returns a reference to the value stored in the collection for the type `T`, if it exists.
+    pub fn get<T: Any + 'static>(&self) -> Option<&T> {
+        self.data.get(&TypeId::of::<T>())
+            .map(|slot| cast_ref::<T>(&*slot.value))
+    }
+
+    /// Returns a mutable reference to the value stored in the collection for the type `T`,
+    /// if it exists, recording it as modified now.
+    #[inline]
+    pub fn get_mut<T: Any + 'static>(&mut self) -> Option<&mut T> {
+        self.get_any_mut(TypeId::of::<T>()).map(cast_mut::<T>)
+    }
+
+    /// Returns true if the collection contains a value of type `T`.
+    #[inline]
+    pub fn contains<T: Any + 'static>(&self) -> bool {
+        self.data.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Returns when the `T` value was inserted, if there is one.
+    #[inline]
+    pub fn inserted_at<T: Any + 'static>(&self) -> Option<Instant> {
+        self.data.get(&TypeId::of::<T>()).map(|slot| slot.inserted)
+    }
+
+    /// Returns when the `T` value was last modified, if there is one.
+    #[inline]
+    pub fn last_modified<T: Any + 'static>(&self) -> Option<Instant> {
+        self.data.get(&TypeId::of::<T>()).map(|slot| slot.modified)
+    }
+
+    /// Records the `T` value as modified now, returning true if there is one.
+    pub fn touch<T: Any + 'static>(&mut self) -> bool {
+        let now = self.clock.now();
+        match self.data.get_mut(&TypeId::of::<T>()) {
+            Some(slot) => { slot.modified = now; true },
+            None => false,
+        }
+    }
+
+    /// Sets the value stored in the collection for the type `T`, recording it as inserted now.
+    /// If the collection already had a value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    #[inline]
+    pub fn insert<T: Any + 'static>(&mut self, value: T) -> Option<T> {
+        self.insert_any(TypeId::of::<T>(), Box::new(value) as Box<dyn Any>)
+            .map(|any| *cast_box::<T>(any))
+    }
+
+    /// Removes the `T` value from the collection,
+    /// returning it if there was one or `None` if there was not.
+    pub fn remove<T: Any + 'static>(&mut self) -> Option<T> {
+        self.data.remove(&TypeId::of::<T>())
+            .map(|slot| *cast_box::<T>(slot.value))
+    }
+
+    /// Gets an iterator over all the values in the collection with when each was last modified,
+    /// the most recently modified first.
+    /// Iterator element type is `(&dyn Any, Instant)`.
+    pub fn iter_by_recency(&self) -> ByRecency<'_> {
+        let mut entries: Vec<(&dyn Any, Instant)> = self.data.values()
+            .map(|slot| (&*slot.value, slot.modified))
+            .collect();
+        entries.sort_by_key(|&(_, modified)| Reverse(modified));
+        ByRecency {
+            inner: entries.into_iter(),
+        }
+    }
+
+    /// Returns the number of items in the collection.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns true if there are no items in the collection.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Removes all items from the collection. Keeps the allocated memory for reuse.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    fn get_any_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        let now = self.clock.now();
+        self.data.get_mut(&id).map(|slot| {
+            slot.modified = now;
+            &mut *slot.value
+        })
+    }
+
+    fn insert_any(&mut self, id: TypeId, value: Box<dyn Any + 'static>)
+                  -> Option<Box<dyn Any + 'static>> {
+        let now = self.clock.now();
+        let slot = Slot {
+            value,
+            inserted: now,
+            modified: now,
+        };
+        self.data.insert(id, slot).map(|slot| slot.value)
+    }
+}
+
+/// Mutable lookups and insertions through `AnyMapLike` are timed as with the typed methods.
+impl AnyMapLike for TimedAnyMap {
+    #[inline]
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.data.contains_key(&id)
+    }
+
+    #[inline]
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        self.data.get(&id).map(|slot| &*slot.value)
+    }
+
+    #[inline]
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        self.get_any_mut(id)
+    }
+
+    #[inline]
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        Ok(self.insert_any((*value).type_id(), value))
+    }
+
+    #[inline]
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        self.data.remove(&id).map(|slot| slot.value)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+/// `TimedAnyMap` iterator, in order of recency, as returned by `TimedAnyMap::iter_by_recency`.
+pub struct ByRecency<'a> {
+    inner: vec::IntoIter<(&'a dyn Any, Instant)>,
+}
+
+impl<'a> Iterator for ByRecency<'a> {
+    type Item = (&'a dyn Any, Instant);
+
+    #[inline]
+    fn next(&mut self) -> Option<(&'a dyn Any, Instant)> {
+        self.inner.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.inner.size_hint() }
+}
+
+#[test]
+fn test_timed() {
+    use clock::ManualClock;
+    use std::time::Duration;
+
+    let clock = ManualClock::new();
+    let start = clock.now();
+    let mut map = TimedAnyMap::with_clock(clock.clone());
+    assert_eq!(map.insert(1u8), None);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(map.insert(2u16), None);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(map.insert(3u32), None);
+    clock.advance(Duration::from_secs(1));
+    *map.get_mut::<u8>().unwrap() += 1;
+    clock.advance(Duration::from_secs(1));
+    assert!(map.touch::<u16>() && !map.touch::<i8>());
+
+    assert_eq!(map.inserted_at::<u8>(), Some(start));
+    assert_eq!(map.last_modified::<u8>(), Some(start + Duration::from_secs(3)));
+    assert_eq!(map.last_modified::<i8>(), None);
+    let order: Vec<TypeId> = map.iter_by_recency().map(|(value, _)| value.type_id()).collect();
+    assert_eq!(order, vec![TypeId::of::<u16>(), TypeId::of::<u8>(), TypeId::of::<u32>()]);
+
+    assert_eq!(map.insert(5u8), Some(2));
+    assert_eq!(map.inserted_at::<u8>(), Some(start + Duration::from_secs(4)));
+    assert_eq!(map.remove::<u32>(), Some(3));
+    assert_eq!(map.len(), 2);
+}
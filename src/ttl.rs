@@ -0,0 +1,349 @@
+//! An `AnyMap` flavour in which values may be given a time to live.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use instrument::Instruments;
+use like::AnyMapLike;
+use {CallbackPanic, EvictionCallback, TypeIdBuildHasher, UNKNOWN_TYPE, call_on_evict, cast_box,
+     cast_mut, cast_ref, type_name};
+use clock::{Clock, SystemClock};
+
+/// A value together with the time it expires, if ever.
+struct Slot {
+    value: Box<dyn Any + 'static>,
+    /// The name of the type of the value, for diagnostics.
+    name: &'static str,
+    expires: Option<Instant>,
+}
+
+impl Slot {
+    #[inline]
+    fn is_live(&self, now: Instant) -> bool {
+        self.expires.is_none_or(|expires| now < expires)
+    }
+}
+
+/// A collection containing zero or one values for any given type, where each value may be given
+/// a time to live.
+///
+/// Once a value has expired it is treated as absent by every method, but it is only evicted when
+/// it is next touched mutably or when `purge_expired` is called. Evicted values are dropped,
+/// unless a callback has been registered with `on_evict` to take them.
+///
+/// Time is measured by a `Clock`, which is the system clock unless another is supplied:
+///
+/// ```rust
+/// # use anymap::clock::ManualClock;
+/// # use anymap::ttl::TtlAnyMap;
+/// # use std::time::Duration;
+/// let clock = ManualClock::new();
+/// let mut sessions = TtlAnyMap::with_clock(clock.clone());
+/// sessions.insert_with_ttl(42u64, Duration::from_secs(60));
+/// clock.advance(Duration::from_secs(59));
+/// assert_eq!(sessions.get::<u64>(), Some(&42));
+/// clock.advance(Duration::from_secs(1));
+/// assert_eq!(sessions.get::<u64>(), None);
+/// ```
+pub struct TtlAnyMap {
+    data: HashMap<TypeId, Slot, TypeIdBuildHasher>,
+    clock: Box<dyn Clock + 'static>,
+    on_evict: Option<EvictionCallback>,
+    evict_panics: Vec<CallbackPanic>,
+    instruments: Instruments,
+}
+
+impl Default for TtlAnyMap {
+    #[inline]
+    fn default() -> TtlAnyMap {
+        TtlAnyMap::new()
+    }
+}
+
+impl TtlAnyMap {
+    /// Construct a new, empty `TtlAnyMap` using the system clock.
+    #[inline]
+    pub fn new() -> TtlAnyMap {
+        TtlAnyMap::with_clock(SystemClock)
+    }
+
+    /// Construct a new, empty `TtlAnyMap` measuring time with the given clock.
+    #[inline]
+    pub fn with_clock<C: Clock + 'static>(clock: C) -> TtlAnyMap {
+        TtlAnyMap {
+            data: HashMap::with_hasher(TypeIdBuildHasher),
+            clock: Box::new(clock) as Box<dyn Clock>,
+            on_evict: None,
+            evict_panics: Vec::new(),
+            instruments: Instruments::new(),
+        }
+    }
+
+    /// Registers a callback to be given each expired value as it is evicted, replacing any
+    /// callback registered before. Values removed explicitly are not passed to it.
+    ///
+    /// A callback which panics does not unwind through the map: the panic is caught, the
+    /// callback unregistered, and the panic recorded, to be collected with `take_evict_panics`.
+    pub fn on_evict<F: FnMut(TypeId, Box<dyn Any + 'static>) + 'static>(&mut self, callback: F) {
+        self.on_evict = Some(Box::new(callback) as EvictionCallback);
+    }
+
+    /// Returns the panics of eviction callbacks caught since the last call, in the order they
+    /// happened, leaving none recorded.
+    #[inline]
+    pub fn take_evict_panics(&mut self) -> Vec<CallbackPanic> {
+        std::mem::take(&mut self.evict_panics)
+    }
+
+    /// Sets the prefix of the names of the metrics emitted for this map, `anymap` by default.
+    ///
+    /// With the `metrics` feature, each eviction increments the counter `<prefix>.evictions`,
+    /// labelled with the name of the type evicted.
+    #[cfg(feature = "metrics")]
+    #[inline]
+    pub fn set_metrics_prefix(&mut self, prefix: &str) {
+        self.instruments.set_metrics_prefix(prefix)
+    }
+
+    /// Returns a reference to the value stored in the collection for the type `T`, if it exists
+    /// and has not expired.
+    pub fn get<T: Any + 'static>(&self) -> Option<&T> {
+        let now = self.clock.now();
+        self.data.get(&TypeId::of::<T>())
+            .and_then(|slot| if slot.is_live(now) { Some(&*slot.value) } else { None })
+            .map(|any| cast_ref::<T>(any))
+    }
+
+    /// Returns a mutable reference to the value stored in the collection for the type `T`,
+    /// if it exists and has not expired. An expired value is evicted.
+    #[inline]
+    pub fn get_mut<T: Any + 'static>(&mut self) -> Option<&mut T> {
+        self.get_any_mut(TypeId::of::<T>()).map(cast_mut::<T>)
+    }
+
+    /// Returns true if the collection contains a value of type `T` which has not expired.
+    pub fn contains<T: Any + 'static>(&self) -> bool {
+        let now = self.clock.now();
+        self.data.get(&TypeId::of::<T>()).is_some_and(|slot| slot.is_live(now))
+    }
+
+    /// Returns how much longer the `T` value has to live, or `None` if there is no live `T` value
+    /// or it never expires.
+    pub fn time_to_live<T: Any + 'static>(&self) -> Option<Duration> {
+        let now = self.clock.now();
+        self.data.get(&TypeId::of::<T>())
+            .and_then(|slot| if slot.is_live(now) { slot.expires } else { None })
+            .map(|expires| expires - now)
+    }
+
+    /// Sets the value stored in the collection for the type `T`, never to expire.
+    /// If the collection already had a live value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    #[inline]
+    pub fn insert<T: Any + 'static>(&mut self, value: T) -> Option<T> {
+        self.insert_any(TypeId::of::<T>(), type_name::<T>(), Box::new(value) as Box<dyn Any>, None)
+            .map(|any| *cast_box::<T>(any))
+    }
+
+    /// Sets the value stored in the collection for the type `T`, to expire after `ttl`.
+    /// If the collection already had a live value of type `T`, that value is returned.
+    /// Otherwise, `None` is returned.
+    #[inline]
+    pub fn insert_with_ttl<T: Any + 'static>(&mut self, value: T, ttl: Duration) -> Option<T> {
+        let expires = Some(self.clock.now() + ttl);
+        let value = Box::new(value) as Box<dyn Any>;
+        self.insert_any(TypeId::of::<T>(), type_name::<T>(), value, expires)
+            .map(|any| *cast_box::<T>(any))
+    }
+
+    /// Removes the `T` value from the collection,
+    /// returning it if there was a live one or `None` if there was not.
+    #[inline]
+    pub fn remove<T: Any + 'static>(&mut self) -> Option<T> {
+        self.remove_any(TypeId::of::<T>()).map(|any| *cast_box::<T>(any))
+    }
+
+    /// Drops all expired values, returning how many there were.
+    pub fn purge_expired(&mut self) -> usize {
+        let now = self.clock.now();
+        let expired: Vec<TypeId> = self.data.iter()
+            .filter(|&(_, slot)| !slot.is_live(now))
+            .map(|(&id, _)| id)
+            .collect();
+        for &id in expired.iter() {
+            let slot = self.data.remove(&id).unwrap();
+            self.evict(id, slot);
+        }
+        expired.len()
+    }
+
+    /// Returns the number of items in the collection, including any which have expired but not
+    /// yet been dropped.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns true if there are no items in the collection, counting any which have expired but
+    /// not yet been dropped.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Removes all items from the collection. Keeps the allocated memory for reuse.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    /// Returns the value for the type with the given ID mutably if it is live, or evicts it.
+    fn get_any_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        let now = self.clock.now();
+        if self.data.get(&id).is_some_and(|slot| !slot.is_live(now)) {
+            let slot = self.data.remove(&id).unwrap();
+            self.evict(id, slot);
+            return None;
+        }
+        self.data.get_mut(&id).map(|slot| &mut *slot.value)
+    }
+
+    fn insert_any(&mut self, id: TypeId, name: &'static str, value: Box<dyn Any + 'static>,
+                  expires: Option<Instant>) -> Option<Box<dyn Any + 'static>> {
+        let now = self.clock.now();
+        let slot = Slot {
+            value,
+            name,
+            expires,
+        };
+        self.data.insert(id, slot)
+            .and_then(|slot| self.live_or_evict(id, slot, now))
+    }
+
+    fn remove_any(&mut self, id: TypeId) -> Option<Box<dyn Any + 'static>> {
+        let now = self.clock.now();
+        self.data.remove(&id)
+            .and_then(|slot| self.live_or_evict(id, slot, now))
+    }
+
+    /// Returns the value of a slot removed from the map if it is still live, or evicts it.
+    fn live_or_evict(&mut self, id: TypeId, slot: Slot, now: Instant)
+                     -> Option<Box<dyn Any + 'static>> {
+        if slot.is_live(now) {
+            Some(slot.value)
+        } else {
+            self.evict(id, slot);
+            None
+        }
+    }
+
+    fn evict(&mut self, id: TypeId, slot: Slot) {
+        self.instruments.evicted(id, slot.name);
+        call_on_evict(&mut self.on_evict, &mut self.evict_panics, id, slot.value);
+    }
+}
+
+/// Expired values are treated as absent through `AnyMapLike` too, and evicted as by the typed
+/// methods when touched mutably; the length counts them until then, as `len` does. Values
+/// inserted through it never expire.
+impl AnyMapLike for TtlAnyMap {
+    fn contains_id(&self, id: TypeId) -> bool {
+        let now = self.clock.now();
+        self.data.get(&id).is_some_and(|slot| slot.is_live(now))
+    }
+
+    fn get_raw(&self, id: TypeId) -> Option<&dyn Any> {
+        let now = self.clock.now();
+        self.data.get(&id)
+            .and_then(|slot| if slot.is_live(now) { Some(&*slot.value) } else { None })
+    }
+
+    #[inline]
+    fn get_raw_mut(&mut self, id: TypeId) -> Option<&mut dyn Any> {
+        self.get_any_mut(id)
+    }
+
+    #[inline]
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        Ok(self.insert_any((*value).type_id(), UNKNOWN_TYPE, value, None))
+    }
+
+    #[inline]
+    fn remove_raw(&mut self, id: TypeId) -> Option<Box<dyn Any>> {
+        self.remove_any(id)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+#[test]
+fn test_ttl() {
+    use clock::ManualClock;
+
+    let clock = ManualClock::new();
+    let mut map = TtlAnyMap::with_clock(clock.clone());
+    assert_eq!(map.insert(1i32), None);
+    assert_eq!(map.insert_with_ttl(2u8, Duration::from_secs(20)), None);
+    assert_eq!(map.insert_with_ttl(3u16, Duration::from_secs(10)), None);
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(map.get::<u16>(), Some(&3));
+    assert_eq!(map.time_to_live::<u8>(), Some(Duration::from_secs(15)));
+    assert_eq!(map.time_to_live::<i32>(), None);
+
+    clock.advance(Duration::from_secs(5));
+    assert!(!map.contains::<u16>());
+    assert_eq!(map.len(), 3);
+    assert_eq!(map.get_mut::<u16>(), None);
+    assert_eq!(map.get::<u8>(), Some(&2));
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.insert_with_ttl(5u32, Duration::from_secs(1)), None);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(map.purge_expired(), 1);
+    assert_eq!(map.len(), 2);
+
+    assert_eq!(map.insert_with_ttl(4u8, Duration::from_secs(1)), Some(2));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(map.remove::<u8>(), None);
+    assert_eq!(map.remove::<i32>(), Some(1));
+    assert!(map.is_empty());
+}
+
+#[test]
+fn test_on_evict() {
+    use clock::ManualClock;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let evicted = Rc::new(RefCell::new(Vec::new()));
+    let log = evicted.clone();
+    let clock = ManualClock::new();
+    let mut map = TtlAnyMap::with_clock(clock.clone());
+    map.on_evict(move |_, value| log.borrow_mut().push(*value.downcast::<i32>().unwrap()));
+    let _ = map.insert_with_ttl(1i32, Duration::from_secs(1));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(map.insert_with_ttl(2i32, Duration::from_secs(1)), None);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(map.purge_expired(), 1);
+    let _ = map.insert_with_ttl(3i32, Duration::from_secs(1));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(map.get_mut::<i32>(), None);
+    let _ = map.insert(4i32);
+    assert_eq!(map.remove::<i32>(), Some(4));
+    assert_eq!(*evicted.borrow(), vec![1, 2, 3]);
+
+    map.on_evict(|_, _| panic!("bad callback"));
+    let _ = map.insert_with_ttl(5u8, Duration::from_secs(1));
+    let _ = map.insert_with_ttl(6u16, Duration::from_secs(1));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(map.purge_expired(), 2);
+    assert!(map.is_empty());
+    let panics = map.take_evict_panics();
+    assert_eq!(panics.len(), 1);
+    assert_eq!(panics[0].message, Some("bad callback".to_string()));
+    assert!(map.take_evict_panics().is_empty());
+}
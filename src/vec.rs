@@ -0,0 +1,273 @@
+//! A vector of values of one type, chosen at runtime, which can be handled without knowing it.
+
+use std::any::{Any, TypeId};
+use std::ops::Range;
+
+use {cast_box, cast_mut, cast_ref, type_name};
+
+/// A boxed element of an `AnyVec`.
+type Erased = Box<dyn Any + 'static>;
+
+/// The operations on a `Vec<T>` which the vector needs without knowing `T`.
+struct Ops {
+    len: fn(&dyn Any) -> usize,
+    get: fn(&dyn Any, usize) -> Option<&dyn Any>,
+    get_mut: fn(&mut dyn Any, usize) -> Option<&mut dyn Any>,
+    push: fn(&mut dyn Any, Erased) -> Result<(), Erased>,
+    pop: fn(&mut dyn Any) -> Option<Erased>,
+    swap_remove: fn(&mut dyn Any, usize) -> Erased,
+    truncate: fn(&mut dyn Any, usize),
+}
+
+fn len<T: 'static>(vec: &dyn Any) -> usize {
+    cast_ref::<Vec<T>>(vec).len()
+}
+
+fn get<T: 'static>(vec: &dyn Any, index: usize) -> Option<&dyn Any> {
+    cast_ref::<Vec<T>>(vec).get(index).map(|value| value as &dyn Any)
+}
+
+fn get_mut<T: 'static>(vec: &mut dyn Any, index: usize) -> Option<&mut dyn Any> {
+    cast_mut::<Vec<T>>(vec).get_mut(index).map(|value| value as &mut dyn Any)
+}
+
+fn push<T: 'static>(vec: &mut dyn Any, value: Erased) -> Result<(), Erased> {
+    if (*value).type_id() != TypeId::of::<T>() {
+        return Err(value);
+    }
+    cast_mut::<Vec<T>>(vec).push(*cast_box::<T>(value));
+    Ok(())
+}
+
+fn pop<T: 'static>(vec: &mut dyn Any) -> Option<Erased> {
+    cast_mut::<Vec<T>>(vec).pop().map(|value| Box::new(value) as Box<dyn Any>)
+}
+
+fn swap_remove<T: 'static>(vec: &mut dyn Any, index: usize) -> Erased {
+    Box::new(cast_mut::<Vec<T>>(vec).swap_remove(index)) as Box<dyn Any>
+}
+
+fn truncate<T: 'static>(vec: &mut dyn Any, len: usize) {
+    cast_mut::<Vec<T>>(vec).truncate(len)
+}
+
+/// A vector of values of one type, chosen when the vector is made, stored contiguously.
+///
+/// Code which knows the element type can work with the underlying `Vec` directly, through
+/// `as_slice` and `as_vec_mut`; code which does not can still push, pop, index and iterate,
+/// with each element as an `Any`. Either way, pushing a value of another type is refused.
+///
+/// ```rust
+/// # use anymap::vec::AnyVec;
+/// # use std::any::Any;
+/// let mut positions = AnyVec::new::<(f32, f32)>();
+/// positions.push((0.0f32, 1.0f32)).unwrap();
+/// positions.push_any(Box::new((2.0f32, 3.0f32)) as Box<dyn Any>).unwrap();
+/// assert!(positions.push(0u8).is_err());
+///
+/// assert_eq!(positions.len(), 2);
+/// let xs: Vec<f32> = positions.as_slice::<(f32, f32)>().unwrap().iter().map(|p| p.0).collect();
+/// assert_eq!(xs, vec![0.0, 2.0]);
+/// ```
+pub struct AnyVec {
+    vec: Box<dyn Any + 'static>,
+    element: TypeId,
+    element_name: &'static str,
+    ops: Ops,
+}
+
+impl AnyVec {
+    /// Construct a new, empty `AnyVec` of values of type `T`.
+    #[inline]
+    pub fn new<T: Any + 'static>() -> AnyVec {
+        AnyVec::from_vec(Vec::<T>::new())
+    }
+
+    /// Construct a new, empty `AnyVec` of values of type `T`, with room for at least `capacity`
+    /// of them.
+    #[inline]
+    pub fn with_capacity<T: Any + 'static>(capacity: usize) -> AnyVec {
+        AnyVec::from_vec(Vec::<T>::with_capacity(capacity))
+    }
+
+    /// Construct an `AnyVec` of values of type `T` from a `Vec`.
+    pub fn from_vec<T: Any + 'static>(vec: Vec<T>) -> AnyVec {
+        AnyVec {
+            vec: Box::new(vec) as Box<dyn Any>,
+            element: TypeId::of::<T>(),
+            element_name: type_name::<T>(),
+            ops: Ops {
+                len: len::<T>,
+                get: get::<T>,
+                get_mut: get_mut::<T>,
+                push: push::<T>,
+                pop: pop::<T>,
+                swap_remove: swap_remove::<T>,
+                truncate: truncate::<T>,
+            },
+        }
+    }
+
+    /// Returns the ID of the type of the values in the vector.
+    #[inline]
+    pub fn element_type_id(&self) -> TypeId {
+        self.element
+    }
+
+    /// Returns the name of the type of the values in the vector.
+    #[inline]
+    pub fn element_type_name(&self) -> &'static str {
+        self.element_name
+    }
+
+    /// Returns true if the values in the vector are of type `T`.
+    #[inline]
+    pub fn holds<T: Any + 'static>(&self) -> bool {
+        self.element == TypeId::of::<T>()
+    }
+
+    /// Returns the values in the vector as a slice, if they are of type `T`.
+    pub fn as_slice<T: Any + 'static>(&self) -> Option<&[T]> {
+        if self.holds::<T>() { Some(&cast_ref::<Vec<T>>(&*self.vec)[..]) } else { None }
+    }
+
+    /// Returns a mutable reference to the underlying `Vec`, if the values are of type `T`.
+    pub fn as_vec_mut<T: Any + 'static>(&mut self) -> Option<&mut Vec<T>> {
+        if self.holds::<T>() { Some(cast_mut::<Vec<T>>(&mut *self.vec)) } else { None }
+    }
+
+    /// Unwraps the underlying `Vec`, if the values are of type `T`, or hands the vector back.
+    pub fn into_vec<T: Any + 'static>(self) -> Result<Vec<T>, AnyVec> {
+        if self.holds::<T>() { Ok(*cast_box::<Vec<T>>(self.vec)) } else { Err(self) }
+    }
+
+    /// Appends a value to the vector, or hands it back if it is not of the vector's type.
+    pub fn push<T: Any + 'static>(&mut self, value: T) -> Result<(), T> {
+        match self.as_vec_mut::<T>() {
+            Some(vec) => { vec.push(value); Ok(()) },
+            None => Err(value),
+        }
+    }
+
+    /// Appends a boxed value to the vector, or hands it back if it is not of the vector's type.
+    #[inline]
+    pub fn push_any(&mut self, value: Box<dyn Any + 'static>)
+                    -> Result<(), Box<dyn Any + 'static>> {
+        (self.ops.push)(&mut *self.vec, value)
+    }
+
+    /// Removes the last value from the vector and returns it boxed, if there is one.
+    #[inline]
+    pub fn pop_any(&mut self) -> Option<Box<dyn Any + 'static>> {
+        (self.ops.pop)(&mut *self.vec)
+    }
+
+    /// Removes the value at `index` from the vector and returns it boxed, replacing it with the
+    /// last value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    #[inline]
+    pub fn swap_remove_any(&mut self, index: usize) -> Box<dyn Any + 'static> {
+        (self.ops.swap_remove)(&mut *self.vec, index)
+    }
+
+    /// Returns a reference to the value at `index`, if there is one.
+    #[inline]
+    pub fn get_any(&self, index: usize) -> Option<&dyn Any> {
+        (self.ops.get)(&*self.vec, index)
+    }
+
+    /// Returns a mutable reference to the value at `index`, if there is one.
+    #[inline]
+    pub fn get_any_mut(&mut self, index: usize) -> Option<&mut dyn Any> {
+        (self.ops.get_mut)(&mut *self.vec, index)
+    }
+
+    /// Gets an iterator over the values in the vector, in order.
+    /// Iterator element type is `&dyn Any`.
+    #[inline]
+    pub fn iter_any(&self) -> IterAny<'_> {
+        IterAny {
+            vec: self,
+            range: 0..self.len(),
+        }
+    }
+
+    /// Shortens the vector to `len` values, dropping the rest. Does nothing if it is no longer.
+    #[inline]
+    pub fn truncate(&mut self, len: usize) {
+        (self.ops.truncate)(&mut *self.vec, len)
+    }
+
+    /// Removes all values from the vector. Keeps the allocated memory for reuse.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.truncate(0)
+    }
+
+    /// Returns the number of values in the vector.
+    #[inline]
+    pub fn len(&self) -> usize {
+        (self.ops.len)(&*self.vec)
+    }
+
+    /// Returns true if there are no values in the vector.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// `AnyVec` iterator over its values as `Any`, as returned by `AnyVec::iter_any`.
+#[derive(Clone)]
+pub struct IterAny<'a> {
+    vec: &'a AnyVec,
+    range: Range<usize>,
+}
+
+impl<'a> Iterator for IterAny<'a> {
+    type Item = &'a dyn Any;
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a dyn Any> {
+        self.range.next().and_then(|index| self.vec.get_any(index))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) { self.range.size_hint() }
+}
+
+impl<'a> DoubleEndedIterator for IterAny<'a> {
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a dyn Any> {
+        self.range.next_back().and_then(|index| self.vec.get_any(index))
+    }
+}
+
+#[test]
+fn test_any_vec() {
+    let mut vec = AnyVec::with_capacity::<u16>(4);
+    assert!(vec.holds::<u16>() && vec.element_type_id() == TypeId::of::<u16>());
+    assert_eq!(vec.element_type_name(), type_name::<u16>());
+    assert_eq!(vec.push(1u16), Ok(()));
+    assert_eq!(vec.push(2u8), Err(2));
+    assert!(vec.push_any(Box::new(2u16) as Box<dyn Any>).is_ok());
+    assert!(vec.push_any(Box::new(3u32) as Box<dyn Any>).is_err());
+    vec.as_vec_mut::<u16>().unwrap().push(3);
+    assert!(vec.as_slice::<u8>().is_none());
+    assert_eq!(vec.as_slice::<u16>(), Some(&[1u16, 2, 3][..]));
+
+    *vec.get_any_mut(0).unwrap().downcast_mut::<u16>().unwrap() = 10;
+    let values: Vec<u16> = vec.iter_any().map(|value| *value.downcast_ref().unwrap()).collect();
+    assert_eq!(values, vec![10, 2, 3]);
+    assert_eq!(vec.iter_any().next_back().and_then(|v| v.downcast_ref::<u16>()), Some(&3));
+    assert_eq!(vec.swap_remove_any(0).downcast_ref::<u16>(), Some(&10));
+    assert_eq!(vec.pop_any().unwrap().downcast_ref::<u16>(), Some(&2));
+    assert!(vec.get_any(1).is_none());
+    vec.clear();
+    assert!(vec.is_empty());
+    let vec = vec.into_vec::<u8>().err().unwrap();
+    assert_eq!(vec.into_vec::<u16>().ok(), Some(vec![]));
+}
@@ -0,0 +1,199 @@
+//! Read-only views of an `AnyMap`, either of the whole map or as structs of references to the
+//! values of the types a piece of code needs, extracted all at once.
+
+use std::any::Any;
+
+use {AnyMap, Iter, TypeSet};
+
+/// A read-only view of an `AnyMap`, as returned by `AnyMap::as_view`.
+///
+/// It is no more than a shared reference to the map, and can be copied freely, but unlike one
+/// it offers only the methods which inspect the map: code given a view cannot change the map,
+/// even by way of interior mutability such as its instrumentation settings.
+///
+/// ```rust
+/// # use anymap::AnyMap;
+/// # use anymap::view::AnyMapView;
+/// fn handler(request: AnyMapView) -> u32 {
+///     *request.get::<u32>().unwrap_or(&0)
+/// }
+///
+/// let mut data = AnyMap::new();
+/// data.insert(7u32);
+/// let view = data.as_view();
+/// assert_eq!(handler(view), 7);
+/// assert_eq!(view.len(), 1);
+/// ```
+#[derive(Clone, Copy)]
+pub struct AnyMapView<'a> {
+    map: &'a AnyMap,
+}
+
+impl AnyMap {
+    /// Returns a read-only view of the collection, which can be handed to code which must not
+    /// change it.
+    #[inline]
+    pub fn as_view(&self) -> AnyMapView<'_> {
+        AnyMapView { map: self }
+    }
+}
+
+impl<'a> AnyMapView<'a> {
+    /// Returns a reference to the value stored in the map for the type `T`, if it exists.
+    #[inline]
+    pub fn get<T: Any + 'static>(self) -> Option<&'a T> {
+        self.map.get::<T>()
+    }
+
+    /// Returns true if the map contains a value of type `T`.
+    #[inline]
+    pub fn contains<T: Any + 'static>(self) -> bool {
+        self.map.contains::<T>()
+    }
+
+    /// Gets an iterator over all the values in the map, in arbitrary order.
+    #[inline]
+    pub fn iter(self) -> Iter<'a> {
+        self.map.iter()
+    }
+
+    /// Returns the number of items in the map.
+    #[inline]
+    pub fn len(self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns true if there are no items in the map.
+    #[inline]
+    pub fn is_empty(self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+/// A field of a struct declared with `view!`: either `&'a T`, for a value which must be in the
+/// map, or `Option<&'a T>`, for one which may not be.
+pub trait Field<'a>: Sized {
+    /// Takes the field's value from the map, or records its type in `missing` and returns
+    /// `None` if the map lacks a value it needs.
+    fn extract(map: &'a AnyMap, missing: &mut TypeSet) -> Option<Self>;
+}
+
+impl<'a, T: Any + 'static> Field<'a> for &'a T {
+    fn extract(map: &'a AnyMap, missing: &mut TypeSet) -> Option<&'a T> {
+        let value = map.get::<T>();
+        if value.is_none() {
+            let _ = missing.insert::<T>();
+        }
+        value
+    }
+}
+
+impl<'a, T: Any + 'static> Field<'a> for Option<&'a T> {
+    #[inline]
+    fn extract(map: &'a AnyMap, _: &mut TypeSet) -> Option<Option<&'a T>> {
+        Some(map.get::<T>())
+    }
+}
+
+/// Declares a struct of references to values in an `AnyMap`, with a method `extract` which
+/// borrows them all from a map at once.
+///
+/// The struct must have exactly one lifetime parameter, and each field must be of type `&'a T`,
+/// when the map must have a `T`, or `Option<&'a T>`, when it need not. If the map lacks any of
+/// the values it must have, `extract` returns the set of their types instead. The struct may
+/// have attributes, and be public with public fields or private with private fields.
+///
+/// ```rust
+/// # #[macro_use] extern crate anymap;
+/// # fn main() {
+/// struct Config { verbose: bool }
+/// struct Stats { requests: u32 }
+/// struct Cache;
+///
+/// view! {
+///     struct Handler<'a> {
+///         config: &'a Config,
+///         stats: &'a Stats,
+///         cache: Option<&'a Cache>,
+///     }
+/// }
+///
+/// let mut data = anymap::AnyMap::new();
+/// data.insert(Config { verbose: false });
+/// assert!(Handler::extract(&data).err().unwrap().contains::<Stats>());
+///
+/// data.insert(Stats { requests: 3 });
+/// let view = Handler::extract(&data).unwrap();
+/// assert!(!view.config.verbose);
+/// assert_eq!(view.stats.requests, 3);
+/// assert!(view.cache.is_none());
+/// # }
+/// ```
+#[macro_export]
+macro_rules! view {
+    ($(#[$attr:meta])*
+     pub struct $name:ident<$lt:lifetime> { $(pub $field:ident: $t:ty),+ $(,)* }) => {
+        $(#[$attr])*
+        pub struct $name<$lt> {
+            $(pub $field: $t),+
+        }
+
+        view!(@impl $name<$lt> { $($field: $t),+ });
+    };
+    ($(#[$attr:meta])* struct $name:ident<$lt:lifetime> { $($field:ident: $t:ty),+ $(,)* }) => {
+        $(#[$attr])*
+        struct $name<$lt> {
+            $($field: $t),+
+        }
+
+        view!(@impl $name<$lt> { $($field: $t),+ });
+    };
+    (@impl $name:ident<$lt:lifetime> { $($field:ident: $t:ty),+ }) => {
+        impl<$lt> $name<$lt> {
+            /// Borrows each field's value from the map, returning the types of any the map
+            /// lacks instead.
+            #[allow(dead_code)]
+            pub fn extract(map: &$lt $crate::AnyMap) -> Result<$name<$lt>, $crate::TypeSet> {
+                let mut missing = $crate::TypeSet::new();
+                $(let $field = <$t as $crate::view::Field<$lt>>::extract(map, &mut missing);)+
+                if !missing.is_empty() {
+                    return Err(missing);
+                }
+                Ok($name {
+                    $($field: $field.unwrap()),+
+                })
+            }
+        }
+    };
+}
+
+#[test]
+fn test_any_map_view() {
+    let mut map = AnyMap::new();
+    let _ = map.insert(1u8);
+    let view = map.as_view();
+    let copy = view;
+    assert_eq!(view.get::<u8>(), Some(&1));
+    assert!(copy.contains::<u8>() && !copy.contains::<u16>());
+    assert_eq!((copy.len(), copy.is_empty(), copy.iter().count()), (1, false, 1));
+}
+
+#[test]
+fn test_view() {
+    view! {
+        struct Numbers<'a> {
+            byte: &'a u8,
+            short: Option<&'a u16>,
+            int: &'a i32,
+        }
+    }
+
+    let mut map = AnyMap::new();
+    let _ = map.insert(1u8);
+    let missing = Numbers::extract(&map).err().unwrap();
+    assert!(missing.contains::<i32>() && missing.len() == 1);
+
+    let _ = map.insert(2i32);
+    let numbers = Numbers::extract(&map).ok().unwrap();
+    assert_eq!((*numbers.byte, numbers.short, *numbers.int), (1, None, 2));
+}
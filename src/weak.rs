@@ -0,0 +1,184 @@
+//! An `AnyMap` flavour holding weak references, which does not keep its values alive.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+
+use {TypeIdBuildHasher, cast_box, cast_ref};
+use like::AnyMapLike;
+
+/// A weak reference, with a way of telling whether its referent is still alive.
+struct Slot {
+    weak: Box<dyn Any + 'static>,
+    is_alive: fn(&dyn Any) -> bool,
+}
+
+/// Returns true if the referent of the `Weak<T>` is still alive.
+fn is_alive<T: Any + 'static>(weak: &dyn Any) -> bool {
+    cast_ref::<Weak<T>>(weak).upgrade().is_some()
+}
+
+/// A collection containing zero or one weak references for any given type, to values shared
+/// through `Arc`s.
+///
+/// The map never keeps its values alive: once the last `Arc` to a value is dropped, its entry
+/// behaves as if absent, and is only dropped itself when it is next touched mutably or when
+/// `prune` is called. This suits caches of shared resources.
+///
+/// ```rust
+/// # use anymap::weak::WeakAnyMap;
+/// # use std::sync::Arc;
+/// let mut cache = WeakAnyMap::new();
+/// let pool = Arc::new(vec!["connection"]);
+/// cache.insert(&pool);
+/// assert_eq!(cache.get_upgraded::<Vec<&str>>(), Some(pool.clone()));
+/// drop(pool);
+/// assert_eq!(cache.get_upgraded::<Vec<&str>>(), None);
+/// assert_eq!(cache.prune(), 1);
+/// ```
+pub struct WeakAnyMap {
+    data: HashMap<TypeId, Slot, TypeIdBuildHasher>,
+}
+
+impl Default for WeakAnyMap {
+    #[inline]
+    fn default() -> WeakAnyMap {
+        WeakAnyMap::new()
+    }
+}
+
+impl WeakAnyMap {
+    /// Construct a new, empty `WeakAnyMap`.
+    #[inline]
+    pub fn new() -> WeakAnyMap {
+        WeakAnyMap {
+            data: HashMap::with_hasher(TypeIdBuildHasher),
+        }
+    }
+
+    /// Returns a strong reference to the value for the type `T`, if there is one and it is still
+    /// alive.
+    pub fn get_upgraded<T: Any + 'static>(&self) -> Option<Arc<T>> {
+        self.data.get(&TypeId::of::<T>())
+            .and_then(|slot| cast_ref::<Weak<T>>(&*slot.weak).upgrade())
+    }
+
+    /// Returns true if the collection contains a value of type `T` which is still alive.
+    pub fn contains<T: Any + 'static>(&self) -> bool {
+        self.data.get(&TypeId::of::<T>()).is_some_and(|slot| (slot.is_alive)(&*slot.weak))
+    }
+
+    /// Sets the value for the type `T` to the value shared by `value`, without keeping it alive.
+    /// If the collection already had a live value of type `T`, a strong reference to that value
+    /// is returned. Otherwise, `None` is returned.
+    #[inline]
+    pub fn insert<T: Any + 'static>(&mut self, value: &Arc<T>) -> Option<Arc<T>> {
+        self.insert_weak(Arc::downgrade(value))
+    }
+
+    /// Sets the weak reference stored in the collection for the type `T`.
+    /// If the collection already had a live value of type `T`, a strong reference to that value
+    /// is returned. Otherwise, `None` is returned.
+    pub fn insert_weak<T: Any + 'static>(&mut self, weak: Weak<T>) -> Option<Arc<T>> {
+        let slot = Slot {
+            weak: Box::new(weak) as Box<dyn Any>,
+            is_alive: is_alive::<T>,
+        };
+        self.data.insert(TypeId::of::<T>(), slot)
+            .and_then(|slot| cast_box::<Weak<T>>(slot.weak).upgrade())
+    }
+
+    /// Removes the weak reference for the type `T` from the collection,
+    /// returning it if there was one or `None` if there was not.
+    pub fn remove<T: Any + 'static>(&mut self) -> Option<Weak<T>> {
+        self.data.remove(&TypeId::of::<T>()).map(|slot| *cast_box::<Weak<T>>(slot.weak))
+    }
+
+    /// Drops all entries whose values are no longer alive, returning how many there were.
+    pub fn prune(&mut self) -> usize {
+        let dead: Vec<TypeId> = self.data.iter()
+            .filter(|&(_, slot)| !(slot.is_alive)(&*slot.weak))
+            .map(|(&id, _)| id)
+            .collect();
+        for id in dead.iter() {
+            let _ = self.data.remove(id);
+        }
+        dead.len()
+    }
+
+    /// Returns the number of entries in the collection, including any whose values are no
+    /// longer alive but which have not yet been pruned.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns true if there are no entries in the collection, counting any whose values are no
+    /// longer alive but which have not yet been pruned.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Removes all entries from the collection. Keeps the allocated memory for reuse.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+}
+
+/// The collection holds no values of its own to lend out or hand back through `AnyMapLike`, only
+/// weak references to them, so lookups, insertions and removals are all refused; a value
+/// inserted as a box would have nothing else to keep it alive. Presence counts only values which
+/// are still alive, as with `contains`, while the length counts every entry, as with `len`.
+impl AnyMapLike for WeakAnyMap {
+    fn contains_id(&self, id: TypeId) -> bool {
+        self.data.get(&id).is_some_and(|slot| (slot.is_alive)(&*slot.weak))
+    }
+
+    #[inline]
+    fn get_raw(&self, _: TypeId) -> Option<&dyn Any> {
+        None
+    }
+
+    #[inline]
+    fn get_raw_mut(&mut self, _: TypeId) -> Option<&mut dyn Any> {
+        None
+    }
+
+    #[inline]
+    fn insert_raw(&mut self, value: Box<dyn Any>)
+                  -> Result<Option<Box<dyn Any>>, Box<dyn Any>> {
+        Err(value)
+    }
+
+    #[inline]
+    fn remove_raw(&mut self, _: TypeId) -> Option<Box<dyn Any>> {
+        None
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+#[test]
+fn test_weak() {
+    let mut map = WeakAnyMap::new();
+    let one = Arc::new(1i32);
+    let two = Arc::new(2u8);
+    assert_eq!(map.insert(&one), None);
+    assert_eq!(map.insert(&two), None);
+    assert_eq!(Arc::strong_count(&one), 1);
+    assert!(map.contains::<i32>());
+    assert_eq!(map.get_upgraded::<u8>(), Some(two.clone()));
+
+    drop(one);
+    assert!(!map.contains::<i32>());
+    assert_eq!(map.insert(&Arc::new(3i32)), None);
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.prune(), 1);
+    assert!(map.remove::<u8>().unwrap().upgrade().is_some());
+    assert!(map.is_empty());
+}